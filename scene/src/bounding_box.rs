@@ -10,15 +10,15 @@ pub struct BoundingBox {
 impl BoundingBox {
     pub fn neg_infinity_bounds() -> Self {
         Self {
-            min: [std::f32::INFINITY; 3].into(),
-            max: [std::f32::NEG_INFINITY; 3].into(),
+            min: [f32::INFINITY; 3].into(),
+            max: [f32::NEG_INFINITY; 3].into(),
         }
     }
 
     pub fn pos_infinity_bounds() -> Self {
         Self {
-            min: [std::f32::NEG_INFINITY; 3].into(),
-            max: [std::f32::INFINITY; 3].into(),
+            min: [f32::NEG_INFINITY; 3].into(),
+            max: [f32::INFINITY; 3].into(),
         }
     }
 
@@ -77,7 +77,7 @@ impl BoundingBox {
     }
 
     pub fn intersection(boxes: impl IntoIterator<Item = Self>) -> Self {
-        let max = Point3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+        let max = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
         let min = max * -1.0; // this ensures that any min/max operation updates the bbox
 
         let mut extents = Self { max, min };
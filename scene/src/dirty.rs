@@ -1,4 +1,3 @@
-use js_sys::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// Tracks mutable access to a value using a dirty flag.
@@ -45,10 +44,7 @@ impl<T> Dirty<T> {
     ///
     /// The `update` callback is invoked if the value is dirty. If the callback
     /// fails by returning an error, the value will remain dirty and unchanged.
-    pub fn clean(
-        this: &mut Self,
-        update: impl FnOnce(&T) -> Result<(), Error>,
-    ) -> Result<bool, Error> {
+    pub fn clean<E>(this: &mut Self, update: impl FnOnce(&T) -> Result<(), E>) -> Result<bool, E> {
         if this.is_clean {
             return Ok(false);
         }
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Environment {
+    Solid {
+        tint: [f32; 3],
+    },
+    Map {
+        tint: [f32; 3],
+        rotation: f32,
+    },
+    /// Analytic daylight sky following the Preetham model, in kcd/m².
+    ///
+    /// The sky includes the solar disk attenuated by the atmosphere. The lower
+    /// half of the sky is a diffuse ground lit by the sun and the sky above it,
+    /// and a sun below the horizon is treated as if it were on the horizon.
+    Sky {
+        tint: [f32; 3],
+        sun_direction: [f32; 3],
+        turbidity: f32,
+        ground_albedo: [f32; 3],
+    },
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::Solid { tint: [1.0; 3] }
+    }
+}
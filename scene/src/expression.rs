@@ -0,0 +1,577 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+/// Error encountered while parsing an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOperator {
+    fn precedence(self) -> u32 {
+        match self {
+            Self::Add | Self::Sub => 1,
+            Self::Mul | Self::Div => 2,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+        }
+    }
+}
+
+/// Built-in functions, all of which have a GLSL equivalent with the same name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpressionFunction {
+    Abs,
+    Sqrt,
+    Exp,
+    Sin,
+    Cos,
+    Tan,
+    Min,
+    Max,
+    Pow,
+}
+
+impl ExpressionFunction {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "abs" => Some(Self::Abs),
+            "sqrt" => Some(Self::Sqrt),
+            "exp" => Some(Self::Exp),
+            "sin" => Some(Self::Sin),
+            "cos" => Some(Self::Cos),
+            "tan" => Some(Self::Tan),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "pow" => Some(Self::Pow),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Abs => "abs",
+            Self::Sqrt => "sqrt",
+            Self::Exp => "exp",
+            Self::Sin => "sin",
+            Self::Cos => "cos",
+            Self::Tan => "tan",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Pow => "pow",
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Min | Self::Max | Self::Pow => 2,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f32]) -> f32 {
+        match self {
+            Self::Abs => args[0].abs(),
+            Self::Sqrt => args[0].sqrt(),
+            Self::Exp => args[0].exp(),
+            Self::Sin => args[0].sin(),
+            Self::Cos => args[0].cos(),
+            Self::Tan => args[0].tan(),
+            Self::Min => args[0].min(args[1]),
+            Self::Max => args[0].max(args[1]),
+            Self::Pow => args[0].powf(args[1]),
+        }
+    }
+}
+
+/// Arithmetic expression over symbolic parameters.
+///
+/// Expressions are written in the usual infix notation, for instance in the
+/// form `2 * radius + max(0.1, sin(angle))`, and can be evaluated either on
+/// the CPU using `Expression::evaluate` or on the GPU via `Expression::glsl`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(f32),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Call(ExpressionFunction, Vec<Expression>),
+}
+
+impl Expression {
+    /// Parses an expression from its textual representation.
+    pub fn parse(text: &str) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            position: 0,
+        };
+
+        let expression = parser.parse_sum()?;
+
+        parser.skip_whitespace();
+
+        if parser.position != text.len() {
+            return Err(parser.error("unexpected character"));
+        }
+
+        Ok(expression)
+    }
+
+    /// Evaluates the expression, or panics if some referenced parameter values
+    /// are absent from the parameter table.
+    pub fn evaluate(&self, symbolic_values: &BTreeMap<String, f32>) -> f32 {
+        match self {
+            Self::Number(number) => *number,
+            Self::Symbol(symbol) => symbolic_values[symbol],
+            Self::Negate(operand) => -operand.evaluate(symbolic_values),
+            Self::Binary(operator, lhs, rhs) => {
+                let lhs = lhs.evaluate(symbolic_values);
+                let rhs = rhs.evaluate(symbolic_values);
+
+                match operator {
+                    BinaryOperator::Add => lhs + rhs,
+                    BinaryOperator::Sub => lhs - rhs,
+                    BinaryOperator::Mul => lhs * rhs,
+                    BinaryOperator::Div => lhs / rhs,
+                }
+            }
+            Self::Call(function, args) => {
+                let args: Vec<f32> = args.iter().map(|x| x.evaluate(symbolic_values)).collect();
+
+                function.apply(&args)
+            }
+        }
+    }
+
+    /// Generates GLSL code for the expression, using a callback to generate
+    /// the code which looks up the value of each symbolic parameter.
+    pub fn glsl(&self, lookup: &impl Fn(&str) -> String) -> String {
+        match self {
+            Self::Number(number) => format!("{:+e}", number),
+            Self::Symbol(symbol) => lookup(symbol),
+            Self::Negate(operand) => format!("(-{})", operand.glsl(lookup)),
+            Self::Binary(operator, lhs, rhs) => format!(
+                "({} {} {})",
+                lhs.glsl(lookup),
+                operator.symbol(),
+                rhs.glsl(lookup)
+            ),
+            Self::Call(function, args) => {
+                let args: Vec<String> = args.iter().map(|x| x.glsl(lookup)).collect();
+
+                format!("{}({})", function.name(), args.join(", "))
+            }
+        }
+    }
+
+    /// Returns all symbolic parameters referenced by this expression, in the
+    /// order in which they appear in the expression.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols = vec![];
+
+        self.symbols_recursive(&mut symbols);
+
+        symbols
+    }
+
+    fn symbols_recursive<'a>(&'a self, symbols: &mut Vec<&'a str>) {
+        match self {
+            Self::Number(_) => {}
+            Self::Symbol(symbol) => symbols.push(symbol),
+            Self::Negate(operand) => operand.symbols_recursive(symbols),
+            Self::Binary(_, lhs, rhs) => {
+                lhs.symbols_recursive(symbols);
+                rhs.symbols_recursive(symbols);
+            }
+            Self::Call(_, args) => {
+                for arg in args {
+                    arg.symbols_recursive(symbols);
+                }
+            }
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{}", number),
+            Self::Symbol(symbol) => write!(f, "{}", symbol),
+            Self::Negate(operand) => write!(f, "-{}", Operand(operand, 3)),
+            Self::Binary(operator, lhs, rhs) => {
+                let precedence = operator.precedence();
+
+                write!(
+                    f,
+                    "{} {} {}",
+                    Operand(lhs, precedence),
+                    operator.symbol(),
+                    Operand(rhs, precedence + 1)
+                )
+            }
+            Self::Call(function, args) => {
+                write!(f, "{}(", function.name())?;
+
+                for (index, arg) in args.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", arg)?;
+                }
+
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Displays an operand, parenthesized if it binds less tightly than required.
+struct Operand<'a>(&'a Expression, u32);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let precedence = match self.0 {
+            Expression::Binary(operator, ..) => operator.precedence(),
+            Expression::Negate(..) => 3,
+            _ => 4,
+        };
+
+        if precedence < self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Recursive descent parser for expressions.
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ExpressionError {
+        ExpressionError {
+            message: message.to_owned(),
+            position: self.position,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.text.len() && self.text[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.position).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ExpressionError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected `{}'", c as char)));
+        }
+
+        self.position += 1;
+
+        Ok(())
+    }
+
+    fn parse_sum(&mut self) -> Result<Expression, ExpressionError> {
+        let mut lhs = self.parse_product()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(b'+') => BinaryOperator::Add,
+                Some(b'-') => BinaryOperator::Sub,
+                _ => return Ok(lhs),
+            };
+
+            self.position += 1;
+
+            let rhs = self.parse_product()?;
+
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expression, ExpressionError> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(b'*') => BinaryOperator::Mul,
+                Some(b'/') => BinaryOperator::Div,
+                _ => return Ok(lhs),
+            };
+
+            self.position += 1;
+
+            let rhs = self.parse_unary()?;
+
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ExpressionError> {
+        match self.peek() {
+            Some(b'-') => {
+                self.position += 1;
+
+                Ok(Expression::Negate(Box::new(self.parse_unary()?)))
+            }
+            Some(b'+') => {
+                self.position += 1;
+
+                self.parse_unary()
+            }
+            _ => self.parse_atom(),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, ExpressionError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.position += 1;
+
+                let expression = self.parse_sum()?;
+
+                self.expect(b')')?;
+
+                Ok(expression)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => self.parse_number(),
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => self.parse_identifier(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Expression, ExpressionError> {
+        let start = self.position;
+
+        while let Some(&c) = self.text.get(self.position) {
+            let is_exponent_sign =
+                (c == b'+' || c == b'-') && matches!(self.text[self.position - 1], b'e' | b'E');
+
+            if c.is_ascii_digit() || c == b'.' || c == b'e' || c == b'E' || is_exponent_sign {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        let text = String::from_utf8_lossy(&self.text[start..self.position]);
+
+        // Numbers which overflow to infinity are rejected, as they cannot be printed
+        // back into an expression nor written as a GLSL floating-point literal.
+
+        match text.parse::<f32>() {
+            Ok(number) if number.is_finite() => Ok(Expression::Number(number)),
+            Ok(_) => Err(ExpressionError {
+                message: format!("number `{}' is out of range", text),
+                position: start,
+            }),
+            Err(_) => Err(ExpressionError {
+                message: format!("invalid number `{}'", text),
+                position: start,
+            }),
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<Expression, ExpressionError> {
+        let start = self.position;
+
+        while let Some(&c) = self.text.get(self.position) {
+            if c.is_ascii_alphanumeric() || c == b'_' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        let name = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+
+        if self.peek() != Some(b'(') {
+            return Ok(Expression::Symbol(name));
+        }
+
+        let function = match ExpressionFunction::from_name(&name) {
+            Some(function) => function,
+            None => {
+                return Err(ExpressionError {
+                    message: format!("unknown function `{}'", name),
+                    position: start,
+                })
+            }
+        };
+
+        self.position += 1;
+
+        let mut args = vec![];
+
+        if self.peek() != Some(b')') {
+            args.push(self.parse_sum()?);
+
+            while self.peek() == Some(b',') {
+                self.position += 1;
+
+                args.push(self.parse_sum()?);
+            }
+        }
+
+        self.expect(b')')?;
+
+        if args.len() != function.arity() {
+            return Err(ExpressionError {
+                message: format!(
+                    "function `{}' expects {} argument(s)",
+                    name,
+                    function.arity()
+                ),
+                position: start,
+            });
+        }
+
+        Ok(Expression::Call(function, args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPRESSIONS: &[&str] = &[
+        "1 + 2 * 3",
+        "(1 + 2) * 3",
+        "x - y - radius",
+        "x - (y - radius)",
+        "x / y / radius",
+        "-x * -y",
+        "--x",
+        "0.1 + 0.2",
+        "1e-3 * x + 1.5e3",
+        "3.4028235e38 - x",
+        "2 * radius + max(0.1, sin(x))",
+        "pow(abs(y), 1.5) / sqrt(radius)",
+        "min(x, y) - exp(-x) * cos(y) + tan(0.5)",
+    ];
+
+    fn parameters() -> BTreeMap<String, f32> {
+        let mut parameters = BTreeMap::new();
+
+        parameters.insert("x".to_owned(), 1.5);
+        parameters.insert("y".to_owned(), -0.25);
+        parameters.insert("radius".to_owned(), 2.0);
+
+        parameters
+    }
+
+    #[test]
+    fn evaluates_with_operator_precedence() {
+        let cases = [
+            ("1 + 2 * 3", 7.0),
+            ("(1 + 2) * 3", 9.0),
+            ("x - y - radius", -0.25),
+            ("x - (y - radius)", 3.75),
+            ("8 / 4 / 2", 1.0),
+            ("-x * -y", -0.375),
+            ("max(x, radius) - min(x, y)", 2.25),
+        ];
+
+        for &(text, value) in &cases {
+            let expression = Expression::parse(text).unwrap();
+
+            assert_eq!(expression.evaluate(&parameters()), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn display_round_trips_through_parser() {
+        for text in EXPRESSIONS {
+            let expression = Expression::parse(text).unwrap();
+            let reparsed = Expression::parse(&expression.to_string()).unwrap();
+
+            assert_eq!(reparsed, expression, "{}", text);
+        }
+    }
+
+    #[test]
+    fn glsl_computes_same_value_as_evaluator() {
+        // The generated GLSL happens to also be valid expression syntax when symbols are
+        // looked up by name, so it is parsed back to check that it computes exactly the
+        // same value as the evaluator, which catches precedence and literal mistakes.
+
+        for text in EXPRESSIONS {
+            let expression = Expression::parse(text).unwrap();
+            let glsl = expression.glsl(&|symbol| format!("({})", symbol));
+            let reparsed = Expression::parse(&glsl).unwrap();
+
+            let expected = expression.evaluate(&parameters());
+            let actual = reparsed.evaluate(&parameters());
+
+            assert_eq!(actual.to_bits(), expected.to_bits(), "{} => {}", text, glsl);
+        }
+    }
+
+    #[test]
+    fn glsl_looks_up_symbols_through_callback() {
+        let expression = Expression::parse("2 * radius").unwrap();
+        let glsl = expression.glsl(&|symbol| format!("lookup(\"{}\")", symbol));
+
+        assert_eq!(glsl, "(+2e0 * lookup(\"radius\"))");
+    }
+
+    #[test]
+    fn symbols_are_listed_in_order() {
+        let expression = Expression::parse("y * max(radius, x) + y").unwrap();
+
+        assert_eq!(expression.symbols(), vec!["y", "radius", "x", "y"]);
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let cases = [
+            "", "1 +", "(1", "1abc", "max(1)", "foo(1)", "1 $ 2", "1.2.3",
+        ];
+
+        for text in &cases {
+            assert!(Expression::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_numbers_out_of_range() {
+        let error = Expression::parse("1e39 * x").unwrap_err();
+
+        assert_eq!(error.position, 0);
+        assert!(Expression::parse("-1e39").is_err());
+    }
+}
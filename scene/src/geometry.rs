@@ -0,0 +1,1228 @@
+use crate::{BoundingBox, Expression};
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Rad, Vector3};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// GeometryParameter
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "RawGeometryParameter", into = "RawGeometryParameter")]
+pub enum GeometryParameter {
+    /// Fixed value across all instances.
+    Constant(f32),
+    /// Reference into a parameter table.
+    Symbolic(String),
+    /// Expression over values in a parameter table.
+    Expression(Expression),
+}
+
+impl GeometryParameter {
+    pub fn value(&self, symbolic_values: &BTreeMap<String, f32>) -> f32 {
+        match self {
+            Self::Constant(number) => *number,
+            Self::Symbolic(symbol) => symbolic_values[symbol],
+            Self::Expression(expression) => expression.evaluate(symbolic_values),
+        }
+    }
+
+    /// Returns the parameter's value if it is the same for all instances.
+    pub fn as_constant(&self) -> Option<f32> {
+        match self {
+            Self::Constant(number) => Some(*number),
+            Self::Symbolic(_) => None,
+            Self::Expression(expression) => {
+                if expression.symbols().is_empty() {
+                    Some(expression.evaluate(&BTreeMap::new()))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Serialized form of a geometry parameter; strings are symbolic parameter
+/// names while expressions are written as an object like `{"expr": "2 * x"}`.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawGeometryParameter {
+    Constant(f32),
+    Symbolic(String),
+    Expression { expr: String },
+}
+
+impl TryFrom<RawGeometryParameter> for GeometryParameter {
+    type Error = String;
+
+    fn try_from(raw: RawGeometryParameter) -> Result<Self, Self::Error> {
+        match raw {
+            RawGeometryParameter::Constant(number) => Ok(Self::Constant(number)),
+            RawGeometryParameter::Symbolic(symbol) => Ok(Self::Symbolic(symbol)),
+            RawGeometryParameter::Expression { expr } => match Expression::parse(&expr) {
+                Ok(expression) => Ok(Self::Expression(expression)),
+                Err(err) => Err(format!("invalid expression `{}': {}", expr, err)),
+            },
+        }
+    }
+}
+
+impl From<GeometryParameter> for RawGeometryParameter {
+    fn from(parameter: GeometryParameter) -> Self {
+        match parameter {
+            GeometryParameter::Constant(number) => Self::Constant(number),
+            GeometryParameter::Symbolic(symbol) => Self::Symbolic(symbol),
+            GeometryParameter::Expression(expression) => Self::Expression {
+                expr: expression.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn index(self) -> usize {
+        match self {
+            Self::X => 0,
+            Self::Y => 1,
+            Self::Z => 2,
+        }
+    }
+
+    /// Returns the two axes perpendicular to this axis, in cyclic order.
+    pub fn perpendicular(self) -> (usize, usize) {
+        match self {
+            Self::X => (1, 2),
+            Self::Y => (2, 0),
+            Self::Z => (0, 1),
+        }
+    }
+}
+
+/// Smallest period of a repeated geometry. Symbolic periods are only known per
+/// instance and may evaluate to zero, so they are clamped to avoid dividing by
+/// zero when folding points into the repeated cell.
+pub const MIN_REPEAT_PERIOD: f32 = 1e-4;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Geometry {
+    Sphere {
+        radius: GeometryParameter,
+    },
+    Ellipsoid {
+        radius: [GeometryParameter; 3],
+    },
+    Cuboid {
+        dimensions: [GeometryParameter; 3],
+    },
+    Cylinder {
+        height: GeometryParameter,
+        radius: GeometryParameter,
+    },
+    Torus {
+        major_radius: GeometryParameter,
+        minor_radius: GeometryParameter,
+    },
+    Capsule {
+        height: GeometryParameter,
+        radius: GeometryParameter,
+    },
+    Cone {
+        height: GeometryParameter,
+        bottom_radius: GeometryParameter,
+        top_radius: GeometryParameter,
+    },
+    Plane {
+        normal: [GeometryParameter; 3],
+        offset: GeometryParameter,
+        #[serde(default = "default_extent")]
+        extent: GeometryParameter,
+    },
+    HexagonalPrism {
+        height: GeometryParameter,
+        radius: GeometryParameter,
+    },
+    TriangularPrism {
+        height: GeometryParameter,
+        radius: GeometryParameter,
+    },
+    Union {
+        children: Vec<Geometry>,
+    },
+    Intersection {
+        children: Vec<Geometry>,
+    },
+    Subtraction {
+        lhs: Box<Geometry>,
+        rhs: Box<Geometry>,
+    },
+    SmoothUnion {
+        children: Vec<Geometry>,
+        blend_radius: GeometryParameter,
+    },
+    SmoothIntersection {
+        children: Vec<Geometry>,
+        blend_radius: GeometryParameter,
+    },
+    SmoothSubtraction {
+        lhs: Box<Geometry>,
+        rhs: Box<Geometry>,
+        blend_radius: GeometryParameter,
+    },
+    Onion {
+        thickness: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    Scale {
+        factor: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    Rotate {
+        axis: [GeometryParameter; 3],
+        angle: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    Translate {
+        translation: [GeometryParameter; 3],
+        child: Box<Geometry>,
+    },
+    Round {
+        radius: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    ForceNumericalNormals {
+        child: Box<Geometry>,
+    },
+    CustomModifier {
+        code: String,
+        expansion: [f32; 3],
+        child: Box<Geometry>,
+    },
+    Twist {
+        amount: GeometryParameter,
+        step: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    Repeat {
+        period: [GeometryParameter; 3],
+        count: Option<[u32; 3]>,
+        #[serde(default = "default_extent")]
+        extent: GeometryParameter,
+        child: Box<Geometry>,
+    },
+    Mirror {
+        axis: Axis,
+        child: Box<Geometry>,
+    },
+    PolarRepeat {
+        axis: Axis,
+        count: u32,
+        child: Box<Geometry>,
+    },
+    /// Triangle mesh loaded from an asset. Meshes are not distance fields, so
+    /// they can only be root geometries, although they may be placed within
+    /// their instance by translations, rotations and scalings.
+    Mesh {
+        mesh: String,
+    },
+}
+
+impl Geometry {
+    /// Returns the approximate cost of evaluating the distance field, based on
+    /// the arbitrary measure that the evaluation cost of the unit sphere is 1.
+    pub fn evaluation_cost(&self) -> f32 {
+        match self {
+            Self::Sphere { .. } => 1.0,
+            Self::Ellipsoid { .. } => 1.0,
+            Self::Cuboid { .. } => 1.5,
+            Self::Cylinder { .. } => 2.0,
+            Self::Torus { .. } => 1.5,
+            Self::Capsule { .. } => 1.5,
+            Self::Cone { .. } => 3.0,
+            Self::Plane { .. } => 0.5,
+            Self::HexagonalPrism { .. } => 2.5,
+            Self::TriangularPrism { .. } => 1.5,
+            Self::Union { children } => children.iter().map(|x| 0.25 + x.evaluation_cost()).sum(),
+            Self::Intersection { children } => {
+                children.iter().map(|x| 0.5 + x.evaluation_cost()).sum()
+            }
+            Self::Subtraction { lhs, rhs } => lhs.evaluation_cost() + rhs.evaluation_cost() + 0.25,
+            Self::SmoothUnion { children, .. } => {
+                children.iter().map(|x| 0.75 + x.evaluation_cost()).sum()
+            }
+            Self::SmoothIntersection { children, .. } => {
+                children.iter().map(|x| 0.75 + x.evaluation_cost()).sum()
+            }
+            Self::SmoothSubtraction { lhs, rhs, .. } => {
+                lhs.evaluation_cost() + rhs.evaluation_cost() + 0.75
+            }
+            Self::Onion { child, .. } => child.evaluation_cost() + 0.25,
+            Self::Scale { child, .. } => child.evaluation_cost() + 1.0,
+            Self::Rotate { child, .. } => child.evaluation_cost() + 2.0,
+            Self::Translate { child, .. } => child.evaluation_cost() + 0.25,
+            Self::Round { child, .. } => child.evaluation_cost() + 0.25,
+            Self::ForceNumericalNormals { child } => child.evaluation_cost(),
+            Self::CustomModifier { child, .. } => child.evaluation_cost() + 3.0,
+            Self::Twist { child, .. } => child.evaluation_cost() + 1.0,
+            Self::Repeat { child, .. } => child.evaluation_cost() + 0.5,
+            Self::Mirror { child, .. } => child.evaluation_cost() + 0.25,
+            Self::PolarRepeat { child, .. } => child.evaluation_cost() + 1.5,
+            Self::Mesh { .. } => 4.0,
+        }
+    }
+
+    /// Returns a bounding box for an instance of this geometry, or panics if
+    /// some referenced parameter values are absent from the parameter table.
+    pub fn bounding_box(&self, parameters: &BTreeMap<String, f32>) -> BoundingBox {
+        self.bounds(parameters).bbox
+    }
+
+    fn bounds(&self, parameters: &BTreeMap<String, f32>) -> GeometryBounds {
+        match self {
+            Self::Sphere { radius } => {
+                let radius = radius.value(parameters);
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius; 3].into(),
+                        max: [radius; 3].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Ellipsoid { radius } => {
+                let radius_x = radius[0].value(parameters);
+                let radius_y = radius[1].value(parameters);
+                let radius_z = radius[2].value(parameters);
+
+                let max_radius = radius_x.max(radius_y).max(radius_z);
+                let min_radius = radius_x.min(radius_y).min(radius_z);
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius_x, -radius_y, -radius_z].into(),
+                        max: [radius_x, radius_y, radius_z].into(),
+                    },
+                    scale_factor: max_radius / min_radius,
+                }
+            }
+            Self::Cuboid { dimensions } => {
+                let dim_x = dimensions[0].value(parameters);
+                let dim_y = dimensions[1].value(parameters);
+                let dim_z = dimensions[2].value(parameters);
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-dim_x, -dim_y, -dim_z].into(),
+                        max: [dim_x, dim_y, dim_z].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Cylinder { height, radius } => {
+                let height = height.value(parameters);
+                let radius = radius.value(parameters);
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius, -height, -radius].into(),
+                        max: [radius, height, radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let minor_radius = minor_radius.value(parameters);
+                let radius = major_radius.value(parameters) + minor_radius;
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius, -minor_radius, -radius].into(),
+                        max: [radius, minor_radius, radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Capsule { height, radius } => {
+                let radius = radius.value(parameters);
+                let height = height.value(parameters) + radius;
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius, -height, -radius].into(),
+                        max: [radius, height, radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Cone {
+                height,
+                bottom_radius,
+                top_radius,
+            } => {
+                let height = height.value(parameters);
+                let radius = bottom_radius
+                    .value(parameters)
+                    .max(top_radius.value(parameters));
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-radius, -height, -radius].into(),
+                        max: [radius, height, radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Plane {
+                normal,
+                offset,
+                extent,
+            } => {
+                // The plane is infinite, so it is clipped to a cube of a given size centred
+                // on the plane's closest point to the origin; if the plane is axis-aligned,
+                // the box is also flattened onto the plane.
+
+                let normal: Vector3<f32> = [
+                    normal[0].value(parameters),
+                    normal[1].value(parameters),
+                    normal[2].value(parameters),
+                ]
+                .into();
+
+                let normal = normal.normalize();
+                let offset = offset.value(parameters);
+                let extent = extent.value(parameters);
+
+                let centre = normal * offset;
+
+                let mut bbox = BoundingBox {
+                    min: [centre.x - extent, centre.y - extent, centre.z - extent].into(),
+                    max: [centre.x + extent, centre.y + extent, centre.z + extent].into(),
+                };
+
+                let axes: Vec<usize> = (0..3).filter(|&i| normal[i] != 0.0).collect();
+
+                if let [axis] = axes[..] {
+                    bbox.min[axis] = offset * normal[axis];
+                    bbox.max[axis] = offset * normal[axis];
+                }
+
+                GeometryBounds {
+                    bbox,
+                    scale_factor: 1.0,
+                }
+            }
+            Self::HexagonalPrism { height, radius } => {
+                let height = height.value(parameters);
+                let radius = radius.value(parameters);
+
+                // The hexagon's vertices lie on the X axis at the circumradius
+
+                let circumradius = radius * 2.0 / 3.0f32.sqrt();
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-circumradius, -height, -radius].into(),
+                        max: [circumradius, height, radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::TriangularPrism { height, radius } => {
+                let height = height.value(parameters);
+                let radius = radius.value(parameters);
+
+                // The triangle points towards +Z with an edge parallel to the X axis
+
+                let half_width = radius * 3.0f32.sqrt();
+
+                GeometryBounds {
+                    bbox: BoundingBox {
+                        min: [-half_width, -height, -radius].into(),
+                        max: [half_width, height, 2.0 * radius].into(),
+                    },
+                    scale_factor: 1.0,
+                }
+            }
+            Self::Union { children } => {
+                let mut bbox = BoundingBox::neg_infinity_bounds();
+                let mut scale_factor: f32 = 0.0;
+
+                for child in children {
+                    let bounds = child.bounds(parameters);
+
+                    scale_factor = scale_factor.max(bounds.scale_factor);
+                    bbox.extend(&bounds.bbox);
+                }
+
+                GeometryBounds { bbox, scale_factor }
+            }
+            Self::Intersection { children } => {
+                let mut bbox = BoundingBox::pos_infinity_bounds();
+                let mut scale_factor: f32 = 0.0;
+
+                for child in children {
+                    let bounds = child.bounds(parameters);
+
+                    scale_factor = scale_factor.max(bounds.scale_factor);
+                    bbox.intersect(&bounds.bbox);
+                }
+
+                GeometryBounds { bbox, scale_factor }
+            }
+            Self::Subtraction { lhs, .. } => lhs.bounds(parameters),
+            Self::SmoothUnion {
+                children,
+                blend_radius,
+            } => {
+                let mut bbox = BoundingBox::neg_infinity_bounds();
+                let mut scale_factor: f32 = 0.0;
+
+                for child in children {
+                    let bounds = child.bounds(parameters);
+
+                    scale_factor = scale_factor.max(bounds.scale_factor);
+                    bbox.extend(&bounds.bbox);
+                }
+
+                // The smooth minimum is at most a quarter of the blend radius below the
+                // regular minimum, and the children are folded pairwise, so the surface
+                // grows by at most that amount for every child after the first one.
+
+                let blend_count = children.len().saturating_sub(1) as f32;
+                let expansion = 0.25 * blend_count * blend_radius.value(parameters) * scale_factor;
+
+                bbox.min.x -= expansion;
+                bbox.min.y -= expansion;
+                bbox.min.z -= expansion;
+                bbox.max.x += expansion;
+                bbox.max.y += expansion;
+                bbox.max.z += expansion;
+
+                GeometryBounds { bbox, scale_factor }
+            }
+            Self::SmoothIntersection { children, .. } => {
+                let mut bbox = BoundingBox::pos_infinity_bounds();
+                let mut scale_factor: f32 = 0.0;
+
+                for child in children {
+                    let bounds = child.bounds(parameters);
+
+                    scale_factor = scale_factor.max(bounds.scale_factor);
+                    bbox.intersect(&bounds.bbox);
+                }
+
+                GeometryBounds { bbox, scale_factor }
+            }
+            Self::SmoothSubtraction { lhs, .. } => lhs.bounds(parameters),
+            Self::Onion { thickness, child } => {
+                let mut bounds = child.bounds(parameters);
+
+                let thickness = thickness.value(parameters) * bounds.scale_factor;
+
+                bounds.bbox.min.x -= thickness;
+                bounds.bbox.min.y -= thickness;
+                bounds.bbox.min.z -= thickness;
+                bounds.bbox.max.x += thickness;
+                bounds.bbox.max.y += thickness;
+                bounds.bbox.max.z += thickness;
+
+                bounds
+            }
+            Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. } => {
+                self.transform_bounds(child.bounds(parameters), parameters)
+            }
+            Self::Round { child, radius } => {
+                let mut bounds = child.bounds(parameters);
+
+                let radius = radius.value(parameters) * bounds.scale_factor;
+
+                bounds.bbox.min.x -= radius;
+                bounds.bbox.min.y -= radius;
+                bounds.bbox.min.z -= radius;
+                bounds.bbox.max.x += radius;
+                bounds.bbox.max.y += radius;
+                bounds.bbox.max.z += radius;
+
+                bounds
+            }
+            Self::ForceNumericalNormals { child } => child.bounds(parameters),
+            Self::CustomModifier {
+                child, expansion, ..
+            } => {
+                let mut bounds = child.bounds(parameters);
+
+                bounds.bbox.min.x -= expansion[0];
+                bounds.bbox.min.y -= expansion[1];
+                bounds.bbox.min.z -= expansion[2];
+
+                bounds.bbox.max.x += expansion[0];
+                bounds.bbox.max.y += expansion[1];
+                bounds.bbox.max.z += expansion[2];
+
+                bounds
+            }
+            Self::Twist { child, .. } => {
+                let mut bounds = child.bounds(parameters);
+
+                let dx = bounds.bbox.max.x - bounds.bbox.min.x;
+                let dy = bounds.bbox.max.y - bounds.bbox.min.y;
+                let dz = bounds.bbox.max.z - bounds.bbox.min.z;
+
+                bounds.bbox.min.x -= dx * 0.5;
+                bounds.bbox.min.y -= dy * 0.5;
+                bounds.bbox.min.z -= dz * 0.5;
+
+                bounds.bbox.max.x += dx * 0.5;
+                bounds.bbox.max.y += dy * 0.5;
+                bounds.bbox.max.z += dz * 0.5;
+
+                bounds
+            }
+            Self::Repeat {
+                period,
+                count,
+                extent,
+                child,
+            } => {
+                let mut bounds = child.bounds(parameters);
+
+                for i in 0..3 {
+                    let period = period[i].value(parameters);
+
+                    if period == 0.0 {
+                        continue;
+                    }
+
+                    // Infinitely repeated axes are clipped to the given extent, since
+                    // bounding boxes must be finite for the geometry to be traversed.
+
+                    if let Some(count) = count {
+                        let offset = period.abs() * (count[i].max(1) - 1) as f32 * 0.5;
+
+                        bounds.bbox.min[i] -= offset;
+                        bounds.bbox.max[i] += offset;
+                    } else {
+                        bounds.bbox.min[i] = -extent.value(parameters);
+                        bounds.bbox.max[i] = extent.value(parameters);
+                    }
+                }
+
+                bounds
+            }
+            Self::Mirror { axis, child } => {
+                let mut bounds = child.bounds(parameters);
+
+                let i = axis.index();
+                let extent = bounds.bbox.max[i].max(0.0);
+
+                bounds.bbox.min[i] = -extent;
+                bounds.bbox.max[i] = extent;
+
+                bounds
+            }
+            Self::PolarRepeat { axis, child, .. } => {
+                let mut bounds = child.bounds(parameters);
+
+                let (u, v) = axis.perpendicular();
+
+                let max_u = bounds.bbox.min[u].abs().max(bounds.bbox.max[u].abs());
+                let max_v = bounds.bbox.min[v].abs().max(bounds.bbox.max[v].abs());
+                let radius = (max_u * max_u + max_v * max_v).sqrt();
+
+                bounds.bbox.min[u] = -radius;
+                bounds.bbox.min[v] = -radius;
+                bounds.bbox.max[u] = radius;
+                bounds.bbox.max[v] = radius;
+
+                bounds
+            }
+            Self::Mesh { .. } => {
+                // The extents of a mesh are only known once its asset has been loaded, so the
+                // device is responsible for substituting the actual bounding box of the mesh.
+
+                GeometryBounds {
+                    bbox: BoundingBox::pos_infinity_bounds(),
+                    scale_factor: 1.0,
+                }
+            }
+        }
+    }
+
+    /// Returns a bounding box for an instance of this mesh geometry given the
+    /// bounding box of the mesh itself, see `transformed_mesh`.
+    pub fn mesh_bounding_box(
+        &self,
+        mesh_bbox: BoundingBox,
+        parameters: &BTreeMap<String, f32>,
+    ) -> BoundingBox {
+        match self {
+            Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. } => {
+                let bounds = GeometryBounds {
+                    bbox: child.mesh_bounding_box(mesh_bbox, parameters),
+                    scale_factor: 1.0,
+                };
+
+                self.transform_bounds(bounds, parameters).bbox
+            }
+            _ => mesh_bbox,
+        }
+    }
+
+    /// Applies the transform of a scaling, rotation or translation to the bounds
+    /// of its child geometry.
+    fn transform_bounds(
+        &self,
+        mut bounds: GeometryBounds,
+        parameters: &BTreeMap<String, f32>,
+    ) -> GeometryBounds {
+        match self {
+            Self::Scale { factor, .. } => {
+                bounds.bbox.min *= factor.value(parameters);
+                bounds.bbox.max *= factor.value(parameters);
+            }
+            Self::Rotate { axis, angle, .. } => {
+                let rotation_axis: Vector3<f32> = [
+                    axis[0].value(parameters),
+                    axis[1].value(parameters),
+                    axis[2].value(parameters),
+                ]
+                .into();
+
+                let rotation = Matrix3::from_axis_angle(
+                    rotation_axis.normalize(),
+                    Rad(angle.value(parameters)),
+                );
+
+                bounds.bbox = bounds.bbox.transform(rotation);
+            }
+            Self::Translate { translation, .. } => {
+                bounds.bbox.min.x += translation[0].value(parameters);
+                bounds.bbox.min.y += translation[1].value(parameters);
+                bounds.bbox.min.z += translation[2].value(parameters);
+                bounds.bbox.max.x += translation[0].value(parameters);
+                bounds.bbox.max.y += translation[1].value(parameters);
+                bounds.bbox.max.z += translation[2].value(parameters);
+            }
+            _ => unreachable!("geometry is not a transform"),
+        }
+
+        bounds
+    }
+
+    /// Returns a vector of all symbolic parameters found in this geometry in
+    /// a deterministic order, representing the approximate evaluation order.
+    pub fn symbolic_parameters(&self) -> Vec<&str> {
+        let mut parameters = vec![];
+
+        self.symbolic_parameters_recursive(&mut parameters);
+
+        parameters
+    }
+
+    fn symbolic_parameters_recursive<'a>(&'a self, parameters: &mut Vec<&'a str>) {
+        match self {
+            Self::Sphere { radius } => {
+                Self::record_parameter(parameters, radius);
+            }
+            Self::Ellipsoid { radius } => {
+                Self::record_parameter(parameters, &radius[0]);
+                Self::record_parameter(parameters, &radius[1]);
+                Self::record_parameter(parameters, &radius[2]);
+            }
+            Self::Cuboid { dimensions } => {
+                Self::record_parameter(parameters, &dimensions[0]);
+                Self::record_parameter(parameters, &dimensions[1]);
+                Self::record_parameter(parameters, &dimensions[2]);
+            }
+            Self::Cylinder { height, radius }
+            | Self::Capsule { height, radius }
+            | Self::HexagonalPrism { height, radius }
+            | Self::TriangularPrism { height, radius } => {
+                Self::record_parameter(parameters, height);
+                Self::record_parameter(parameters, radius);
+            }
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                Self::record_parameter(parameters, major_radius);
+                Self::record_parameter(parameters, minor_radius);
+            }
+            Self::Cone {
+                height,
+                bottom_radius,
+                top_radius,
+            } => {
+                Self::record_parameter(parameters, height);
+                Self::record_parameter(parameters, bottom_radius);
+                Self::record_parameter(parameters, top_radius);
+            }
+            Self::Plane {
+                normal,
+                offset,
+                extent,
+            } => {
+                Self::record_parameter(parameters, &normal[0]);
+                Self::record_parameter(parameters, &normal[1]);
+                Self::record_parameter(parameters, &normal[2]);
+                Self::record_parameter(parameters, offset);
+                Self::record_parameter(parameters, extent);
+            }
+            Self::Union { children } | Self::Intersection { children } => {
+                for child in children {
+                    child.symbolic_parameters_recursive(parameters);
+                }
+            }
+            Self::Subtraction { lhs, rhs } => {
+                lhs.symbolic_parameters_recursive(parameters);
+                rhs.symbolic_parameters_recursive(parameters);
+            }
+            Self::SmoothUnion {
+                children,
+                blend_radius,
+            }
+            | Self::SmoothIntersection {
+                children,
+                blend_radius,
+            } => {
+                Self::record_parameter(parameters, blend_radius);
+
+                for child in children {
+                    child.symbolic_parameters_recursive(parameters);
+                }
+            }
+            Self::SmoothSubtraction {
+                lhs,
+                rhs,
+                blend_radius,
+            } => {
+                Self::record_parameter(parameters, blend_radius);
+
+                lhs.symbolic_parameters_recursive(parameters);
+                rhs.symbolic_parameters_recursive(parameters);
+            }
+            Self::Onion { thickness, child } => {
+                Self::record_parameter(parameters, thickness);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Scale { factor, child } => {
+                Self::record_parameter(parameters, factor);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Rotate { axis, angle, child } => {
+                Self::record_parameter(parameters, &axis[0]);
+                Self::record_parameter(parameters, &axis[1]);
+                Self::record_parameter(parameters, &axis[2]);
+                Self::record_parameter(parameters, angle);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Translate { translation, child } => {
+                Self::record_parameter(parameters, &translation[0]);
+                Self::record_parameter(parameters, &translation[1]);
+                Self::record_parameter(parameters, &translation[2]);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Round { child, radius } => {
+                Self::record_parameter(parameters, radius);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::ForceNumericalNormals { child } => {
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::CustomModifier { child, .. } => {
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Twist {
+                child,
+                amount,
+                step,
+            } => {
+                Self::record_parameter(parameters, amount);
+                Self::record_parameter(parameters, step);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Repeat {
+                period,
+                extent,
+                child,
+                ..
+            } => {
+                Self::record_parameter(parameters, &period[0]);
+                Self::record_parameter(parameters, &period[1]);
+                Self::record_parameter(parameters, &period[2]);
+                Self::record_parameter(parameters, extent);
+
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Mirror { child, .. } | Self::PolarRepeat { child, .. } => {
+                child.symbolic_parameters_recursive(parameters);
+            }
+            Self::Mesh { .. } => {}
+        }
+    }
+
+    /// Returns the blend radius of every smooth operator in this geometry.
+    pub fn blend_radii(&self) -> Vec<&GeometryParameter> {
+        let mut blend_radii = vec![];
+
+        self.blend_radii_recursive(&mut blend_radii);
+
+        blend_radii
+    }
+
+    fn blend_radii_recursive<'a>(&'a self, blend_radii: &mut Vec<&'a GeometryParameter>) {
+        match self {
+            Self::Union { children } | Self::Intersection { children } => {
+                for child in children {
+                    child.blend_radii_recursive(blend_radii);
+                }
+            }
+            Self::SmoothUnion {
+                children,
+                blend_radius,
+            }
+            | Self::SmoothIntersection {
+                children,
+                blend_radius,
+            } => {
+                blend_radii.push(blend_radius);
+
+                for child in children {
+                    child.blend_radii_recursive(blend_radii);
+                }
+            }
+            Self::Subtraction { lhs, rhs } => {
+                lhs.blend_radii_recursive(blend_radii);
+                rhs.blend_radii_recursive(blend_radii);
+            }
+            Self::SmoothSubtraction {
+                lhs,
+                rhs,
+                blend_radius,
+            } => {
+                blend_radii.push(blend_radius);
+
+                lhs.blend_radii_recursive(blend_radii);
+                rhs.blend_radii_recursive(blend_radii);
+            }
+            Self::Onion { child, .. }
+            | Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. }
+            | Self::Round { child, .. }
+            | Self::ForceNumericalNormals { child }
+            | Self::CustomModifier { child, .. }
+            | Self::Twist { child, .. }
+            | Self::Repeat { child, .. }
+            | Self::Mirror { child, .. }
+            | Self::PolarRepeat { child, .. } => child.blend_radii_recursive(blend_radii),
+            _ => {}
+        }
+    }
+
+    /// Returns the mesh of this geometry if it is a mesh geometry, that is a mesh
+    /// possibly under a chain of translations, rotations and scalings.
+    pub fn transformed_mesh(&self) -> Option<&str> {
+        match self {
+            Self::Mesh { mesh } => Some(mesh),
+            Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. } => child.transformed_mesh(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this geometry contains a mesh anywhere below its root,
+    /// other than under a chain of translations, rotations and scalings from
+    /// the root. Meshes are not distance fields and cannot be combined.
+    pub fn has_nested_mesh(&self) -> bool {
+        match self {
+            Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. } => child.has_nested_mesh(),
+            _ => self.has_mesh_below_root(),
+        }
+    }
+
+    fn has_mesh_below_root(&self) -> bool {
+        match self {
+            Self::Union { children }
+            | Self::Intersection { children }
+            | Self::SmoothUnion { children, .. }
+            | Self::SmoothIntersection { children, .. } => children
+                .iter()
+                .any(|child| child.is_mesh() || child.has_mesh_below_root()),
+            Self::Subtraction { lhs, rhs } | Self::SmoothSubtraction { lhs, rhs, .. } => {
+                lhs.is_mesh()
+                    || lhs.has_mesh_below_root()
+                    || rhs.is_mesh()
+                    || rhs.has_mesh_below_root()
+            }
+            Self::Onion { child, .. }
+            | Self::Scale { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Translate { child, .. }
+            | Self::Round { child, .. }
+            | Self::ForceNumericalNormals { child }
+            | Self::CustomModifier { child, .. }
+            | Self::Twist { child, .. }
+            | Self::Repeat { child, .. }
+            | Self::Mirror { child, .. }
+            | Self::PolarRepeat { child, .. } => child.is_mesh() || child.has_mesh_below_root(),
+            _ => false,
+        }
+    }
+
+    fn is_mesh(&self) -> bool {
+        matches!(self, Self::Mesh { .. })
+    }
+
+    fn record_parameter<'a>(parameters: &mut Vec<&'a str>, parameter: &'a GeometryParameter) {
+        match parameter {
+            GeometryParameter::Constant(_) => {}
+            GeometryParameter::Symbolic(symbol) => parameters.push(symbol),
+            GeometryParameter::Expression(expression) => parameters.extend(expression.symbols()),
+        }
+    }
+}
+
+fn default_extent() -> GeometryParameter {
+    GeometryParameter::Constant(1000.0)
+}
+
+#[derive(Debug)]
+struct GeometryBounds {
+    bbox: BoundingBox,
+    scale_factor: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<GeometryParameter, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn strings_are_always_symbolic_parameters() {
+        assert_eq!(
+            parse(r#""my-param""#).unwrap(),
+            GeometryParameter::Symbolic("my-param".to_owned())
+        );
+
+        assert_eq!(
+            parse(r#""1abc""#).unwrap(),
+            GeometryParameter::Symbolic("1abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn expressions_are_parsed_from_objects() {
+        let parameter = parse(r#"{"expr": "2 * radius + 1"}"#).unwrap();
+
+        let mut symbolic_values = BTreeMap::new();
+        symbolic_values.insert("radius".to_owned(), 0.5);
+
+        assert_eq!(parameter.value(&symbolic_values), 2.0);
+        assert_eq!(parameter.as_constant(), None);
+
+        assert_eq!(
+            parse(r#"{"expr": "2 * 3"}"#).unwrap().as_constant(),
+            Some(6.0)
+        );
+        assert!(parse(r#"{"expr": "2 *"}"#).is_err());
+        assert!(parse(r#"{"expr": "1e39"}"#).is_err());
+    }
+
+    #[test]
+    fn parameters_round_trip_through_json() {
+        let cases = [
+            r#"1.5"#,
+            r#""radius""#,
+            r#""my-param""#,
+            r#"{"expr": "-(x - y) / max(0.5, radius)"}"#,
+            r#"{"expr": "3.4028235e38"}"#,
+        ];
+
+        for json in &cases {
+            let parameter = parse(json).unwrap();
+            let serialized = serde_json::to_string(&parameter).unwrap();
+
+            assert_eq!(parse(&serialized).unwrap(), parameter, "{}", json);
+        }
+    }
+
+    #[test]
+    fn smooth_union_bounds_contain_blended_surface() {
+        let sphere = Geometry::Sphere {
+            radius: GeometryParameter::Constant(1.0),
+        };
+
+        let blend_radius = 1.0;
+
+        for count in 1..=4 {
+            let geometry = Geometry::SmoothUnion {
+                children: vec![sphere.clone(); count],
+                blend_radius: GeometryParameter::Constant(blend_radius),
+            };
+
+            // Mirrors the pairwise fold of `geo_smooth_min` in the generated shader code.
+
+            let distance = |x: f32| {
+                let d = x - 1.0;
+
+                (1..count).fold(d, |a, _| {
+                    let h = (blend_radius - (a - d).abs()).max(0.0) / blend_radius;
+
+                    a.min(d) - h * h * blend_radius * 0.25
+                })
+            };
+
+            let mut surface = 0.0;
+
+            while distance(surface + 1e-3) < 0.0 {
+                surface += 1e-3;
+            }
+
+            let bbox = geometry.bounding_box(&BTreeMap::new());
+
+            assert!(bbox.max.x >= surface, "{} children", count);
+            assert!(bbox.min.x <= -surface, "{} children", count);
+        }
+    }
+
+    #[test]
+    fn transformed_meshes_are_root_geometries() {
+        let geometry: Geometry = serde_json::from_value(serde_json::json!({
+            "type": "translate",
+            "translation": [1.0, 2.0, 3.0],
+            "child": {
+                "type": "scale",
+                "factor": "size",
+                "child": {"type": "mesh", "mesh": "bunny.obj"},
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(geometry.transformed_mesh(), Some("bunny.obj"));
+        assert!(!geometry.has_nested_mesh());
+
+        let union = Geometry::Union {
+            children: vec![geometry.clone()],
+        };
+
+        assert_eq!(union.transformed_mesh(), None);
+        assert!(union.has_nested_mesh());
+
+        let rounded = Geometry::Translate {
+            translation: [
+                GeometryParameter::Constant(0.0),
+                GeometryParameter::Constant(0.0),
+                GeometryParameter::Constant(0.0),
+            ],
+            child: Box::new(Geometry::Round {
+                radius: GeometryParameter::Constant(0.1),
+                child: Box::new(geometry),
+            }),
+        };
+
+        assert_eq!(rounded.transformed_mesh(), None);
+        assert!(rounded.has_nested_mesh());
+    }
+
+    #[test]
+    fn plane_bounds_follow_the_offset() {
+        let plane = |normal: [f32; 3]| Geometry::Plane {
+            normal: [
+                GeometryParameter::Constant(normal[0]),
+                GeometryParameter::Constant(normal[1]),
+                GeometryParameter::Constant(normal[2]),
+            ],
+            offset: GeometryParameter::Constant(500.0),
+            extent: GeometryParameter::Constant(10.0),
+        };
+
+        let bbox = plane([0.0, 3.0, 0.0]).bounding_box(&BTreeMap::new());
+        assert_eq!(bbox.min, [-10.0, 500.0, -10.0].into());
+        assert_eq!(bbox.max, [10.0, 500.0, 10.0].into());
+
+        let bbox = plane([3.0, 0.0, 4.0]).bounding_box(&BTreeMap::new());
+        let expected_min = [290.0, -10.0, 390.0];
+        let expected_max = [310.0, 10.0, 410.0];
+
+        for i in 0..3 {
+            assert!((bbox.min[i] - expected_min[i]).abs() < 1e-3, "{:?}", bbox);
+            assert!((bbox.max[i] - expected_max[i]).abs() < 1e-3, "{:?}", bbox);
+        }
+    }
+
+    #[test]
+    fn mesh_bounds_are_transformed() {
+        let mesh = Geometry::Mesh {
+            mesh: "bunny.obj".to_owned(),
+        };
+
+        let geometry = Geometry::Translate {
+            translation: [
+                GeometryParameter::Constant(10.0),
+                GeometryParameter::Constant(0.0),
+                GeometryParameter::Symbolic("z".to_owned()),
+            ],
+            child: Box::new(Geometry::Rotate {
+                axis: [
+                    GeometryParameter::Constant(0.0),
+                    GeometryParameter::Constant(0.0),
+                    GeometryParameter::Constant(1.0),
+                ],
+                angle: GeometryParameter::Constant(std::f32::consts::FRAC_PI_2),
+                child: Box::new(Geometry::Scale {
+                    factor: GeometryParameter::Constant(2.0),
+                    child: Box::new(mesh.clone()),
+                }),
+            }),
+        };
+
+        let mesh_bbox = BoundingBox {
+            min: [0.0, -1.0, -1.0].into(),
+            max: [3.0, 1.0, 1.0].into(),
+        };
+
+        let mut parameters = BTreeMap::new();
+        parameters.insert("z".to_owned(), -5.0);
+
+        let bbox = mesh.mesh_bounding_box(mesh_bbox, &parameters);
+        assert_eq!((bbox.min, bbox.max), (mesh_bbox.min, mesh_bbox.max));
+
+        let bbox = geometry.mesh_bounding_box(mesh_bbox, &parameters);
+        let expected_min = [8.0, 0.0, -7.0];
+        let expected_max = [12.0, 6.0, -3.0];
+
+        for i in 0..3 {
+            assert!((bbox.min[i] - expected_min[i]).abs() < 1e-5, "{:?}", bbox);
+            assert!((bbox.max[i] - expected_max[i]).abs() < 1e-5, "{:?}", bbox);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use smart_default::SmartDefault;
+use std::collections::BTreeMap;
+
+/// Wavelength of the sodium d-line in nanometers, at which refractive indices are
+/// evaluated when dispersion is not rendered.
+pub const D_LINE_WAVELENGTH: f32 = 587.6;
+
+/// Wavelength dependence of the refractive index of a medium.
+///
+/// Coefficients are for wavelengths in micrometers, and dispersion is only
+/// rendered by the spectral integrator. Cauchy's equation keeps the index of the
+/// medium at the sodium d-line (587.6nm) and varies it by the coefficient B,
+/// while the three-term Sellmeier equation replaces it entirely. Either way, the
+/// RGB integrator uses the index at the d-line.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, SmartDefault)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Dispersion {
+    #[default]
+    None,
+    Cauchy {
+        coefficient: f32,
+    },
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+/// Homogeneous medium filling the inside of an instance.
+///
+/// The extinction is the imaginary part of the refractive index, from which the
+/// extinction coefficient of the medium is derived at each wavelength. Of this
+/// extinction, the fraction given by the scattering albedo is due to scattering
+/// instead of absorption, with the directions of scattered light following the
+/// Henyey-Greenstein phase function of the given asymmetry parameter.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Medium {
+    pub extinction: [f32; 3],
+    pub refractive_index: f32,
+    #[serde(default)]
+    pub scattering_albedo: [f32; 3],
+    #[serde(default)]
+    pub phase_asymmetry: f32,
+    #[serde(default)]
+    pub dispersion: Dispersion,
+}
+
+impl Medium {
+    /// Returns whether light is ever scattered inside this medium.
+    pub fn is_scattering(&self) -> bool {
+        (0..3).any(|i| self.extinction[i] > 0.0 && self.scattering_albedo[i] > 0.0)
+    }
+
+    /// Returns the refractive index of this medium at the sodium d-line, which is
+    /// used by the RGB integrator so that it agrees with the spectral integrator.
+    pub fn nominal_refractive_index(&self) -> f32 {
+        self.refractive_index_at(D_LINE_WAVELENGTH)
+    }
+
+    /// Returns the refractive index of this medium at a wavelength in nanometers.
+    pub fn refractive_index_at(&self, wavelength: f32) -> f32 {
+        let x = (wavelength * 1e-3).powi(2);
+
+        match self.dispersion {
+            Dispersion::None => self.refractive_index,
+            Dispersion::Cauchy { coefficient } => {
+                self.refractive_index + coefficient * (1.0 / x - 1.0 / 0.5876f32.powi(2))
+            }
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * x / (x - c[i])).sum();
+
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Instance {
+    pub geometry: String,
+    pub material: String,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, f32>,
+    #[serde(default = "true_default")]
+    pub sample_explicit: bool,
+    #[serde(default = "true_default")]
+    pub visible: bool,
+
+    pub medium: Medium,
+    pub parent: Option<String>,
+}
+
+fn true_default() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medium(dispersion: Dispersion) -> Medium {
+        Medium {
+            extinction: [0.0; 3],
+            refractive_index: 1.5,
+            scattering_albedo: [0.0; 3],
+            phase_asymmetry: 0.0,
+            dispersion,
+        }
+    }
+
+    #[test]
+    fn nominal_refractive_index_is_at_the_d_line() {
+        let cauchy = medium(Dispersion::Cauchy { coefficient: 0.004 });
+
+        assert!((cauchy.nominal_refractive_index() - 1.5).abs() < 1e-6);
+
+        // BK7 glass, which has a refractive index of 1.5168 at the d-line
+        let sellmeier = medium(Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        });
+
+        assert!((sellmeier.nominal_refractive_index() - 1.5168).abs() < 1e-4);
+    }
+}
@@ -30,4 +30,12 @@ pub struct Integrator {
 
     #[default(5.0)]
     pub geometry_pushback: f32,
+
+    /// Traces a single wavelength per pass instead of RGB, to render dispersion.
+    #[default(false)]
+    pub spectral: bool,
+
+    /// Seed of the random sequences, renders with different seeds are independent.
+    #[default(0)]
+    pub seed: u32,
 }
@@ -0,0 +1,41 @@
+//! The Equinox scene description model.
+//!
+//! This crate contains the scene types along with their validation logic, and
+//! has no dependency on WebGL or wasm-bindgen so that it can be used natively.
+
+#![allow(clippy::module_inception)]
+#![forbid(unsafe_code, while_true)]
+
+mod aperture;
+mod bounding_box;
+mod camera;
+mod dirty;
+mod display;
+mod environment;
+mod expression;
+mod geometry;
+mod instance;
+mod integrator;
+mod light;
+mod material;
+mod metadata;
+mod raster;
+mod scene;
+mod validation;
+
+pub use aperture::*;
+pub use bounding_box::*;
+pub use camera::*;
+pub use dirty::*;
+pub use display::*;
+pub use environment::*;
+pub use expression::*;
+pub use geometry::*;
+pub use instance::*;
+pub use integrator::*;
+pub use light::*;
+pub use material::*;
+pub use metadata::*;
+pub use raster::*;
+pub use scene::*;
+pub use validation::*;
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Analytic light source illuminating the scene alongside the environment.
+///
+/// Lights are invisible to camera rays and to specular reflections, and only
+/// illuminate surfaces through explicit light sampling and photon emission.
+/// Directions point towards where the light is travelling.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Light {
+    /// Point emitting a radiant intensity uniformly in all directions.
+    Point {
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    /// Point emitting a radiant intensity inside a cone of some half-angle.
+    ///
+    /// The intensity smoothly falls off to zero over the outer `blend` fraction
+    /// of the cone, with a blend of zero producing a hard-edged spot light.
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        angle: f32,
+        #[serde(default)]
+        blend: f32,
+    },
+    /// Sphere emitting a uniform radiance from its surface.
+    Sphere {
+        position: [f32; 3],
+        radius: f32,
+        radiance: [f32; 3],
+    },
+    /// Parallelogram centered on a position emitting a uniform radiance from
+    /// the side its normal points towards, which is `edge_u` cross `edge_v`.
+    Rectangle {
+        position: [f32; 3],
+        edge_u: [f32; 3],
+        edge_v: [f32; 3],
+        radiance: [f32; 3],
+    },
+    /// Infinitely distant light arriving from a single direction, like the sun.
+    Directional {
+        direction: [f32; 3],
+        irradiance: [f32; 3],
+    },
+}
+
+impl Light {
+    /// Returns the color of the light's emission, regardless of its units.
+    pub fn emission(&self) -> [f32; 3] {
+        match self {
+            Self::Point { intensity, .. } | Self::Spot { intensity, .. } => *intensity,
+            Self::Sphere { radiance, .. } | Self::Rectangle { radiance, .. } => *radiance,
+            Self::Directional { irradiance, .. } => *irradiance,
+        }
+    }
+}
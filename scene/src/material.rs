@@ -0,0 +1,423 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MaterialParameterType {
+    Scalar(f32),
+    Vector([f32; 3]),
+}
+
+impl MaterialParameterType {
+    pub fn as_vec3(&self) -> [f32; 3] {
+        match self {
+            Self::Scalar(c) => [*c; 3],
+            Self::Vector(v) => *v,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MaterialParameterTexture {
+    Single(String),
+    Multi { horz: String, vert: String },
+}
+
+impl MaterialParameterTexture {
+    pub fn horz_texture(&self) -> &str {
+        match self {
+            Self::Single(texture) => texture,
+            Self::Multi { horz, .. } => horz,
+        }
+    }
+
+    pub fn vert_texture(&self) -> &str {
+        match self {
+            Self::Single(texture) => texture,
+            Self::Multi { vert, .. } => vert,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TexturedMaterialParameter {
+    pub base: MaterialParameterType,
+    pub factor: MaterialParameterType,
+    pub texture: MaterialParameterTexture,
+    pub contrast: f32,
+
+    pub uv_scale: f32,
+    pub uv_offset: [f32; 2],
+    pub uv_rotation: f32,
+
+    pub stochastic: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MaterialParameter {
+    Constant(MaterialParameterType),
+    Textured(TexturedMaterialParameter),
+}
+
+impl MaterialParameter {
+    /// Returns the value of a constant parameter as the renderer reads scalars,
+    /// that is the luminance of its color, or `None` if it is textured.
+    pub fn constant_scalar(&self) -> Option<f32> {
+        match self {
+            Self::Constant(value) => Some(luminance(value.as_vec3())),
+            Self::Textured(_) => None,
+        }
+    }
+
+    /// Returns the smallest value the parameter takes as the renderer reads scalars.
+    /// Textures are assumed to lie in [0, 1] and are remapped by their contrast to
+    /// [0.5 - contrast, 0.5 + contrast] before being scaled by the factor.
+    pub fn min_scalar(&self) -> f32 {
+        match self {
+            Self::Constant(value) => luminance(value.as_vec3()),
+            Self::Textured(textured) => {
+                let base = textured.base.as_vec3();
+                let factor = textured.factor.as_vec3();
+                let contrast = textured.contrast.abs();
+
+                let min = |i: usize| {
+                    base[i] + (factor[i] * (0.5 - contrast)).min(factor[i] * (0.5 + contrast))
+                };
+
+                luminance([min(0), min(1), min(2)])
+            }
+        }
+    }
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+impl Default for MaterialParameter {
+    fn default() -> MaterialParameter {
+        MaterialParameter::Constant(MaterialParameterType::Scalar(0.0))
+    }
+}
+
+/// Minimum roughness of dielectrics and clear coats receiving photons. Photons
+/// are gathered as if the surface were diffuse, which is a good approximation
+/// for frosted glass or satin coats but would visibly blur the caustics seen
+/// through, or reflected by, smoother surfaces. Textured roughnesses must reach
+/// it everywhere, see `MaterialParameter::min_scalar`.
+pub const FROSTED_DIELECTRIC_ROUGHNESS: f32 = 0.5;
+
+/// Measured complex refractive index of a common metal, reduced to RGB.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    /// Returns the real and imaginary parts of the refractive index of the metal,
+    /// obtained by integrating its measured spectral data over the sRGB primaries.
+    pub fn ior(self) -> (&'static MaterialParameter, &'static MaterialParameter) {
+        let [eta, k] = &CONDUCTOR_PRESETS[self as usize];
+
+        (eta, k)
+    }
+}
+
+const fn constant(value: [f32; 3]) -> MaterialParameter {
+    MaterialParameter::Constant(MaterialParameterType::Vector(value))
+}
+
+static CONDUCTOR_PRESETS: [[MaterialParameter; 2]; 4] = [
+    [
+        constant([0.143_119, 0.374_957, 1.442_48]),
+        constant([3.983_16, 2.385_72, 1.603_22]),
+    ],
+    [
+        constant([0.200_438, 0.924_033, 1.102_21]),
+        constant([3.912_95, 2.452_85, 2.142_19]),
+    ],
+    [
+        constant([1.657_46, 0.880_369, 0.521_229]),
+        constant([9.223_87, 6.269_52, 4.837]),
+    ],
+    [
+        constant([0.155_265, 0.116_723, 0.138_342]),
+        constant([4.828_35, 3.122_25, 2.146_96]),
+    ],
+];
+
+/// Complex refractive index of a conductor, either a preset or arbitrary values.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum ConductorIor {
+    Preset(ConductorPreset),
+    Custom {
+        eta: MaterialParameter,
+        k: MaterialParameter,
+    },
+}
+
+impl ConductorIor {
+    /// Returns the real and imaginary parts of the refractive index.
+    pub fn parameters(&self) -> (&MaterialParameter, &MaterialParameter) {
+        match self {
+            Self::Preset(preset) => preset.ior(),
+            Self::Custom { eta, k } => (eta, k),
+        }
+    }
+}
+
+/// Base layer of a coated material, underneath its clear coat.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+#[allow(clippy::large_enum_variant)]
+pub enum CoatedBase {
+    Lambertian {
+        albedo: MaterialParameter,
+    },
+    Conductor {
+        ior: ConductorIor,
+        roughness: MaterialParameter,
+        #[serde(default)]
+        anisotropy: MaterialParameter,
+    },
+}
+
+impl CoatedBase {
+    /// Returns a list of parameters referenced by this base layer.
+    pub fn parameters(&self) -> Vec<(&str, &MaterialParameter)> {
+        match self {
+            Self::Lambertian { albedo } => vec![("albedo", albedo)],
+            Self::Conductor {
+                ior,
+                roughness,
+                anisotropy,
+            } => {
+                let (eta, k) = ior.parameters();
+
+                vec![
+                    ("eta", eta),
+                    ("k", k),
+                    ("roughness", roughness),
+                    ("anisotropy", anisotropy),
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Materials {
+    pub list: Vec<Material>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+#[allow(clippy::large_enum_variant)]
+pub enum Material {
+    Lambertian {
+        albedo: MaterialParameter,
+    },
+    IdealReflection {
+        reflectance: MaterialParameter,
+    },
+    IdealRefraction {
+        transmittance: MaterialParameter,
+    },
+    Phong {
+        albedo: MaterialParameter,
+        shininess: MaterialParameter,
+    },
+    /// Glass-like interface with a GGX microfacet distribution.
+    ///
+    /// The roughness behaves like the conductor roughness, a roughness of zero
+    /// giving a perfectly smooth interface. Surfaces with a roughness of at
+    /// least `FROSTED_DIELECTRIC_ROUGHNESS` everywhere are treated as frosted
+    /// glass and receive photons like diffuse surfaces.
+    Dielectric {
+        base_color: MaterialParameter,
+        #[serde(default)]
+        roughness: MaterialParameter,
+    },
+    /// Surface emitting light from its front side and absorbing all light.
+    Emissive {
+        radiance: MaterialParameter,
+    },
+    /// Metallic surface with a GGX microfacet distribution.
+    ///
+    /// The roughness is the perceptual roughness in [0, 1], which is squared to
+    /// obtain the GGX alpha parameter. The anisotropy in [0, 1] stretches the
+    /// highlights along an arbitrary but consistent tangent direction.
+    Conductor {
+        ior: ConductorIor,
+        roughness: MaterialParameter,
+        #[serde(default)]
+        anisotropy: MaterialParameter,
+    },
+    /// Base layer covered by a dielectric clear coat of refractive index 1.5.
+    ///
+    /// The coat roughness behaves like the conductor roughness. Light crossing
+    /// the coat is attenuated by its absorption coefficient over a path length
+    /// depending on its thickness, in the same units as the absorption. Like
+    /// frosted glass, a Lambertian base under a coat with a roughness of at
+    /// least `FROSTED_DIELECTRIC_ROUGHNESS` everywhere receives photons, the coat
+    /// lobe being folded into the diffuse approximation of the whole surface.
+    Coated {
+        base: CoatedBase,
+        #[serde(default)]
+        coat_roughness: MaterialParameter,
+        #[serde(default)]
+        coat_thickness: MaterialParameter,
+        #[serde(default)]
+        coat_absorption: MaterialParameter,
+    },
+}
+
+impl Material {
+    pub fn has_delta_bsdf(&self) -> bool {
+        match self {
+            Self::Lambertian { .. } => false,
+            Self::IdealReflection { .. } => true,
+            Self::IdealRefraction { .. } => true,
+            Self::Phong { .. } => false,
+            Self::Dielectric { roughness, .. } => {
+                matches!(roughness.constant_scalar(), Some(r) if r <= 0.0)
+            }
+            Self::Emissive { .. } => false,
+            Self::Conductor { .. } => false,
+            Self::Coated { .. } => false,
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        matches!(self, Self::Emissive { .. })
+    }
+
+    /// Returns whether photons are deposited on this material; the photon map is
+    /// only used on diffuse surfaces since it ignores the direction of photons.
+    pub fn is_photon_receiver(&self) -> bool {
+        match self {
+            Self::Lambertian { .. } => true,
+            Self::Dielectric { roughness, .. } => {
+                roughness.min_scalar() >= FROSTED_DIELECTRIC_ROUGHNESS
+            }
+            Self::Coated {
+                base: CoatedBase::Lambertian { .. },
+                coat_roughness,
+                ..
+            } => coat_roughness.min_scalar() >= FROSTED_DIELECTRIC_ROUGHNESS,
+            _ => false,
+        }
+    }
+
+    /// Returns a list of parameters referenced by this material.
+    pub fn parameters(&self) -> Vec<(&str, &MaterialParameter)> {
+        match self {
+            Self::Lambertian { albedo } => vec![("albedo", albedo)],
+            Self::IdealReflection { reflectance } => vec![("reflectance", reflectance)],
+            Self::IdealRefraction { transmittance } => vec![("transmittance", transmittance)],
+            Self::Phong { albedo, shininess } => {
+                vec![("albedo", albedo), ("shininess", shininess)]
+            }
+            Self::Dielectric {
+                base_color,
+                roughness,
+            } => vec![("base_color", base_color), ("roughness", roughness)],
+            Self::Emissive { radiance } => vec![("radiance", radiance)],
+            Self::Conductor {
+                ior,
+                roughness,
+                anisotropy,
+            } => {
+                let (eta, k) = ior.parameters();
+
+                vec![
+                    ("eta", eta),
+                    ("k", k),
+                    ("roughness", roughness),
+                    ("anisotropy", anisotropy),
+                ]
+            }
+            Self::Coated {
+                base,
+                coat_roughness,
+                coat_thickness,
+                coat_absorption,
+            } => {
+                let mut parameters = base.parameters();
+
+                parameters.push(("coat_roughness", coat_roughness));
+                parameters.push(("coat_thickness", coat_thickness));
+                parameters.push(("coat_absorption", coat_absorption));
+
+                parameters
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn material(value: serde_json::Value) -> Material {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn textured_roughness(base: f32, factor: f32) -> serde_json::Value {
+        json!({
+            "base": base,
+            "factor": factor,
+            "texture": "roughness.raw",
+            "contrast": 0.5,
+            "uv_scale": 1.0,
+            "uv_offset": [0.0, 0.0],
+            "uv_rotation": 0.0,
+            "stochastic": false,
+        })
+    }
+
+    fn rough_dielectric(roughness: serde_json::Value) -> serde_json::Value {
+        json!({"type": "dielectric", "base_color": 1.0, "roughness": roughness})
+    }
+
+    #[test]
+    fn diffuse_and_frosted_surfaces_receive_photons() {
+        let receivers = [
+            rough_dielectric(textured_roughness(0.5, 0.4)),
+            rough_dielectric(textured_roughness(1.0, -0.5)),
+            json!({"type": "lambertian", "albedo": 0.5}),
+            json!({"type": "dielectric", "base_color": 1.0, "roughness": 0.5}),
+            json!({"type": "coated", "base": {"type": "lambertian", "albedo": 0.5}, "coat_roughness": 0.8}),
+        ];
+
+        for value in receivers.iter() {
+            assert!(material(value.clone()).is_photon_receiver(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn glossy_and_smooth_surfaces_do_not_receive_photons() {
+        let non_receivers = [
+            json!({"type": "ideal-reflection", "reflectance": 1.0}),
+            json!({"type": "dielectric", "base_color": 1.0}),
+            json!({"type": "dielectric", "base_color": 1.0, "roughness": 0.3}),
+            rough_dielectric(textured_roughness(0.0, 1.0)),
+            rough_dielectric(textured_roughness(0.8, -0.4)),
+            json!({"type": "coated", "base": {"type": "lambertian", "albedo": 0.5}}),
+            json!({"type": "coated", "base": {"type": "conductor", "ior": "gold", "roughness": 0.2}, "coat_roughness": 0.8}),
+            json!({"type": "emissive", "radiance": 1.0}),
+        ];
+
+        for value in non_receivers.iter() {
+            assert!(!material(value.clone()).is_photon_receiver(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,1095 @@
+use crate::{
+    Aperture, ApertureShape, Camera, Dirty, Dispersion, Display, Environment, Geometry,
+    GeometryParameter, Instance, Integrator, JsonPointer, Light, Material, MaterialParameter,
+    Metadata, Raster, ValidationError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+macro_rules! validate {
+    ($errors: expr, $path: expr, $value: expr, $op: tt $bound: expr) => {
+        // Comparisons involving NaN are false, so NaN values never satisfy a constraint.
+        let satisfied = $value $op $bound;
+
+        if !satisfied {
+            $errors.push(ValidationError::InvalidValue {
+                path: $path,
+                constraint: concat!(stringify!($op), " ", stringify!($bound)).to_owned(),
+                value: format!("{:?}", $value),
+            });
+        }
+    };
+}
+
+macro_rules! validate_constant {
+    ($errors: expr, $path: expr, $parameter: expr, $op: tt $bound: expr) => {
+        if let Some(value) = $parameter.as_constant() {
+            validate!($errors, $path, value, $op $bound);
+        }
+    };
+}
+
+/// Maximum nesting depth of a geometry, to keep shader sizes reasonable.
+pub const MAX_GEOMETRY_DEPTH: usize = 32;
+
+/// # Dirty Flags
+///
+/// For pragmatic reasons, the scene structure maintains dirty flags relative to
+/// a particular device instance's internal state. As a consequence care must be
+/// taken when using the same scene instance on multiple devices simultaneously.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Scene {
+    pub metadata: Dirty<Metadata>,
+    pub camera: Dirty<Camera>,
+    pub raster: Dirty<Raster>,
+    pub instance_list: Dirty<BTreeMap<String, Instance>>,
+    pub geometry_list: Dirty<BTreeMap<String, Geometry>>,
+    pub material_list: Dirty<BTreeMap<String, Material>>,
+    pub environment_map: Dirty<Option<String>>,
+    pub environment: Dirty<Environment>,
+    #[serde(default)]
+    pub light_list: Dirty<BTreeMap<String, Light>>,
+    pub display: Dirty<Display>,
+    pub aperture: Dirty<Option<Aperture>>,
+    pub integrator: Dirty<Integrator>,
+}
+
+impl Scene {
+    /// Marks the entire contents of this scene as dirty.
+    ///
+    /// This method will force a complete device update the next time the
+    /// device is updated using this scene, and should be used sparingly.
+    pub fn dirty_all_fields(&mut self) {
+        Dirty::dirty(&mut self.metadata);
+        Dirty::dirty(&mut self.camera);
+        Dirty::dirty(&mut self.raster);
+        Dirty::dirty(&mut self.instance_list);
+        Dirty::dirty(&mut self.geometry_list);
+        Dirty::dirty(&mut self.material_list);
+        Dirty::dirty(&mut self.environment);
+        Dirty::dirty(&mut self.environment_map);
+        Dirty::dirty(&mut self.light_list);
+        Dirty::dirty(&mut self.display);
+        Dirty::dirty(&mut self.aperture);
+        Dirty::dirty(&mut self.integrator);
+    }
+
+    /// Returns all referenced assets.
+    pub fn assets(&self) -> Vec<&str> {
+        let mut assets = vec![];
+
+        if let Some(asset) = self.environment_map.as_ref() {
+            assets.push(asset.as_str());
+        }
+
+        if let Some(aperture) = self.aperture.as_ref() {
+            assets.push(&aperture.filter);
+        }
+
+        for geometry in self.geometry_list.values() {
+            if let Some(mesh) = geometry.transformed_mesh() {
+                assets.push(mesh);
+            }
+        }
+
+        for material in self.material_list.values() {
+            for (_, parameter) in material.parameters() {
+                if let MaterialParameter::Textured(info) = parameter {
+                    assets.push(info.texture.horz_texture());
+                    assets.push(info.texture.vert_texture());
+                }
+            }
+        }
+
+        assets.sort_unstable();
+        assets.dedup();
+        assets
+    }
+
+    /// Patches this scene to be equal to another scene.
+    ///
+    /// Scene contents which are identical between the two scenes will not be
+    /// modified, so the method will avoid dirtying as many fields as it can.
+    pub fn patch_from_other(&mut self, other: Self) {
+        if self.metadata != other.metadata {
+            self.metadata = other.metadata;
+        }
+
+        if self.camera != other.camera {
+            self.camera = other.camera;
+        }
+
+        if self.display != other.display {
+            self.display = other.display;
+        }
+
+        if self.environment_map != other.environment_map {
+            self.environment_map = other.environment_map;
+        }
+
+        if self.environment != other.environment {
+            self.environment = other.environment;
+        }
+
+        if self.light_list != other.light_list {
+            self.light_list = other.light_list;
+        }
+
+        if self.geometry_list != other.geometry_list {
+            self.geometry_list = other.geometry_list;
+        }
+
+        if self.material_list != other.material_list {
+            self.material_list = other.material_list;
+        }
+
+        if self.raster != other.raster {
+            self.raster = other.raster;
+        }
+
+        if self.instance_list != other.instance_list {
+            self.instance_list = other.instance_list;
+        }
+
+        if self.aperture != other.aperture {
+            self.aperture = other.aperture;
+        }
+
+        if self.integrator != other.integrator {
+            self.integrator = other.integrator;
+        }
+    }
+
+    /// Validates all dirty contents of this scene.
+    ///
+    /// If this method succeeds, then the scene should always be renderable
+    /// without errors, excluding device limitations and/or missing assets.
+    /// Otherwise, every validation error found in the scene is returned.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+
+        if let Some(metadata) = Dirty::as_dirty(&self.metadata) {
+            self.validate_metadata(metadata, &mut errors);
+        }
+
+        if let Some(camera) = Dirty::as_dirty(&self.camera) {
+            self.validate_camera(camera, &mut errors);
+        }
+
+        if let Some(raster) = Dirty::as_dirty(&self.raster) {
+            self.validate_raster(raster, &mut errors);
+        }
+
+        if let Some(environment) = Dirty::as_dirty(&self.environment) {
+            self.validate_environment(environment, &mut errors);
+        }
+
+        if let Some(light_list) = Dirty::as_dirty(&self.light_list) {
+            self.validate_light_list(light_list, &mut errors);
+        }
+
+        if let Some(display) = Dirty::as_dirty(&self.display) {
+            self.validate_display(display, &mut errors);
+        }
+
+        if let Some(integrator) = Dirty::as_dirty(&self.integrator) {
+            self.validate_integrator(integrator, &mut errors);
+        }
+
+        if let Some(instance_list) = Dirty::as_dirty(&self.instance_list) {
+            self.validate_instance_list(instance_list, &mut errors);
+        }
+
+        if let Some(geometry_list) = Dirty::as_dirty(&self.geometry_list) {
+            self.validate_geometry_list(geometry_list, &mut errors);
+        }
+
+        if let Some(material_list) = Dirty::as_dirty(&self.material_list) {
+            self.validate_material_list(material_list, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns whether photons are deposited anywhere in the scene, either on the
+    /// surface of an instance or inside the medium it contains.
+    pub fn has_photon_receivers(&self) -> bool {
+        self.instance_list
+            .values()
+            .filter(|instance| instance.visible)
+            .any(|instance| {
+                if instance.medium.is_scattering() {
+                    return true;
+                }
+
+                if let Some(material) = self.material_list.get(&instance.material) {
+                    material.is_photon_receiver()
+                } else {
+                    false
+                }
+            })
+    }
+
+    fn validate_metadata(&self, metadata: &Metadata, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("metadata");
+
+        validate!(errors, path.join("name"), metadata.name, != "");
+    }
+
+    fn validate_camera(&self, camera: &Camera, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("camera");
+
+        validate!(errors, path.join("focal_distance"), camera.focal_distance, > 0.0);
+        validate!(errors, path.join("field_of_view"), camera.field_of_view, > 0.0);
+        validate!(errors, path.join("field_of_view"), camera.field_of_view, <= 1.0);
+        validate!(errors, path.join("focal_curvature"), camera.focal_curvature, >= 0.0);
+        validate!(errors, path.join("direction"), camera.direction, != [0.0, 0.0, 0.0]);
+        validate!(errors, path.join("up_vector"), camera.up_vector, != [0.0, 0.0, 0.0]);
+
+        let path = path.join("aperture");
+
+        match camera.aperture {
+            ApertureShape::Point => {}
+            ApertureShape::Circle { radius } => {
+                validate!(errors, path.join("radius"), radius, >= 0.0);
+                validate!(errors, path.join("radius"), radius, <= 100.0);
+            }
+            ApertureShape::Ngon { radius, sides, .. } => {
+                validate!(errors, path.join("radius"), radius, >= 0.0);
+                validate!(errors, path.join("radius"), radius, <= 100.0);
+                validate!(errors, path.join("sides"), sides, >= 3);
+            }
+        }
+    }
+
+    fn validate_raster(&self, raster: &Raster, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("raster");
+
+        validate!(errors, path.join("width"), raster.width, >= 1);
+        validate!(errors, path.join("height"), raster.height, >= 1);
+        validate!(errors, path.join("width"), raster.width, <= 8192);
+        validate!(errors, path.join("height"), raster.height, <= 8192);
+    }
+
+    fn validate_environment(&self, environment: &Environment, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("environment");
+
+        match environment {
+            Environment::Solid { tint }
+            | Environment::Map { tint, .. }
+            | Environment::Sky { tint, .. } => {
+                for (i, value) in tint.iter().enumerate() {
+                    validate!(errors, path.join("tint").join(i), *value, >= 0.0);
+                }
+            }
+        }
+
+        if let Environment::Sky {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            ..
+        } = environment
+        {
+            validate!(errors, path.join("sun_direction"), *sun_direction, != [0.0, 0.0, 0.0]);
+            validate!(errors, path.join("turbidity"), *turbidity, >= 2.0);
+            validate!(errors, path.join("turbidity"), *turbidity, <= 10.0);
+
+            for (i, value) in ground_albedo.iter().enumerate() {
+                validate!(errors, path.join("ground_albedo").join(i), *value, >= 0.0);
+                validate!(errors, path.join("ground_albedo").join(i), *value, <= 1.0);
+            }
+        }
+
+        if let Environment::Map { .. } = environment {
+            if self.environment_map.is_none() {
+                errors.push(ValidationError::InvalidValue {
+                    path: JsonPointer::root().join("environment_map"),
+                    constraint: "!= null".to_owned(),
+                    value: "null".to_owned(),
+                });
+            }
+        }
+    }
+
+    fn validate_light_list(
+        &self,
+        light_list: &BTreeMap<String, Light>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let light_list_path = JsonPointer::root().join("light_list");
+
+        for (name, light) in light_list {
+            let path = light_list_path.join(name);
+
+            let emission_path = path.join(match light {
+                Light::Point { .. } | Light::Spot { .. } => "intensity",
+                Light::Sphere { .. } | Light::Rectangle { .. } => "radiance",
+                Light::Directional { .. } => "irradiance",
+            });
+
+            for (i, value) in light.emission().iter().enumerate() {
+                validate!(errors, emission_path.join(i), *value, >= 0.0);
+            }
+
+            match light {
+                Light::Point { .. } => {}
+                Light::Spot {
+                    direction,
+                    angle,
+                    blend,
+                    ..
+                } => {
+                    validate!(errors, path.join("direction"), *direction, != [0.0, 0.0, 0.0]);
+                    validate!(errors, path.join("angle"), *angle, > 0.0);
+                    validate!(errors, path.join("angle"), *angle, <= std::f32::consts::PI);
+                    validate!(errors, path.join("blend"), *blend, >= 0.0);
+                    validate!(errors, path.join("blend"), *blend, <= 1.0);
+                }
+                Light::Sphere { radius, .. } => {
+                    validate!(errors, path.join("radius"), *radius, > 0.0);
+                }
+                Light::Rectangle { edge_u, edge_v, .. } => {
+                    let normal = [
+                        edge_u[1] * edge_v[2] - edge_u[2] * edge_v[1],
+                        edge_u[2] * edge_v[0] - edge_u[0] * edge_v[2],
+                        edge_u[0] * edge_v[1] - edge_u[1] * edge_v[0],
+                    ];
+
+                    if normal == [0.0, 0.0, 0.0] {
+                        errors.push(ValidationError::InvalidValue {
+                            path: path.join("edge_v"),
+                            constraint: "not parallel to edge_u".to_owned(),
+                            value: format!("{:?}", edge_v),
+                        });
+                    }
+                }
+                Light::Directional { direction, .. } => {
+                    validate!(errors, path.join("direction"), *direction, != [0.0, 0.0, 0.0]);
+                }
+            }
+        }
+    }
+
+    fn validate_display(&self, display: &Display, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("display");
+
+        validate!(errors, path.join("exposure"), display.exposure, >= -10.0);
+        validate!(errors, path.join("exposure"), display.exposure, <= 10.0);
+        validate!(errors, path.join("saturation"), display.saturation, >= 0.0);
+        validate!(errors, path.join("saturation"), display.saturation, <= 1.0);
+
+        let tiles_per_pass = display.lens_flare_tiles_per_pass;
+
+        validate!(errors, path.join("lens_flare_tiles_per_pass"), tiles_per_pass, > 0);
+
+        if display.lens_flare_enabled && self.aperture.is_none() {
+            errors.push(ValidationError::InvalidValue {
+                path: path.join("lens_flare_enabled"),
+                constraint: "aperture != null".to_owned(),
+                value: format!("{:?}", display.lens_flare_enabled),
+            });
+        }
+    }
+
+    fn validate_integrator(&self, integrator: &Integrator, errors: &mut Vec<ValidationError>) {
+        let path = JsonPointer::root().join("integrator");
+
+        validate!(errors, path.join("hash_table_bits"), integrator.hash_table_bits, >= 18);
+        validate!(errors, path.join("hash_table_bits"), integrator.hash_table_bits, <= 24);
+        validate!(errors, path.join("photons_per_pass"), integrator.photons_per_pass, > 0);
+        validate!(errors, path.join("max_search_radius"), integrator.max_search_radius, > 0.0);
+        validate!(errors, path.join("min_search_radius"), integrator.min_search_radius, > 0.0);
+        validate!(errors, path.join("alpha"), integrator.alpha, >= 0.0);
+        validate!(errors, path.join("alpha"), integrator.alpha, <= 1.0);
+        validate!(errors, path.join("max_scatter_bounces"), integrator.max_scatter_bounces, > 0);
+        validate!(errors, path.join("max_gather_bounces"), integrator.max_gather_bounces, > 0);
+        validate!(errors, path.join("geometry_precision"), integrator.geometry_precision, >= 1e-5);
+        validate!(errors, path.join("geometry_precision"), integrator.geometry_precision, <= 1e-2);
+        validate!(errors, path.join("geometry_pushback"), integrator.geometry_pushback, >= 2.0);
+    }
+
+    fn validate_instance_list(
+        &self,
+        instance_list: &BTreeMap<String, Instance>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let geometry_list = &self.geometry_list;
+        let material_list = &self.material_list;
+
+        let instance_list_path = JsonPointer::root().join("instance_list");
+        let geometry_list_path = JsonPointer::root().join("geometry_list");
+        let material_list_path = JsonPointer::root().join("material_list");
+
+        for (
+            name,
+            Instance {
+                geometry,
+                material,
+                parameters,
+                parent,
+                medium,
+                ..
+            },
+        ) in instance_list.iter()
+        {
+            let path = instance_list_path.join(name);
+
+            validate_contains(
+                errors,
+                path.join("geometry"),
+                geometry,
+                geometry_list,
+                &geometry_list_path,
+            );
+            validate_contains(
+                errors,
+                path.join("material"),
+                material,
+                material_list,
+                &material_list_path,
+            );
+
+            if let Some(parent) = parent {
+                validate_contains(
+                    errors,
+                    path.join("parent"),
+                    parent,
+                    instance_list,
+                    &instance_list_path,
+                );
+            }
+
+            let medium_path = path.join("medium");
+
+            for (i, value) in medium.extinction.iter().enumerate() {
+                validate!(errors, medium_path.join("extinction").join(i), *value, >= 0.0);
+            }
+
+            let refractive_index = medium.refractive_index;
+
+            validate!(errors, medium_path.join("refractive_index"), refractive_index, >= 1.0);
+
+            for (i, value) in medium.scattering_albedo.iter().enumerate() {
+                let albedo_path = medium_path.join("scattering_albedo").join(i);
+
+                validate!(errors, albedo_path.clone(), *value, >= 0.0);
+                validate!(errors, albedo_path, *value, <= 1.0);
+            }
+
+            let phase_asymmetry = medium.phase_asymmetry;
+
+            validate!(errors, medium_path.join("phase_asymmetry"), phase_asymmetry, > -1.0);
+            validate!(errors, medium_path.join("phase_asymmetry"), phase_asymmetry, < 1.0);
+
+            let dispersion_path = medium_path.join("dispersion");
+
+            match medium.dispersion {
+                Dispersion::None => {}
+                Dispersion::Cauchy { coefficient } => {
+                    validate!(errors, dispersion_path.join("coefficient"), coefficient, >= 0.0);
+                }
+                Dispersion::Sellmeier { b, c } => {
+                    for i in 0..3 {
+                        validate!(errors, dispersion_path.join("b").join(i), b[i], >= 0.0);
+                        validate!(errors, dispersion_path.join("c").join(i), c[i], >= 0.0);
+                    }
+                }
+            }
+
+            // The refractive index must remain physically meaningful over the visible range,
+            // which may not be the case for Sellmeier coefficients near visible wavelengths.
+
+            let physical = (380..=780)
+                .step_by(10)
+                .all(|wavelength| medium.refractive_index_at(wavelength as f32) >= 1.0);
+
+            if !physical {
+                errors.push(ValidationError::InvalidValue {
+                    path: dispersion_path,
+                    constraint: "refractive index >= 1.0 over the visible range".to_owned(),
+                    value: format!("{:?}", medium.dispersion),
+                });
+            }
+
+            if let Some(geometry_data) = geometry_list.get(geometry) {
+                let mut missing_parameters = false;
+
+                for parameter in geometry_data.symbolic_parameters() {
+                    if !parameters.contains_key(parameter) {
+                        missing_parameters = true;
+
+                        errors.push(ValidationError::MissingParameter {
+                            path: path.join("parameters"),
+                            parameter: parameter.to_owned(),
+                            geometry: geometry_list_path.join(geometry),
+                        });
+                    }
+                }
+
+                // Smooth operators divide by their blend radius, so blend radii which are
+                // not constant are checked against the parameter values of each instance.
+
+                if !missing_parameters {
+                    for blend_radius in geometry_data.blend_radii() {
+                        if blend_radius.as_constant().is_some() {
+                            continue; // already validated with the geometry
+                        }
+
+                        let parameter_path = match blend_radius {
+                            GeometryParameter::Symbolic(symbol) => {
+                                path.join("parameters").join(symbol)
+                            }
+                            _ => path.join("parameters"),
+                        };
+
+                        let value = blend_radius.value(parameters);
+                        let positive = value > 0.0;
+
+                        if !positive {
+                            errors.push(ValidationError::InvalidValue {
+                                path: parameter_path,
+                                constraint: "blend_radius > 0.0".to_owned(),
+                                value: format!("{:?}", value),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_geometry_list(
+        &self,
+        geometry_list: &BTreeMap<String, Geometry>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let path = JsonPointer::root().join("geometry_list");
+
+        for (name, geometry) in geometry_list.iter() {
+            Self::validate_geometry(geometry, path.join(name), 0, true, errors);
+        }
+    }
+
+    /// Validates a geometry, where meshes are only allowed if `mesh_allowed` is set,
+    /// which is the case for root geometries and the children of their transforms.
+    fn validate_geometry(
+        geometry: &Geometry,
+        path: JsonPointer,
+        depth: usize,
+        mesh_allowed: bool,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        if depth > MAX_GEOMETRY_DEPTH {
+            errors.push(ValidationError::TooDeep {
+                path,
+                max_depth: MAX_GEOMETRY_DEPTH,
+            });
+
+            return;
+        }
+
+        match geometry {
+            Geometry::Sphere { radius } => {
+                validate_constant!(errors, path.join("radius"), radius, > 0.0);
+            }
+            Geometry::Ellipsoid { radius } => {
+                for (i, radius) in radius.iter().enumerate() {
+                    validate_constant!(errors, path.join("radius").join(i), radius, > 0.0);
+                }
+            }
+            Geometry::Cuboid { dimensions } => {
+                for (i, dimension) in dimensions.iter().enumerate() {
+                    validate_constant!(errors, path.join("dimensions").join(i), dimension, > 0.0);
+                }
+            }
+            Geometry::Cylinder { height, radius }
+            | Geometry::HexagonalPrism { height, radius }
+            | Geometry::TriangularPrism { height, radius } => {
+                validate_constant!(errors, path.join("height"), height, > 0.0);
+                validate_constant!(errors, path.join("radius"), radius, > 0.0);
+            }
+            Geometry::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                validate_constant!(errors, path.join("major_radius"), major_radius, > 0.0);
+                validate_constant!(errors, path.join("minor_radius"), minor_radius, > 0.0);
+            }
+            Geometry::Capsule { height, radius } => {
+                validate_constant!(errors, path.join("height"), height, >= 0.0);
+                validate_constant!(errors, path.join("radius"), radius, > 0.0);
+            }
+            Geometry::Cone {
+                height,
+                bottom_radius,
+                top_radius,
+            } => {
+                validate_constant!(errors, path.join("height"), height, > 0.0);
+                validate_constant!(errors, path.join("bottom_radius"), bottom_radius, >= 0.0);
+                validate_constant!(errors, path.join("top_radius"), top_radius, >= 0.0);
+            }
+            Geometry::Plane { normal, extent, .. } => {
+                let normal: Option<Vec<f32>> = normal.iter().map(|x| x.as_constant()).collect();
+
+                if let Some(normal) = normal {
+                    validate!(errors, path.join("normal"), normal, != [0.0, 0.0, 0.0]);
+                }
+
+                validate_constant!(errors, path.join("extent"), extent, > 0.0);
+            }
+            Geometry::Union { children } | Geometry::Intersection { children } => {
+                if children.is_empty() {
+                    errors.push(ValidationError::InvalidValue {
+                        path: path.join("children"),
+                        constraint: "non-empty".to_owned(),
+                        value: "[]".to_owned(),
+                    });
+                }
+
+                for (i, child) in children.iter().enumerate() {
+                    let child_path = path.join("children").join(i);
+
+                    Self::validate_geometry(child, child_path, depth + 1, false, errors);
+                }
+            }
+            Geometry::Subtraction { lhs, rhs } => {
+                Self::validate_geometry(lhs, path.join("lhs"), depth + 1, false, errors);
+                Self::validate_geometry(rhs, path.join("rhs"), depth + 1, false, errors);
+            }
+            Geometry::SmoothUnion {
+                children,
+                blend_radius,
+            }
+            | Geometry::SmoothIntersection {
+                children,
+                blend_radius,
+            } => {
+                validate_constant!(errors, path.join("blend_radius"), blend_radius, > 0.0);
+
+                if children.is_empty() {
+                    errors.push(ValidationError::InvalidValue {
+                        path: path.join("children"),
+                        constraint: "non-empty".to_owned(),
+                        value: "[]".to_owned(),
+                    });
+                }
+
+                for (i, child) in children.iter().enumerate() {
+                    let child_path = path.join("children").join(i);
+
+                    Self::validate_geometry(child, child_path, depth + 1, false, errors);
+                }
+            }
+            Geometry::SmoothSubtraction {
+                lhs,
+                rhs,
+                blend_radius,
+            } => {
+                validate_constant!(errors, path.join("blend_radius"), blend_radius, > 0.0);
+
+                Self::validate_geometry(lhs, path.join("lhs"), depth + 1, false, errors);
+                Self::validate_geometry(rhs, path.join("rhs"), depth + 1, false, errors);
+            }
+            Geometry::Onion { thickness, child } => {
+                validate_constant!(errors, path.join("thickness"), thickness, > 0.0);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::Scale { factor, child } => {
+                validate_constant!(errors, path.join("factor"), factor, > 0.0);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, mesh_allowed, errors);
+            }
+            Geometry::Rotate { axis, child, .. } => {
+                // A partially symbolic axis can only be checked once instantiated
+
+                let axis: Option<Vec<f32>> = axis.iter().map(|x| x.as_constant()).collect();
+
+                if let Some(axis) = axis {
+                    validate!(errors, path.join("axis"), axis, != [0.0, 0.0, 0.0]);
+                }
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, mesh_allowed, errors);
+            }
+            Geometry::Translate { child, .. } => {
+                Self::validate_geometry(child, path.join("child"), depth + 1, mesh_allowed, errors);
+            }
+            Geometry::Round { radius, child } => {
+                validate_constant!(errors, path.join("radius"), radius, >= 0.0);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::ForceNumericalNormals { child } => {
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::CustomModifier {
+                code,
+                expansion,
+                child,
+            } => {
+                if let Err(constraint) = check_custom_code(code) {
+                    errors.push(ValidationError::InvalidValue {
+                        path: path.join("code"),
+                        constraint: constraint.to_owned(),
+                        value: format!("{:?}", code),
+                    });
+                }
+
+                for (i, expansion) in expansion.iter().enumerate() {
+                    validate!(errors, path.join("expansion").join(i), *expansion, >= 0.0);
+                }
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::Twist { step, child, .. } => {
+                validate_constant!(errors, path.join("step"), step, > 0.0);
+                validate_constant!(errors, path.join("step"), step, <= 1.0);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::Repeat {
+                period,
+                count,
+                extent,
+                child,
+            } => {
+                for (i, period) in period.iter().enumerate() {
+                    validate_constant!(errors, path.join("period").join(i), period, >= 0.0);
+                }
+
+                if let Some(count) = count {
+                    for (i, count) in count.iter().enumerate() {
+                        validate!(errors, path.join("count").join(i), *count, >= 1);
+                    }
+                }
+
+                validate_constant!(errors, path.join("extent"), extent, > 0.0);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::Mirror { child, .. } => {
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::PolarRepeat { count, child, .. } => {
+                validate!(errors, path.join("count"), *count, >= 1);
+
+                Self::validate_geometry(child, path.join("child"), depth + 1, false, errors);
+            }
+            Geometry::Mesh { .. } => {
+                if !mesh_allowed {
+                    errors.push(ValidationError::NestedMesh { path });
+                }
+            }
+        }
+    }
+
+    fn validate_material_list(
+        &self,
+        material_list: &BTreeMap<String, Material>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let path = JsonPointer::root().join("material_list");
+
+        for (name, material) in material_list.iter() {
+            for (parameter_name, parameter) in material.parameters() {
+                if let MaterialParameter::Textured(info) = parameter {
+                    let path = path.join(name).join(parameter_name).join("contrast");
+
+                    validate!(errors, path.clone(), info.contrast, >= 0.0);
+                    validate!(errors, path, info.contrast, <= 1.0);
+                }
+            }
+        }
+    }
+}
+
+/// Checks that a key refers to an existing entry of a list.
+fn validate_contains<T>(
+    errors: &mut Vec<ValidationError>,
+    path: JsonPointer,
+    key: &str,
+    list: &BTreeMap<String, T>,
+    list_path: &JsonPointer,
+) {
+    if !list.contains_key(key) {
+        errors.push(ValidationError::MissingReference {
+            path,
+            value: format!("{:?}", key),
+            list: list_path.clone(),
+        });
+    }
+}
+
+/// Performs basic syntactic checks on the GLSL code of a custom modifier,
+/// returning the violated constraint if the code is obviously malformed.
+///
+/// This catches most typos before shader compilation, but does not guarantee
+/// the code will compile, as that would require a full GLSL parser.
+fn check_custom_code(code: &str) -> Result<(), &'static str> {
+    if !code.chars().all(|c| c.is_ascii() && c != '#' && c != '\\') {
+        return Err("no preprocessor directives or non-ASCII characters");
+    }
+
+    if !code.contains("return") {
+        return Err("contains a return statement");
+    }
+
+    let mut stack = vec![];
+
+    for c in code.chars() {
+        match c {
+            '(' | '[' | '{' => stack.push(c),
+            ')' if stack.pop() != Some('(') => return Err("balanced brackets"),
+            ']' if stack.pop() != Some('[') => return Err("balanced brackets"),
+            '}' if stack.pop() != Some('{') => return Err("balanced brackets"),
+            _ => {}
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err("balanced brackets");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn example_scene_json() -> Value {
+        let mut json = serde_json::to_value(Scene::default()).unwrap();
+
+        json["geometry_list"] = json!({
+            "ball": {"type": "sphere", "radius": "size"},
+            "floor": {"type": "cuboid", "dimensions": [10.0, 0.1, {"expr": "2 * size"}]},
+        });
+
+        json["material_list"] = json!({
+            "paint": {"type": "lambertian", "albedo": [0.8, 0.2, 0.1]},
+            "glass": {"type": "dielectric", "base_color": 1.0, "roughness": 0.0},
+        });
+
+        json["instance_list"] = json!({
+            "ball": {
+                "geometry": "ball",
+                "material": "glass",
+                "parameters": {"size": 0.5},
+                "medium": {"extinction": [0.1, 0.1, 0.1], "refractive_index": 1.5},
+                "parent": null,
+            },
+            "floor": {
+                "geometry": "floor",
+                "material": "paint",
+                "parameters": {"size": 3.0},
+                "medium": {"extinction": [0.0, 0.0, 0.0], "refractive_index": 1.0},
+                "parent": null,
+            },
+        });
+
+        json
+    }
+
+    fn example_scene() -> Scene {
+        serde_json::from_value(example_scene_json()).unwrap()
+    }
+
+    fn clean_all_fields(scene: &mut Scene) {
+        Dirty::clean::<()>(&mut scene.metadata, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.camera, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.raster, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.instance_list, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.geometry_list, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.material_list, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.environment_map, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.environment, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.light_list, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.display, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.aperture, |_| Ok(())).unwrap();
+        Dirty::clean::<()>(&mut scene.integrator, |_| Ok(())).unwrap();
+    }
+
+    fn error_paths(scene: &Scene) -> Vec<String> {
+        let errors = scene.validate().err().unwrap_or_default();
+
+        errors.iter().map(|e| e.path().to_string()).collect()
+    }
+
+    #[test]
+    fn default_scene_is_valid() {
+        assert_eq!(Scene::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn example_scene_is_valid() {
+        assert_eq!(example_scene().validate(), Ok(()));
+    }
+
+    #[test]
+    fn scene_round_trips_through_json() {
+        let scene = example_scene();
+
+        let json = serde_json::to_value(&scene).unwrap();
+        let reparsed: Scene = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+
+        assert_eq!(*reparsed.camera, *scene.camera);
+        assert_eq!(*reparsed.geometry_list, *scene.geometry_list);
+        assert_eq!(*reparsed.material_list, *scene.material_list);
+        assert_eq!(*reparsed.instance_list, *scene.instance_list);
+        assert_eq!(*reparsed.integrator, *scene.integrator);
+    }
+
+    #[test]
+    fn light_list_is_optional() {
+        let mut json = example_scene_json();
+        json.as_object_mut().unwrap().remove("light_list");
+
+        let scene: Scene = serde_json::from_value(json).unwrap();
+
+        assert!(scene.light_list.is_empty());
+    }
+
+    #[test]
+    fn patching_only_dirties_changed_fields() {
+        let mut scene = example_scene();
+        clean_all_fields(&mut scene);
+
+        let mut other = example_scene();
+        other.camera.field_of_view = 0.25;
+
+        scene.patch_from_other(other);
+
+        assert!(Dirty::is_dirty(&scene.camera));
+        assert!(!Dirty::is_dirty(&scene.geometry_list));
+        assert!(!Dirty::is_dirty(&scene.instance_list));
+    }
+
+    #[test]
+    fn validation_reports_every_error_with_its_path() {
+        let mut scene = example_scene();
+
+        scene.camera.field_of_view = 2.0;
+        scene.raster.width = 0;
+        scene.instance_list.get_mut("ball").unwrap().material = "chrome".to_owned();
+        scene
+            .instance_list
+            .get_mut("floor")
+            .unwrap()
+            .parameters
+            .clear();
+
+        let paths = error_paths(&scene);
+
+        assert!(paths.contains(&"/camera/field_of_view".to_owned()));
+        assert!(paths.contains(&"/raster/width".to_owned()));
+        assert!(paths.contains(&"/instance_list/ball/material".to_owned()));
+        assert!(paths.contains(&"/instance_list/floor/parameters".to_owned()));
+    }
+
+    #[test]
+    fn validation_rejects_nan_values() {
+        let mut scene = example_scene();
+        scene.camera.focal_distance = f32::NAN;
+
+        assert_eq!(error_paths(&scene), vec!["/camera/focal_distance"]);
+    }
+
+    #[test]
+    fn validation_escapes_json_pointer_keys() {
+        let mut scene = example_scene();
+        let mut instance = scene.instance_list["ball"].clone();
+        instance.geometry = "missing".to_owned();
+        scene.instance_list.insert("a/b~c".to_owned(), instance);
+
+        assert_eq!(error_paths(&scene), vec!["/instance_list/a~1b~0c/geometry"]);
+    }
+
+    #[test]
+    fn clean_fields_are_not_validated() {
+        let mut scene = example_scene();
+        scene.raster.width = 0;
+
+        Dirty::clean::<()>(&mut scene.raster, |_| Ok(())).unwrap();
+
+        assert_eq!(scene.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validation_rejects_zero_symbolic_blend_radius() {
+        let mut json = example_scene_json();
+
+        json["geometry_list"]["blob"] = json!({
+            "type": "smooth-union",
+            "blend_radius": "k",
+            "children": [
+                {"type": "sphere", "radius": 1.0},
+                {"type": "sphere", "radius": {"expr": "k + 1"}},
+            ],
+        });
+
+        json["instance_list"]["blob"] = json!({
+            "geometry": "blob",
+            "material": "paint",
+            "parameters": {"k": 0.0},
+            "medium": {"extinction": [0.0, 0.0, 0.0], "refractive_index": 1.0},
+            "parent": null,
+        });
+
+        let mut scene: Scene = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            error_paths(&scene),
+            vec!["/instance_list/blob/parameters/k"]
+        );
+
+        scene
+            .instance_list
+            .get_mut("blob")
+            .unwrap()
+            .parameters
+            .insert("k".to_owned(), 0.1);
+
+        assert_eq!(scene.validate(), Ok(()));
+    }
+
+    #[test]
+    fn meshes_may_only_be_placed_by_transforms() {
+        let mut json = example_scene_json();
+
+        json["geometry_list"]["bunny"] = json!({
+            "type": "rotate",
+            "axis": [0.0, 1.0, 0.0],
+            "angle": 1.0,
+            "child": {
+                "type": "translate",
+                "translation": [0.0, 1.0, 0.0],
+                "child": {"type": "mesh", "mesh": "bunny.obj"},
+            },
+        });
+
+        json["geometry_list"]["blob"] = json!({
+            "type": "union",
+            "children": [
+                {"type": "sphere", "radius": 1.0},
+                {"type": "scale", "factor": 2.0, "child": {"type": "mesh", "mesh": "bunny.obj"}},
+            ],
+        });
+
+        let scene: Scene = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            error_paths(&scene),
+            vec!["/geometry_list/blob/children/1/child"]
+        );
+
+        assert!(scene.assets().contains(&"bunny.obj"));
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+/// JSON pointer (RFC 6901) referencing a value inside a scene's JSON form.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct JsonPointer(String);
+
+impl JsonPointer {
+    /// Returns the pointer referencing the entire scene.
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// Returns a pointer referencing a member of the referenced value.
+    pub fn join(&self, key: impl Display) -> Self {
+        let key = key.to_string().replace('~', "~0").replace('/', "~1");
+
+        Self(format!("{}/{}", self.0, key))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for JsonPointer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Error describing one reason why a scene failed validation.
+///
+/// Offending values are stored in their debug representation, which matches
+/// their JSON representation for the numbers, strings and arrays of numbers
+/// found in scenes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ValidationError {
+    /// A value does not satisfy a constraint.
+    InvalidValue {
+        path: JsonPointer,
+        constraint: String,
+        value: String,
+    },
+    /// A value refers to a list entry which does not exist.
+    MissingReference {
+        path: JsonPointer,
+        value: String,
+        list: JsonPointer,
+    },
+    /// A symbolic geometry parameter is not provided by an instance.
+    MissingParameter {
+        path: JsonPointer,
+        parameter: String,
+        geometry: JsonPointer,
+    },
+    /// A mesh geometry is nested inside a geometry other than a transform.
+    NestedMesh { path: JsonPointer },
+    /// A geometry is nested more deeply than the maximum supported depth.
+    TooDeep { path: JsonPointer, max_depth: usize },
+}
+
+impl ValidationError {
+    /// Returns a pointer to the value which failed validation.
+    pub fn path(&self) -> &JsonPointer {
+        match self {
+            Self::InvalidValue { path, .. } => path,
+            Self::MissingReference { path, .. } => path,
+            Self::MissingParameter { path, .. } => path,
+            Self::NestedMesh { path } => path,
+            Self::TooDeep { path, .. } => path,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidValue {
+                path,
+                constraint,
+                value,
+            } => write!(
+                f,
+                "validation error: {} must satisfy `{}', got {}",
+                path, constraint, value
+            ),
+            Self::MissingReference { path, value, list } => write!(
+                f,
+                "validation error: {} refers to {} which is not in {}",
+                path, value, list
+            ),
+            Self::MissingParameter {
+                path,
+                parameter,
+                geometry,
+            } => write!(
+                f,
+                "validation error: {} is missing parameter `{}' used by {}",
+                path, parameter, geometry
+            ),
+            Self::NestedMesh { path } => write!(
+                f,
+                "validation error: {} is a mesh but meshes must be root geometries, \
+                 optionally under translations, rotations and scalings",
+                path
+            ),
+            Self::TooDeep { path, max_depth } => write!(
+                f,
+                "validation error: {} exceeds the maximum nesting depth of {}",
+                path, max_depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Formats a list of validation errors into a single message, one error per line.
+pub fn validation_report(errors: &[ValidationError]) -> String {
+    let lines: Vec<String> = errors.iter().map(ToString::to_string).collect();
+
+    lines.join("\n")
+}
@@ -1,5 +1,7 @@
 use itertools::Position;
 use js_sys::Error;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use web_sys::WebGl2RenderingContext as Context;
 
 use crate::*;
@@ -18,14 +20,23 @@ pub struct Device {
     pub(crate) geometry_buffer: UniformBuffer<[GeometryParamData]>,
     pub(crate) material_buffer: UniformBuffer<[MaterialParamData]>,
     pub(crate) instance_buffer: UniformBuffer<[SceneInstanceNode]>,
+    pub(crate) light_buffer: UniformBuffer<[LightData]>,
+    pub(crate) emitters: Vec<LightData>,
 
-    pub(crate) envmap_marg_cdf: Texture<R16F>,
-    pub(crate) envmap_cond_cdf: Texture<R16F>,
+    pub(crate) envmap_marg_cdf: Texture<R32F>,
+    pub(crate) envmap_cond_cdf: Texture<R32F>,
     pub(crate) envmap_color: Texture<RGBA16F>,
+    pub(crate) envmap_luminance: f32,
+    pub(crate) envmap_is_sky: bool,
 
     pub(crate) material_textures: Texture<SRGB_S3TC_DXT1>,
     pub(crate) loaded_textures: Vec<String>,
 
+    pub(crate) mesh_nodes: Texture<RGBA32F>,
+    pub(crate) mesh_triangles: Texture<RGBA32F>,
+    pub(crate) loaded_meshes: BTreeMap<String, MeshLocation>,
+    pub(crate) asset_hashes: BTreeMap<String, u64>,
+
     pub(crate) display_buffer: UniformBuffer<DisplayData>,
     pub(crate) camera_buffer: UniformBuffer<CameraData>,
     pub(crate) integrator_buffer: UniformBuffer<IntegratorData>,
@@ -76,7 +87,10 @@ pub struct Device {
     pub(crate) placeholder_texture: Texture<R8>,
     pub(crate) placeholder_texture_array: Texture<R8>,
 
-    device_lost: bool,
+    pub(crate) device_lost: bool,
+
+    pub(crate) recovery_snapshot: Option<RenderState>,
+    pub(crate) recovery_snapshot_time: f64,
 
     pub(crate) state: IntegratorState,
     pub(crate) postproc: PostProcState,
@@ -93,6 +107,11 @@ impl Device {
             material_textures: Texture::new(gl.clone()),
             loaded_textures: vec![],
 
+            mesh_nodes: Texture::new(gl.clone()),
+            mesh_triangles: Texture::new(gl.clone()),
+            loaded_meshes: BTreeMap::new(),
+            asset_hashes: BTreeMap::new(),
+
             placeholder_texture: Texture::new(gl.clone()),
             placeholder_texture_array: Texture::new(gl.clone()),
 
@@ -165,6 +184,8 @@ impl Device {
             geometry_buffer: UniformBuffer::new(gl.clone()),
             material_buffer: UniformBuffer::new(gl.clone()),
             instance_buffer: UniformBuffer::new(gl.clone()),
+            light_buffer: UniformBuffer::new(gl.clone()),
+            emitters: vec![],
             gather_quasi_buffer: UniformBuffer::new(gl.clone()),
             scatter_quasi_buffer: UniformBuffer::new(gl.clone()),
             raster_buffer: UniformBuffer::new(gl.clone()),
@@ -172,6 +193,8 @@ impl Device {
             integrator_buffer: UniformBuffer::new(gl.clone()),
             environment_buffer: UniformBuffer::new(gl.clone()),
             envmap_color: Texture::new(gl.clone()),
+            envmap_luminance: 0.0,
+            envmap_is_sky: false,
             envmap_marg_cdf: Texture::new(gl.clone()),
             envmap_cond_cdf: Texture::new(gl.clone()),
             convolution_output: Texture::new(gl.clone()),
@@ -180,6 +203,8 @@ impl Device {
             convolution_output_fbo: Framebuffer::new(gl.clone()),
             integrator_scatter_fbo: Framebuffer::new(gl.clone()),
             device_lost: true,
+            recovery_snapshot: None,
+            recovery_snapshot_time: 0.0,
             state: IntegratorState::default(),
             postproc: PostProcState::default(),
             render_region: None,
@@ -197,7 +222,9 @@ impl Device {
             return Ok(false);
         }
 
-        scene.validate()?;
+        scene
+            .validate()
+            .map_err(|e| Error::new(&validation_report(&e)))?;
 
         let mut expensive = false;
 
@@ -215,19 +242,56 @@ impl Device {
     }
 
     /// Updates this device to render a given scene or returns an error.
+    ///
+    /// After a context loss, the render resumes from the last recovery snapshot if
+    /// the scene did not change in the meantime, in which case it is not reset.
     pub fn update(
         &mut self,
         scene: &mut Scene,
         assets: impl Fn(&str) -> Result<Vec<u8>, Error>,
     ) -> Result<bool, Error> {
-        if self.device_lost && !self.try_restore(scene)? {
-            return Ok(false); // context currently lost
+        // Assets are hashed as they are loaded for the render fingerprint, and their
+        // hashes are kept even if the update fails as the assets may not be reloaded.
+
+        let asset_hashes = RefCell::new(vec![]);
+
+        let result = self.update_scene(scene, &|name: &str| {
+            let data = assets(name)?;
+            asset_hashes
+                .borrow_mut()
+                .push((name.to_owned(), asset_hash(&data)));
+
+            Ok(data)
+        });
+
+        self.asset_hashes.extend(asset_hashes.into_inner());
+
+        result
+    }
+
+    fn update_scene(
+        &mut self,
+        scene: &mut Scene,
+        assets: &dyn Fn(&str) -> Result<Vec<u8>, Error>,
+    ) -> Result<bool, Error> {
+        let restored = self.device_lost;
+
+        if self.device_lost {
+            if Self::scene_invalidates_render(scene) {
+                self.discard_recovery_snapshot();
+            }
+
+            if !self.try_restore(scene)? {
+                return Ok(false); // context currently lost
+            }
         }
 
-        scene.validate()?;
+        scene
+            .validate()
+            .map_err(|e| Error::new(&validation_report(&e)))?;
 
         // We do nothing with the scene metadata object
-        Dirty::clean(&mut scene.metadata, |_| Ok(()))?;
+        Dirty::clean::<Error>(&mut scene.metadata, |_| Ok(()))?;
 
         let mut invalidated = false;
         let mut reset_tiles = false;
@@ -236,7 +300,7 @@ impl Device {
         self.placeholder_texture_array.create_array(1, 1, 1);
         self.placeholder_texture_array.upload_layer(1, 1, 0, &[0]);
 
-        invalidated |= Dirty::clean(&mut scene.camera, |camera| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.camera, |camera| {
             self.update_camera(camera)?;
 
             Ok(())
@@ -244,16 +308,28 @@ impl Device {
 
         let instances = &mut scene.instance_list;
 
-        invalidated |= Dirty::clean(&mut scene.geometry_list, |geometries| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.geometry_list, |geometries| {
+            self.update_meshes(geometries, assets)?;
+
             let mut generator = GeometryGlslGenerator::new();
 
             let mut geometry_functions = vec![];
 
             for geometry in geometries.values() {
-                geometry_functions.push((
-                    generator.add_distance_function(geometry),
-                    generator.add_normal_function(geometry),
-                ));
+                if let Some(mesh) = geometry.transformed_mesh() {
+                    let location = &self.loaded_meshes[mesh];
+
+                    geometry_functions.push(GeometryFn::Mesh(generator.add_mesh_function(
+                        geometry,
+                        location.root,
+                        location.end,
+                    )));
+                } else {
+                    geometry_functions.push(GeometryFn::DistanceField(
+                        generator.add_distance_function(geometry),
+                        generator.add_normal_function(geometry),
+                    ));
+                }
             }
 
             let code = generator.generate(&geometry_functions);
@@ -268,8 +344,8 @@ impl Device {
             Ok(())
         })?;
 
-        invalidated |= Dirty::clean(&mut scene.material_list, |materials| {
-            self.update_materials(materials, &assets)?;
+        invalidated |= Dirty::clean::<Error>(&mut scene.material_list, |materials| {
+            self.update_materials(materials, assets)?;
 
             Dirty::dirty(instances);
 
@@ -278,30 +354,48 @@ impl Device {
 
         let geometry_list = &scene.geometry_list;
         let material_list = &scene.material_list;
+        let light_list = &mut scene.light_list;
 
-        invalidated |= Dirty::clean(&mut scene.instance_list, |instances| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.instance_list, |instances| {
             self.update_instances(geometry_list, material_list, instances)?;
 
+            Dirty::dirty(light_list);
+
             Ok(())
         })?;
 
+        // Sky environments are rendered into the environment map textures, so the map
+        // needs to be reloaded when switching from a sky back to a map environment.
+
+        if let Some(Environment::Map { .. }) = Dirty::as_dirty(&scene.environment) {
+            if self.envmap_is_sky {
+                Dirty::dirty(&mut scene.environment_map);
+            }
+        }
+
         let environment = &mut scene.environment;
 
-        invalidated |= Dirty::clean(&mut scene.environment_map, |environment_map| {
-            self.update_environment_map(&assets, environment_map.as_ref().map(String::as_str))?;
+        invalidated |= Dirty::clean::<Error>(&mut scene.environment_map, |environment_map| {
+            self.update_environment_map(assets, environment_map.as_ref().map(String::as_str))?;
 
             Dirty::dirty(environment);
 
             Ok(())
         })?;
 
-        invalidated |= Dirty::clean(&mut scene.environment, |environment| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.environment, |environment| {
             self.update_environment(environment)?;
 
             Ok(())
         })?;
 
-        invalidated |= Dirty::clean(&mut scene.raster, |raster| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.light_list, |lights| {
+            self.update_lights(lights)?;
+
+            Ok(())
+        })?;
+
+        invalidated |= Dirty::clean::<Error>(&mut scene.raster, |raster| {
             self.update_raster(raster)?;
 
             let render_cols = raster.width as usize;
@@ -333,14 +427,14 @@ impl Device {
         self.execute_fft_pass_shader.rebuild()?;
         self.load_filter_tile_shader.rebuild()?;
 
-        reset_tiles |= Dirty::clean(&mut scene.aperture, |aperture| {
+        reset_tiles |= Dirty::clean::<Error>(&mut scene.aperture, |aperture| {
             self.fft_filter_fbo.clear();
             self.fft_filter_tile_r.clear();
             self.fft_filter_tile_g.clear();
             self.fft_filter_tile_b.clear();
 
             if let Some(aperture) = aperture {
-                self.update_aperture_filter(aperture, &assets)?;
+                self.update_aperture_filter(aperture, assets)?;
             } else {
                 self.fft_signal_fbo.invalidate();
                 self.fft_buffer_fbo.invalidate();
@@ -355,7 +449,7 @@ impl Device {
             Ok(())
         })?;
 
-        invalidated |= Dirty::clean(&mut scene.integrator, |integrator| {
+        invalidated |= Dirty::clean::<Error>(&mut scene.integrator, |integrator| {
             self.update_integrator(integrator)?;
 
             let col_bits = integrator.hash_table_bits / 2;
@@ -381,7 +475,7 @@ impl Device {
         // These are post-processing settings that don't directly apply to the light
         // transport simulation; we don't need to invalidate any render buffer here.
 
-        reset_tiles |= Dirty::clean(&mut scene.display, |display| {
+        reset_tiles |= Dirty::clean::<Error>(&mut scene.display, |display| {
             self.update_display(display)?;
 
             Ok(())
@@ -404,9 +498,39 @@ impl Device {
             self.reset_convolution_state(scene);
         }
 
+        if restored {
+            if let Some(snapshot) = self.recovery_snapshot.take() {
+                self.restore_render_state(&snapshot)?;
+                self.recovery_snapshot = Some(snapshot);
+
+                return Ok(false); // render resumed
+            }
+        }
+
+        if invalidated {
+            self.discard_recovery_snapshot();
+        }
+
         Ok(invalidated)
     }
 
+    /// Returns whether the pending changes of a scene would reset the render.
+    pub(crate) fn scene_invalidates_render(scene: &Scene) -> bool {
+        let mut invalidated = false;
+
+        invalidated |= Dirty::is_dirty(&scene.camera);
+        invalidated |= Dirty::is_dirty(&scene.raster);
+        invalidated |= Dirty::is_dirty(&scene.instance_list);
+        invalidated |= Dirty::is_dirty(&scene.geometry_list);
+        invalidated |= Dirty::is_dirty(&scene.material_list);
+        invalidated |= Dirty::is_dirty(&scene.environment);
+        invalidated |= Dirty::is_dirty(&scene.environment_map);
+        invalidated |= Dirty::is_dirty(&scene.light_list);
+        invalidated |= Dirty::is_dirty(&scene.integrator);
+
+        invalidated
+    }
+
     /// Refines the current render state by performing an SPPM pass.
     pub fn refine(&mut self) -> Result<(), Error> {
         if self.device_lost {
@@ -458,6 +582,8 @@ impl Device {
             self.post_process(&self.integrator_radiance_estimate);
         }
 
+        self.take_recovery_snapshot();
+
         Ok(())
     }
 
@@ -544,6 +670,9 @@ impl Device {
 
         self.material_textures.invalidate();
 
+        self.mesh_nodes.invalidate();
+        self.mesh_triangles.invalidate();
+
         self.placeholder_texture.invalidate();
         self.placeholder_texture_array.invalidate();
 
@@ -558,6 +687,7 @@ impl Device {
         self.geometry_buffer.invalidate();
         self.material_buffer.invalidate();
         self.instance_buffer.invalidate();
+        self.light_buffer.invalidate();
         self.display_buffer.invalidate();
         self.envmap_marg_cdf.invalidate();
         self.envmap_cond_cdf.invalidate();
@@ -0,0 +1,133 @@
+use crate::{Device, Scene};
+use js_sys::Error;
+use std::collections::BTreeMap;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Computes the fingerprint of a render of a scene after some number of passes.
+///
+/// This is a 64-bit FNV-1a hash of the crate version, the scene in JSON form, the
+/// hashes of the scene assets, the integrator seed and the number of passes, which
+/// is formatted as 16 hexadecimal digits. As every pass of the integrator is
+/// reproducible, renders with the same fingerprint should be equal, up to
+/// floating-point differences between devices.
+pub fn render_fingerprint(
+    scene: &Scene,
+    asset_hashes: &BTreeMap<String, u64>,
+    passes: u32,
+) -> Result<String, Error> {
+    let json = serde_json::to_vec(scene).map_err(|e| Error::new(&e.to_string()))?;
+
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, env!("CARGO_PKG_VERSION").as_bytes());
+    hash = fnv1a(hash, &json);
+
+    // Assets that were never loaded are hashed by name only.
+
+    for asset in scene.assets() {
+        hash = fnv1a(hash, asset.as_bytes());
+
+        if let Some(asset_hash) = asset_hashes.get(asset) {
+            hash = fnv1a(hash, &asset_hash.to_le_bytes());
+        }
+    }
+
+    hash = fnv1a(hash, &scene.integrator.seed.to_le_bytes());
+    hash = fnv1a(hash, &passes.to_le_bytes());
+
+    Ok(format!("{:016x}", hash))
+}
+
+/// Returns the 64-bit FNV-1a hash of the contents of an asset.
+pub(crate) fn asset_hash(data: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, data)
+}
+
+fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &byte in data {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+impl Device {
+    /// Returns the fingerprint of the current render of a scene.
+    ///
+    /// The device must have been updated with the scene, so that the fingerprint
+    /// describes the render as it currently is on the device.
+    pub fn render_fingerprint(&self, scene: &Scene) -> Result<String, Error> {
+        self.render_fingerprint_at(scene, self.state.current_pass)
+    }
+
+    /// Returns the fingerprint of the render of a scene after some number of passes.
+    pub(crate) fn render_fingerprint_at(
+        &self,
+        scene: &Scene,
+        passes: u32,
+    ) -> Result<String, Error> {
+        if Self::scene_invalidates_render(scene) {
+            return Err(Error::new("scene has changes not yet applied to device"));
+        }
+
+        render_fingerprint(scene, &self.asset_hashes, passes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assets(texture: &[u8]) -> BTreeMap<String, u64> {
+        let mut assets = BTreeMap::new();
+        assets.insert("mesh.obj".to_owned(), asset_hash(b"v 0 0 0"));
+        assets.insert("texture.raw".to_owned(), asset_hash(texture));
+        assets
+    }
+
+    fn scene() -> Scene {
+        let mut json = serde_json::to_value(Scene::default()).unwrap();
+
+        json["geometry_list"] = serde_json::json!({
+            "mesh": {"type": "mesh", "mesh": "mesh.obj"},
+        });
+
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn identical_inputs_give_identical_fingerprints() {
+        let fingerprint = render_fingerprint(&scene(), &assets(b"abc"), 10).unwrap();
+
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(
+            fingerprint,
+            render_fingerprint(&scene(), &assets(b"abc"), 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn fingerprints_depend_on_assets_and_passes() {
+        let fingerprint = render_fingerprint(&scene(), &assets(b"abc"), 10).unwrap();
+
+        let mut changed_mesh = assets(b"abc");
+        changed_mesh.insert("mesh.obj".to_owned(), asset_hash(b"v 1 0 0"));
+
+        assert_ne!(
+            fingerprint,
+            render_fingerprint(&scene(), &changed_mesh, 10).unwrap()
+        );
+        assert_ne!(
+            fingerprint,
+            render_fingerprint(&scene(), &assets(b"abc"), 11).unwrap()
+        );
+
+        // Assets which the scene does not use do not affect its fingerprint.
+
+        assert_eq!(
+            fingerprint,
+            render_fingerprint(&scene(), &assets(b"xyz"), 10).unwrap()
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use crate::{Geometry, GeometryParameter};
+use crate::{Axis, Geometry, GeometryParameter, MIN_REPEAT_PERIOD};
 use std::collections::HashMap;
 use std::fmt::Display;
 
+const COMPONENTS: [&str; 3] = ["x", "y", "z"];
+
 #[derive(Debug, Default)]
 pub struct GeometryGlslGenerator {
     functions: Vec<String>,
@@ -21,11 +23,92 @@ impl GeometryGlslGenerator {
         self.normal_recursive(geometry, &Self::build_parameter_map(geometry))
     }
 
+    /// Adds the functions needed to intersect a mesh geometry whose BVH nodes are
+    /// [root, end) in the device mesh textures. Rays are transformed into the local
+    /// space of the mesh, and normals back, by the transforms above the mesh.
+    pub fn add_mesh_function(&mut self, geometry: &Geometry, root: u32, end: u32) -> MeshFn {
+        let parameters = Self::build_parameter_map(geometry);
+
+        let mut ray_code = vec![];
+        let mut normal_code = vec![];
+        let mut node = geometry;
+
+        loop {
+            match node {
+                Geometry::Translate { translation, child } => {
+                    let tx = self.lookup_parameter(&translation[0], &parameters);
+                    let ty = self.lookup_parameter(&translation[1], &parameters);
+                    let tz = self.lookup_parameter(&translation[2], &parameters);
+
+                    ray_code.push(format!("ray.org -= vec3({}, {}, {});", tx, ty, tz));
+
+                    node = child;
+                }
+                Geometry::Scale { factor, child } => {
+                    let factor = self.lookup_parameter(factor, &parameters);
+
+                    ray_code.push(format!("ray.org /= {}; ray.dir /= {};", factor, factor));
+
+                    node = child;
+                }
+                Geometry::Rotate { axis, angle, child } => {
+                    let kx = self.lookup_parameter(&axis[0], &parameters);
+                    let ky = self.lookup_parameter(&axis[1], &parameters);
+                    let kz = self.lookup_parameter(&axis[2], &parameters);
+                    let theta = self.lookup_parameter(angle, &parameters);
+
+                    let k = format!("normalize(vec3({}, {}, {}))", kx, ky, kz);
+
+                    ray_code.push(format!(
+                        "ray.org = mesh_rotate(ray.org, {k}, -{t}); \
+                         ray.dir = mesh_rotate(ray.dir, {k}, -{t});",
+                        k = k,
+                        t = theta
+                    ));
+
+                    // Normals are transformed back starting from the innermost transform.
+                    normal_code.insert(0, format!("n = mesh_rotate(n, {}, {});", k, theta));
+
+                    node = child;
+                }
+                _ => break,
+            }
+        }
+
+        if ray_code.is_empty() {
+            return MeshFn {
+                root,
+                end,
+                transform: None,
+            };
+        }
+
+        let id = self.generate_id();
+
+        self.functions.push(format!(
+            "ray_t geo_mesh_ray_{}(uint inst, ray_t ray) {{ {} return ray; }}",
+            id,
+            ray_code.join(" ")
+        ));
+
+        self.functions.push(format!(
+            "vec3 geo_mesh_normal_{}(uint inst, vec3 n) {{ {} return n; }}",
+            id,
+            normal_code.join(" ")
+        ));
+
+        MeshFn {
+            root,
+            end,
+            transform: Some(id),
+        }
+    }
+
     /// Generates GLSL code for a list of geometries.
     ///
     /// When a normal function is not given, a gradient estimate implementation
     /// will be inserted that should generate reasonable normals in most cases.
-    pub fn generate(self, geometries: &[(DistanceFn, Option<NormalFn>)]) -> String {
+    pub fn generate(self, geometries: &[GeometryFn]) -> String {
         let mut code = vec![];
 
         for function in self.functions {
@@ -38,17 +121,25 @@ impl GeometryGlslGenerator {
         );
         code.push("  switch (geometry) {".to_owned());
 
-        for (index, (distance, _)) in geometries.iter().enumerate() {
+        for (index, geometry) in geometries.iter().enumerate() {
             code.push(format!("    case {}U:", index));
-            code.push("      while (range.x <= range.y) {{".to_owned());
-            code.push(format!(
-                "        float dist = abs({});",
-                distance.call("ray.org + range.x * ray.dir")
-            ));
-            code.push("        if (dist < PREC) {{ return true; }}".to_owned());
-            code.push("        range.x += dist;".to_owned());
-            code.push("      }}".to_owned());
-            code.push("      break;".to_owned());
+
+            match geometry {
+                GeometryFn::DistanceField(distance, _) => {
+                    code.push("      while (range.x <= range.y) {{".to_owned());
+                    code.push(format!(
+                        "        float dist = abs({});",
+                        distance.call("ray.org + range.x * ray.dir")
+                    ));
+                    code.push("        if (dist < PREC) {{ return true; }}".to_owned());
+                    code.push("        range.x += dist;".to_owned());
+                    code.push("      }}".to_owned());
+                    code.push("      break;".to_owned());
+                }
+                GeometryFn::Mesh(mesh) => {
+                    code.push(format!("      return {};", mesh.intersect("ray", "range")));
+                }
+            }
         }
 
         code.push("  }".to_owned());
@@ -58,14 +149,20 @@ impl GeometryGlslGenerator {
         code.push("vec3 geo_normal(uint geometry, uint inst, vec3 p) {".to_owned());
         code.push("  switch (geometry) {".to_owned());
 
-        for (index, (distance, normal)) in geometries.iter().enumerate() {
+        for (index, geometry) in geometries.iter().enumerate() {
             code.push(format!("    case {}U:", index));
 
-            if let Some(normal) = normal {
-                code.push(format!("      return {};", normal.call("p")));
-            } else {
-                let estimate = Self::gradient_estimate(&distance);
-                code.push(format!("      return {};", estimate));
+            match geometry {
+                GeometryFn::DistanceField(_, Some(normal)) => {
+                    code.push(format!("      return {};", normal.call("p")));
+                }
+                GeometryFn::DistanceField(distance, None) => {
+                    let estimate = Self::gradient_estimate(&distance);
+                    code.push(format!("      return {};", estimate));
+                }
+                GeometryFn::Mesh(mesh) => {
+                    code.push(format!("      return {};", mesh.normal("p")));
+                }
             }
         }
 
@@ -78,14 +175,14 @@ impl GeometryGlslGenerator {
     }
 
     fn build_parameter_map(geometry: &Geometry) -> HashMap<&str, usize> {
-        // The +8 offset here is to accommodate the medium information which
-        // currently uses the first two parameter blocks for every instance.
+        // The +24 offset here is to accommodate the medium information which
+        // currently uses the first six parameter blocks for every instance.
 
         geometry
             .symbolic_parameters()
             .iter()
             .enumerate()
-            .map(|(index, &symbol)| (symbol, 8 + index))
+            .map(|(index, &symbol)| (symbol, 24 + index))
             .collect()
     }
 
@@ -139,6 +236,94 @@ impl GeometryGlslGenerator {
                     radius, height
                 )
             }
+            Geometry::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let major_radius = self.lookup_parameter(major_radius, parameters);
+                let minor_radius = self.lookup_parameter(minor_radius, parameters);
+
+                format!(
+                    "return length(vec2(length(p.xz) - {}, p.y)) - {};",
+                    major_radius, minor_radius
+                )
+            }
+            Geometry::Capsule { height, radius } => {
+                let height = self.lookup_parameter(height, parameters);
+                let radius = self.lookup_parameter(radius, parameters);
+
+                format!(
+                    r#"
+                    float h = {};
+                    return length(vec3(p.x, p.y - clamp(p.y, -h, h), p.z)) - {};
+                    "#,
+                    height, radius
+                )
+            }
+            Geometry::Cone {
+                height,
+                bottom_radius,
+                top_radius,
+            } => {
+                let height = self.lookup_parameter(height, parameters);
+                let bottom_radius = self.lookup_parameter(bottom_radius, parameters);
+                let top_radius = self.lookup_parameter(top_radius, parameters);
+
+                format!(
+                    r#"
+                    float h = {};
+                    float r1 = {};
+                    float r2 = {};
+                    vec2 q = vec2(length(p.xz), p.y);
+                    vec2 k1 = vec2(r2, h);
+                    vec2 k2 = vec2(r2 - r1, 2.0 * h);
+                    vec2 ca = vec2(q.x - min(q.x, (q.y < 0.0) ? r1 : r2), abs(q.y) - h);
+                    vec2 cb = q - k1 + k2 * clamp(dot(k1 - q, k2) / dot(k2, k2), 0.0, 1.0);
+                    float s = (cb.x < 0.0 && ca.y < 0.0) ? -1.0 : 1.0;
+                    return s * sqrt(min(dot(ca, ca), dot(cb, cb)));
+                    "#,
+                    height, bottom_radius, top_radius
+                )
+            }
+            Geometry::Plane { normal, offset, .. } => {
+                let nx = self.lookup_parameter(&normal[0], parameters);
+                let ny = self.lookup_parameter(&normal[1], parameters);
+                let nz = self.lookup_parameter(&normal[2], parameters);
+                let offset = self.lookup_parameter(offset, parameters);
+
+                format!(
+                    "return dot(p, normalize(vec3({}, {}, {}))) - {};",
+                    nx, ny, nz, offset
+                )
+            }
+            Geometry::HexagonalPrism { height, radius } => {
+                let height = self.lookup_parameter(height, parameters);
+                let radius = self.lookup_parameter(radius, parameters);
+
+                format!(
+                    r#"
+                    const vec3 k = vec3(-0.8660254, 0.5, 0.57735);
+                    float r = {};
+                    p = abs(p);
+                    p.xz -= 2.0 * min(dot(k.xy, p.xz), 0.0) * k.xy;
+                    vec2 d = vec2(length(p.xz - vec2(clamp(p.x, -k.z * r, k.z * r), r)) * sign(p.z - r), p.y - {});
+                    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+                    "#,
+                    radius, height
+                )
+            }
+            Geometry::TriangularPrism { height, radius } => {
+                let height = self.lookup_parameter(height, parameters);
+                let radius = self.lookup_parameter(radius, parameters);
+
+                format!(
+                    r#"
+                    vec3 q = abs(p);
+                    return max(q.y - {}, max(q.x * 0.8660254 + p.z * 0.5, -p.z) - {});
+                    "#,
+                    height, radius
+                )
+            }
             Geometry::Union { children } => self.nary_operator(children, parameters, "min"),
             Geometry::Intersection { children } => self.nary_operator(children, parameters, "max"),
             Geometry::Subtraction { lhs, rhs } => {
@@ -151,6 +336,39 @@ impl GeometryGlslGenerator {
                     rhs_function.call("p")
                 )
             }
+            Geometry::SmoothUnion {
+                children,
+                blend_radius,
+            } => {
+                let blend_radius = self.lookup_parameter(blend_radius, parameters);
+
+                self.smooth_nary_operator(children, parameters, "geo_smooth_min", &blend_radius)
+            }
+            Geometry::SmoothIntersection {
+                children,
+                blend_radius,
+            } => {
+                let blend_radius = self.lookup_parameter(blend_radius, parameters);
+
+                self.smooth_nary_operator(children, parameters, "geo_smooth_max", &blend_radius)
+            }
+            Geometry::SmoothSubtraction {
+                lhs,
+                rhs,
+                blend_radius,
+            } => {
+                let blend_radius = self.lookup_parameter(blend_radius, parameters);
+
+                let lhs_function = self.distance_recursive(lhs, parameters);
+                let rhs_function = self.distance_recursive(rhs, parameters);
+
+                format!(
+                    "return geo_smooth_max({}, -{}, {});",
+                    lhs_function.call("p"),
+                    rhs_function.call("p"),
+                    blend_radius
+                )
+            }
             Geometry::Onion { thickness, child } => {
                 let thickness = self.lookup_parameter(thickness, parameters);
 
@@ -222,6 +440,9 @@ impl GeometryGlslGenerator {
 
                 code.replace("f(", &format!("{}(", function.name()))
             }
+            Geometry::Mesh { .. } => {
+                panic!("meshes are not distance fields and must be root geometries")
+            }
             Geometry::Twist {
                 amount,
                 step,
@@ -245,6 +466,36 @@ impl GeometryGlslGenerator {
                     step,
                 )
             }
+            Geometry::Repeat {
+                period,
+                count,
+                child,
+                ..
+            } => {
+                let code = self.repeat_fold(period, *count, parameters);
+
+                let function = self.distance_recursive(child, parameters);
+
+                format!("{} return {};", code, function.call("q"))
+            }
+            Geometry::Mirror { axis, child } => {
+                let function = self.distance_recursive(child, parameters);
+
+                format!(
+                    "p.{c} = abs(p.{c}); return {};",
+                    function.call("p"),
+                    c = COMPONENTS[axis.index()]
+                )
+            }
+            Geometry::PolarRepeat { axis, count, child } => {
+                let function = self.distance_recursive(child, parameters);
+
+                format!(
+                    "{} return {};",
+                    Self::polar_fold(*axis, *count),
+                    function.call("p")
+                )
+            }
         };
 
         self.register_distance_function(code.trim())
@@ -257,6 +508,29 @@ impl GeometryGlslGenerator {
     ) -> Option<NormalFn> {
         let code = match geometry {
             Geometry::Sphere { .. } => Some("return normalize(p);".to_owned()),
+            Geometry::Torus { major_radius, .. } => {
+                let major_radius = self.lookup_parameter(major_radius, parameters);
+
+                Some(format!(
+                    "return normalize(p - {} * normalize(vec3(p.x, 0.0, p.z)));",
+                    major_radius
+                ))
+            }
+            Geometry::Capsule { height, .. } => {
+                let height = self.lookup_parameter(height, parameters);
+
+                Some(format!(
+                    "float h = {}; return normalize(vec3(p.x, p.y - clamp(p.y, -h, h), p.z));",
+                    height
+                ))
+            }
+            Geometry::Plane { normal, .. } => {
+                let nx = self.lookup_parameter(&normal[0], parameters);
+                let ny = self.lookup_parameter(&normal[1], parameters);
+                let nz = self.lookup_parameter(&normal[2], parameters);
+
+                Some(format!("return normalize(vec3({}, {}, {}));", nx, ny, nz))
+            }
             Geometry::Ellipsoid { radius } => {
                 let radius_x = self.lookup_parameter(&radius[0], parameters);
                 let radius_y = self.lookup_parameter(&radius[1], parameters);
@@ -321,6 +595,53 @@ impl GeometryGlslGenerator {
                     function.call(format!("p / {}", scale))
                 ))
             }
+            Geometry::Repeat {
+                period,
+                count,
+                child,
+                ..
+            } => {
+                let code = self.repeat_fold(period, *count, parameters);
+
+                let function = self.normal_recursive(child, parameters)?;
+
+                Some(format!("{} return {};", code, function.call("q")))
+            }
+            Geometry::Mirror { axis, child } => {
+                let function = self.normal_recursive(child, parameters)?;
+
+                Some(format!(
+                    r#"
+                    float s = p.{c} < 0.0 ? -1.0 : 1.0;
+                    p.{c} = abs(p.{c});
+                    vec3 n = {};
+                    n.{c} *= s;
+                    return n;
+                "#,
+                    function.call("p"),
+                    c = COMPONENTS[axis.index()]
+                ))
+            }
+            Geometry::PolarRepeat { axis, count, child } => {
+                let function = self.normal_recursive(child, parameters)?;
+
+                let (u, v) = axis.perpendicular();
+
+                Some(format!(
+                    r#"
+                    {}
+                    vec3 n = {};
+                    float cd = cos(a - b);
+                    float sd = sin(a - b);
+                    n.{u}{v} = vec2(n.{u} * cd - n.{v} * sd, n.{u} * sd + n.{v} * cd);
+                    return n;
+                "#,
+                    Self::polar_fold(*axis, *count),
+                    function.call("p"),
+                    u = COMPONENTS[u],
+                    v = COMPONENTS[v]
+                ))
+            }
             _ => None,
         };
 
@@ -360,6 +681,89 @@ impl GeometryGlslGenerator {
         format!("return {};", code)
     }
 
+    /// Folds `p` into the cell of a grid repetition containing the origin,
+    /// storing the folded point into a new variable `q`.
+    fn repeat_fold(
+        &self,
+        period: &[GeometryParameter; 3],
+        count: Option<[u32; 3]>,
+        parameters: &HashMap<&str, usize>,
+    ) -> String {
+        let mut code = "vec3 q = p;".to_owned();
+
+        for i in 0..3 {
+            if period[i].as_constant() == Some(0.0) {
+                continue;
+            }
+
+            let c = format!(
+                "max({}, {:e})",
+                self.lookup_parameter(&period[i], parameters),
+                MIN_REPEAT_PERIOD
+            );
+            let x = format!("q.{}", COMPONENTS[i]);
+
+            if let Some(count) = count {
+                let h = (count[i].max(1) - 1) as f32 * 0.5;
+
+                code += &format!(
+                    " {} -= {c} * (clamp(round({} / {c} + {h:+e}), 0.0, {:+e}) - {h:+e});",
+                    x,
+                    x,
+                    2.0 * h,
+                    c = c,
+                    h = h
+                );
+            } else {
+                code += &format!(" {} -= {c} * round({} / {c});", x, x, c = c);
+            }
+        }
+
+        code
+    }
+
+    /// Folds `p` into the first sector of a polar repetition about an axis,
+    /// leaving the original and folded angles in the variables `a` and `b`.
+    fn polar_fold(axis: Axis, count: u32) -> String {
+        let (u, v) = axis.perpendicular();
+
+        format!(
+            r#"
+            float sector = 6.28318531 / {:+e};
+            float a = atan(p.{v}, p.{u});
+            float b = mod(a + 0.5 * sector, sector) - 0.5 * sector;
+            p.{u}{v} = length(p.{u}{v}) * vec2(cos(b), sin(b));
+        "#,
+            count.max(1) as f32,
+            u = COMPONENTS[u],
+            v = COMPONENTS[v]
+        )
+    }
+
+    fn smooth_nary_operator(
+        &mut self,
+        children: &[Geometry],
+        parameters: &HashMap<&str, usize>,
+        op: &str,
+        blend_radius: &str,
+    ) -> String {
+        assert!(!children.is_empty());
+
+        let mut code = format!("float k = {};", blend_radius);
+
+        for (i, child) in children.iter().enumerate() {
+            let function = self.distance_recursive(child, parameters);
+
+            if i == 0 {
+                code += &format!(" float d = {};", function.call("p"));
+            } else {
+                code += &format!(" d = {}(d, {}, k);", op, function.call("p"));
+            }
+        }
+
+        code + " return d;"
+    }
+
     // TODO: could make the "geometry_buffer" string a parameter possibly
 
     fn lookup_parameter(
@@ -372,6 +776,9 @@ impl GeometryGlslGenerator {
             GeometryParameter::Symbolic(symbol) => {
                 self.lookup_symbolic_parameter(parameters[symbol.as_str()])
             }
+            GeometryParameter::Expression(expression) => {
+                expression.glsl(&|symbol| self.lookup_symbolic_parameter(parameters[symbol]))
+            }
         }
     }
 
@@ -427,6 +834,13 @@ impl GeometryGlslGenerator {
     }
 }
 
+/// Intersection and normal functions for a root geometry.
+#[derive(Clone, Debug)]
+pub enum GeometryFn {
+    DistanceField(DistanceFn, Option<NormalFn>),
+    Mesh(MeshFn),
+}
+
 #[derive(Clone, Debug)]
 pub struct DistanceFn {
     id: u32,
@@ -474,3 +888,33 @@ impl NormalFn {
         format!("geo_normal_{}", self.id)
     }
 }
+
+/// Reference to a mesh BVH stored in the device mesh textures, along with the
+/// transform functions of the mesh geometry if it has any.
+#[derive(Clone, Debug)]
+pub struct MeshFn {
+    root: u32,
+    end: u32,
+    transform: Option<u32>,
+}
+
+impl MeshFn {
+    pub fn intersect(&self, ray: impl Display, range: impl Display) -> String {
+        let ray = match self.transform {
+            Some(id) => format!("geo_mesh_ray_{}(inst, {})", id, ray),
+            None => ray.to_string(),
+        };
+
+        format!(
+            "mesh_intersect({}U, {}U, {}, {})",
+            self.root, self.end, ray, range
+        )
+    }
+
+    pub fn normal(&self, point: impl Display) -> String {
+        match self.transform {
+            Some(id) => format!("geo_mesh_normal_{}(inst, mesh_normal({}))", id, point),
+            None => format!("mesh_normal({})", point),
+        }
+    }
+}
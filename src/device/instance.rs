@@ -1,7 +1,7 @@
 #[allow(unused_imports)]
 use log::{debug, info, warn};
 
-use crate::{material_index, BoundingBox, Device, Geometry, Instance, Material};
+use crate::{material_index, BoundingBox, Device, Geometry, Instance, LightData, Material, Medium};
 use itertools::izip;
 use js_sys::Error;
 use std::cmp::Ordering;
@@ -33,6 +33,7 @@ impl Device {
         }
 
         let mut instance_info = Vec::with_capacity(instance_list.len());
+        let mut emitting_areas = BTreeMap::new();
         let mut geometry_start = 0;
 
         for instance in instance_list.values() {
@@ -43,7 +44,17 @@ impl Device {
             let geometry = &geometry_list[&instance.geometry];
             let material = &material_list[&instance.material];
 
-            let bbox = geometry.bounding_box(&instance.parameters);
+            let bbox = if let Some(mesh) = geometry.transformed_mesh() {
+                geometry.mesh_bounding_box(self.loaded_meshes[mesh].bbox, &instance.parameters)
+            } else {
+                geometry.bounding_box(&instance.parameters)
+            };
+
+            if material.is_emissive() {
+                let area = self.emitting_area(geometry, &instance.parameters, &bbox);
+
+                emitting_areas.insert(geometry_start, area);
+            }
 
             instance_info.push(InstanceInfo {
                 bbox,
@@ -60,7 +71,7 @@ impl Device {
 
             let parameters = geometry_list[&instance.geometry].symbolic_parameters();
 
-            geometry_start += 2 + (parameters.len() as u16 + 3) / 4;
+            geometry_start += 6 + (parameters.len() as u16 + 3) / 4;
         }
 
         let node_count = HierarchyBuilder::node_count_for_leaves(instance_info.len());
@@ -69,6 +80,29 @@ impl Device {
 
         HierarchyBuilder::new(&mut nodes).build(&mut instance_info);
 
+        // Instances with an emissive material also emit photons, so keep track of them
+        // here; they are uploaded to the shaders with the lights which are dirtied next.
+
+        let emissive_materials: BTreeMap<u16, &Material> = material_list
+            .iter()
+            .filter(|(_, material)| material.is_emissive())
+            .map(|(name, material)| (material_start[name], material))
+            .collect();
+
+        self.emitters.clear();
+
+        for (index, node) in nodes.iter().enumerate() {
+            if let Some(material) = node
+                .material_instance()
+                .and_then(|mat_inst| emissive_materials.get(&mat_inst))
+            {
+                let area = emitting_areas[&node.geometry_instance().unwrap()];
+
+                self.emitters
+                    .push(LightData::emitter(area, index, material));
+            }
+        }
+
         self.instance_buffer
             .write_array(self.instance_buffer.max_len(), &nodes)?;
         self.integrator_gather_photons_shader
@@ -76,6 +110,14 @@ impl Device {
         self.integrator_scatter_photons_shader
             .set_define("INSTANCE_DATA_LEN", self.instance_buffer.len());
 
+        if self.emitters.is_empty() {
+            self.integrator_gather_photons_shader
+                .set_define("EMITTERS_PRESENT", 0);
+        } else {
+            self.integrator_gather_photons_shader
+                .set_define("EMITTERS_PRESENT", 1);
+        }
+
         if instance_info.is_empty() {
             self.integrator_gather_photons_shader
                 .set_define("INSTANCE_DATA_PRESENT", 0);
@@ -100,27 +142,48 @@ impl Device {
                 continue;
             }
 
-            if let Some(parent) = &instance.parent {
-                let parent = &instance_list[parent];
+            // The first six parameter blocks of every instance describe the media outside
+            // and inside of it, see `medium_for_instance` in the shader for their layout.
 
-                params.push(GeometryParamData([
-                    parent.medium.extinction[0],
-                    parent.medium.extinction[1],
-                    parent.medium.extinction[2],
-                    parent.medium.refractive_index,
-                ]));
-            } else {
-                params.push(GeometryParamData([0.0, 0.0, 0.0, 1.0]));
+            let ext_medium = (instance.parent.as_ref()).map(|parent| &instance_list[parent].medium);
+
+            let int_medium = Some(&instance.medium);
+
+            for medium in &[ext_medium, int_medium] {
+                if let Some(medium) = medium {
+                    params.push(GeometryParamData([
+                        medium.extinction[0],
+                        medium.extinction[1],
+                        medium.extinction[2],
+                        medium.nominal_refractive_index(),
+                    ]));
+                } else {
+                    params.push(GeometryParamData([0.0, 0.0, 0.0, 1.0]));
+                }
             }
 
-            params.push(GeometryParamData([
-                instance.medium.extinction[0],
-                instance.medium.extinction[1],
-                instance.medium.extinction[2],
-                instance.medium.refractive_index,
-            ]));
+            for medium in &[ext_medium, int_medium] {
+                if let Some(medium) = medium {
+                    params.push(GeometryParamData([
+                        medium.scattering_albedo[0],
+                        medium.scattering_albedo[1],
+                        medium.scattering_albedo[2],
+                        medium.phase_asymmetry,
+                    ]));
+                } else {
+                    params.push(GeometryParamData([0.0, 0.0, 0.0, 0.0]));
+                }
+            }
+
+            for medium in &[ext_medium, int_medium] {
+                if let Some(medium) = medium {
+                    params.push(GeometryParamData(dispersion_coefficients(medium)));
+                } else {
+                    params.push(GeometryParamData([1.0, 0.0, 0.0, 0.0]));
+                }
+            }
 
-            offset += 2;
+            offset += 6;
 
             let parameters = geometry_list[&instance.geometry].symbolic_parameters();
             let block_count = (parameters.len() + 3) / 4;
@@ -154,6 +217,32 @@ impl Device {
     }
 }
 
+/// Fits the refractive index of a medium over the visible range by the polynomial
+/// a + b / λ² + c / λ⁴ in micrometers, interpolating it at three wavelengths. This
+/// is exact for Cauchy's equation and very accurate for the Sellmeier equation.
+fn dispersion_coefficients(medium: &Medium) -> [f32; 4] {
+    let point = |wavelength: f64| {
+        let refractive_index = medium.refractive_index_at(wavelength as f32);
+
+        (1e6 / (wavelength * wavelength), f64::from(refractive_index))
+    };
+
+    let (x0, n0) = point(400.0);
+    let (x1, n1) = point(550.0);
+    let (x2, n2) = point(700.0);
+
+    let f01 = (n1 - n0) / (x1 - x0);
+    let f12 = (n2 - n1) / (x2 - x1);
+    let f012 = (f12 - f01) / (x2 - x0);
+
+    [
+        (n0 - f01 * x0 + f012 * x0 * x1) as f32,
+        (f01 - f012 * (x0 + x1)) as f32,
+        f012 as f32,
+        0.0,
+    ]
+}
+
 #[repr(align(16), C)]
 #[derive(AsBytes, FromBytes, Clone, Copy, Debug, Default)]
 pub struct GeometryParamData([f32; 4]);
@@ -200,6 +289,31 @@ impl SceneInstanceNode {
         }
     }
 
+    /// Returns the material parameter offset of a leaf node.
+    pub fn material_instance(&self) -> Option<u16> {
+        if self.word2 != 0xffff_ffff {
+            Some((self.word2 >> 16) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the geometry parameter offset of a leaf node.
+    pub fn geometry_instance(&self) -> Option<u16> {
+        if self.word2 != 0xffff_ffff {
+            Some((self.word1 >> 16) as u16)
+        } else {
+            None
+        }
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            min: self.min.into(),
+            max: self.max.into(),
+        }
+    }
+
     pub fn make_node(min: [f32; 3], max: [f32; 3], skip_val: u32) -> Self {
         Self {
             min,
@@ -1,10 +1,12 @@
 #[allow(unused_imports)]
 use log::{debug, info, warn};
 
-use crate::{BlendMode, Device, Integrator, RasterFilter, Scene};
+use crate::{
+    wavelength_weight, BlendMode, Device, Integrator, RasterFilter, Scene, VISIBLE_WAVELENGTHS,
+};
 use js_sys::Error;
 use quasi_rd::Sequence;
-use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
 use zerocopy::{AsBytes, FromBytes};
 
 #[repr(align(16), C)]
@@ -22,7 +24,7 @@ pub struct IntegratorData {
     current_pass: u32,
     photon_count: f32,
     sppm_alpha: f32,
-    padding: u32,
+    sample_key: u32,
 
     search_radius: f32,
     search_radius_squared: f32,
@@ -36,6 +38,9 @@ pub struct IntegratorData {
 
     max_scatter_bounces: u32,
     max_gather_bounces: u32,
+
+    wavelength_weight: [f32; 3],
+    wavelength: f32,
 }
 
 pub struct IntegratorPass {
@@ -47,6 +52,9 @@ pub struct IntegratorPass {
 pub struct IntegratorState {
     pub(crate) rng: StdRng,
     pub(crate) filter_rng: Sequence,
+    pub(crate) wavelength_rng: Sequence,
+    pub(crate) sequence_shifts: [f32; 3],
+    pub(crate) sample_key: u32,
 
     pub(crate) filter: RasterFilter,
     pub(crate) integrator: Integrator,
@@ -63,6 +71,9 @@ impl Default for IntegratorState {
         Self {
             rng: StdRng::seed_from_u64(0),
             filter_rng: Sequence::new(2),
+            wavelength_rng: Sequence::new(1),
+            sequence_shifts: [0.0; 3],
+            sample_key: 0,
             filter: RasterFilter::default(),
             integrator: Integrator::default(),
             kernel_radii: KernelRadiusSequence::default(),
@@ -73,6 +84,60 @@ impl Default for IntegratorState {
     }
 }
 
+/// Random numbers drawn by the integrator for a pass.
+struct PassSamples {
+    filter: [f32; 2],
+    hash_key: [u32; 4],
+    wavelength: f32,
+}
+
+impl IntegratorState {
+    /// Resets the random sequences to their initial state for the integrator seed.
+    ///
+    /// The quasi-random sequences are randomly shifted modulo one and the per-pixel
+    /// sequences of the shaders are keyed by the seed, so that all random numbers
+    /// drawn during a render depend on the seed and differ between seeds.
+    pub(crate) fn reset_sequences(&mut self) {
+        let mut rng = StdRng::seed_from_u64(u64::from(self.integrator.seed));
+
+        self.sample_key = rng.next_u32();
+        self.sequence_shifts = [rng.gen(), rng.gen(), rng.gen()];
+
+        self.rng = rng;
+        self.filter_rng.seek(0);
+        self.wavelength_rng.seek(0);
+    }
+
+    /// Resets the random sequences to the state they have after some number of passes,
+    /// by drawing every sample of these passes again, which is how render states are
+    /// restored without having to serialize the internal state of these sequences.
+    pub(crate) fn replay_sequences(&mut self, passes: u32) {
+        self.reset_sequences();
+
+        for _ in 0..passes {
+            self.next_pass_samples();
+        }
+    }
+
+    fn next_pass_samples(&mut self) -> PassSamples {
+        let shifts = self.sequence_shifts;
+
+        PassSamples {
+            filter: [
+                (self.filter_rng.next_f32() + shifts[0]).fract(),
+                (self.filter_rng.next_f32() + shifts[1]).fract(),
+            ],
+            hash_key: [
+                self.rng.next_u32(),
+                self.rng.next_u32(),
+                self.rng.next_u32(),
+                self.rng.next_u32(),
+            ],
+            wavelength: (self.wavelength_rng.next_f32() + shifts[2]).fract(),
+        }
+    }
+}
+
 impl Device {
     pub(crate) fn update_integrator(&mut self, integrator: &Integrator) -> Result<(), Error> {
         if integrator.hash_table_bits < 16 {
@@ -95,8 +160,8 @@ impl Device {
             return Err(Error::new("max_scatter_bounces must be 100 or less"));
         }
 
-        let gather_dimensions = 2 + 5 * integrator.max_gather_bounces as usize;
-        let scatter_dimensions = 5 + 4 * integrator.max_scatter_bounces as usize;
+        let gather_dimensions = 2 + 10 * integrator.max_gather_bounces as usize;
+        let scatter_dimensions = 7 + 6 * integrator.max_scatter_bounces as usize;
 
         let mut quasi_buffer =
             vec![SamplerDimensionAlpha::default(); gather_dimensions.max(scatter_dimensions)];
@@ -126,19 +191,22 @@ impl Device {
             .set_define("PUSHBACK", format!("{:.32}", integrator.geometry_pushback));
         self.integrator_scatter_photons_shader
             .set_define("PUSHBACK", format!("{:.32}", integrator.geometry_pushback));
+        self.integrator_gather_photons_shader
+            .set_define("SPECTRAL", integrator.spectral as u32);
+        self.integrator_scatter_photons_shader
+            .set_define("SPECTRAL", integrator.spectral as u32);
 
         Ok(())
     }
 
     pub(crate) fn reset_integrator_state(&mut self, scene: &mut Scene) {
-        self.state.rng = StdRng::seed_from_u64(0);
-        self.state.filter_rng.seek(0);
-        self.state.photon_count = 0.0;
-        self.state.current_pass = 0;
-
         self.state.filter = scene.raster.filter;
         self.state.integrator = *scene.integrator;
 
+        self.state.reset_sequences();
+        self.state.photon_count = 0.0;
+        self.state.current_pass = 0;
+
         Self::clamp_integrator_settings(&mut self.state.integrator);
 
         // The photon positions are limited by the geometry precision, clamp the minimum
@@ -181,18 +249,18 @@ impl Device {
         }
 
         let mut data = IntegratorData::default();
-        let x = self.state.filter_rng.next_f32();
-        let y = self.state.filter_rng.next_f32();
-
-        data.filter_offset[0] = 4.0 * self.state.filter.importance_sample(x) - 2.0;
-        data.filter_offset[1] = 4.0 * self.state.filter.importance_sample(y) - 2.0;
-        data.hash_key[0] = self.state.rng.next_u32();
-        data.hash_key[1] = self.state.rng.next_u32();
-        data.hash_key[2] = self.state.rng.next_u32();
-        data.hash_key[3] = 0;
+        let samples = self.state.next_pass_samples();
+
+        data.filter_offset[0] = 4.0 * self.state.filter.importance_sample(samples.filter[0]) - 2.0;
+        data.filter_offset[1] = 4.0 * self.state.filter.importance_sample(samples.filter[1]) - 2.0;
+        data.hash_key[0] = samples.hash_key[0];
+        data.hash_key[1] = samples.hash_key[1];
+        data.hash_key[2] = samples.hash_key[2];
+        data.hash_key[3] = samples.hash_key[3] | 0x0001_0001;
         data.current_pass = self.state.current_pass;
         data.photon_count = self.state.photon_count.max(1.0);
         data.sppm_alpha = self.state.integrator.alpha;
+        data.sample_key = self.state.sample_key;
         data.search_radius = pass.search_radius;
         data.search_radius_squared = pass.search_radius * pass.search_radius;
         data.photons_for_pass = (pass.n) as f32;
@@ -204,6 +272,20 @@ impl Device {
         data.hash_cols_mask = (self.integrator_scatter_fbo.cols() - 1) as u32;
         data.hash_rows_mask = (self.integrator_scatter_fbo.rows() - 1) as u32;
 
+        // The spectral integrator traces a single wavelength for all photons and camera rays
+        // of a pass, and the radiance estimate is weighted by its color before accumulation.
+        // This converts every pass from XYZ to sRGB rather than the accumulated result, which
+        // is equivalent since the conversion is linear and the accumulation is not clamped.
+
+        if self.state.integrator.spectral {
+            let (min, max) = VISIBLE_WAVELENGTHS;
+
+            data.wavelength = min + (max - min) * samples.wavelength;
+            data.wavelength_weight = wavelength_weight(data.wavelength);
+        } else {
+            data.wavelength_weight = [1.0; 3];
+        }
+
         self.integrator_buffer.write(&data)
     }
 
@@ -224,6 +306,7 @@ impl Device {
         command.bind(&self.integrator_buffer, "Integrator");
         command.bind(&self.raster_buffer, "Raster");
         command.bind(&self.environment_buffer, "Environment");
+        command.bind(&self.light_buffer, "Light");
         command.bind(&self.scatter_quasi_buffer, "QuasiSampler");
 
         if self.envmap_color.is_invalid() {
@@ -250,6 +333,18 @@ impl Device {
             command.bind(&self.material_textures, "material_textures");
         }
 
+        if self.mesh_nodes.is_invalid() {
+            command.bind(&self.placeholder_texture, "mesh_nodes");
+        } else {
+            command.bind(&self.mesh_nodes, "mesh_nodes");
+        }
+
+        if self.mesh_triangles.is_invalid() {
+            command.bind(&self.placeholder_texture, "mesh_triangles");
+        } else {
+            command.bind(&self.mesh_triangles, "mesh_triangles");
+        }
+
         command.set_viewport(
             0,
             0,
@@ -274,6 +369,7 @@ impl Device {
         command.bind(&self.integrator_buffer, "Integrator");
         command.bind(&self.raster_buffer, "Raster");
         command.bind(&self.environment_buffer, "Environment");
+        command.bind(&self.light_buffer, "Light");
         command.bind(&self.gather_quasi_buffer, "QuasiSampler");
         command.bind(&self.integrator_photon_table_pos, "photon_table_pos");
         command.bind(&self.integrator_photon_table_sum, "photon_table_sum");
@@ -302,6 +398,18 @@ impl Device {
             command.bind(&self.material_textures, "material_textures");
         }
 
+        if self.mesh_nodes.is_invalid() {
+            command.bind(&self.placeholder_texture, "mesh_nodes");
+        } else {
+            command.bind(&self.mesh_nodes, "mesh_nodes");
+        }
+
+        if self.mesh_triangles.is_invalid() {
+            command.bind(&self.placeholder_texture, "mesh_triangles");
+        } else {
+            command.bind(&self.mesh_triangles, "mesh_triangles");
+        }
+
         command.set_framebuffer(&self.integrator_gather_fbo);
 
         if let Some([x, y, w, h]) = self.render_region {
@@ -376,6 +484,16 @@ impl KernelRadiusSequence {
         }
     }
 
+    /// Returns the position of the sequence, from which it can be resumed.
+    pub fn position(&self) -> [f32; 2] {
+        [self.product, self.iters]
+    }
+
+    pub fn seek(&mut self, position: [f32; 2]) {
+        self.product = position[0];
+        self.iters = position[1];
+    }
+
     pub fn next_radius(&mut self) -> f32 {
         let radius = (self.max * (self.product / self.iters).sqrt()).max(self.min);
 
@@ -0,0 +1,342 @@
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use crate::{
+    has_custom_modifier, ray_bbox, BoundingBox, Device, Geometry, Light, Material,
+    MaterialParameter, ReferenceGeometry, ReferenceRay,
+};
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector2, Vector3};
+use js_sys::Error;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BTreeMap;
+use zerocopy::{AsBytes, FromBytes};
+
+pub const LIGHT_TYPE_POINT: u32 = 0;
+pub const LIGHT_TYPE_SPOT: u32 = 1;
+pub const LIGHT_TYPE_SPHERE: u32 = 2;
+pub const LIGHT_TYPE_RECTANGLE: u32 = 3;
+pub const LIGHT_TYPE_DIRECTIONAL: u32 = 4;
+pub const LIGHT_TYPE_EMISSIVE: u32 = 5;
+
+#[repr(align(16), C)]
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug, Default)]
+pub struct LightData {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    param0: f32,
+    tangent: [f32; 3],
+    param1: f32,
+    emission: [f32; 3],
+    padding: f32,
+}
+
+impl LightData {
+    /// Creates an emitter for an emissive instance at some node of the instance BVH,
+    /// whose power is proportional to the emitting surface area of the instance.
+    pub(crate) fn emitter(area: f32, node: usize, material: &Material) -> Self {
+        let mut data = Self::default();
+
+        if let Material::Emissive { radiance } = material {
+            // Textured emission is approximated by its average radiance, which only serves
+            // to choose how many photons each emitter emits so need not be very accurate.
+
+            let radiance = match radiance {
+                MaterialParameter::Constant(value) => value.as_vec3(),
+                MaterialParameter::Textured(textured) => {
+                    let base = textured.base.as_vec3();
+                    let factor = textured.factor.as_vec3();
+
+                    [
+                        base[0] + 0.5 * factor[0],
+                        base[1] + 0.5 * factor[1],
+                        base[2] + 0.5 * factor[2],
+                    ]
+                }
+            };
+
+            data.emission[0] = radiance[0].max(0.0);
+            data.emission[1] = radiance[1].max(0.0);
+            data.emission[2] = radiance[2].max(0.0);
+        }
+
+        data.kind = LIGHT_TYPE_EMISSIVE;
+        data.param0 = area;
+        data.param1 = node as f32;
+
+        data
+    }
+}
+
+impl Device {
+    /// Returns the surface area of an emissive instance, which only serves as a proxy
+    /// for its power so need not be very accurate. Mesh areas are computed exactly,
+    /// while distance field areas are estimated on the CPU if possible.
+    pub(crate) fn emitting_area(
+        &self,
+        geometry: &Geometry,
+        parameters: &BTreeMap<String, f32>,
+        bbox: &BoundingBox,
+    ) -> f32 {
+        if let Some(mesh) = geometry.transformed_mesh() {
+            let mut scale = 1.0;
+            let mut node = geometry;
+
+            loop {
+                match node {
+                    Geometry::Scale { factor, child } => {
+                        scale *= factor.value(parameters);
+                        node = child;
+                    }
+                    Geometry::Rotate { child, .. } | Geometry::Translate { child, .. } => {
+                        node = child;
+                    }
+                    _ => break,
+                }
+            }
+
+            return self.loaded_meshes[mesh].area * scale * scale;
+        }
+
+        // Fall back to the area of the bounding box if the estimate is not possible.
+
+        let extent = bbox.max - bbox.min;
+        let bbox_area = 2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x);
+
+        if !bbox_area.is_finite() || has_custom_modifier(geometry) {
+            return bbox_area;
+        }
+
+        let area = estimate_surface_area(geometry, parameters, bbox);
+
+        if area > 0.0 {
+            area
+        } else {
+            bbox_area
+        }
+    }
+
+    pub(crate) fn update_lights(
+        &mut self,
+        light_list: &BTreeMap<String, Light>,
+    ) -> Result<(), Error> {
+        let mut lights = Vec::with_capacity(light_list.len() + self.emitters.len());
+
+        for light in light_list.values() {
+            let mut data = LightData::default();
+
+            let emission = light.emission();
+
+            data.emission[0] = emission[0].max(0.0);
+            data.emission[1] = emission[1].max(0.0);
+            data.emission[2] = emission[2].max(0.0);
+
+            match light {
+                Light::Point { position, .. } => {
+                    data.kind = LIGHT_TYPE_POINT;
+                    data.position = *position;
+                }
+                Light::Spot {
+                    position,
+                    direction,
+                    angle,
+                    blend,
+                    ..
+                } => {
+                    let angle = angle.max(0.0).min(std::f32::consts::PI);
+                    let blend = blend.max(0.0).min(1.0);
+
+                    data.kind = LIGHT_TYPE_SPOT;
+                    data.position = *position;
+                    data.direction = Vector3::from(*direction).normalize().into();
+                    data.param0 = angle.cos();
+                    data.param1 = (angle * (1.0 - blend)).cos();
+                }
+                Light::Sphere {
+                    position, radius, ..
+                } => {
+                    data.kind = LIGHT_TYPE_SPHERE;
+                    data.position = *position;
+                    data.param0 = *radius;
+                }
+                Light::Rectangle {
+                    position,
+                    edge_u,
+                    edge_v,
+                    ..
+                } => {
+                    let edge_u = Vector3::from(*edge_u);
+                    let edge_v = Vector3::from(*edge_v);
+
+                    // The shaders parameterize the rectangle from one of its corners.
+
+                    data.kind = LIGHT_TYPE_RECTANGLE;
+                    data.position = (Vector3::from(*position) - (edge_u + edge_v) * 0.5).into();
+                    data.direction = edge_u.into();
+                    data.tangent = edge_v.into();
+                    data.param0 = edge_u.cross(edge_v).magnitude();
+                }
+                Light::Directional { direction, .. } => {
+                    data.kind = LIGHT_TYPE_DIRECTIONAL;
+                    data.direction = Vector3::from(*direction).normalize().into();
+                }
+            }
+
+            lights.push(data);
+        }
+
+        lights.extend_from_slice(&self.emitters);
+
+        self.light_buffer
+            .write_array(self.light_buffer.max_len(), &lights)?;
+        self.integrator_gather_photons_shader
+            .set_define("LIGHT_DATA_LEN", self.light_buffer.len());
+        self.integrator_scatter_photons_shader
+            .set_define("LIGHT_DATA_LEN", self.light_buffer.len());
+        self.integrator_gather_photons_shader
+            .set_define("LIGHT_COUNT", lights.len());
+        self.integrator_scatter_photons_shader
+            .set_define("LIGHT_COUNT", lights.len());
+
+        Ok(())
+    }
+}
+
+/// Number of random lines used to estimate the surface area of emissive instances.
+const EMITTER_AREA_LINES: usize = 256;
+
+/// Estimates the surface area of a distance field inside a bounding box from the
+/// number of times random lines crossing the bounding box cross the surface. This
+/// is the Cauchy-Crofton formula, as used by `generate_emitter_photon_ray`.
+fn estimate_surface_area(
+    geometry: &Geometry,
+    parameters: &BTreeMap<String, f32>,
+    bbox: &BoundingBox,
+) -> f32 {
+    let geometry = ReferenceGeometry::DistanceField(geometry.clone());
+    let extent = bbox.max - bbox.min;
+    let precision = 1e-4 * extent.magnitude();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut total = 0.0;
+
+    for _ in 0..EMITTER_AREA_LINES {
+        let z = 2.0 * rng.gen::<f32>() - 1.0;
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        let r = (1.0 - z * z).sqrt();
+
+        let dir = Vector3::new(r * phi.cos(), z, r * phi.sin());
+
+        // Choose the face the line enters through in proportion to its projected area.
+
+        let areas = [
+            extent.y * extent.z * dir.x.abs(),
+            extent.x * extent.z * dir.y.abs(),
+            extent.x * extent.y * dir.z.abs(),
+        ];
+
+        let projected_area = areas[0] + areas[1] + areas[2];
+        let w = rng.gen::<f32>() * projected_area;
+
+        let axis = if w < areas[0] {
+            0
+        } else if w < areas[0] + areas[1] {
+            1
+        } else {
+            2
+        };
+
+        let mut org = Point3::new(0.0, 0.0, 0.0);
+
+        for i in 0..3 {
+            org[i] = if i == axis {
+                if dir[i] > 0.0 {
+                    bbox.min[i]
+                } else {
+                    bbox.max[i]
+                }
+            } else {
+                bbox.min[i] + extent[i] * rng.gen::<f32>()
+            };
+        }
+
+        let idir = Vector3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let mut ray = ReferenceRay {
+            org: org.to_vec(),
+            dir,
+        };
+
+        let mut crossings = 0;
+
+        for _ in 0..16 {
+            let mut range = Vector2::new(0.0, std::f32::INFINITY);
+
+            if !ray_bbox(&ray, idir, &mut range, bbox, precision) {
+                break;
+            }
+
+            let hit = match geometry.intersect(parameters, &ray, &mut range, precision) {
+                Some(hit) => hit,
+                None => break,
+            };
+
+            let point = ray.org + ray.dir * range.x;
+            let normal = geometry.normal(parameters, hit, point, precision);
+
+            crossings += 1;
+
+            ray = ReferenceRay::leaving_surface(point, dir, normal, 10.0 * precision);
+        }
+
+        total += projected_area * crossings as f32;
+    }
+
+    // The measure of lines crossing a surface counted with multiplicity is π times
+    // its area, while the measure of lines crossing the bounding box is 4π times its
+    // average projected area, so every crossing accounts for half a projected area.
+
+    2.0 * total / EMITTER_AREA_LINES as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeometryParameter;
+    use std::f32::consts::PI;
+
+    fn estimated_area(geometry: &Geometry) -> f32 {
+        let parameters = BTreeMap::new();
+
+        estimate_surface_area(geometry, &parameters, &geometry.bounding_box(&parameters))
+    }
+
+    #[test]
+    fn distance_field_areas_are_estimated() {
+        let sphere = Geometry::Sphere {
+            radius: GeometryParameter::Constant(1.0),
+        };
+
+        let torus = Geometry::Torus {
+            major_radius: GeometryParameter::Constant(2.0),
+            minor_radius: GeometryParameter::Constant(0.5),
+        };
+
+        let sphere_area = estimated_area(&sphere);
+        let torus_area = estimated_area(&torus);
+
+        let sphere_ratio = sphere_area / (4.0 * PI);
+        let torus_ratio = torus_area / (4.0 * PI * PI);
+
+        assert!(
+            (sphere_ratio - 1.0).abs() < 0.1,
+            "sphere ratio {}",
+            sphere_ratio
+        );
+        assert!(
+            (torus_ratio - 1.0).abs() < 0.1,
+            "torus ratio {}",
+            torus_ratio
+        );
+    }
+}
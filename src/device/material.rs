@@ -1,7 +1,7 @@
 #[allow(unused_imports)]
 use log::{debug, info, warn};
 
-use crate::{Device, Material, MaterialParameter};
+use crate::{CoatedBase, Device, Material, MaterialParameter};
 use img2raw::{ColorSpace, DataFormat, Header};
 use js_sys::Error;
 use std::collections::BTreeMap;
@@ -26,6 +26,12 @@ pub(crate) fn material_index(material: &Material) -> u16 {
         Material::Phong { .. } => 2,
         Material::IdealRefraction { .. } => 3,
         Material::Dielectric { .. } => 4,
+        Material::Emissive { .. } => 5,
+        Material::Conductor { .. } => 6,
+        Material::Coated { base, .. } => match base {
+            CoatedBase::Lambertian { .. } => 7,
+            CoatedBase::Conductor { .. } => 8,
+        },
     }
 }
 
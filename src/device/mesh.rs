@@ -0,0 +1,150 @@
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use crate::{load_mesh, BoundingBox, Device, Geometry, MeshHierarchy, Texture, RGBA32F};
+use js_sys::Error;
+use std::collections::BTreeMap;
+
+/// Location of a loaded mesh BVH within the device mesh textures.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshLocation {
+    pub bbox: BoundingBox,
+    pub area: f32,
+    pub root: u32,
+    pub end: u32,
+}
+
+impl Device {
+    pub(crate) const MESH_DATA_COLS: usize = 2048;
+    pub(crate) const MESH_DATA_ROWS: usize = 2048;
+
+    fn meshes_out_of_date(&self, meshes: &[&str]) -> bool {
+        if self.loaded_meshes.len() != meshes.len() {
+            return true;
+        }
+
+        !meshes.iter().eq(self.loaded_meshes.keys())
+    }
+
+    pub(crate) fn update_meshes(
+        &mut self,
+        geometries: &BTreeMap<String, Geometry>,
+        assets: &dyn Fn(&str) -> Result<Vec<u8>, Error>,
+    ) -> Result<(), Error> {
+        let mut meshes = vec![];
+
+        for geometry in geometries.values() {
+            if let Some(mesh) = geometry.transformed_mesh() {
+                meshes.push(mesh);
+            }
+        }
+
+        meshes.sort_unstable();
+        meshes.dedup();
+
+        self.integrator_gather_photons_shader
+            .set_define("MESH_DATA_COLS", format!("{}U", Self::MESH_DATA_COLS));
+        self.integrator_scatter_photons_shader
+            .set_define("MESH_DATA_COLS", format!("{}U", Self::MESH_DATA_COLS));
+
+        if !self.mesh_nodes.is_invalid() && !self.meshes_out_of_date(&meshes) {
+            return Ok(());
+        }
+
+        // Every BVH node takes up two texels, storing its bounding box and its skip
+        // index followed by its triangle range. Every triangle takes up six texels,
+        // storing its vertex positions followed by its vertex normals, which are set
+        // to the face normal if absent. Indices are stored as floating-point values.
+
+        let mut node_data: Vec<f32> = vec![];
+        let mut triangle_data: Vec<f32> = vec![];
+        let mut locations = BTreeMap::new();
+
+        for &mesh in &meshes {
+            let triangle_mesh = load_mesh(mesh, &assets(mesh)?)
+                .map_err(|err| Error::new(&format!("failed to load mesh `{}': {}", mesh, err)))?;
+
+            let hierarchy = MeshHierarchy::build(&triangle_mesh);
+
+            let node_start = (node_data.len() / 8) as u32;
+            let triangle_start = (triangle_data.len() / 24) as u32;
+
+            for node in &hierarchy.nodes {
+                let leaf_info = if node.is_leaf() {
+                    ((triangle_start + node.first) * 8 + node.count) as f32
+                } else {
+                    -1.0
+                };
+
+                node_data.extend_from_slice(&[
+                    node.bbox.min.x,
+                    node.bbox.min.y,
+                    node.bbox.min.z,
+                    (node_start + node.skip) as f32,
+                    node.bbox.max.x,
+                    node.bbox.max.y,
+                    node.bbox.max.z,
+                    leaf_info,
+                ]);
+            }
+
+            for &triangle in &hierarchy.triangles {
+                let vertices = triangle_mesh.vertices(triangle as usize);
+
+                for vertex in &vertices {
+                    triangle_data.extend_from_slice(&[vertex.x, vertex.y, vertex.z, 0.0]);
+                }
+
+                for normal in &triangle_mesh.vertex_normals(triangle as usize) {
+                    triangle_data.extend_from_slice(&[normal.x, normal.y, normal.z, 0.0]);
+                }
+            }
+
+            locations.insert(
+                mesh.to_owned(),
+                MeshLocation {
+                    bbox: hierarchy.bounding_box(),
+                    area: triangle_mesh.surface_area(),
+                    root: node_start,
+                    end: node_start + hierarchy.nodes.len() as u32,
+                },
+            );
+        }
+
+        // Triangle ranges are packed into a single float which must be exactly
+        // representable, which limits the number of triangles we can support.
+
+        if triangle_data.len() / 24 * 8 >= 1 << 24 {
+            return Err(Error::new("too many mesh triangles in scene"));
+        }
+
+        self.loaded_meshes.clear();
+
+        Self::upload_mesh_data(&mut self.mesh_nodes, node_data)?;
+        Self::upload_mesh_data(&mut self.mesh_triangles, triangle_data)?;
+
+        self.loaded_meshes = locations;
+
+        Ok(())
+    }
+
+    fn upload_mesh_data(texture: &mut Texture<RGBA32F>, mut data: Vec<f32>) -> Result<(), Error> {
+        if data.is_empty() {
+            texture.reset();
+
+            return Ok(());
+        }
+
+        let rows = (data.len() / 4 + Self::MESH_DATA_COLS - 1) / Self::MESH_DATA_COLS;
+
+        if rows > Self::MESH_DATA_ROWS {
+            return Err(Error::new("mesh data exceeds texture size limit"));
+        }
+
+        data.resize(rows * Self::MESH_DATA_COLS * 4, 0.0);
+
+        texture.upload(Self::MESH_DATA_COLS, rows, &data);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{encode_exr, encode_png, encode_radiance_hdr, Device};
+use js_sys::Error;
+
+/// File format to encode a render into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Hdr,
+    Exr,
+}
+
+impl ImageFormat {
+    /// Returns the image format with a given name, i.e. "png", "hdr" or "exr".
+    pub fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "png" => Ok(Self::Png),
+            "hdr" => Ok(Self::Hdr),
+            "exr" => Ok(Self::Exr),
+            _ => Err(Error::new(&format!("unknown image format `{}'", name))),
+        }
+    }
+}
+
+/// Render read back from the device.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image. The radiance is the linear sRGB estimate of the integrator, while
+/// the composited render is what gets presented, after exposure, lens flare and
+/// tone mapping, encoded as 8-bit sRGB.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceRender {
+    pub cols: usize,
+    pub rows: usize,
+    pub radiance: Vec<[f32; 3]>,
+    pub composited: Vec<[u8; 3]>,
+}
+
+impl DeviceRender {
+    /// Encodes the render into a file of some format.
+    ///
+    /// The PNG file contains the render exactly as presented, while the HDR and
+    /// OpenEXR files contain the linear radiance before any post-processing.
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Png => encode_png(self.cols, self.rows, &self.composited),
+            ImageFormat::Hdr => encode_radiance_hdr(self.cols, self.rows, &self.radiance),
+            ImageFormat::Exr => encode_exr(self.cols, self.rows, &self.radiance),
+        }
+    }
+}
+
+impl Device {
+    /// Reads back the current render from the device.
+    pub fn read_render(&mut self) -> Result<DeviceRender, Error> {
+        if self.device_lost {
+            return Err(Error::new("cannot read back render while device is lost"));
+        }
+
+        let cols = self.integrator_gather_fbo.cols();
+        let rows = self.integrator_gather_fbo.rows();
+
+        // The radiance estimate accumulates the sample count in its alpha channel, and any
+        // invalid pixels are discarded exactly as the post-processing shader would do.
+
+        let radiance_data = self.integrator_gather_fbo.read_pixels_f32(0);
+        let composited_data = self.composited_fbo.read_pixels_u8(0);
+
+        let mut radiance = Vec::with_capacity(cols * rows);
+        let mut composited = Vec::with_capacity(cols * rows);
+
+        for row in radiance_data.chunks(4 * cols.max(1)).rev() {
+            radiance.extend(row.chunks(4).map(|rgba| {
+                let pixel = [rgba[0] / rgba[3], rgba[1] / rgba[3], rgba[2] / rgba[3]];
+
+                if pixel.iter().all(|x| x.is_finite()) {
+                    pixel
+                } else {
+                    [0.0; 3]
+                }
+            }));
+        }
+
+        for row in composited_data.chunks(4 * cols.max(1)).rev() {
+            composited.extend(row.chunks(4).map(|rgba| [rgba[0], rgba[1], rgba[2]]));
+        }
+
+        Ok(DeviceRender {
+            cols,
+            rows,
+            radiance,
+            composited,
+        })
+    }
+}
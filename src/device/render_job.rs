@@ -0,0 +1,179 @@
+use crate::{Device, DeviceRender};
+use js_sys::{Date, Error};
+use serde::{Deserialize, Serialize};
+
+/// Criterion for terminating a render job.
+///
+/// The noise level is the relative RMS error of the luminance of the render,
+/// which is estimated by comparing the render against an earlier snapshot.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RenderTermination {
+    Passes { count: u32 },
+    Photons { count: f64 },
+    Time { seconds: f64 },
+    Noise { level: f32 },
+}
+
+/// Progress of a render job.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RenderProgress {
+    pub passes: u32,
+    pub photons: f64,
+    pub elapsed_seconds: f64,
+    pub noise_level: Option<f32>,
+    /// Estimated fraction of the job which has been completed.
+    pub completion: f32,
+    pub done: bool,
+}
+
+/// Render job refining the render of a device until a termination criterion.
+///
+/// The job is advanced in slices of bounded duration so that the caller can
+/// yield to the browser in between, and the render is complete once the job
+/// reports it is done. Passes already performed by the device are counted.
+#[derive(Debug)]
+pub struct RenderJob {
+    termination: RenderTermination,
+    start_time: Option<f64>,
+    snapshot: Option<(u32, Vec<[f32; 3]>)>,
+    noise_level: Option<f32>,
+    done: bool,
+}
+
+impl RenderJob {
+    /// Number of passes between two estimates of the noise level of the render,
+    /// which is limited because each estimate requires reading back the render.
+    pub const NOISE_CHECK_INTERVAL: u32 = 16;
+
+    pub fn new(termination: RenderTermination) -> Self {
+        Self {
+            termination,
+            start_time: None,
+            snapshot: None,
+            noise_level: None,
+            done: false,
+        }
+    }
+
+    /// Restarts the job, which must be done whenever the render is reset.
+    pub fn restart(&mut self) {
+        *self = Self::new(self.termination);
+    }
+
+    /// Refines the render for at most some milliseconds or until the job is done,
+    /// calling back with the progress of the job after every pass. Returns the
+    /// final render once the job is done.
+    pub fn advance(
+        &mut self,
+        device: &mut Device,
+        budget_ms: f64,
+        mut progress: impl FnMut(&RenderProgress),
+    ) -> Result<Option<DeviceRender>, Error> {
+        let slice_start = Date::now();
+        let start_time = *self.start_time.get_or_insert(slice_start);
+
+        while !self.done && Date::now() - slice_start < budget_ms {
+            let previous_passes = device.state.current_pass;
+
+            device.refine()?;
+
+            let passes = device.state.current_pass;
+
+            // The device does not refine the render while its context is lost, so stop
+            // here and let the caller update the device to restore it before retrying.
+
+            if passes == previous_passes {
+                break;
+            }
+
+            if let RenderTermination::Noise { .. } = self.termination {
+                if passes % Self::NOISE_CHECK_INTERVAL == 0 {
+                    self.estimate_noise_level(device)?;
+                }
+            }
+
+            let progress_data = self.progress(device, Date::now() - start_time);
+            self.done = progress_data.done;
+
+            progress(&progress_data);
+        }
+
+        if self.done {
+            Ok(Some(device.read_render()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn progress(&self, device: &Device, elapsed_ms: f64) -> RenderProgress {
+        let passes = device.state.current_pass;
+        let photons = f64::from(device.state.photon_count);
+        let elapsed_seconds = elapsed_ms / 1000.0;
+
+        // The noise level of a progressive render decreases as the inverse square
+        // root of the number of passes, so its completion is estimated from that.
+
+        let completion = match self.termination {
+            RenderTermination::Passes { count } => passes as f32 / count as f32,
+            RenderTermination::Photons { count } => (photons / count) as f32,
+            RenderTermination::Time { seconds } => (elapsed_seconds / seconds) as f32,
+            RenderTermination::Noise { level } => match self.noise_level {
+                Some(noise_level) => (level / noise_level).powi(2),
+                None => 0.0,
+            },
+        };
+
+        RenderProgress {
+            passes,
+            photons,
+            elapsed_seconds,
+            noise_level: self.noise_level,
+            completion: completion.max(0.0).min(1.0),
+            done: completion >= 1.0 || completion.is_nan(),
+        }
+    }
+
+    /// Estimates the noise level of the render from its difference with a snapshot
+    /// taken earlier; for independent passes, the variance of the difference of the
+    /// renders after q and p passes is that of the render after p passes times p/q - 1.
+    fn estimate_noise_level(&mut self, device: &mut Device) -> Result<(), Error> {
+        let passes = device.state.current_pass;
+        let radiance = device.read_render()?.radiance;
+
+        if let Some((snapshot_passes, snapshot)) = &self.snapshot {
+            if snapshot.len() == radiance.len() && *snapshot_passes < passes {
+                let mut error = 0.0;
+                let mut total = 0.0;
+
+                for (current, previous) in radiance.iter().zip(snapshot) {
+                    let difference = luminance(*current) - luminance(*previous);
+
+                    error += f64::from(difference * difference);
+                    total += f64::from(luminance(*current).powi(2));
+                }
+
+                let ratio = f64::from(passes) / f64::from(*snapshot_passes) - 1.0;
+
+                if total > 0.0 {
+                    self.noise_level = Some((error / (total * ratio)).sqrt() as f32);
+                }
+            }
+
+            // Keep comparing against the same snapshot until it has half as many passes
+            // as the render, so that the difference remains dominated by its noise.
+
+            if *snapshot_passes * 2 > passes && snapshot.len() == radiance.len() {
+                return Ok(());
+            }
+        }
+
+        self.snapshot = Some((passes, radiance));
+
+        Ok(())
+    }
+}
+
+fn luminance(color: [f32; 3]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
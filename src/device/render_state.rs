@@ -0,0 +1,239 @@
+use crate::{Device, Scene};
+use js_sys::{Date, Error};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::core::inflate_flags::{
+    TINFL_FLAG_PARSE_ZLIB_HEADER, TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+};
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+use std::convert::TryInto;
+
+const RENDER_STATE_SIGNATURE: &[u8; 8] = b"EQXSTATE";
+const RENDER_STATE_VERSION: u32 = 2;
+
+/// Minimum time in milliseconds between two recovery snapshots of the render, which
+/// is limited because each snapshot requires reading back the radiance estimate.
+pub const RECOVERY_SNAPSHOT_INTERVAL_MS: f64 = 10_000.0;
+
+/// State of a progressive render captured from the device.
+///
+/// The pixels are the radiance estimate with its sample counts, in the order in
+/// which they are stored on the device. The random sequences of the integrator
+/// are not captured, as they are deterministic and can be replayed when needed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderState {
+    pub cols: usize,
+    pub rows: usize,
+    pub current_pass: u32,
+    pub photon_count: f32,
+    pub kernel_radii_position: [f32; 2],
+    pub pixels: Vec<f32>,
+}
+
+// A saved render state consists of the signature and version, the dimensions of the render,
+// the integrator state, the render fingerprint and then the zlib-compressed radiance estimate
+// with its sample counts, all stored in little-endian byte order.
+
+impl Device {
+    /// Captures the state of the progressive render.
+    pub fn capture_render_state(&mut self) -> Result<RenderState, Error> {
+        if self.device_lost {
+            return Err(Error::new(
+                "cannot capture render state while device is lost",
+            ));
+        }
+
+        Ok(RenderState {
+            cols: self.integrator_gather_fbo.cols(),
+            rows: self.integrator_gather_fbo.rows(),
+            current_pass: self.state.current_pass,
+            photon_count: self.state.photon_count,
+            kernel_radii_position: self.state.kernel_radii.position(),
+            pixels: self.integrator_gather_fbo.read_pixels_f32(0),
+        })
+    }
+
+    /// Restores a previously captured state of the progressive render.
+    ///
+    /// The device must first be updated with the same scene as when the state was
+    /// captured, and the render then continues exactly from where it was captured.
+    /// Note that any later update of the device which resets the render discards it.
+    pub fn restore_render_state(&mut self, state: &RenderState) -> Result<(), Error> {
+        if self.device_lost {
+            return Err(Error::new(
+                "cannot restore render state while device is lost",
+            ));
+        }
+
+        let expected_cols = self.integrator_gather_fbo.cols();
+        let expected_rows = self.integrator_gather_fbo.rows();
+
+        if (state.cols, state.rows) != (expected_cols, expected_rows) {
+            return Err(Error::new("render state dimensions do not match raster"));
+        }
+
+        if state.pixels.len() != state.cols * state.rows * 4 {
+            return Err(Error::new("unexpected render state pixel data size"));
+        }
+
+        self.integrator_radiance_estimate
+            .upload(state.cols, state.rows, &state.pixels);
+
+        self.state.replay_sequences(state.current_pass);
+        self.state.current_pass = state.current_pass;
+        self.state.photon_count = state.photon_count;
+        self.state.kernel_radii.seek(state.kernel_radii_position);
+
+        Ok(())
+    }
+
+    /// Saves the state of the progressive render of a scene so that it can be resumed
+    /// later. The saved state includes the render fingerprint, which identifies the
+    /// scene, including its integrator settings and raster, and the number of passes.
+    pub fn save_render_state(&mut self, scene: &Scene) -> Result<Vec<u8>, Error> {
+        if self.device_lost {
+            return Err(Error::new("cannot save render state while device is lost"));
+        }
+
+        let fingerprint = self.render_fingerprint(scene)?;
+        let state = self.capture_render_state()?;
+        let [product, iters] = state.kernel_radii_position;
+
+        let mut output = RENDER_STATE_SIGNATURE.to_vec();
+
+        output.extend_from_slice(&RENDER_STATE_VERSION.to_le_bytes());
+        output.extend_from_slice(&(state.cols as u32).to_le_bytes());
+        output.extend_from_slice(&(state.rows as u32).to_le_bytes());
+        output.extend_from_slice(&state.current_pass.to_le_bytes());
+        output.extend_from_slice(&state.photon_count.to_le_bytes());
+        output.extend_from_slice(&product.to_le_bytes());
+        output.extend_from_slice(&iters.to_le_bytes());
+        output.extend_from_slice(fingerprint.as_bytes());
+
+        let pixels: Vec<u8> = (state.pixels.iter())
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect();
+
+        output.extend(compress_to_vec_zlib(&pixels, 6));
+
+        Ok(output)
+    }
+
+    /// Resumes a progressive render of a scene from a saved state.
+    ///
+    /// This has the same requirements as restoring a captured render state, and the
+    /// state is rejected unless it was saved from a render of exactly the same scene.
+    pub fn load_render_state(&mut self, scene: &Scene, data: &[u8]) -> Result<(), Error> {
+        if self.device_lost {
+            return Err(Error::new("cannot load render state while device is lost"));
+        }
+
+        let mut reader = StateReader(data);
+
+        if reader.read_bytes(8)? != RENDER_STATE_SIGNATURE {
+            return Err(Error::new("invalid render state signature"));
+        }
+
+        if reader.read_u32()? != RENDER_STATE_VERSION {
+            return Err(Error::new("unsupported render state version"));
+        }
+
+        let cols = reader.read_u32()? as usize;
+        let rows = reader.read_u32()? as usize;
+
+        let expected_cols = self.integrator_gather_fbo.cols();
+        let expected_rows = self.integrator_gather_fbo.rows();
+
+        if (cols, rows) != (expected_cols, expected_rows) {
+            return Err(Error::new("render state dimensions do not match raster"));
+        }
+
+        let current_pass = reader.read_u32()?;
+        let photon_count = reader.read_f32()?;
+        let kernel_radii_position = [reader.read_f32()?, reader.read_f32()?];
+        let fingerprint = reader.read_bytes(16)?;
+
+        if fingerprint != self.render_fingerprint_at(scene, current_pass)?.as_bytes() {
+            return Err(Error::new("render state was saved from a different scene"));
+        }
+
+        // Decompress into a buffer of exactly the expected size, so that a corrupt
+        // or malicious render state cannot make us allocate arbitrary memory.
+
+        let mut pixels = vec![0u8; cols * rows * 16];
+        let mut decompressor = Box::<DecompressorOxide>::default();
+        let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+
+        match decompress(&mut decompressor, reader.0, &mut pixels, 0, flags) {
+            (TINFLStatus::Done, _, size) if size == pixels.len() => {}
+            (TINFLStatus::Done, _, _) | (TINFLStatus::HasMoreOutput, _, _) => {
+                return Err(Error::new("unexpected render state pixel data size"));
+            }
+            _ => return Err(Error::new("invalid render state pixel data")),
+        }
+
+        let pixels: Vec<f32> = (pixels.chunks(4))
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        self.restore_render_state(&RenderState {
+            cols,
+            rows,
+            current_pass,
+            photon_count,
+            kernel_radii_position,
+            pixels,
+        })
+    }
+
+    /// Periodically captures the render state into memory, so that the render can be
+    /// resumed from that snapshot rather than from scratch if the context gets lost.
+    pub(crate) fn take_recovery_snapshot(&mut self) {
+        let now = Date::now();
+
+        if now - self.recovery_snapshot_time < RECOVERY_SNAPSHOT_INTERVAL_MS {
+            return;
+        }
+
+        // The context may already be lost without the device having been notified yet,
+        // in which case the read back radiance estimate would not be valid.
+
+        if self.gl.is_context_lost() {
+            return;
+        }
+
+        if let Ok(state) = self.capture_render_state() {
+            self.recovery_snapshot = Some(state);
+            self.recovery_snapshot_time = now;
+        }
+    }
+
+    /// Discards the recovery snapshot, which must be done whenever the render is reset.
+    pub(crate) fn discard_recovery_snapshot(&mut self) {
+        self.recovery_snapshot = None;
+        self.recovery_snapshot_time = Date::now();
+    }
+}
+
+struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < count {
+            return Err(Error::new("unexpected end of render state"));
+        }
+
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+}
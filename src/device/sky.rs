@@ -0,0 +1,304 @@
+use crate::EnvironmentImage;
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use std::f32::consts::PI;
+
+/// Width of the environment maps generated for sky environments.
+pub const SKY_MAP_COLS: usize = 512;
+/// Height of the environment maps generated for sky environments.
+pub const SKY_MAP_ROWS: usize = 256;
+
+/// Luminance of the solar disk outside of the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f32 = 1.6e6;
+/// Solid angle subtended by the solar disk, in steradians.
+const SUN_SOLID_ANGLE: f32 = 6.8e-5;
+
+/// Renders the Preetham daylight model into an equirectangular environment map
+/// of `SKY_MAP_COLS` by `SKY_MAP_ROWS` pixels. The solar disk is smaller than a
+/// pixel, so its radiance is spread over the pixel containing the sun.
+///
+/// See "A Practical Analytic Model for Daylight" by Preetham et al. (1999).
+pub fn render_sky_map(
+    sun_direction: [f32; 3],
+    turbidity: f32,
+    ground_albedo: [f32; 3],
+) -> EnvironmentImage {
+    let (cols, rows) = (SKY_MAP_COLS, SKY_MAP_ROWS);
+
+    let sun = Vector3::from(sun_direction).normalize();
+
+    // The model is only defined for a sun above the horizon, so clamp its zenith
+    // angle; the sun's azimuth is arbitrary if it lies exactly at the zenith.
+
+    let sun_theta = sun.y.max(0.0).min(1.0).acos();
+
+    let (sun_x, sun_z) = if sun.x != 0.0 || sun.z != 0.0 {
+        let length = sun.x.hypot(sun.z);
+
+        (sun.x / length, sun.z / length)
+    } else {
+        (1.0, 0.0)
+    };
+
+    let sun = Vector3::new(
+        sun_theta.sin() * sun_x,
+        sun_theta.cos(),
+        sun_theta.sin() * sun_z,
+    );
+
+    let sky = PerezSky::new(sun_theta, turbidity);
+
+    let mut pixels = vec![[0.0; 3]; cols * rows];
+    let mut irradiance = Vector3::zero();
+
+    let pixel_solid_angle = (PI / rows as f32) * (2.0 * PI / cols as f32);
+
+    for y in 0..rows {
+        let theta = (y as f32 + 0.5) / (rows as f32) * PI;
+        let (sin_theta, cos_theta) = theta.sin_cos();
+
+        if cos_theta <= 0.0 {
+            continue; // ground
+        }
+
+        for x in 0..cols {
+            let phi = -(x as f32 + 0.5) / (cols as f32) * 2.0 * PI;
+
+            let dir = Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+
+            let radiance = sky.radiance(cos_theta, dir.dot(sun).max(-1.0).min(1.0));
+
+            irradiance += radiance * cos_theta * sin_theta * pixel_solid_angle;
+            pixels[y * cols + x] = radiance.into();
+        }
+    }
+
+    let sun_radiance = sun_radiance(sun_theta, turbidity);
+    irradiance += sun_radiance * SUN_SOLID_ANGLE * sun_theta.cos();
+
+    let sun_phi = (-sun.z.atan2(sun.x) / (2.0 * PI)).rem_euclid(1.0);
+    let sun_x = ((sun_phi * cols as f32) as usize).min(cols - 1);
+    let sun_y = ((sun_theta / PI * rows as f32) as usize).min(rows - 1);
+
+    let theta = (sun_y as f32 + 0.5) / (rows as f32) * PI;
+
+    if theta.cos() > 0.0 {
+        let pixel = &mut pixels[sun_y * cols + sun_x];
+        let radiance = sun_radiance * SUN_SOLID_ANGLE / (pixel_solid_angle * theta.sin());
+
+        *pixel = (Vector3::from(*pixel) + radiance).into();
+    }
+
+    // The ground is a diffuse surface lit by the entire sky without occlusion.
+
+    let ground = Vector3::from(ground_albedo).mul_element_wise(irradiance) / PI;
+
+    for y in 0..rows {
+        if ((y as f32 + 0.5) / (rows as f32) * PI).cos() <= 0.0 {
+            for pixel in &mut pixels[y * cols..(y + 1) * cols] {
+                *pixel = ground.into();
+            }
+        }
+    }
+
+    EnvironmentImage { cols, rows, pixels }
+}
+
+/// Returns the radiance of the solar disk seen through the atmosphere, taking
+/// into account Rayleigh and aerosol extinction as in the Preetham model.
+fn sun_radiance(sun_theta: f32, turbidity: f32) -> Vector3<f32> {
+    // Kasten and Young's relative optical air mass, which is finite at the horizon
+    let zenith_angle = sun_theta.to_degrees();
+    let air_mass = 1.0 / (sun_theta.cos() + 0.50572 * (96.07995 - zenith_angle).powf(-1.6364));
+
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    // extinction at representative red, green and blue wavelengths, in μm
+    let transmittance = |wavelength: f32| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+
+    Vector3::new(
+        transmittance(0.68),
+        transmittance(0.55),
+        transmittance(0.44),
+    ) * SUN_LUMINANCE
+}
+
+/// Perez luminance distributions of the Preetham model for the luminance and
+/// the chromaticity of the sky, normalized by their values at the zenith.
+struct PerezSky {
+    sun_theta: f32,
+    coefficients: [[f32; 5]; 3],
+    zenith: [f32; 3],
+}
+
+impl PerezSky {
+    fn new(sun_theta: f32, t: f32) -> Self {
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+
+        let theta = sun_theta;
+        let theta2 = theta * theta;
+        let theta3 = theta2 * theta;
+
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+
+        Self {
+            sun_theta,
+            coefficients,
+            zenith: [zenith_luminance.max(0.0), zenith_x, zenith_y],
+        }
+    }
+
+    /// Returns the radiance of the sky for a view direction above the horizon.
+    fn radiance(&self, cos_theta: f32, cos_gamma: f32) -> Vector3<f32> {
+        let gamma = cos_gamma.acos();
+        let mut xyy = [0.0; 3];
+
+        for (i, value) in xyy.iter_mut().enumerate() {
+            let coefficients = &self.coefficients[i];
+
+            let numerator = perez(coefficients, cos_theta, gamma, cos_gamma);
+            let denominator = perez(coefficients, 1.0, self.sun_theta, self.sun_theta.cos());
+
+            *value = self.zenith[i] * numerator / denominator;
+        }
+
+        let [luminance, x, y] = xyy;
+
+        if y <= 0.0 {
+            return Vector3::zero();
+        }
+
+        let cie_x = x / y * luminance;
+        let cie_z = (1.0 - x - y) / y * luminance;
+
+        Vector3::new(
+            3.2406 * cie_x - 1.5372 * luminance - 0.4986 * cie_z,
+            -0.9689 * cie_x + 1.8758 * luminance + 0.0415 * cie_z,
+            0.0557 * cie_x - 0.2040 * luminance + 1.0570 * cie_z,
+        )
+        .map(|c| c.max(0.0))
+    }
+}
+
+fn perez(coefficients: &[f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::luminance;
+
+    fn pixel_direction(x: usize, y: usize) -> Vector3<f32> {
+        let theta = (y as f32 + 0.5) / (SKY_MAP_ROWS as f32) * PI;
+        let phi = -(x as f32 + 0.5) / (SKY_MAP_COLS as f32) * 2.0 * PI;
+
+        Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        )
+    }
+
+    #[test]
+    fn sun_direction_is_the_luminance_peak() {
+        for &(elevation, azimuth) in &[(20.0f32, 0.0f32), (45.0, 1.0), (70.0, 4.0)] {
+            let (elevation, azimuth) = (elevation.to_radians(), azimuth);
+
+            let sun = Vector3::new(
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                elevation.cos() * azimuth.sin(),
+            );
+
+            for &turbidity in &[2.0, 5.0, 10.0] {
+                let map = render_sky_map(sun.into(), turbidity, [0.2; 3]);
+
+                let (peak, _) = (map.pixels.iter().enumerate())
+                    .map(|(index, &pixel)| (index, luminance(pixel.into())))
+                    .fold(
+                        (0, std::f32::NEG_INFINITY),
+                        |a, b| if b.1 > a.1 { b } else { a },
+                    );
+
+                let peak = pixel_direction(peak % SKY_MAP_COLS, peak / SKY_MAP_COLS);
+
+                // allow for the angular size of a pixel of the sky map
+                assert!(peak.angle(sun).0 < 2.0f32.to_radians());
+            }
+        }
+    }
+
+    #[test]
+    fn radiance_is_finite_and_non_negative_at_the_horizon() {
+        for turbidity in 2..=10 {
+            for &sun_theta in &[0.0, 0.5, 1.0, 1.5, PI / 2.0] {
+                let sky = PerezSky::new(sun_theta, turbidity as f32);
+
+                for step in 0..=100 {
+                    let cos_gamma = step as f32 / 50.0 - 1.0;
+
+                    for &cos_theta in &[0.0, 1e-6, 1e-3] {
+                        let radiance = sky.radiance(cos_theta, cos_gamma);
+
+                        for i in 0..3 {
+                            assert!(radiance[i].is_finite() && radiance[i] >= 0.0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sky_maps_are_finite_and_non_negative() {
+        for turbidity in 2..=10 {
+            for &sun in &[[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.3, 0.1, -0.8]] {
+                let map = render_sky_map(sun, turbidity as f32, [0.5; 3]);
+
+                for pixel in &map.pixels {
+                    assert!(pixel.iter().all(|c| c.is_finite() && *c >= 0.0));
+                }
+            }
+        }
+    }
+}
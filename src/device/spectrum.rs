@@ -0,0 +1,49 @@
+/// Range of wavelengths in nanometers traced by the spectral integrator.
+pub const VISIBLE_WAVELENGTHS: (f32, f32) = (380.0, 780.0);
+
+// Integrals of the linear sRGB color matching functions over the visible wavelengths, used
+// to normalize the spectral weights so that a constant spectrum of one converges to white.
+const SRGB_INTEGRALS: [f32; 3] = [128.361, 101.538, 97.0648];
+
+fn piecewise_gaussian(wavelength: f32, mean: f32, lower_stddev: f32, upper_stddev: f32) -> f32 {
+    let stddev = if wavelength < mean {
+        lower_stddev
+    } else {
+        upper_stddev
+    };
+
+    (-0.5 * ((wavelength - mean) / stddev).powi(2)).exp()
+}
+
+/// Evaluates the CIE 1931 color matching functions at a wavelength in nanometers,
+/// using the multi-lobe fit from Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(wavelength: f32) -> [f32; 3] {
+    let g = |mean, lower, upper| piecewise_gaussian(wavelength, mean, lower, upper);
+
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
+
+/// Returns the linear sRGB weight of a wavelength sampled uniformly over the visible
+/// wavelengths, by which radiance at that wavelength is converted to a color. This is
+/// normalized so that the weights average to white over all wavelengths. The weights
+/// of some wavelengths are negative, since their colors are outside of the sRGB gamut.
+pub fn wavelength_weight(wavelength: f32) -> [f32; 3] {
+    let [x, y, z] = cie_xyz(wavelength);
+    let (min, max) = VISIBLE_WAVELENGTHS;
+
+    let rgb = [
+        3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    ];
+
+    [
+        rgb[0] * (max - min) / SRGB_INTEGRALS[0],
+        rgb[1] * (max - min) / SRGB_INTEGRALS[1],
+        rgb[2] * (max - min) / SRGB_INTEGRALS[2],
+    ]
+}
@@ -2,7 +2,7 @@
 use log::{debug, info, warn};
 
 use crate::{Color, DepthStencil, RenderTarget};
-use js_sys::{Array, Error};
+use js_sys::{Array, Error, Float32Array};
 use web_sys::{WebGl2RenderingContext as Context, WebGlFramebuffer, WebGlTexture};
 
 pub trait AsAttachment {
@@ -137,6 +137,54 @@ impl Framebuffer {
             .clear_bufferfv_with_f32_array(Context::COLOR, attachment as i32, &color);
     }
 
+    /// Reads back the RGBA pixels of a floating-point attachment, bottom row first.
+    pub fn read_pixels_f32(&self, attachment: usize) -> Vec<f32> {
+        self.gl
+            .bind_framebuffer(Context::READ_FRAMEBUFFER, self.handle.as_ref());
+        self.gl
+            .read_buffer(Context::COLOR_ATTACHMENT0 + attachment as u32);
+
+        let array = Float32Array::new_with_length((4 * self.cols * self.rows) as u32);
+
+        self.gl
+            .read_pixels_with_opt_array_buffer_view(
+                0,
+                0,
+                self.cols as i32,
+                self.rows as i32,
+                Context::RGBA,
+                Context::FLOAT,
+                Some(&array),
+            )
+            .unwrap();
+
+        array.to_vec()
+    }
+
+    /// Reads back the RGBA pixels of an 8-bit attachment, bottom row first.
+    pub fn read_pixels_u8(&self, attachment: usize) -> Vec<u8> {
+        self.gl
+            .bind_framebuffer(Context::READ_FRAMEBUFFER, self.handle.as_ref());
+        self.gl
+            .read_buffer(Context::COLOR_ATTACHMENT0 + attachment as u32);
+
+        let mut data = vec![0; 4 * self.cols * self.rows];
+
+        self.gl
+            .read_pixels_with_opt_u8_array(
+                0,
+                0,
+                self.cols as i32,
+                self.rows as i32,
+                Context::RGBA,
+                Context::UNSIGNED_BYTE,
+                Some(&mut data),
+            )
+            .unwrap();
+
+        data
+    }
+
     pub fn clear_depth_stencil(&self, depth: f32, stencil: u8) {
         self.gl
             .bind_framebuffer(Context::DRAW_FRAMEBUFFER, self.handle.as_ref());
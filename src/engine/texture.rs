@@ -441,6 +441,12 @@ impl TextureFormat for RGBA32F {
     const GL_INTERNAL_FORMAT: u32 = Context::RGBA32F;
     const GL_FORMAT: u32 = Context::RGBA;
     const GL_TYPE: u32 = Context::FLOAT;
+
+    fn into_texture_source_data(cols: usize, rows: usize, layer: &[Self::Data]) -> Object {
+        assert!(layer.len() == cols * rows * 4);
+
+        Float32Array::from(layer).into()
+    }
 }
 
 impl TextureFormat for R32F {
@@ -454,6 +460,12 @@ impl TextureFormat for R32F {
     const GL_INTERNAL_FORMAT: u32 = Context::R32F;
     const GL_FORMAT: u32 = Context::RED;
     const GL_TYPE: u32 = Context::FLOAT;
+
+    fn into_texture_source_data(cols: usize, rows: usize, layer: &[Self::Data]) -> Object {
+        assert!(layer.len() == cols * rows);
+
+        Float32Array::from(layer).into()
+    }
 }
 
 impl TextureFormat for R32UI {
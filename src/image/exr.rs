@@ -0,0 +1,570 @@
+use crate::{pixel_count, EnvironmentImage, ImageError};
+use half::f16;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+use std::convert::{TryFrom, TryInto};
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZIPS: u8 = 2;
+const COMPRESSION_ZIP: u8 = 3;
+
+const PIXEL_TYPE_UINT: u32 = 0;
+const PIXEL_TYPE_HALF: u32 = 1;
+const PIXEL_TYPE_FLOAT: u32 = 2;
+
+/// Parses a single-part scanline OpenEXR file into an image.
+///
+/// Pixel data may be uncompressed or ZIP-compressed, with half, float or uint
+/// channels. The R, G and B channels are read if present, or otherwise the Y
+/// channel is read as a grayscale image; every other channel is ignored.
+pub fn load_exr(data: &[u8]) -> Result<EnvironmentImage, ImageError> {
+    let mut reader = ExrReader(data);
+
+    if reader.read_bytes(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(ImageError::Malformed("missing signature".to_owned()));
+    }
+
+    let version = reader.read_u32()?;
+
+    if version & 0xff != 2 {
+        return Err(ImageError::Unsupported(format!(
+            "version {}",
+            version & 0xff
+        )));
+    }
+
+    if version & 0x200 != 0 {
+        return Err(ImageError::Unsupported("tiled image".to_owned()));
+    }
+
+    if version & 0x1800 != 0 {
+        return Err(ImageError::Unsupported(
+            "deep or multi-part image".to_owned(),
+        ));
+    }
+
+    let header = ExrHeader::parse(&mut reader)?;
+
+    let lines_per_block = match header.compression {
+        COMPRESSION_NONE | COMPRESSION_ZIPS => 1,
+        COMPRESSION_ZIP => 16,
+        compression => {
+            return Err(ImageError::Unsupported(format!(
+                "compression method {}",
+                compression
+            )));
+        }
+    };
+
+    let [x_min, y_min, x_max, y_max] = header.data_window;
+
+    if x_max < x_min || y_max < y_min {
+        return Err(ImageError::Malformed("invalid data window".to_owned()));
+    }
+
+    let dimension = |min: i32, max: i32| {
+        usize::try_from(i64::from(max) - i64::from(min) + 1).unwrap_or(usize::max_value())
+    };
+
+    let cols = dimension(x_min, x_max);
+    let rows = dimension(y_min, y_max);
+    let pixel_count = pixel_count(cols, rows)?;
+
+    let line_size = (header.channels.iter())
+        .try_fold(0usize, |size, (_, pixel_type)| {
+            size.checked_add(pixel_size(*pixel_type) * cols)
+        })
+        .ok_or_else(|| ImageError::Malformed("scanline size overflow".to_owned()))?;
+
+    // Select the channels making up the image, and then compute the byte offset of
+    // each channel within a scanline, in which channels are stored one after another.
+
+    let names: &[&str] = if header.channel("R").is_some() {
+        &["R", "G", "B"]
+    } else {
+        &["Y", "Y", "Y"]
+    };
+
+    let mut channels = [(0, 0); 3];
+
+    for (channel, name) in channels.iter_mut().zip(names) {
+        let index = header
+            .channel(name)
+            .ok_or_else(|| ImageError::Unsupported(format!("missing channel {}", name)))?;
+
+        let offset: usize = header.channels[..index]
+            .iter()
+            .map(|(_, pixel_type)| pixel_size(*pixel_type) * cols)
+            .sum();
+
+        *channel = (offset, header.channels[index].1);
+    }
+
+    let block_count = (rows + lines_per_block - 1) / lines_per_block;
+
+    // Make sure the offset table is actually present before allocating space for
+    // it, so that a bogus data window cannot cause an arbitrarily large allocation.
+
+    if block_count > reader.0.len() / 8 {
+        return Err(ImageError::UnexpectedEnd);
+    }
+
+    let mut offsets = Vec::with_capacity(block_count);
+
+    for _ in 0..block_count {
+        offsets.push(reader.read_u64()?);
+    }
+
+    let mut pixels = vec![[0.0; 3]; pixel_count];
+
+    for offset in offsets {
+        let offset = usize::try_from(offset).map_err(|_| ImageError::UnexpectedEnd)?;
+
+        let mut reader = ExrReader(data.get(offset..).ok_or(ImageError::UnexpectedEnd)?);
+
+        let y = i64::from(reader.read_i32()?) - i64::from(y_min);
+        let size = reader.read_u32()? as usize;
+        let block = reader.read_bytes(size)?;
+
+        if y < 0 || y as usize >= rows {
+            return Err(ImageError::Malformed(
+                "block outside data window".to_owned(),
+            ));
+        }
+
+        let y = y as usize;
+        let lines = lines_per_block.min(rows - y);
+
+        let block_size = (lines.checked_mul(line_size))
+            .ok_or_else(|| ImageError::Malformed("block size overflow".to_owned()))?;
+
+        let block = if header.compression == COMPRESSION_NONE || size == block_size {
+            block.to_vec() // blocks which would not shrink are stored uncompressed
+        } else {
+            decompress_zip_block(block, block_size)?
+        };
+
+        if block.len() != block_size {
+            return Err(ImageError::Malformed("unexpected block size".to_owned()));
+        }
+
+        for (line, scanline) in block.chunks(line_size).enumerate() {
+            let row = &mut pixels[(y + line) * cols..(y + line + 1) * cols];
+
+            for (c, &(offset, pixel_type)) in channels.iter().enumerate() {
+                let size = pixel_size(pixel_type);
+
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let bytes = &scanline[offset + x * size..offset + (x + 1) * size];
+
+                    pixel[c] = match pixel_type {
+                        PIXEL_TYPE_UINT => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        PIXEL_TYPE_HALF => {
+                            f16::from_bits(u16::from_le_bytes(bytes.try_into().unwrap())).to_f32()
+                        }
+                        _ => f32::from_le_bytes(bytes.try_into().unwrap()),
+                    };
+                }
+            }
+        }
+    }
+
+    Ok(EnvironmentImage { cols, rows, pixels })
+}
+
+/// Encodes a high dynamic range image as a single-part scanline OpenEXR file.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image, and are written as ZIP-compressed float R, G and B channels.
+pub fn encode_exr(cols: usize, rows: usize, pixels: &[[f32; 3]]) -> Vec<u8> {
+    assert_eq!(pixels.len(), cols * rows, "invalid image dimensions");
+
+    let mut output = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    let window = [0, 0, cols as i32 - 1, rows as i32 - 1];
+
+    let mut channels = vec![];
+
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // linear flag and reserved bytes
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+
+    channels.push(0);
+
+    let window: Vec<u8> = window
+        .iter()
+        .flat_map(|x| x.to_le_bytes().to_vec())
+        .collect();
+
+    write_attribute(&mut output, "channels", "chlist", &channels);
+    write_attribute(
+        &mut output,
+        "compression",
+        "compression",
+        &[COMPRESSION_ZIP],
+    );
+    write_attribute(&mut output, "dataWindow", "box2i", &window);
+    write_attribute(&mut output, "displayWindow", "box2i", &window);
+    write_attribute(&mut output, "lineOrder", "lineOrder", &[0]); // increasing y
+    write_attribute(
+        &mut output,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    write_attribute(&mut output, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut output,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+
+    output.push(0); // end of header
+
+    // Each block holds 16 scanlines, in which every channel is stored one after another,
+    // and the offset table pointing to each block precedes the blocks themselves.
+
+    let blocks: Vec<Vec<u8>> = pixels
+        .chunks(16 * cols.max(1))
+        .map(|block| {
+            let mut data = Vec::with_capacity(12 * block.len());
+
+            for line in block.chunks(cols) {
+                for &c in &[2, 1, 0] {
+                    for pixel in line {
+                        data.extend_from_slice(&pixel[c].to_le_bytes());
+                    }
+                }
+            }
+
+            compress_zip_block(data)
+        })
+        .collect();
+
+    let mut offset = output.len() + 8 * blocks.len();
+
+    for block in &blocks {
+        output.extend_from_slice(&(offset as u64).to_le_bytes());
+        offset += 8 + block.len();
+    }
+
+    for (index, block) in blocks.iter().enumerate() {
+        output.extend_from_slice(&(16 * index as i32).to_le_bytes());
+        output.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+
+    output
+}
+
+fn write_attribute(output: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    output.extend_from_slice(name.as_bytes());
+    output.push(0);
+    output.extend_from_slice(kind.as_bytes());
+    output.push(0);
+    output.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    output.extend_from_slice(value);
+}
+
+/// Compresses a ZIP block after applying the byte interleaving and predictor,
+/// leaving the block uncompressed if compression would not make it smaller.
+fn compress_zip_block(block: Vec<u8>) -> Vec<u8> {
+    let (even, odd): (Vec<_>, Vec<_>) = block.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+
+    let mut data: Vec<u8> = even.into_iter().chain(odd).map(|(_, &byte)| byte).collect();
+
+    for i in (1..data.len()).rev() {
+        data[i] = (i32::from(data[i]) - i32::from(data[i - 1]) + 128) as u8;
+    }
+
+    let compressed = compress_to_vec_zlib(&data, 6);
+
+    if compressed.len() < block.len() {
+        compressed
+    } else {
+        block
+    }
+}
+
+/// Decompresses a ZIP block of at most `max_size` bytes, undoing the predictor
+/// and byte interleaving which OpenEXR applies to the pixel data beforehand.
+fn decompress_zip_block(block: &[u8], max_size: usize) -> Result<Vec<u8>, ImageError> {
+    let mut data = decompress_to_vec_zlib_with_limit(block, max_size)
+        .map_err(|_| ImageError::Malformed("invalid ZIP data".to_owned()))?;
+
+    for i in 1..data.len() {
+        data[i] = (i32::from(data[i - 1]) + i32::from(data[i]) - 128) as u8;
+    }
+
+    let (lo, hi) = data.split_at((data.len() + 1) / 2);
+
+    let mut output = Vec::with_capacity(data.len());
+
+    for (i, &byte) in lo.iter().enumerate() {
+        output.push(byte);
+
+        if let Some(&byte) = hi.get(i) {
+            output.push(byte);
+        }
+    }
+
+    Ok(output)
+}
+
+fn pixel_size(pixel_type: u32) -> usize {
+    if pixel_type == PIXEL_TYPE_HALF {
+        2
+    } else {
+        4
+    }
+}
+
+#[derive(Debug)]
+struct ExrHeader {
+    channels: Vec<(String, u32)>,
+    compression: u8,
+    data_window: [i32; 4],
+}
+
+impl ExrHeader {
+    fn parse(reader: &mut ExrReader) -> Result<Self, ImageError> {
+        let mut channels = None;
+        let mut compression = None;
+        let mut data_window = None;
+
+        loop {
+            let name = reader.read_string()?;
+
+            if name.is_empty() {
+                break; // end of header
+            }
+
+            let kind = reader.read_string()?;
+            let size = reader.read_u32()? as usize;
+            let mut value = ExrReader(reader.read_bytes(size)?);
+
+            match (name.as_str(), kind.as_str()) {
+                ("channels", "chlist") => channels = Some(Self::parse_channels(&mut value)?),
+                ("compression", "compression") => compression = Some(value.read_bytes(1)?[0]),
+                ("dataWindow", "box2i") => {
+                    data_window = Some([
+                        value.read_i32()?,
+                        value.read_i32()?,
+                        value.read_i32()?,
+                        value.read_i32()?,
+                    ])
+                }
+                _ => {}
+            }
+        }
+
+        let missing = |name: &str| ImageError::Malformed(format!("missing {} attribute", name));
+
+        Ok(Self {
+            channels: channels.ok_or_else(|| missing("channels"))?,
+            compression: compression.ok_or_else(|| missing("compression"))?,
+            data_window: data_window.ok_or_else(|| missing("dataWindow"))?,
+        })
+    }
+
+    fn parse_channels(reader: &mut ExrReader) -> Result<Vec<(String, u32)>, ImageError> {
+        let mut channels = vec![];
+
+        loop {
+            let name = reader.read_string()?;
+
+            if name.is_empty() {
+                break; // end of channel list
+            }
+
+            let pixel_type = reader.read_u32()?;
+            reader.read_bytes(4)?; // linear flag and reserved bytes
+            let x_sampling = reader.read_i32()?;
+            let y_sampling = reader.read_i32()?;
+
+            if pixel_type > PIXEL_TYPE_FLOAT {
+                return Err(ImageError::Malformed(format!("pixel type {}", pixel_type)));
+            }
+
+            if x_sampling != 1 || y_sampling != 1 {
+                return Err(ImageError::Unsupported("subsampled channel".to_owned()));
+            }
+
+            channels.push((name, pixel_type));
+        }
+
+        Ok(channels)
+    }
+
+    fn channel(&self, name: &str) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|(channel, _)| channel == name)
+    }
+}
+
+struct ExrReader<'a>(&'a [u8]);
+
+impl<'a> ExrReader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], ImageError> {
+        if self.0.len() < count {
+            return Err(ImageError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ImageError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, ImageError> {
+        Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, ImageError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_string(&mut self) -> Result<String, ImageError> {
+        let end = self
+            .0
+            .iter()
+            .position(|&c| c == 0)
+            .ok_or(ImageError::UnexpectedEnd)?;
+
+        let string = String::from_utf8_lossy(&self.0[..end]).into_owned();
+        self.0 = &self.0[end + 1..];
+
+        Ok(string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_IMAGE_DIMENSION;
+
+    /// Returns the header of a grayscale half float image, which has no pixel
+    /// data or offset table following it.
+    fn grayscale_header(compression: u8, data_window: [i32; 4]) -> Vec<u8> {
+        let mut output = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+        let mut channels = b"Y\0".to_vec();
+        channels.extend_from_slice(&PIXEL_TYPE_HALF.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.push(0);
+
+        let window: Vec<u8> = (data_window.iter())
+            .flat_map(|x| x.to_le_bytes().to_vec())
+            .collect();
+
+        write_attribute(&mut output, "channels", "chlist", &channels);
+        write_attribute(&mut output, "compression", "compression", &[compression]);
+        write_attribute(&mut output, "dataWindow", "box2i", &window);
+
+        output.push(0);
+        output
+    }
+
+    /// Appends a single block and its offset table to a header.
+    fn with_block(mut output: Vec<u8>, block: &[u8]) -> Vec<u8> {
+        let offset = output.len() as u64 + 8;
+
+        output.extend_from_slice(&offset.to_le_bytes());
+        output.extend_from_slice(&0i32.to_le_bytes());
+        output.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        output.extend_from_slice(block);
+        output
+    }
+
+    fn test_pixels(cols: usize, rows: usize) -> Vec<[f32; 3]> {
+        (0..cols * rows)
+            .map(|i| [i as f32, 0.5 * i as f32, 1e-3 / (1.0 + i as f32)])
+            .collect()
+    }
+
+    #[test]
+    fn encoded_images_round_trip() {
+        for &(cols, rows) in &[(1, 1), (37, 21), (5, 16), (3, 33)] {
+            let pixels = test_pixels(cols, rows);
+            let image = load_exr(&encode_exr(cols, rows, &pixels)).unwrap();
+
+            assert_eq!(image, EnvironmentImage { cols, rows, pixels });
+        }
+    }
+
+    #[test]
+    fn grayscale_images_are_loaded() {
+        let mut block = vec![];
+        block.extend_from_slice(&f16::from_f32(0.25).to_bits().to_le_bytes());
+        block.extend_from_slice(&f16::from_f32(4.0).to_bits().to_le_bytes());
+
+        let data = with_block(grayscale_header(COMPRESSION_NONE, [0, 0, 1, 0]), &block);
+        let image = load_exr(&data).unwrap();
+
+        assert_eq!(image.pixels, vec![[0.25; 3], [4.0; 3]]);
+    }
+
+    #[test]
+    fn truncated_images_are_rejected() {
+        let data = encode_exr(7, 20, &test_pixels(7, 20));
+
+        for len in 0..data.len() {
+            assert!(load_exr(&data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_data_windows_are_rejected() {
+        let max = MAX_IMAGE_DIMENSION as i32;
+
+        for &window in &[
+            [0, 0, max, 0],
+            [0, 0, 0, max],
+            [i32::min_value(), 0, i32::max_value(), 0],
+            [0, i32::min_value(), 0, i32::max_value()],
+        ] {
+            let data = grayscale_header(COMPRESSION_ZIP, window);
+
+            assert!(matches!(load_exr(&data), Err(ImageError::Unsupported(_))));
+        }
+    }
+
+    #[test]
+    fn missing_offset_table_is_rejected_before_allocating() {
+        let max = MAX_IMAGE_DIMENSION as i32 - 1;
+
+        let mut data = grayscale_header(COMPRESSION_NONE, [0, 0, max, max]);
+        data.extend_from_slice(&[0; 64]);
+
+        assert_eq!(load_exr(&data), Err(ImageError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn out_of_range_block_offsets_are_rejected() {
+        let mut data = grayscale_header(COMPRESSION_NONE, [0, 0, 0, 0]);
+        data.extend_from_slice(&u64::max_value().to_le_bytes());
+
+        assert_eq!(load_exr(&data), Err(ImageError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn oversized_compressed_blocks_are_rejected() {
+        let bomb = compress_to_vec_zlib(&[0; 1 << 20], 9);
+        let data = with_block(grayscale_header(COMPRESSION_ZIPS, [0, 0, 1, 0]), &bomb);
+
+        assert!(matches!(load_exr(&data), Err(ImageError::Malformed(_))));
+    }
+}
@@ -0,0 +1,426 @@
+use crate::{pixel_count, EnvironmentImage, ImageError};
+use std::str::from_utf8;
+
+/// Parses a Radiance HDR file into an image.
+///
+/// Both run-length encoded and flat scanlines are supported, but only in the
+/// standard `-Y rows +X cols` orientation or its vertically flipped version.
+/// Only RGBE pixels are supported and exposure adjustments are ignored.
+pub fn load_radiance_hdr(data: &[u8]) -> Result<EnvironmentImage, ImageError> {
+    let (cols, rows, flip, mut body) = parse_header(data)?;
+
+    let mut pixels = Vec::with_capacity(pixel_count(cols, rows)?);
+    let mut scanline = vec![0u8; cols * 4];
+
+    for _ in 0..rows {
+        body = read_scanline(body, &mut scanline)?;
+
+        pixels.extend(scanline.chunks(4).map(|rgbe8| {
+            let (r, g, b) = unpack_rgbe8(rgbe8);
+
+            [r, g, b]
+        }));
+    }
+
+    if flip {
+        let mut flipped = Vec::with_capacity(pixels.len());
+
+        for row in pixels.chunks(cols).rev() {
+            flipped.extend_from_slice(row);
+        }
+
+        pixels = flipped;
+    }
+
+    Ok(EnvironmentImage { cols, rows, pixels })
+}
+
+/// Encodes a high dynamic range image as a Radiance HDR file.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image, and scanlines are run-length encoded whenever the width allows.
+pub fn encode_radiance_hdr(cols: usize, rows: usize, pixels: &[[f32; 3]]) -> Vec<u8> {
+    assert_eq!(pixels.len(), cols * rows, "invalid image dimensions");
+
+    let mut output = Vec::new();
+
+    output.extend_from_slice(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n");
+    output.extend_from_slice(format!("-Y {} +X {}\n", rows, cols).as_bytes());
+
+    let mut channel = vec![0u8; cols];
+
+    for row in pixels.chunks(cols.max(1)) {
+        let scanline: Vec<[u8; 4]> = row.iter().map(|&pixel| pack_rgbe8(pixel)).collect();
+
+        if !(8..=0x7fff).contains(&cols) {
+            scanline
+                .iter()
+                .for_each(|rgbe| output.extend_from_slice(rgbe));
+            continue;
+        }
+
+        output.extend_from_slice(&[2, 2, (cols >> 8) as u8, cols as u8]);
+
+        for c in 0..4 {
+            for (value, rgbe) in channel.iter_mut().zip(&scanline) {
+                *value = rgbe[c];
+            }
+
+            write_rle_channel(&mut output, &channel);
+        }
+    }
+
+    output
+}
+
+/// Writes one channel of a scanline as runs of at least four identical values
+/// separated by literal sequences, as expected by the `read_scanline` function.
+fn write_rle_channel(output: &mut Vec<u8>, values: &[u8]) {
+    let mut x = 0;
+
+    while x < values.len() {
+        let mut start = x;
+        let mut run = 0;
+
+        while start < values.len() {
+            let value = values[start];
+            run = (values[start..].iter().take(127))
+                .take_while(|&&v| v == value)
+                .count();
+
+            if run >= 4 {
+                break;
+            }
+
+            start += run;
+        }
+
+        while x < start {
+            let count = (start - x).min(128);
+
+            output.push(count as u8);
+            output.extend_from_slice(&values[x..x + count]);
+
+            x += count;
+        }
+
+        if run >= 4 {
+            output.push(128 + run as u8);
+            output.push(values[start]);
+
+            x += run;
+        }
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(usize, usize, bool, &[u8]), ImageError> {
+    let (signature, mut rest) = split_line(data)?;
+
+    if !signature.starts_with("#?") {
+        return Err(ImageError::Malformed("missing signature".to_owned()));
+    }
+
+    // The header consists of variables on separate lines, terminated by an empty line
+    // and followed by a single line containing the resolution of the image.
+
+    loop {
+        let (line, next) = split_line(rest)?;
+        rest = next;
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(ImageError::Unsupported(format!("pixel format {}", format)));
+            }
+        }
+    }
+
+    let (line, body) = split_line(rest)?;
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    let (rows, cols) = match tokens.as_slice() {
+        [y, rows, "+X", cols] if *y == "-Y" || *y == "+Y" => (rows, cols),
+        _ => {
+            return Err(ImageError::Unsupported(format!("orientation {}", line)));
+        }
+    };
+
+    let rows = rows
+        .parse()
+        .map_err(|_| ImageError::Malformed(format!("invalid height {}", rows)))?;
+    let cols = cols
+        .parse()
+        .map_err(|_| ImageError::Malformed(format!("invalid width {}", cols)))?;
+
+    Ok((cols, rows, tokens[0] == "+Y", body))
+}
+
+fn split_line(data: &[u8]) -> Result<(&str, &[u8]), ImageError> {
+    let end = data
+        .iter()
+        .position(|&c| c == b'\n')
+        .ok_or(ImageError::UnexpectedEnd)?;
+
+    let line = from_utf8(&data[..end])
+        .map_err(|_| ImageError::Malformed("invalid UTF-8 in header".to_owned()))?;
+
+    Ok((line.trim_end_matches('\r'), &data[end + 1..]))
+}
+
+/// Reads a single scanline of RGBE pixels, returning the remaining data.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [u8]) -> Result<&'a [u8], ImageError> {
+    let cols = scanline.len() / 4;
+
+    if data.len() < 4 {
+        return Err(ImageError::UnexpectedEnd);
+    }
+
+    // Scanlines using the newer run-length encoding begin with two bytes of value 2 and
+    // then the scanline width; each channel is then encoded separately as a set of runs.
+
+    if !(8..=0x7fff).contains(&cols) || data[0] != 2 || data[1] != 2 || data[2] & 0x80 != 0 {
+        return read_flat_scanline(data, scanline);
+    }
+
+    if (usize::from(data[2]) << 8 | usize::from(data[3])) != cols {
+        return Err(ImageError::Malformed("scanline width mismatch".to_owned()));
+    }
+
+    let mut data = &data[4..];
+
+    for channel in 0..4 {
+        let mut x = 0;
+
+        while x < cols {
+            let (&count, rest) = data.split_first().ok_or(ImageError::UnexpectedEnd)?;
+
+            if count > 128 {
+                let count = usize::from(count - 128);
+                let (&value, rest) = rest.split_first().ok_or(ImageError::UnexpectedEnd)?;
+
+                if x + count > cols {
+                    return Err(ImageError::Malformed("run exceeds scanline".to_owned()));
+                }
+
+                for i in x..x + count {
+                    scanline[4 * i + channel] = value;
+                }
+
+                x += count;
+                data = rest;
+            } else {
+                let count = usize::from(count);
+
+                if count == 0 || x + count > cols {
+                    return Err(ImageError::Malformed("invalid run length".to_owned()));
+                }
+
+                if rest.len() < count {
+                    return Err(ImageError::UnexpectedEnd);
+                }
+
+                for (i, &value) in (x..x + count).zip(&rest[..count]) {
+                    scanline[4 * i + channel] = value;
+                }
+
+                x += count;
+                data = &rest[count..];
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Reads a scanline of RGBE pixels which may use the older run-length encoding,
+/// where a pixel of (1, 1, 1, n) repeats the previous pixel a number of times.
+fn read_flat_scanline<'a>(mut data: &'a [u8], scanline: &mut [u8]) -> Result<&'a [u8], ImageError> {
+    let cols = scanline.len() / 4;
+
+    let mut x = 0;
+    let mut shift = 0;
+
+    while x < cols {
+        if data.len() < 4 {
+            return Err(ImageError::UnexpectedEnd);
+        }
+
+        let (pixel, rest) = data.split_at(4);
+        data = rest;
+
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if x == 0 || shift > 24 {
+                return Err(ImageError::Malformed("invalid run".to_owned()));
+            }
+
+            let count = usize::from(pixel[3]) << shift;
+
+            if x + count > cols {
+                return Err(ImageError::Malformed("run exceeds scanline".to_owned()));
+            }
+
+            for i in x..x + count {
+                scanline.copy_within(4 * (x - 1)..4 * x, 4 * i);
+            }
+
+            x += count;
+            shift += 8;
+        } else {
+            scanline[4 * x..4 * x + 4].copy_from_slice(pixel);
+
+            x += 1;
+            shift = 0;
+        }
+    }
+
+    Ok(data)
+}
+
+pub(crate) fn unpack_rgbe8(rgbe: &[u8]) -> (f32, f32, f32) {
+    if rgbe[3] == 0 {
+        return (0.0, 0.0, 0.0);
+    }
+
+    let f = 2.0f32.powi(rgbe[3] as i32 - 128 - 8);
+
+    let r = (rgbe[0] as f32 * f).max(0.0).min(65500.0);
+    let g = (rgbe[1] as f32 * f).max(0.0).min(65500.0);
+    let b = (rgbe[2] as f32 * f).max(0.0).min(65500.0);
+
+    (r, g, b)
+}
+
+fn pack_rgbe8(pixel: [f32; 3]) -> [u8; 4] {
+    let sanitize = |x: f32| if x.is_finite() { x.max(0.0) } else { 0.0 };
+
+    let (r, g, b) = (sanitize(pixel[0]), sanitize(pixel[1]), sanitize(pixel[2]));
+
+    let max = r.max(g).max(b);
+
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // Find the exponent such that the largest component is in [128, 256) once scaled.
+
+    let mut exponent = max.log2().floor() as i32 + 1;
+
+    if max / 2.0f32.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+
+    let scale = 256.0 / 2.0f32.powi(exponent);
+
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_IMAGE_DIMENSION;
+
+    fn test_pixels(cols: usize, rows: usize) -> Vec<[f32; 3]> {
+        (0..cols * rows)
+            .map(|i| [(i % 7) as f32, 0.5, 1e3 * (i / 9) as f32])
+            .collect()
+    }
+
+    fn assert_close(image: &EnvironmentImage, pixels: &[[f32; 3]]) {
+        assert_eq!(image.pixels.len(), pixels.len());
+
+        for (actual, expected) in image.pixels.iter().zip(pixels) {
+            let max = expected[0].max(expected[1]).max(expected[2]);
+
+            for c in 0..3 {
+                assert!((actual[c] - expected[c]).abs() <= max / 128.0);
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_images_round_trip() {
+        // images narrower than 8 pixels cannot be run-length encoded
+
+        for &(cols, rows) in &[(1, 1), (5, 3), (40, 6), (300, 2)] {
+            let pixels = test_pixels(cols, rows);
+            let image = load_radiance_hdr(&encode_radiance_hdr(cols, rows, &pixels)).unwrap();
+
+            assert_eq!((image.cols, image.rows), (cols, rows));
+            assert_close(&image, &pixels);
+        }
+    }
+
+    #[test]
+    fn flipped_images_are_loaded_top_down() {
+        let pixels = test_pixels(10, 4);
+        let mut data = encode_radiance_hdr(10, 4, &pixels);
+
+        let position = data.windows(4).position(|w| w == b"-Y 4").unwrap();
+        data[position] = b'+';
+
+        let flipped: Vec<[f32; 3]> = pixels.chunks(10).rev().flatten().copied().collect();
+
+        assert_close(&load_radiance_hdr(&data).unwrap(), &flipped);
+    }
+
+    #[test]
+    fn truncated_images_are_rejected() {
+        for &(cols, rows) in &[(5, 3), (40, 6)] {
+            let data = encode_radiance_hdr(cols, rows, &test_pixels(cols, rows));
+
+            for len in 0..data.len() {
+                assert!(load_radiance_hdr(&data[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let too_large = MAX_IMAGE_DIMENSION + 1;
+
+        for resolution in &[
+            format!("-Y {} +X 8", too_large),
+            format!("-Y 8 +X {}", too_large),
+            format!("-Y {} +X {}", usize::max_value(), usize::max_value()),
+        ] {
+            let data = format!("#?RADIANCE\n\n{}\n", resolution);
+
+            assert!(matches!(
+                load_radiance_hdr(data.as_bytes()),
+                Err(ImageError::Unsupported(_))
+            ));
+        }
+
+        let data = b"#?RADIANCE\n\n-Y 99999999999999999999999 +X 8\n";
+
+        assert!(matches!(
+            load_radiance_hdr(data),
+            Err(ImageError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_scanlines_are_rejected() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+
+        let malformed = |scanline: &[u8]| {
+            let data = [header.as_slice(), scanline].concat();
+
+            matches!(load_radiance_hdr(&data), Err(ImageError::Malformed(_)))
+        };
+
+        assert!(malformed(&[2, 2, 0, 9]));
+        assert!(malformed(&[2, 2, 0, 8, 128 + 9, 0]));
+        assert!(malformed(&[2, 2, 0, 8, 0]));
+        assert!(malformed(&[1, 1, 1, 4]));
+    }
+}
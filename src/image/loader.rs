@@ -0,0 +1,131 @@
+use crate::{load_exr, load_radiance_hdr, unpack_rgbe8};
+use img2raw::{ColorSpace, DataFormat, Header};
+use std::fmt::{self, Display, Formatter};
+use zerocopy::LayoutVerified;
+
+/// Error encountered while loading an environment map asset.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageError {
+    /// The image format could not be determined from the asset.
+    UnknownFormat,
+    /// The image data ended before the expected end of the asset.
+    UnexpectedEnd,
+    /// The image uses a feature of its format which is not supported.
+    Unsupported(String),
+    /// The image data contained an invalid value.
+    Malformed(String),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "unknown image format"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of image data"),
+            Self::Unsupported(message) => write!(f, "unsupported image: {}", message),
+            Self::Malformed(message) => write!(f, "malformed image data: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Largest supported width or height of an image, which matches the maximum
+/// texture size of most WebGL2 implementations.
+pub const MAX_IMAGE_DIMENSION: usize = 16384;
+
+/// Returns the number of pixels in an image of the given dimensions, making
+/// sure that neither dimension exceeds the maximum supported image dimension.
+pub(crate) fn pixel_count(cols: usize, rows: usize) -> Result<usize, ImageError> {
+    if cols > MAX_IMAGE_DIMENSION || rows > MAX_IMAGE_DIMENSION {
+        return Err(ImageError::Unsupported(format!(
+            "image size {}x{} exceeds {}x{}",
+            cols, rows, MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION
+        )));
+    }
+
+    cols.checked_mul(rows)
+        .ok_or_else(|| ImageError::Malformed("image size overflow".to_owned()))
+}
+
+/// High dynamic range image in linear sRGB, such as an environment map.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image, which for environment maps is the direction of the zenith.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvironmentImage {
+    pub cols: usize,
+    pub rows: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+/// Parses an environment map asset into an image.
+///
+/// The image format is determined by the extension of the asset name or else
+/// by the signature of the data. Supported formats are img2raw RGBE8 assets,
+/// Radiance HDR files and scanline OpenEXR files. The returned image is never
+/// empty, no larger than `MAX_IMAGE_DIMENSION` in either dimension, and its
+/// pixels are finite, nonnegative and within half float range.
+pub fn load_environment_image(name: &str, data: &[u8]) -> Result<EnvironmentImage, ImageError> {
+    let extension = name.rsplit('.').next().unwrap_or("");
+
+    let mut image = match extension.to_ascii_lowercase().as_str() {
+        "hdr" | "pic" => load_radiance_hdr(data)?,
+        "exr" => load_exr(data)?,
+        "raw" => load_img2raw(data)?,
+        _ if data.starts_with(b"#?") => load_radiance_hdr(data)?,
+        _ if data.starts_with(&[0x76, 0x2f, 0x31, 0x01]) => load_exr(data)?,
+        _ => return Err(ImageError::UnknownFormat),
+    };
+
+    if image.cols == 0 || image.rows == 0 {
+        return Err(ImageError::Malformed("empty image".to_owned()));
+    }
+
+    if image.pixels.len() != image.cols * image.rows {
+        return Err(ImageError::UnexpectedEnd);
+    }
+
+    for pixel in &mut image.pixels {
+        for value in pixel {
+            *value = value.max(0.0).min(65504.0); // this also replaces NaNs by zero
+        }
+    }
+
+    Ok(image)
+}
+
+fn load_img2raw(data: &[u8]) -> Result<EnvironmentImage, ImageError> {
+    let (header, data) = match LayoutVerified::<_, Header>::new_from_prefix(data) {
+        Some(layout) => layout,
+        None => return Err(ImageError::UnexpectedEnd),
+    };
+
+    if header.data_format.try_parse() != Some(DataFormat::RGBE8) {
+        return Err(ImageError::Unsupported("expected RGBE8 data".to_owned()));
+    }
+
+    if header.color_space.try_parse() != Some(ColorSpace::LinearSRGB) {
+        return Err(ImageError::Unsupported("expected linear sRGB".to_owned()));
+    }
+
+    let cols = header.dimensions[0] as usize;
+    let rows = header.dimensions[1] as usize;
+
+    let size = (pixel_count(cols, rows)?.checked_mul(4))
+        .ok_or_else(|| ImageError::Malformed("image size overflow".to_owned()))?;
+
+    if data.len() < size {
+        return Err(ImageError::UnexpectedEnd);
+    }
+
+    let pixels = data[..size]
+        .chunks(4)
+        .map(|rgbe8| {
+            let (r, g, b) = unpack_rgbe8(rgbe8);
+
+            [r, g, b]
+        })
+        .collect();
+
+    Ok(EnvironmentImage { cols, rows, pixels })
+}
@@ -0,0 +1,60 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+/// Encodes an 8-bit sRGB image as a PNG file.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image. Scanlines are not filtered before being compressed.
+pub fn encode_png(cols: usize, rows: usize, pixels: &[[u8; 3]]) -> Vec<u8> {
+    assert_eq!(pixels.len(), cols * rows, "invalid image dimensions");
+
+    let mut output = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+    let mut header = vec![];
+    header.extend_from_slice(&(cols as u32).to_be_bytes());
+    header.extend_from_slice(&(rows as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, not interlaced
+
+    write_chunk(&mut output, b"IHDR", &header);
+    write_chunk(&mut output, b"sRGB", &[0]); // perceptual rendering intent
+
+    let mut data = Vec::with_capacity((3 * cols + 1) * rows);
+
+    for row in pixels.chunks(cols.max(1)) {
+        data.push(0); // no filtering
+
+        for pixel in row {
+            data.extend_from_slice(pixel);
+        }
+    }
+
+    write_chunk(&mut output, b"IDAT", &compress_to_vec_zlib(&data, 6));
+    write_chunk(&mut output, b"IEND", &[]);
+
+    output
+}
+
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+
+    let crc = crc32(crc32(0xffff_ffff, kind), data) ^ 0xffff_ffff;
+
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
@@ -0,0 +1,352 @@
+use crate::EnvironmentImage;
+use cgmath::Vector2;
+use std::f32::consts::PI;
+
+/// Importance sampling distribution of an equirectangular environment map.
+///
+/// Pixels are chosen with probability proportional to their luminance times
+/// the solid angle they subtend, and sampled uniformly in UV space. The PDF of
+/// each pixel is always computed from the differences between adjacent CDF
+/// values, so that it is exactly the density with which the pixel is sampled.
+///
+/// The device uploads these CDFs as single precision textures and evaluates
+/// the same computations in `environment.glsl`; keep them in sync.
+#[derive(Clone, Debug)]
+pub struct EnvironmentSampler {
+    cols: usize,
+    rows: usize,
+    marg_cdf: Vec<f32>,
+    cond_cdf: Vec<f32>,
+    average_luminance: f32,
+}
+
+impl EnvironmentSampler {
+    pub fn new(image: &EnvironmentImage) -> Self {
+        let (cols, rows) = (image.cols, image.rows);
+
+        let mut cond_cdf = Vec::with_capacity(cols * rows);
+        let mut marg_cdf = Vec::with_capacity(rows);
+
+        for (y, row) in image.pixels.chunks(cols).enumerate() {
+            let solid_angle = row_solid_angle(y, rows) / cols as f32;
+
+            let start = cond_cdf.len();
+
+            for &[r, g, b] in row {
+                cond_cdf.push(r.mul_add(0.2126, g.mul_add(0.7152, b * 0.0722)) * solid_angle);
+            }
+
+            marg_cdf.push(pdf_to_cdf(&mut cond_cdf[start..]));
+        }
+
+        let integral = pdf_to_cdf(&mut marg_cdf);
+
+        Self {
+            cols,
+            rows,
+            marg_cdf,
+            cond_cdf,
+            average_luminance: integral / (4.0 * PI),
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the marginal CDF over the rows of the environment map.
+    pub fn marg_cdf(&self) -> &[f32] {
+        &self.marg_cdf
+    }
+
+    /// Returns the conditional CDFs over each row of the environment map.
+    pub fn cond_cdf(&self) -> &[f32] {
+        &self.cond_cdf
+    }
+
+    /// Returns the average luminance of the map over the sphere of directions.
+    pub fn average_luminance(&self) -> f32 {
+        self.average_luminance
+    }
+
+    /// Samples UV coordinates, returning them along with their PDF with respect
+    /// to UV area, which is zero if the sample should be discarded.
+    pub fn sample(&self, u1: f32, u2: f32) -> (Vector2<f32>, f32) {
+        let (v, y) = inverse_transform(&self.marg_cdf, u1);
+        let (u, x) = inverse_transform(self.cond_row(y), u2);
+
+        (Vector2::new(u, v), self.pixel_pdf(x, y))
+    }
+
+    /// Returns the PDF with respect to UV area of sampling some UV coordinates.
+    pub fn pdf(&self, uv: Vector2<f32>) -> f32 {
+        let x = ((uv.x * self.cols as f32) as usize).min(self.cols - 1);
+        let y = ((uv.y * self.rows as f32) as usize).min(self.rows - 1);
+
+        self.pixel_pdf(x, y)
+    }
+
+    fn cond_row(&self, y: usize) -> &[f32] {
+        &self.cond_cdf[y * self.cols..(y + 1) * self.cols]
+    }
+
+    fn pixel_pdf(&self, x: usize, y: usize) -> f32 {
+        let marg_pdf = bucket_probability(&self.marg_cdf, y);
+        let cond_pdf = bucket_probability(self.cond_row(y), x);
+
+        marg_pdf * cond_pdf * (self.rows as f32) * (self.cols as f32)
+    }
+}
+
+/// Returns the solid angle subtended by a row of an equirectangular map.
+fn row_solid_angle(y: usize, rows: usize) -> f32 {
+    let theta0 = y as f32 / rows as f32 * PI;
+    let theta1 = (y + 1) as f32 / rows as f32 * PI;
+
+    2.0 * PI * (theta0.cos() - theta1.cos())
+}
+
+/// Converts a discrete PDF into a CDF in place with an implicit final value of
+/// 1, returning the integral of the PDF; an all-zero PDF becomes uniform.
+fn pdf_to_cdf(data: &mut [f32]) -> f32 {
+    let mut integral = 0.0;
+
+    for value in data.iter_mut() {
+        let temp = *value;
+        *value = integral;
+        integral += temp;
+    }
+
+    let count = data.len() as f32;
+
+    for (i, value) in data.iter_mut().enumerate() {
+        if integral > 0.0 {
+            *value /= integral;
+        } else {
+            *value = i as f32 / count;
+        }
+    }
+
+    integral
+}
+
+fn bucket_probability(cdf: &[f32], index: usize) -> f32 {
+    cdf.get(index + 1).copied().unwrap_or(1.0) - cdf[index]
+}
+
+/// Inverts a discrete CDF with an implicit final value of 1, returning the
+/// continuous sample in [0, 1) along with the index of the sampled bucket.
+fn inverse_transform(cdf: &[f32], u: f32) -> (f32, usize) {
+    let mut low = 0;
+    let mut high = cdf.len();
+
+    while low < high {
+        let mid = (low + high) / 2;
+
+        if cdf[mid] > u {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    let index = low.max(1) - 1;
+
+    let probability = bucket_probability(cdf, index);
+
+    let mut du = u - cdf[index];
+
+    if probability > 0.0 {
+        du /= probability;
+    }
+
+    ((index as f32 + du.min(1.0)) / cdf.len() as f32, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn image(cols: usize, rows: usize, pixel: impl Fn(usize, usize) -> f32) -> EnvironmentImage {
+        let mut pixels = Vec::with_capacity(cols * rows);
+
+        for y in 0..rows {
+            for x in 0..cols {
+                pixels.push([pixel(x, y); 3]);
+            }
+        }
+
+        EnvironmentImage { cols, rows, pixels }
+    }
+
+    fn gradient_image() -> EnvironmentImage {
+        image(32, 16, |x, y| {
+            0.1 + (x as f32 * 0.37 + y as f32 * 1.3).sin().abs()
+        })
+    }
+
+    fn sun_image() -> EnvironmentImage {
+        image(64, 32, |x, y| if (x, y) == (40, 12) { 1e6 } else { 0.5 })
+    }
+
+    fn pixel_center(sampler: &EnvironmentSampler, x: usize, y: usize) -> Vector2<f32> {
+        Vector2::new(
+            (x as f32 + 0.5) / sampler.cols() as f32,
+            (y as f32 + 0.5) / sampler.rows() as f32,
+        )
+    }
+
+    /// Integrates the PDF over the UV square, in which all pixels have equal area.
+    fn uv_integral(sampler: &EnvironmentSampler) -> f64 {
+        let mut integral = 0.0;
+
+        for y in 0..sampler.rows() {
+            for x in 0..sampler.cols() {
+                integral += f64::from(sampler.pdf(pixel_center(sampler, x, y)));
+            }
+        }
+
+        integral / (sampler.cols() * sampler.rows()) as f64
+    }
+
+    /// Integrates the PDF over the sphere of directions, converting it from a PDF
+    /// with respect to UV area using the Jacobian of the equirectangular mapping.
+    fn solid_angle_integral(sampler: &EnvironmentSampler) -> f64 {
+        let mut integral = 0.0;
+
+        for y in 0..sampler.rows() {
+            let solid_angle = row_solid_angle(y, sampler.rows()) / sampler.cols() as f32;
+
+            for x in 0..sampler.cols() {
+                let uv = pixel_center(sampler, x, y);
+                let jacobian = 2.0 * PI * PI * (uv.y * PI).sin();
+
+                integral += f64::from(sampler.pdf(uv) / jacobian * solid_angle);
+            }
+        }
+
+        integral
+    }
+
+    /// Checks that a histogram of the sampled pixels matches the PDF of each pixel.
+    fn check_histogram(sampler: &EnvironmentSampler) {
+        const SAMPLES: usize = 400_000;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut histogram = vec![0usize; sampler.cols() * sampler.rows()];
+
+        for _ in 0..SAMPLES {
+            let (uv, pdf) = sampler.sample(rng.gen(), rng.gen());
+
+            assert!(uv.x >= 0.0 && uv.x < 1.0 && uv.y >= 0.0 && uv.y < 1.0);
+            assert!(pdf > 0.0, "sampled a pixel with zero probability");
+            assert_eq!(pdf, sampler.pdf(uv));
+
+            let x = (uv.x * sampler.cols() as f32) as usize;
+            let y = (uv.y * sampler.rows() as f32) as usize;
+
+            histogram[y * sampler.cols() + x] += 1;
+        }
+
+        let area = 1.0 / (sampler.cols() * sampler.rows()) as f64;
+
+        for y in 0..sampler.rows() {
+            for x in 0..sampler.cols() {
+                let probability = f64::from(sampler.pdf(pixel_center(sampler, x, y))) * area;
+
+                let expected = probability * SAMPLES as f64;
+                let actual = histogram[y * sampler.cols() + x] as f64;
+
+                // Allow five standard deviations of the binomial count of each pixel, plus a
+                // few samples for the pixels which are only expected to be sampled rarely.
+
+                let tolerance = 5.0 * (expected * (1.0 - probability)).sqrt() + 3.0;
+
+                assert!(
+                    (actual - expected).abs() <= tolerance,
+                    "pixel ({}, {}): expected {} samples, got {}",
+                    x,
+                    y,
+                    expected,
+                    actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let sampler = EnvironmentSampler::new(&gradient_image());
+
+        assert!((uv_integral(&sampler) - 1.0).abs() < 1e-4);
+        assert!((solid_angle_integral(&sampler) - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn samples_follow_pdf() {
+        check_histogram(&EnvironmentSampler::new(&gradient_image()));
+    }
+
+    #[test]
+    fn pdf_is_proportional_to_luminance_times_solid_angle() {
+        let sampler = EnvironmentSampler::new(&image(16, 8, |_, _| 1.0));
+
+        for y in 0..sampler.rows() {
+            let expected = row_solid_angle(y, sampler.rows()) / (4.0 * PI) * sampler.rows() as f32;
+
+            for x in 0..sampler.cols() {
+                let pdf = sampler.pdf(pixel_center(&sampler, x, y));
+
+                assert!((pdf - expected).abs() < 1e-4 * expected.max(1.0));
+            }
+        }
+
+        assert!((sampler.average_luminance() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn all_zero_map_is_sampled_uniformly() {
+        let sampler = EnvironmentSampler::new(&image(16, 8, |_, _| 0.0));
+
+        assert_eq!(sampler.average_luminance(), 0.0);
+
+        for y in 0..sampler.rows() {
+            for x in 0..sampler.cols() {
+                assert!((sampler.pdf(pixel_center(&sampler, x, y)) - 1.0).abs() < 1e-5);
+            }
+        }
+
+        assert!((uv_integral(&sampler) - 1.0).abs() < 1e-5);
+
+        check_histogram(&sampler);
+    }
+
+    #[test]
+    fn single_bright_sun_pixel_is_not_clipped() {
+        let sampler = EnvironmentSampler::new(&sun_image());
+
+        assert!((uv_integral(&sampler) - 1.0).abs() < 1e-4);
+        assert!((solid_angle_integral(&sampler) - 1.0).abs() < 1e-2);
+
+        // The sun carries almost all of the energy, but the background must remain
+        // reachable with its own small probability despite single precision CDFs.
+
+        let sun_pdf = sampler.pdf(pixel_center(&sampler, 40, 12));
+        let sun_probability = sun_pdf / (sampler.cols() * sampler.rows()) as f32;
+
+        assert!(sun_probability > 0.99);
+
+        for y in 0..sampler.rows() {
+            for x in 0..sampler.cols() {
+                assert!(sampler.pdf(pixel_center(&sampler, x, y)) > 0.0);
+            }
+        }
+
+        check_histogram(&sampler);
+    }
+}
@@ -8,12 +8,20 @@ mod device {
     pub mod device;
     pub mod display;
     pub mod environment;
+    pub mod fingerprint;
     pub mod geometry;
     pub mod instance;
     pub mod integrator;
     pub mod lens_flare;
+    pub mod light;
     pub mod material;
+    pub mod mesh;
     pub mod raster;
+    pub mod readback;
+    pub mod render_job;
+    pub mod render_state;
+    pub mod sky;
+    pub mod spectrum;
 }
 
 mod engine {
@@ -24,30 +32,43 @@ mod engine {
     pub mod vertex_array;
 }
 
-mod scene {
-    pub mod aperture;
-    pub mod bounding_box;
+mod image {
+    pub mod exr;
+    pub mod hdr;
+    pub mod loader;
+    pub mod png;
+    pub mod sampler;
+}
+
+mod mesh {
+    pub mod bvh;
+    pub mod loader;
+    pub mod mesh;
+    pub mod obj;
+    pub mod ply;
+}
+
+mod reference {
     pub mod camera;
-    pub mod dirty;
-    pub mod display;
     pub mod environment;
     pub mod geometry;
-    pub mod instance;
-    pub mod integrator;
+    pub mod light;
     pub mod material;
-    pub mod metadata;
-    pub mod raster;
-    pub mod scene;
+    pub mod medium;
+    pub mod renderer;
 }
 
 pub use device::{
-    camera::*, device::*, display::*, environment::*, geometry::*, instance::*, integrator::*,
-    lens_flare::*, material::*, raster::*,
+    camera::*, device::*, display::*, environment::*, fingerprint::*, geometry::*, instance::*,
+    integrator::*, lens_flare::*, light::*, material::*, mesh::*, raster::*, readback::*,
+    render_job::*, render_state::*, sky::*, spectrum::*,
 };
 pub use engine::{framebuffer::*, shader::*, texture::*, uniform_buffer::*, vertex_array::*};
-pub use scene::{
-    aperture::*, bounding_box::*, camera::*, dirty::*, display::*, environment::*, geometry::*,
-    instance::*, integrator::*, material::*, metadata::*, raster::*, scene::*,
+pub use equinox_scene::*;
+pub use image::{exr::*, hdr::*, loader::*, png::*, sampler::*};
+pub use mesh::{bvh::*, loader::*, mesh::*, obj::*, ply::*};
+pub use reference::{
+    camera::*, environment::*, geometry::*, light::*, material::*, medium::*, renderer::*,
 };
 
 /// WebGL shaders from the `shader` directory.
@@ -99,15 +120,28 @@ impl WebScene {
     /// Reconfigures the scene using the provided scene JSON data.
     ///
     /// This method will attempt to dirty the least amount of scene data
-    /// possible, which should make later device updates more efficient.
+    /// possible, which should make later device updates more efficient. An
+    /// invalid scene is rejected with every validation error, one per line.
     pub fn set_json(&mut self, json: &JsValue) -> Result<(), JsValue> {
         let temporary: Scene = from_json(json)?;
-        temporary.validate()?;
+        temporary
+            .validate()
+            .map_err(|e| Error::new(&validation_report(&e)))?;
         self.scene.patch_from_other(temporary);
 
         Ok(())
     }
 
+    /// Returns all validation errors in the provided scene JSON data.
+    ///
+    /// Each error is an object with a `type` and a JSON pointer `path` to the
+    /// offending value; the array is empty if the scene data is valid.
+    pub fn validation_errors(json: &JsValue) -> Result<JsValue, JsValue> {
+        let temporary: Scene = from_json(json)?;
+
+        as_json(&temporary.validate().err().unwrap_or_default())
+    }
+
     /// Returns all assets which are referenced in this scene.
     pub fn assets(&self) -> Array {
         self.scene
@@ -201,6 +235,44 @@ impl WebDevice {
         Ok(self.device.present()?)
     }
 
+    /// Reads back the current render encoded as a "png", "hdr" or "exr" file.
+    ///
+    /// The PNG file contains the render exactly as presented, while the HDR and
+    /// OpenEXR files contain the linear radiance before any post-processing.
+    pub fn export_render(&mut self, format: &str) -> Result<Uint8Array, JsValue> {
+        let format = ImageFormat::from_name(format)?;
+        let data = self.device.read_render()?.encode(format);
+
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+
+    /// Saves the state of the SPPM integrator render of a scene so that it can be
+    /// resumed. The device must have been updated with the scene.
+    pub fn save_render_state(&mut self, scene: &WebScene) -> Result<Uint8Array, JsValue> {
+        let data = self.device.save_render_state(&scene.scene)?;
+
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+
+    /// Resumes an SPPM integrator render from a saved state, after the device has
+    /// been updated with the same scene as when the state was saved.
+    pub fn load_render_state(
+        &mut self,
+        scene: &WebScene,
+        data: &Uint8Array,
+    ) -> Result<(), JsValue> {
+        Ok(self
+            .device
+            .load_render_state(&scene.scene, &data.to_vec())?)
+    }
+
+    /// Returns a fingerprint of the current render of a scene, which identifies the
+    /// renderer version, the scene and its assets, the integrator seed and the number
+    /// of passes of the render.
+    pub fn render_fingerprint(&self, scene: &WebScene) -> Result<String, JsValue> {
+        Ok(self.device.render_fingerprint(&scene.scene)?)
+    }
+
     /// Returns the number of photons traced by the SPPM integrator.
     pub fn sppm_photons(&self) -> f64 {
         self.device.state.photon_count as f64
@@ -217,6 +289,57 @@ impl WebDevice {
     }
 }
 
+/// WASM wrapper for a render job.
+#[wasm_bindgen]
+pub struct WebRenderJob {
+    job: RenderJob,
+    format: ImageFormat,
+}
+
+#[wasm_bindgen]
+impl WebRenderJob {
+    /// Creates a render job from termination criterion JSON data, whose final
+    /// render is encoded as a "png", "hdr" or "exr" file.
+    #[wasm_bindgen(constructor)]
+    pub fn new(termination: &JsValue, format: &str) -> Result<WebRenderJob, JsValue> {
+        Ok(Self {
+            job: RenderJob::new(from_json(termination)?),
+            format: ImageFormat::from_name(format)?,
+        })
+    }
+
+    /// Renders a scene for at most some milliseconds, returning the encoded final
+    /// render once done and undefined until then.
+    ///
+    /// The device is first updated with the scene, which restarts the job if the
+    /// render was reset. The progress callback is called with a progress object
+    /// after every pass. This returns early if the device context is lost.
+    pub fn run(
+        &mut self,
+        device: &mut WebDevice,
+        scene: &mut WebScene,
+        assets: &Function,
+        budget_ms: f64,
+        progress: &Function,
+    ) -> Result<Option<Uint8Array>, JsValue> {
+        if device.update(scene, assets)? {
+            self.job.restart();
+        }
+
+        let mut result = Ok(JsValue::NULL);
+
+        let render = self.job.advance(&mut device.device, budget_ms, |data| {
+            if result.is_ok() {
+                result = as_json(data).and_then(|data| progress.call1(&JsValue::NULL, &data));
+            }
+        })?;
+
+        result?;
+
+        Ok(render.map(|render| Uint8Array::from(render.encode(self.format).as_slice())))
+    }
+}
+
 #[allow(dead_code)]
 mod build_metadata {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
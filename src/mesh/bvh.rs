@@ -0,0 +1,464 @@
+use crate::{BoundingBox, TriangleMesh};
+use cgmath::prelude::*;
+use cgmath::Point3;
+
+/// Node in a mesh BVH.
+///
+/// Nodes are laid out in depth-first order such that the left child of every
+/// interior node immediately follows it. The `skip` index points to the next
+/// node to visit when the ray misses this node, and is equal to the number of
+/// nodes in the hierarchy if there are no more nodes to visit afterwards.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshNode {
+    pub bbox: BoundingBox,
+    pub skip: u32,
+    /// First triangle of this node, as an index into the triangle ordering.
+    pub first: u32,
+    /// Number of triangles in this node, or zero if this is an interior node.
+    pub count: u32,
+}
+
+impl MeshNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count != 0
+    }
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh.
+#[derive(Clone, Debug)]
+pub struct MeshHierarchy {
+    pub nodes: Vec<MeshNode>,
+    /// Mesh triangle indices ordered such that every leaf node refers to a
+    /// contiguous range of triangles within this list.
+    pub triangles: Vec<u32>,
+}
+
+impl MeshHierarchy {
+    /// Maximum number of triangles in a leaf node.
+    pub const MAX_LEAF_TRIANGLES: usize = 4;
+
+    /// Builds a BVH for a mesh, which must contain at least one triangle.
+    pub fn build(mesh: &TriangleMesh) -> Self {
+        assert!(!mesh.triangles.is_empty());
+
+        let mut builder = MeshHierarchyBuilder::new(mesh);
+        let mut triangles: Vec<u32> = (0..mesh.triangles.len() as u32).collect();
+
+        builder.build_recursive(0, &mut triangles);
+
+        Self {
+            nodes: builder.nodes,
+            triangles,
+        }
+    }
+
+    /// Returns the bounding box of the entire hierarchy.
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.nodes[0].bbox
+    }
+}
+
+const SPLIT_BINS: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+struct SplitBin {
+    pub bbox: BoundingBox,
+    pub count: usize,
+}
+
+impl Default for SplitBin {
+    fn default() -> Self {
+        Self {
+            bbox: BoundingBox::neg_infinity_bounds(),
+            count: 0,
+        }
+    }
+}
+
+/// Builds a mesh BVH using the binned surface area heuristic.
+struct MeshHierarchyBuilder {
+    bboxes: Vec<BoundingBox>,
+    centroids: Vec<Point3<f32>>,
+    nodes: Vec<MeshNode>,
+}
+
+impl MeshHierarchyBuilder {
+    pub fn new(mesh: &TriangleMesh) -> Self {
+        let bboxes: Vec<BoundingBox> = (0..mesh.triangles.len())
+            .map(|triangle| mesh.triangle_bounding_box(triangle))
+            .collect();
+
+        let centroids = (bboxes.iter())
+            .map(|bbox| bbox.min.midpoint(bbox.max))
+            .collect();
+
+        Self {
+            bboxes,
+            centroids,
+            nodes: vec![],
+        }
+    }
+
+    fn build_recursive(&mut self, first: usize, triangles: &mut [u32]) {
+        let bbox = BoundingBox::from_extents(triangles.iter().map(|&i| self.bboxes[i as usize]));
+
+        let current = self.nodes.len();
+
+        self.nodes.push(MeshNode {
+            bbox,
+            skip: 0,
+            first: first as u32,
+            count: 0,
+        });
+
+        if triangles.len() <= MeshHierarchy::MAX_LEAF_TRIANGLES {
+            self.nodes[current].count = triangles.len() as u32;
+            self.nodes[current].skip = current as u32 + 1;
+
+            return;
+        }
+
+        let split = self.partition(triangles);
+        let (lhs, rhs) = triangles.split_at_mut(split);
+
+        self.build_recursive(first, lhs);
+        self.build_recursive(first + split, rhs);
+
+        self.nodes[current].skip = self.nodes.len() as u32;
+    }
+
+    /// Reorders the triangles and returns the split position, which is chosen
+    /// to minimize the surface area heuristic along the widest centroid axis.
+    fn partition(&self, triangles: &mut [u32]) -> usize {
+        let centroid_bbox = BoundingBox::from_extents(
+            (triangles.iter()).map(|&i| BoundingBox::from_point(self.centroids[i as usize])),
+        );
+
+        let extent = centroid_bbox.max - centroid_bbox.min;
+        let mut axis = 0;
+
+        if extent.y > extent[axis] {
+            axis = 1;
+        }
+
+        if extent.z > extent[axis] {
+            axis = 2;
+        }
+
+        if extent[axis] <= 0.0 {
+            return triangles.len() / 2; // all centroids coincide
+        }
+
+        let bin_index = |triangle: u32| {
+            let offset = self.centroids[triangle as usize][axis] - centroid_bbox.min[axis];
+            let bin = (offset / extent[axis] * SPLIT_BINS as f32) as usize;
+
+            bin.min(SPLIT_BINS - 1)
+        };
+
+        let mut bins = [SplitBin::default(); SPLIT_BINS];
+
+        for &triangle in triangles.iter() {
+            let bin = &mut bins[bin_index(triangle)];
+
+            bin.bbox.extend(&self.bboxes[triangle as usize]);
+            bin.count += 1;
+        }
+
+        let mut best_cost = std::f32::INFINITY;
+        let mut best_bin = 0;
+
+        for split in 1..SPLIT_BINS {
+            let (lhs, rhs) = bins.split_at(split);
+
+            let lhs_count: usize = lhs.iter().map(|bin| bin.count).sum();
+            let rhs_count: usize = rhs.iter().map(|bin| bin.count).sum();
+
+            if lhs_count == 0 || rhs_count == 0 {
+                continue;
+            }
+
+            let lhs_bbox = BoundingBox::from_extents(lhs.iter().map(|bin| bin.bbox));
+            let rhs_bbox = BoundingBox::from_extents(rhs.iter().map(|bin| bin.bbox));
+
+            let cost = lhs_bbox.surface_area() * lhs_count as f32
+                + rhs_bbox.surface_area() * rhs_count as f32;
+
+            if cost < best_cost {
+                best_cost = cost;
+                best_bin = split;
+            }
+        }
+
+        if best_cost.is_infinite() {
+            return triangles.len() / 2; // degenerate binning
+        }
+
+        triangles.sort_unstable_by_key(|&triangle| bin_index(triangle) >= best_bin);
+        triangles
+            .iter()
+            .filter(|&&i| bin_index(i) < best_bin)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Point3, Vector3};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn random_mesh(rng: &mut StdRng, count: usize) -> TriangleMesh {
+        let mut mesh = TriangleMesh::default();
+
+        for triangle in 0..count as u32 {
+            let center: [f32; 3] = rng.gen();
+
+            for _ in 0..3 {
+                let offset: [f32; 3] = rng.gen();
+
+                mesh.positions.push([
+                    center[0] * 10.0 + offset[0] - 0.5,
+                    center[1] * 10.0 + offset[1] - 0.5,
+                    center[2] * 10.0 + offset[2] - 0.5,
+                ]);
+            }
+
+            mesh.triangles
+                .push([3 * triangle, 3 * triangle + 1, 3 * triangle + 2]);
+        }
+
+        mesh
+    }
+
+    fn contains(outer: &BoundingBox, inner: &BoundingBox) -> bool {
+        (0..3).all(|i| outer.min[i] <= inner.min[i] && inner.max[i] <= outer.max[i])
+    }
+
+    /// Checks the skip indices, the leaf sizes, that every triangle is in exactly
+    /// one leaf and that every node contains the bounding boxes of its triangles.
+    fn check_hierarchy(mesh: &TriangleMesh, hierarchy: &MeshHierarchy) {
+        let mut seen = vec![0; mesh.triangles.len()];
+        let mut sorted = hierarchy.triangles.clone();
+
+        sorted.sort_unstable();
+        assert!(sorted.iter().copied().eq(0..mesh.triangles.len() as u32));
+
+        for (index, node) in hierarchy.nodes.iter().enumerate() {
+            assert!(node.skip as usize > index);
+            assert!(node.skip as usize <= hierarchy.nodes.len());
+
+            if node.is_leaf() {
+                assert!(node.count as usize <= MeshHierarchy::MAX_LEAF_TRIANGLES);
+                assert_eq!(node.skip as usize, index + 1);
+
+                let first = node.first as usize;
+
+                for &triangle in &hierarchy.triangles[first..first + node.count as usize] {
+                    seen[triangle as usize] += 1;
+                }
+            }
+
+            let first = node.first as usize;
+            let last = match hierarchy.nodes.get(node.skip as usize) {
+                Some(next) => next.first as usize,
+                None => hierarchy.triangles.len(),
+            };
+
+            for &triangle in &hierarchy.triangles[first..last] {
+                let bbox = mesh.triangle_bounding_box(triangle as usize);
+                assert!(contains(&node.bbox, &bbox));
+            }
+        }
+
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    fn ray_hits_bbox(org: Point3<f32>, dir: Vector3<f32>, bbox: &BoundingBox) -> bool {
+        let mut tmin = 0.0f32;
+        let mut tmax = std::f32::INFINITY;
+
+        for i in 0..3 {
+            let t0 = (bbox.min[i] - org[i]) / dir[i];
+            let t1 = (bbox.max[i] - org[i]) / dir[i];
+
+            tmin = tmin.max(t0.min(t1));
+            tmax = tmax.min(t0.max(t1));
+        }
+
+        tmin <= tmax
+    }
+
+    fn ray_triangle(
+        mesh: &TriangleMesh,
+        triangle: usize,
+        org: Point3<f32>,
+        dir: Vector3<f32>,
+    ) -> Option<f32> {
+        let [v0, v1, v2] = mesh.vertices(triangle);
+
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+        let p = dir.cross(e2);
+        let det = e1.dot(p);
+
+        if det == 0.0 {
+            return None;
+        }
+
+        let s = org - v0;
+        let q = s.cross(e1);
+
+        let u = s.dot(p) / det;
+        let v = dir.dot(q) / det;
+        let t = e2.dot(q) / det;
+
+        if u < 0.0 || v < 0.0 || u + v > 1.0 || t < 0.0 {
+            None
+        } else {
+            Some(t)
+        }
+    }
+
+    /// Traverses the hierarchy in the same way as the mesh shader.
+    fn traverse(
+        mesh: &TriangleMesh,
+        hierarchy: &MeshHierarchy,
+        org: Point3<f32>,
+        dir: Vector3<f32>,
+    ) -> Option<f32> {
+        let mut closest: Option<f32> = None;
+        let mut index = 0;
+
+        while index != hierarchy.nodes.len() {
+            let node = &hierarchy.nodes[index];
+
+            if ray_hits_bbox(org, dir, &node.bbox) {
+                let first = node.first as usize;
+
+                for &triangle in &hierarchy.triangles[first..first + node.count as usize] {
+                    if let Some(t) = ray_triangle(mesh, triangle as usize, org, dir) {
+                        closest = Some(closest.map_or(t, |c| c.min(t)));
+                    }
+                }
+
+                index += 1;
+            } else {
+                index = node.skip as usize;
+            }
+        }
+
+        closest
+    }
+
+    fn brute_force(mesh: &TriangleMesh, org: Point3<f32>, dir: Vector3<f32>) -> Option<f32> {
+        (0..mesh.triangles.len())
+            .filter_map(|triangle| ray_triangle(mesh, triangle, org, dir))
+            .fold(None, |closest, t| {
+                Some(closest.map_or(t, |c: f32| c.min(t)))
+            })
+    }
+
+    #[test]
+    fn hierarchy_is_well_formed() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for &count in &[1, 4, 5, 37, 500] {
+            let mesh = random_mesh(&mut rng, count);
+            let hierarchy = MeshHierarchy::build(&mesh);
+
+            check_hierarchy(&mesh, &hierarchy);
+        }
+    }
+
+    #[test]
+    fn surface_area_heuristic_separates_clusters() {
+        let mut mesh = TriangleMesh::default();
+
+        for triangle in 0..16u32 {
+            let x = if triangle % 2 == 0 { 0.0 } else { 100.0 };
+            let y = triangle as f32 * 0.01;
+
+            mesh.positions.push([x, y, 0.0]);
+            mesh.positions.push([x + 1.0, y, 0.0]);
+            mesh.positions.push([x, y + 1.0, 0.0]);
+            mesh.triangles
+                .push([3 * triangle, 3 * triangle + 1, 3 * triangle + 2]);
+        }
+
+        let hierarchy = MeshHierarchy::build(&mesh);
+        check_hierarchy(&mesh, &hierarchy);
+
+        let lhs = &hierarchy.nodes[1];
+        let rhs = &hierarchy.nodes[hierarchy.nodes[1].skip as usize];
+
+        assert!(lhs.bbox.max.x <= 1.0);
+        assert!(rhs.bbox.min.x >= 100.0);
+    }
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mesh = random_mesh(&mut rng, 300);
+        let hierarchy = MeshHierarchy::build(&mesh);
+
+        let mut hits = 0;
+
+        for _ in 0..1000 {
+            let org: [f32; 3] = rng.gen();
+            let target: [f32; 3] = rng.gen();
+
+            let org = Point3::new(org[0] * 20.0 - 5.0, org[1] * 20.0 - 5.0, -10.0);
+            let target = Point3::new(target[0] * 10.0, target[1] * 10.0, target[2] * 10.0);
+            let dir = (target - org).normalize();
+
+            let expected = brute_force(&mesh, org, dir);
+
+            assert_eq!(traverse(&mesh, &hierarchy, org, dir), expected);
+
+            if expected.is_some() {
+                hits += 1;
+            }
+        }
+
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn flat_and_coincident_triangles_are_supported() {
+        let mut flat = TriangleMesh::default();
+
+        for triangle in 0..64u32 {
+            let x = (triangle % 8) as f32;
+            let z = (triangle / 8) as f32;
+
+            flat.positions.push([x, 0.0, z]);
+            flat.positions.push([x + 1.0, 0.0, z]);
+            flat.positions.push([x, 0.0, z + 1.0]);
+            flat.triangles
+                .push([3 * triangle, 3 * triangle + 1, 3 * triangle + 2]);
+        }
+
+        let hierarchy = MeshHierarchy::build(&flat);
+        check_hierarchy(&flat, &hierarchy);
+
+        let org = Point3::new(2.25, 5.0, 3.25);
+        let dir = Vector3::new(0.0, -1.0, 0.0);
+
+        assert_eq!(traverse(&flat, &hierarchy, org, dir), Some(5.0));
+
+        let mut coincident = TriangleMesh::default();
+        coincident.positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        coincident.triangles = vec![[0, 1, 2]; 20];
+
+        let hierarchy = MeshHierarchy::build(&coincident);
+        check_hierarchy(&coincident, &hierarchy);
+
+        let mut degenerate = TriangleMesh::default();
+        degenerate.positions = vec![[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]];
+        degenerate.triangles = vec![[0, 1, 2], [0, 0, 0], [2, 1, 0], [1, 1, 2], [0, 2, 2]];
+
+        let hierarchy = MeshHierarchy::build(&degenerate);
+        check_hierarchy(&degenerate, &hierarchy);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{load_obj, load_ply, TriangleMesh};
+use std::fmt::{self, Display, Formatter};
+
+/// Error encountered while loading a mesh asset.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshError {
+    /// The mesh format could not be determined from the asset name.
+    UnknownFormat,
+    /// The mesh data ended before the expected end of the asset.
+    UnexpectedEnd,
+    /// The mesh data is well-formed but contains no triangles.
+    NoTriangles,
+    /// The mesh data contained an invalid value.
+    Malformed(String),
+    /// The mesh data contained a syntax error on a specific line.
+    Syntax { line: usize, message: String },
+}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "unknown mesh format"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of mesh data"),
+            Self::NoTriangles => write!(f, "mesh contains no triangles"),
+            Self::Malformed(message) => write!(f, "malformed mesh data: {}", message),
+            Self::Syntax { line, message } => {
+                write!(f, "syntax error on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// Parses a mesh asset into a triangle mesh.
+///
+/// The mesh format is determined by the extension of the asset name, and the
+/// returned mesh is guaranteed to contain at least one triangle and to have
+/// finite vertex attributes and in-bounds vertex indices. Degenerate triangles
+/// have no area and no well-defined normal, so they are removed.
+pub fn load_mesh(name: &str, data: &[u8]) -> Result<TriangleMesh, MeshError> {
+    let extension = name.rsplit('.').next().unwrap_or("");
+
+    let mut mesh = match extension.to_ascii_lowercase().as_str() {
+        "obj" => load_obj(data)?,
+        "ply" => load_ply(data)?,
+        _ if data.starts_with(b"ply") => load_ply(data)?,
+        _ => return Err(MeshError::UnknownFormat),
+    };
+
+    validate_mesh(&mesh)?;
+
+    mesh.triangles = (0..mesh.triangles.len())
+        .filter(|&triangle| mesh.face_normal(triangle).is_some())
+        .map(|triangle| mesh.triangles[triangle])
+        .collect();
+
+    if mesh.triangles.is_empty() {
+        return Err(MeshError::NoTriangles);
+    }
+
+    Ok(mesh)
+}
+
+fn validate_mesh(mesh: &TriangleMesh) -> Result<(), MeshError> {
+    if mesh.triangles.is_empty() {
+        return Err(MeshError::NoTriangles);
+    }
+
+    if !mesh.normals.is_empty() && mesh.normals.len() != mesh.positions.len() {
+        return Err(MeshError::Malformed(
+            "vertex normal count mismatch".to_owned(),
+        ));
+    }
+
+    if !mesh.uvs.is_empty() && mesh.uvs.len() != mesh.positions.len() {
+        return Err(MeshError::Malformed("vertex UV count mismatch".to_owned()));
+    }
+
+    for position in &mesh.positions {
+        if !position.iter().all(|x| x.is_finite()) {
+            return Err(MeshError::Malformed(
+                "non-finite vertex position".to_owned(),
+            ));
+        }
+    }
+
+    for normal in &mesh.normals {
+        if !normal.iter().all(|x| x.is_finite()) {
+            return Err(MeshError::Malformed("non-finite vertex normal".to_owned()));
+        }
+    }
+
+    for uv in &mesh.uvs {
+        if !uv.iter().all(|x| x.is_finite()) {
+            return Err(MeshError::Malformed("non-finite vertex UV".to_owned()));
+        }
+    }
+
+    for triangle in &mesh.triangles {
+        if triangle.iter().any(|&i| i as usize >= mesh.positions.len()) {
+            return Err(MeshError::Malformed(
+                "vertex index out of bounds".to_owned(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_determined_from_the_name() {
+        let obj = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+        assert!(load_mesh("models/triangle.OBJ", obj).is_ok());
+        assert_eq!(
+            load_mesh("triangle.glb", obj),
+            Err(MeshError::UnknownFormat)
+        );
+        assert_eq!(load_mesh("triangle", obj), Err(MeshError::UnknownFormat));
+    }
+
+    #[test]
+    fn invalid_meshes_are_rejected() {
+        assert_eq!(
+            load_mesh("empty.obj", b"v 0 0 0\n"),
+            Err(MeshError::NoTriangles)
+        );
+
+        assert!(matches!(
+            load_mesh("nan.obj", b"v 0 0 NaN\nv 1 0 0\nv 0 1 0\nf 1 2 3\n"),
+            Err(MeshError::Malformed(_))
+        ));
+
+        assert!(matches!(
+            load_mesh(
+                "inf.obj",
+                b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 inf 1\nf 1//1 2//1 3//1\n"
+            ),
+            Err(MeshError::Malformed(_))
+        ));
+
+        let out_of_bounds = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+
+        assert!(matches!(
+            load_mesh("mesh.ply", out_of_bounds.as_bytes()),
+            Err(MeshError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn degenerate_triangles_are_removed() {
+        let mesh = load_mesh(
+            "mesh.obj",
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\nf 3 3 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+
+        assert_eq!(
+            load_mesh("line.obj", b"v 0 0 0\nv 1 0 0\nv 2 0 0\nf 1 2 3\n"),
+            Err(MeshError::NoTriangles)
+        );
+    }
+
+    #[test]
+    fn vertex_normals_are_always_finite() {
+        let mesh = load_mesh(
+            "mesh.obj",
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 2\nvn 0 0 0\nf 1//1 2//2 3//1\n",
+        )
+        .unwrap();
+
+        let normals = mesh.vertex_normals(0);
+
+        assert_eq!(normals[0], [0.0, 0.0, 1.0].into());
+        assert_eq!(normals[1], mesh.face_normal(0).unwrap());
+        assert_eq!(normals[2], [0.0, 0.0, 1.0].into());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::BoundingBox;
+use cgmath::{InnerSpace, Point3, Vector3, Zero};
+
+/// Indexed triangle mesh.
+///
+/// Triangles are stored as triples of vertex indices, with a counter-clockwise
+/// winding order defining the outward facing direction. The vertex normals and
+/// UVs are each either empty, or have one element for every vertex position.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl TriangleMesh {
+    /// Returns the three vertices of a triangle in this mesh.
+    pub fn vertices(&self, triangle: usize) -> [Point3<f32>; 3] {
+        let [a, b, c] = self.triangles[triangle];
+
+        [
+            self.positions[a as usize].into(),
+            self.positions[b as usize].into(),
+            self.positions[c as usize].into(),
+        ]
+    }
+
+    /// Returns the unit normal of a triangle in this mesh, or `None` if the
+    /// triangle is degenerate and has no well-defined normal.
+    pub fn face_normal(&self, triangle: usize) -> Option<Vector3<f32>> {
+        let [a, b, c] = self.vertices(triangle);
+
+        normalize((b - a).cross(c - a))
+    }
+
+    /// Returns the unit normals at the three vertices of a triangle in this
+    /// mesh, falling back to the face normal wherever the mesh has no vertex
+    /// normal or the vertex normal has zero length.
+    pub fn vertex_normals(&self, triangle: usize) -> [Vector3<f32>; 3] {
+        let face_normal = self.face_normal(triangle).unwrap_or_else(Vector3::zero);
+
+        if self.normals.is_empty() {
+            return [face_normal; 3];
+        }
+
+        let [a, b, c] = self.triangles[triangle];
+
+        let normal = |vertex: u32| -> Vector3<f32> {
+            normalize(self.normals[vertex as usize].into()).unwrap_or(face_normal)
+        };
+
+        [normal(a), normal(b), normal(c)]
+    }
+
+    /// Returns the total surface area of the triangles in this mesh.
+    pub fn surface_area(&self) -> f32 {
+        (0..self.triangles.len())
+            .map(|triangle| {
+                let [a, b, c] = self.vertices(triangle);
+
+                0.5 * (b - a).cross(c - a).magnitude()
+            })
+            .sum()
+    }
+
+    /// Returns the bounding box of a triangle in this mesh.
+    pub fn triangle_bounding_box(&self, triangle: usize) -> BoundingBox {
+        let [a, b, c] = self.vertices(triangle);
+
+        let mut bbox = BoundingBox::from_point(a);
+        bbox.extend(&BoundingBox::from_point(b));
+        bbox.extend(&BoundingBox::from_point(c));
+
+        bbox
+    }
+
+    /// Returns the bounding box of every triangle in this mesh.
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_extents((0..self.triangles.len()).map(|i| self.triangle_bounding_box(i)))
+    }
+}
+
+fn normalize(vector: Vector3<f32>) -> Option<Vector3<f32>> {
+    let length = vector.magnitude();
+
+    if length > 0.0 && length.is_finite() {
+        Some(vector / length)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{MeshError, TriangleMesh};
+use std::collections::HashMap;
+use std::str::{from_utf8, FromStr, SplitWhitespace};
+
+/// Parses a Wavefront OBJ file into a triangle mesh.
+///
+/// Only the geometry is loaded, polygonal faces are triangulated as fans and
+/// all other statements such as groups and material libraries are ignored.
+/// Vertex normals and UVs are kept only if every face vertex has them.
+pub fn load_obj(data: &[u8]) -> Result<TriangleMesh, MeshError> {
+    let text = from_utf8(data).map_err(|_| MeshError::Malformed("invalid UTF-8".to_owned()))?;
+
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+
+    // OBJ faces index positions, normals and UVs separately, so every distinct
+    // combination of attributes needs to become its own vertex in the mesh.
+
+    let mut vertex_map: HashMap<FaceVertex, u32> = HashMap::new();
+    let mut vertices: Vec<FaceVertex> = vec![];
+    let mut triangles = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let mut parser = LineParser::new(index + 1, line);
+
+        match parser.tokens.next() {
+            Some("v") => positions.push([parser.parse()?, parser.parse()?, parser.parse()?]),
+            Some("vn") => normals.push([parser.parse()?, parser.parse()?, parser.parse()?]),
+            Some("vt") => uvs.push([parser.parse()?, parser.parse_or(0.0)?]),
+            Some("f") => {
+                let mut face = vec![];
+
+                while let Some(token) = parser.tokens.next() {
+                    let vertex = parser.face_vertex(token, &positions, &normals, &uvs)?;
+
+                    face.push(*vertex_map.entry(vertex).or_insert_with(|| {
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    }));
+                }
+
+                if face.len() < 3 {
+                    return Err(parser.error("face has fewer than three vertices"));
+                }
+
+                for i in 1..face.len() - 1 {
+                    triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut mesh = TriangleMesh {
+        positions: vertices.iter().map(|v| positions[v.position]).collect(),
+        triangles,
+        ..Default::default()
+    };
+
+    if vertices.iter().all(|v| v.normal.is_some()) {
+        mesh.normals = vertices
+            .iter()
+            .map(|v| normals[v.normal.unwrap()])
+            .collect();
+    }
+
+    if vertices.iter().all(|v| v.uv.is_some()) {
+        mesh.uvs = vertices.iter().map(|v| uvs[v.uv.unwrap()]).collect();
+    }
+
+    Ok(mesh)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    position: usize,
+    normal: Option<usize>,
+    uv: Option<usize>,
+}
+
+struct LineParser<'a> {
+    line: usize,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> LineParser<'a> {
+    pub fn new(line: usize, text: &'a str) -> Self {
+        let text = text.split('#').next().unwrap_or("");
+
+        Self {
+            line,
+            tokens: text.split_whitespace(),
+        }
+    }
+
+    pub fn error(&self, message: impl ToString) -> MeshError {
+        MeshError::Syntax {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn parse<T: FromStr>(&mut self) -> Result<T, MeshError> {
+        match self.tokens.next() {
+            Some(token) => self.parse_token(token),
+            None => Err(self.error("missing value")),
+        }
+    }
+
+    pub fn parse_or<T: FromStr>(&mut self, default: T) -> Result<T, MeshError> {
+        match self.tokens.next() {
+            Some(token) => self.parse_token(token),
+            None => Ok(default),
+        }
+    }
+
+    fn parse_token<T: FromStr>(&self, token: &str) -> Result<T, MeshError> {
+        token
+            .parse()
+            .map_err(|_| self.error(format!("invalid value `{}'", token)))
+    }
+
+    /// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex, and resolves
+    /// all relative (negative) indices into absolute zero-based indices.
+    pub fn face_vertex(
+        &self,
+        token: &str,
+        positions: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        uvs: &[[f32; 2]],
+    ) -> Result<FaceVertex, MeshError> {
+        let mut indices = token.split('/');
+
+        let position = match indices.next() {
+            Some(index) => self.resolve_index(index, positions.len())?,
+            None => return Err(self.error("missing vertex index")),
+        };
+
+        let uv = match indices.next() {
+            Some("") | None => None,
+            Some(index) => Some(self.resolve_index(index, uvs.len())?),
+        };
+
+        let normal = match indices.next() {
+            Some("") | None => None,
+            Some(index) => Some(self.resolve_index(index, normals.len())?),
+        };
+
+        if indices.next().is_some() {
+            return Err(self.error(format!("invalid face vertex `{}'", token)));
+        }
+
+        Ok(FaceVertex {
+            position,
+            normal,
+            uv,
+        })
+    }
+
+    fn resolve_index(&self, index: &str, count: usize) -> Result<usize, MeshError> {
+        let value: i64 = self.parse_token(index)?;
+
+        let resolved = if value > 0 {
+            value - 1
+        } else {
+            count as i64 + value
+        };
+
+        if value == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("index `{}' out of bounds", index)));
+        }
+
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syntax_error_line(data: &str) -> usize {
+        match load_obj(data.as_bytes()) {
+            Err(MeshError::Syntax { line, .. }) => line,
+            result => panic!("expected syntax error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let mesh =
+            load_obj(b"# unit square\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng square\nf 1 2 3 4\n")
+                .unwrap();
+
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn negative_indices_are_relative_to_the_end() {
+        let relative = load_obj(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").unwrap();
+        let absolute = load_obj(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+
+        assert_eq!(relative, absolute);
+    }
+
+    #[test]
+    fn shared_face_vertices_are_deduplicated() {
+        let mesh = load_obj(b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n").unwrap();
+
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn normals_are_split_per_face_vertex() {
+        let mesh = load_obj(
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nvn 0 0 -1\nf 1/1/1 2/1/1 3/1/1\nf 1//2 3//2 2//2\n",
+        )
+        .unwrap();
+
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.normals.len(), 6);
+        assert_eq!(mesh.normals[0], [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.normals[3], [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn normals_are_dropped_unless_every_vertex_has_one() {
+        let mesh = load_obj(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3\n").unwrap();
+
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn uvs_are_split_per_face_vertex() {
+        let mesh = load_obj(
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0 0\nvt 0.5\nf 1/1 2/2 3/3\nf 1/3 3/2 2/1\n",
+        )
+        .unwrap();
+
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.uvs.len(), 6);
+        assert_eq!(mesh.uvs[1], [1.0, 0.0]);
+        assert_eq!(mesh.uvs[2], [0.5, 0.0]);
+        assert_eq!(mesh.uvs[3], [0.5, 0.0]);
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn uvs_are_dropped_unless_every_vertex_has_one() {
+        let mesh = load_obj(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nf 1/1 2/1 3//\n").unwrap();
+
+        assert!(mesh.uvs.is_empty());
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        assert_eq!(syntax_error_line("v 0 0 0\nv 1 0 zero\n"), 2);
+        assert_eq!(syntax_error_line("v 0 0\n"), 1);
+        assert_eq!(
+            syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n"),
+            5
+        );
+        assert_eq!(syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n"), 4);
+        assert_eq!(
+            syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 1 2\n"),
+            4
+        );
+        assert_eq!(syntax_error_line("v 0 0 0\nv 1 0 0\nf 1 2\n"), 3);
+        assert_eq!(
+            syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3//1\n"),
+            4
+        );
+        assert_eq!(
+            syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2 3\n"),
+            4
+        );
+        assert_eq!(
+            syntax_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3/1/1/1\n"),
+            4
+        );
+
+        assert!(matches!(
+            load_obj(b"v 0 0 0\xff\n"),
+            Err(MeshError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::{MeshError, TriangleMesh};
+use std::convert::TryInto;
+use std::str::from_utf8;
+
+/// Parses an ASCII or binary PLY file into a triangle mesh.
+///
+/// Vertex positions, normals and UVs are read from the `vertex` element and
+/// polygonal faces from the `face` element are triangulated as fans. Every
+/// other element and property is parsed according to its type and ignored.
+pub fn load_ply(data: &[u8]) -> Result<TriangleMesh, MeshError> {
+    let (header, body) = PlyHeader::parse(data)?;
+
+    let mut reader = match header.format {
+        PlyFormat::Ascii => {
+            let text =
+                from_utf8(body).map_err(|_| MeshError::Malformed("invalid UTF-8".to_owned()))?;
+
+            PlyReader::Ascii(text.split_whitespace())
+        }
+        PlyFormat::BinaryLittleEndian => PlyReader::Binary(body, false),
+        PlyFormat::BinaryBigEndian => PlyReader::Binary(body, true),
+    };
+
+    let mut mesh = TriangleMesh::default();
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => load_vertices(&mut reader, element, &mut mesh)?,
+            "face" => load_faces(&mut reader, element, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        reader.skip_property(property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn load_vertices(
+    reader: &mut PlyReader,
+    element: &PlyElement,
+    mesh: &mut TriangleMesh,
+) -> Result<(), MeshError> {
+    let position = [
+        element.property_index(&["x"]),
+        element.property_index(&["y"]),
+        element.property_index(&["z"]),
+    ];
+
+    let normal = [
+        element.property_index(&["nx"]),
+        element.property_index(&["ny"]),
+        element.property_index(&["nz"]),
+    ];
+
+    let uv = [
+        element.property_index(&["u", "s", "texture_u", "texture_s"]),
+        element.property_index(&["v", "t", "texture_v", "texture_t"]),
+    ];
+
+    if position.iter().any(Option::is_none) {
+        return Err(MeshError::Malformed("missing vertex position".to_owned()));
+    }
+
+    let has_normals = normal.iter().all(Option::is_some);
+    let has_uvs = uv.iter().all(Option::is_some);
+
+    let mut values = vec![0.0; element.properties.len()];
+
+    for _ in 0..element.count {
+        for (value, property) in values.iter_mut().zip(&element.properties) {
+            *value = reader.read_scalar_property(property)? as f32;
+        }
+
+        mesh.positions.push([
+            values[position[0].unwrap()],
+            values[position[1].unwrap()],
+            values[position[2].unwrap()],
+        ]);
+
+        if has_normals {
+            mesh.normals.push([
+                values[normal[0].unwrap()],
+                values[normal[1].unwrap()],
+                values[normal[2].unwrap()],
+            ]);
+        }
+
+        if has_uvs {
+            mesh.uvs
+                .push([values[uv[0].unwrap()], values[uv[1].unwrap()]]);
+        }
+    }
+
+    Ok(())
+}
+
+fn load_faces(
+    reader: &mut PlyReader,
+    element: &PlyElement,
+    mesh: &mut TriangleMesh,
+) -> Result<(), MeshError> {
+    let indices = element.property_index(&["vertex_indices", "vertex_index"]);
+
+    let indices = match indices {
+        Some(index) => index,
+        None => return Err(MeshError::Malformed("missing face indices".to_owned())),
+    };
+
+    for _ in 0..element.count {
+        for (index, property) in element.properties.iter().enumerate() {
+            if index != indices {
+                reader.skip_property(property)?;
+                continue;
+            }
+
+            let face = match property {
+                PlyProperty::List { count, item, .. } => {
+                    let len = reader.read(*count)? as usize;
+                    let mut face = vec![];
+
+                    for _ in 0..len {
+                        let value = reader.read(*item)?;
+
+                        if value < 0.0 || value > f64::from(u32::max_value()) {
+                            return Err(MeshError::Malformed("invalid face index".to_owned()));
+                        }
+
+                        face.push(value as u32);
+                    }
+
+                    face
+                }
+                PlyProperty::Scalar { .. } => {
+                    return Err(MeshError::Malformed(
+                        "face indices must be a list".to_owned(),
+                    ));
+                }
+            };
+
+            if face.len() < 3 {
+                return Err(MeshError::Malformed(
+                    "face has fewer than three vertices".to_owned(),
+                ));
+            }
+
+            for i in 1..face.len() - 1 {
+                mesh.triangles.push([face[0], face[i], face[i + 1]]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl PlyType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Self::Char),
+            "uchar" | "uint8" => Some(Self::UChar),
+            "short" | "int16" => Some(Self::Short),
+            "ushort" | "uint16" => Some(Self::UShort),
+            "int" | "int32" => Some(Self::Int),
+            "uint" | "uint32" => Some(Self::UInt),
+            "float" | "float32" => Some(Self::Float),
+            "double" | "float64" => Some(Self::Double),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            Self::Char | Self::UChar => 1,
+            Self::Short | Self::UShort => 2,
+            Self::Int | Self::UInt | Self::Float => 4,
+            Self::Double => 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PlyProperty {
+    Scalar {
+        name: String,
+        value: PlyType,
+    },
+    List {
+        name: String,
+        count: PlyType,
+        item: PlyType,
+    },
+}
+
+impl PlyProperty {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Scalar { name, .. } | Self::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    pub fn property_index(&self, names: &[&str]) -> Option<usize> {
+        (self.properties.iter()).position(|property| names.contains(&property.name()))
+    }
+}
+
+#[derive(Debug)]
+struct PlyHeader {
+    format: PlyFormat,
+    elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    /// Parses the PLY header and returns it along with the remaining data.
+    pub fn parse(data: &[u8]) -> Result<(Self, &[u8]), MeshError> {
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = vec![];
+        let mut offset = 0;
+
+        for line_number in 1.. {
+            let end = match data[offset..].iter().position(|&c| c == b'\n') {
+                Some(end) => offset + end,
+                None => return Err(MeshError::UnexpectedEnd),
+            };
+
+            let error = |message: &str| MeshError::Syntax {
+                line: line_number,
+                message: message.to_owned(),
+            };
+
+            let line = from_utf8(&data[offset..end]).map_err(|_| error("invalid UTF-8"))?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            offset = end + 1;
+
+            if line_number == 1 {
+                if tokens != ["ply"] {
+                    return Err(error("missing PLY magic"));
+                }
+
+                continue;
+            }
+
+            match tokens.as_slice() {
+                ["format", name, "1.0"] => {
+                    format = Some(match *name {
+                        "ascii" => PlyFormat::Ascii,
+                        "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                        "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                        _ => return Err(error("unsupported format")),
+                    });
+                }
+                ["element", name, count] => elements.push(PlyElement {
+                    name: (*name).to_owned(),
+                    count: count.parse().map_err(|_| error("invalid element count"))?,
+                    properties: vec![],
+                }),
+                ["property", "list", count, item, name] => {
+                    let property = PlyProperty::List {
+                        name: (*name).to_owned(),
+                        count: PlyType::parse(count).ok_or_else(|| error("invalid type"))?,
+                        item: PlyType::parse(item).ok_or_else(|| error("invalid type"))?,
+                    };
+
+                    match elements.last_mut() {
+                        Some(element) => element.properties.push(property),
+                        None => return Err(error("property outside of element")),
+                    }
+                }
+                ["property", value, name] => {
+                    let property = PlyProperty::Scalar {
+                        name: (*name).to_owned(),
+                        value: PlyType::parse(value).ok_or_else(|| error("invalid type"))?,
+                    };
+
+                    match elements.last_mut() {
+                        Some(element) => element.properties.push(property),
+                        None => return Err(error("property outside of element")),
+                    }
+                }
+                ["end_header"] => break,
+                ["comment", ..] | ["obj_info", ..] | [] => {}
+                _ => return Err(error("invalid header statement")),
+            }
+        }
+
+        match format {
+            Some(format) => Ok((Self { format, elements }, &data[offset..])),
+            None => Err(MeshError::Malformed("missing format statement".to_owned())),
+        }
+    }
+}
+
+enum PlyReader<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary(&'a [u8], bool),
+}
+
+impl<'a> PlyReader<'a> {
+    pub fn read(&mut self, ty: PlyType) -> Result<f64, MeshError> {
+        match self {
+            Self::Ascii(tokens) => match tokens.next() {
+                Some(token) => token
+                    .parse()
+                    .map_err(|_| MeshError::Malformed(format!("invalid value `{}'", token))),
+                None => Err(MeshError::UnexpectedEnd),
+            },
+            Self::Binary(data, big_endian) => {
+                if data.len() < ty.size() {
+                    return Err(MeshError::UnexpectedEnd);
+                }
+
+                let (bytes, rest) = data.split_at(ty.size());
+                *data = rest;
+
+                Ok(Self::decode(ty, bytes, *big_endian))
+            }
+        }
+    }
+
+    pub fn read_scalar_property(&mut self, property: &PlyProperty) -> Result<f64, MeshError> {
+        match property {
+            PlyProperty::Scalar { value, .. } => self.read(*value),
+            PlyProperty::List { .. } => {
+                self.skip_property(property)?;
+
+                Ok(0.0) // list properties are never used as scalars
+            }
+        }
+    }
+
+    pub fn skip_property(&mut self, property: &PlyProperty) -> Result<(), MeshError> {
+        match property {
+            PlyProperty::Scalar { value, .. } => {
+                self.read(*value)?;
+            }
+            PlyProperty::List { count, item, .. } => {
+                for _ in 0..self.read(*count)? as usize {
+                    self.read(*item)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode(ty: PlyType, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! decode {
+            ($type: ty) => {{
+                let bytes = bytes.try_into().unwrap();
+
+                if big_endian {
+                    <$type>::from_be_bytes(bytes) as f64
+                } else {
+                    <$type>::from_le_bytes(bytes) as f64
+                }
+            }};
+        }
+
+        match ty {
+            PlyType::Char => decode!(i8),
+            PlyType::UChar => decode!(u8),
+            PlyType::Short => decode!(i16),
+            PlyType::UShort => decode!(u16),
+            PlyType::Int => decode!(i32),
+            PlyType::UInt => decode!(u32),
+            PlyType::Float => decode!(f32),
+            PlyType::Double => decode!(f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment unit square with an extra element
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float u
+property float v
+element face 1
+property uchar intensity
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0 0 0 1 0 0
+1 0 0 0 0 1 1 0
+1 1 0 0 0 1 1 1
+0 1 0 0 0 1 0 1
+7 4 0 1 2 3
+0 1
+";
+
+    fn binary_triangle(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "big" } else { "little" };
+
+        let mut data = format!(
+            "ply\nformat binary_{}_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar uint vertex_indices\nend_header\n",
+            format
+        )
+        .into_bytes();
+
+        for &value in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            if big_endian {
+                data.extend_from_slice(&value.to_be_bytes());
+            } else {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        data.push(3);
+
+        for &index in &[0u32, 1, 2] {
+            if big_endian {
+                data.extend_from_slice(&index.to_be_bytes());
+            } else {
+                data.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        data
+    }
+
+    #[test]
+    fn ascii_meshes_are_loaded() {
+        let mesh = load_ply(ASCII_QUAD.as_bytes()).unwrap();
+
+        assert_eq!(mesh.positions[2], [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.normals, vec![[0.0, 0.0, 1.0]; 4]);
+        assert_eq!(mesh.uvs[2], [1.0, 1.0]);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn binary_meshes_are_loaded() {
+        let little = load_ply(&binary_triangle(false)).unwrap();
+        let big = load_ply(&binary_triangle(true)).unwrap();
+
+        assert_eq!(little, big);
+        assert_eq!(little.positions[1], [1.0, 0.0, 0.0]);
+        assert_eq!(little.triangles, vec![[0, 1, 2]]);
+        assert!(little.uvs.is_empty());
+    }
+
+    #[test]
+    fn uvs_are_loaded_under_either_name() {
+        let data = "ply
+format ascii 1.0
+element vertex 3
+property float s
+property float x
+property float y
+property float z
+property float t
+element face 1
+property list uchar int vertex_indices
+end_header
+0.25 0 0 0 0.5
+1 1 0 0 0
+0 0 1 0 1
+3 0 1 2
+";
+
+        let mesh = load_ply(data.as_bytes()).unwrap();
+
+        assert_eq!(mesh.positions[1], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.uvs, vec![[0.25, 0.5], [1.0, 0.0], [0.0, 1.0]]);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let data = binary_triangle(false);
+
+        for len in [data.len() - 1, data.len() - 13, 20].iter() {
+            assert_eq!(load_ply(&data[..*len]), Err(MeshError::UnexpectedEnd));
+        }
+
+        let text = &ASCII_QUAD[..ASCII_QUAD.len() - 10];
+        assert_eq!(load_ply(text.as_bytes()), Err(MeshError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let error_line = |data: &str| match load_ply(data.as_bytes()) {
+            Err(MeshError::Syntax { line, .. }) => line,
+            result => panic!("expected syntax error, got {:?}", result),
+        };
+
+        assert_eq!(error_line("plyx\nformat ascii 1.0\nend_header\n"), 1);
+        assert_eq!(error_line("ply\nformat binary 1.0\nend_header\n"), 2);
+        assert_eq!(error_line("ply\nformat ascii 1.0\nproperty float x\n"), 3);
+        assert_eq!(error_line("ply\nelement vertex -1\n"), 2);
+        assert_eq!(error_line("ply\nelement vertex 1\nproperty half x\n"), 3);
+        assert_eq!(error_line("ply\nfoo\nend_header\n"), 2);
+
+        assert!(matches!(
+            load_ply(b"ply\nend_header\n"),
+            Err(MeshError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_bodies_are_rejected() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+        let vertices = "0 0 0\n1 0 0\n0 1 0\n";
+
+        let malformed = |body: &str| {
+            let data = format!("{}{}", header, body);
+
+            matches!(load_ply(data.as_bytes()), Err(MeshError::Malformed(_)))
+        };
+
+        assert!(malformed("0 0 0\n1 0 0\n0 one 0\n3 0 1 2\n"));
+        assert!(malformed(&format!("{}3 0 -1 2\n", vertices)));
+        assert!(malformed(&format!("{}2 0 1\n", vertices)));
+
+        let missing_position = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nend_header\n0 0\n";
+        assert!(matches!(
+            load_ply(missing_position.as_bytes()),
+            Err(MeshError::Malformed(_))
+        ));
+    }
+}
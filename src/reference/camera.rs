@@ -0,0 +1,90 @@
+use crate::{ApertureShape, Camera, ReferenceRay};
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector2, Vector3};
+use std::f32::consts::PI;
+
+/// Camera model, see `evaluate_camera_ray` in the camera shader.
+#[derive(Clone, Debug)]
+pub struct ReferenceCamera {
+    transform: Matrix4<f32>,
+    aperture: ApertureShape,
+    field_of_view: f32,
+    focal_distance: f32,
+    focal_curvature: f32,
+}
+
+impl ReferenceCamera {
+    pub fn new(camera: &Camera) -> Self {
+        let position: Point3<f32> = camera.position.into();
+        let direction = Vector3::from(camera.direction).normalize();
+        let up_vector = Vector3::from(camera.up_vector).normalize();
+
+        // Negate the camera direction to work around Matrix4::look_at being
+        // right-handed, exactly like the device camera transform does.
+
+        let xfm: Matrix4<f32> = Transform::look_at(position, position - direction, up_vector);
+
+        Self {
+            transform: xfm.inverse_transform().unwrap(),
+            aperture: camera.aperture,
+            field_of_view: camera.field_of_view,
+            focal_distance: camera.focal_distance,
+            focal_curvature: camera.focal_curvature,
+        }
+    }
+
+    /// Generates a camera ray through a point on the image plane, where the Y
+    /// coordinate lies in [-1, 1] and the X coordinate is scaled by the aspect
+    /// ratio, using two random numbers to sample a point on the aperture.
+    pub fn ray(&self, uv: Vector2<f32>, u1: f32, u2: f32) -> ReferenceRay {
+        let aperture = self.aperture_point(u1, u2);
+
+        let origin = Point3::new(aperture.x, aperture.y, 0.0);
+
+        let direction = Vector3::new(uv.x * self.field_of_view, uv.y * self.field_of_view, 1.0);
+        let cos_theta_squared = 1.0 / direction.magnitude2();
+
+        let a = self.focal_curvature * (1.0 - cos_theta_squared) / cos_theta_squared;
+        let c = self.focal_distance;
+
+        let target = Point3::from_vec(direction * 2.0 * c / (1.0 + (1.0 + 4.0 * a * c).sqrt()));
+
+        let origin = self.transform.transform_point(origin);
+        let target = self.transform.transform_point(target);
+
+        ReferenceRay {
+            org: origin.to_vec(),
+            dir: (target - origin).normalize(),
+        }
+    }
+
+    fn aperture_point(&self, u1: f32, u2: f32) -> Vector2<f32> {
+        match self.aperture {
+            ApertureShape::Point => Vector2::zero(),
+            ApertureShape::Circle { radius } => {
+                let a = u1 * 2.0 * PI;
+
+                Vector2::new(a.cos(), a.sin()) * u2.sqrt() * radius
+            }
+            ApertureShape::Ngon {
+                radius,
+                sides,
+                rotation,
+            } => {
+                let sides = sides as f32;
+                let corner = (u1 * sides).floor();
+
+                let u = 1.0 - (u1 * sides - corner).sqrt();
+                let v = u2 * (1.0 - u);
+
+                let a = PI / sides;
+
+                let (s, c) = (rotation + corner * 2.0 * a).sin_cos();
+
+                let p = Vector2::new((u + v) * a.cos(), (u - v) * a.sin());
+
+                Vector2::new(c * p.x - s * p.y, s * p.x + c * p.y) * radius
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    render_sky_map, to_spherical, Environment, EnvironmentImage, EnvironmentSampler, ReferenceError,
+};
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+use std::f32::consts::PI;
+
+/// Environment map with its importance sampling distribution.
+#[derive(Debug)]
+struct EnvironmentMap {
+    cols: usize,
+    rows: usize,
+    pixels: Vec<Vector3<f32>>,
+    sampler: EnvironmentSampler,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: &EnvironmentImage) -> Self {
+        Self {
+            cols: image.cols,
+            rows: image.rows,
+            pixels: image.pixels.iter().map(|&pixel| pixel.into()).collect(),
+            sampler: EnvironmentSampler::new(image),
+        }
+    }
+
+    /// Bilinearly interpolates the map with a repeating wrap mode.
+    fn color(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let x = uv.x * self.cols as f32 - 0.5;
+        let y = uv.y * self.rows as f32 - 0.5;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |dx: i64, dy: i64| {
+            let x = (x0 as i64 + dx).rem_euclid(self.cols as i64) as usize;
+            let y = (y0 as i64 + dy).rem_euclid(self.rows as i64) as usize;
+
+            self.pixels[y * self.cols + x]
+        };
+
+        (texel(0, 0) * (1.0 - fx) + texel(1, 0) * fx) * (1.0 - fy)
+            + (texel(0, 1) * (1.0 - fx) + texel(1, 1) * fx) * fy
+    }
+}
+
+/// Environment light, either a solid color or an environment map.
+#[derive(Debug)]
+pub struct ReferenceEnvironment {
+    tint: Vector3<f32>,
+    rotation: f32,
+    map: Option<EnvironmentMap>,
+}
+
+impl ReferenceEnvironment {
+    /// Creates the environment, with the environment map image if the
+    /// environment is a map; the map is ignored for other environments.
+    pub fn new(
+        environment: &Environment,
+        map: Option<&EnvironmentImage>,
+    ) -> Result<Self, ReferenceError> {
+        match environment {
+            Environment::Solid { tint } => Ok(Self {
+                tint: Vector3::from(*tint).map(|x| x.max(0.0)),
+                rotation: 0.0,
+                map: None,
+            }),
+            Environment::Map { tint, rotation } => match map {
+                Some(map) => Ok(Self {
+                    tint: Vector3::from(*tint).map(|x| x.max(0.0)),
+                    rotation: rotation % (2.0 * PI),
+                    map: Some(EnvironmentMap::new(map)),
+                }),
+                None => Err(ReferenceError::new("missing environment map")),
+            },
+            Environment::Sky {
+                tint,
+                sun_direction,
+                turbidity,
+                ground_albedo,
+            } => {
+                let image = render_sky_map(*sun_direction, *turbidity, *ground_albedo);
+
+                Ok(Self {
+                    tint: Vector3::from(*tint).map(|x| x.max(0.0)),
+                    rotation: 0.0,
+                    map: Some(EnvironmentMap::new(&image)),
+                })
+            }
+        }
+    }
+
+    /// Samples a direction towards the environment, returning the direction,
+    /// the radiance divided by the PDF, and the PDF of sampling the direction.
+    pub fn sample(&self, u1: f32, u2: f32) -> (Vector3<f32>, Vector3<f32>, f32) {
+        let map = match &self.map {
+            Some(map) => map,
+            None => {
+                let z = 2.0 * u1 - 1.0;
+                let r = (1.0 - z * z).sqrt();
+                let phi = 2.0 * PI * u2;
+
+                let wi = Vector3::new(phi.cos() * r, z, phi.sin() * r);
+
+                return (wi, self.tint * 4.0 * PI, 1.0 / (4.0 * PI));
+            }
+        };
+
+        let (uv, pdf) = map.sampler.sample(u1, u2);
+
+        let wi = to_spherical(self.rotation - uv.x * 2.0 * PI, uv.y * PI);
+
+        let sin_theta = (uv.y * PI).sin();
+
+        if sin_theta == 0.0 || pdf == 0.0 {
+            return (wi, Vector3::zero(), 0.0);
+        }
+
+        let pdf = pdf / (sin_theta * 2.0 * PI * PI);
+
+        (wi, self.tint.mul_element_wise(map.color(uv)) / pdf, pdf)
+    }
+
+    /// Returns the radiance arriving from a direction, along with the PDF of
+    /// sampling that direction using the `sample` method.
+    pub fn eval(&self, wi: Vector3<f32>) -> (Vector3<f32>, f32) {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return (self.tint, 1.0 / (4.0 * PI)),
+        };
+
+        let u = (self.rotation - wi.z.atan2(wi.x)) / (2.0 * PI);
+        let v = wi.y.max(-1.0).min(1.0).acos() / PI;
+
+        let uv = Vector2::new(u.rem_euclid(1.0), v);
+        let sin_theta = (1.0 - wi.y * wi.y).max(0.0).sqrt();
+        let pdf = map.sampler.pdf(uv);
+
+        if sin_theta == 0.0 || pdf == 0.0 {
+            return (Vector3::zero(), 0.0);
+        }
+
+        (
+            self.tint.mul_element_wise(map.color(uv)),
+            pdf / (sin_theta * 2.0 * PI * PI),
+        )
+    }
+}
@@ -0,0 +1,696 @@
+use crate::{
+    Axis, BoundingBox, Geometry, GeometryParameter, MeshHierarchy, TriangleMesh, MIN_REPEAT_PERIOD,
+};
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Ray with an origin and a unit direction.
+#[derive(Clone, Copy, Debug)]
+pub struct ReferenceRay {
+    pub org: Vector3<f32>,
+    pub dir: Vector3<f32>,
+}
+
+impl ReferenceRay {
+    /// Creates a ray leaving a surface, offsetting the origin along the normal
+    /// so that the ray does not immediately intersect the surface it left.
+    pub fn leaving_surface(
+        org: Vector3<f32>,
+        dir: Vector3<f32>,
+        normal: Vector3<f32>,
+        offset: f32,
+    ) -> Self {
+        Self {
+            org: org + normal * offset * dir.dot(normal).signum(),
+            dir,
+        }
+    }
+}
+
+/// Clips a ray range to a bounding box padded by some distance, and returns
+/// whether the clipped range is non-empty, exactly like the shader version.
+pub(crate) fn ray_bbox(
+    ray: &ReferenceRay,
+    idir: Vector3<f32>,
+    range: &mut Vector2<f32>,
+    bbox: &BoundingBox,
+    padding: f32,
+) -> bool {
+    let bot = (bbox.min.to_vec() - Vector3::from_value(padding) - ray.org).mul_element_wise(idir);
+    let top = (bbox.max.to_vec() + Vector3::from_value(padding) - ray.org).mul_element_wise(idir);
+
+    let tmin = Vector3::new(bot.x.min(top.x), bot.y.min(top.y), bot.z.min(top.z));
+    let tmax = Vector3::new(bot.x.max(top.x), bot.y.max(top.y), bot.z.max(top.z));
+
+    range.x = tmin.x.max(tmin.y).max(tmin.z).max(range.x);
+    range.y = tmax.x.min(tmax.y).min(tmax.z).min(range.y);
+
+    range.x <= range.y
+}
+
+/// Triangle mesh along with its BVH.
+#[derive(Debug)]
+pub struct ReferenceMesh {
+    mesh: TriangleMesh,
+    hierarchy: MeshHierarchy,
+}
+
+impl ReferenceMesh {
+    pub fn new(mesh: TriangleMesh) -> Self {
+        Self {
+            hierarchy: MeshHierarchy::build(&mesh),
+            mesh,
+        }
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.hierarchy.bounding_box()
+    }
+
+    fn intersect(
+        &self,
+        ray: &ReferenceRay,
+        range: &mut Vector2<f32>,
+        precision: f32,
+    ) -> Option<ReferenceHit> {
+        let idir = Vector3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let mut closest = None;
+        let mut index = 0;
+
+        while index != self.hierarchy.nodes.len() {
+            let node = &self.hierarchy.nodes[index];
+            let mut node_range = *range;
+
+            if ray_bbox(ray, idir, &mut node_range, &node.bbox, precision) {
+                let first = node.first as usize;
+                let count = node.count as usize;
+
+                for &triangle in &self.hierarchy.triangles[first..first + count] {
+                    if let Some(uv) = self.intersect_triangle(triangle as usize, ray, range) {
+                        closest = Some(ReferenceHit::Mesh {
+                            triangle: triangle as usize,
+                            barycentrics: uv,
+                        });
+                    }
+                }
+
+                index += 1;
+            } else {
+                index = node.skip as usize;
+            }
+        }
+
+        if closest.is_some() {
+            range.x = range.y;
+        }
+
+        closest
+    }
+
+    fn intersect_triangle(
+        &self,
+        triangle: usize,
+        ray: &ReferenceRay,
+        range: &mut Vector2<f32>,
+    ) -> Option<Vector2<f32>> {
+        let [v0, v1, v2] = self.mesh.vertices(triangle);
+
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+
+        let p = ray.dir.cross(e2);
+        let det = e1.dot(p);
+
+        if det == 0.0 {
+            return None;
+        }
+
+        let s = ray.org - v0.to_vec();
+        let q = s.cross(e1);
+
+        let u = s.dot(p) / det;
+        let v = ray.dir.dot(q) / det;
+        let t = e2.dot(q) / det;
+
+        if u < 0.0 || v < 0.0 || u + v > 1.0 || t < range.x || t > range.y {
+            return None;
+        }
+
+        range.y = t;
+
+        Some(Vector2::new(u, v))
+    }
+
+    fn normal(&self, triangle: usize, barycentrics: Vector2<f32>) -> Vector3<f32> {
+        let face_normal = self
+            .mesh
+            .face_normal(triangle)
+            .unwrap_or_else(Vector3::zero);
+
+        if self.mesh.normals.is_empty() {
+            return face_normal;
+        }
+
+        let [n0, n1, n2] = self.mesh.vertex_normals(triangle);
+
+        let u = barycentrics.x;
+        let v = barycentrics.y;
+
+        let normal = n0 * (1.0 - u - v) + n1 * u + n2 * v;
+
+        if normal.magnitude2() == 0.0 {
+            face_normal
+        } else {
+            normal.normalize()
+        }
+    }
+}
+
+/// Geometry-specific information about a ray intersection, which is needed
+/// to compute the surface normal at the intersection point.
+#[derive(Clone, Copy, Debug)]
+pub enum ReferenceHit {
+    DistanceField,
+    Mesh {
+        triangle: usize,
+        barycentrics: Vector2<f32>,
+    },
+}
+
+/// Root geometry of an instance. Meshes keep their geometry, which may place
+/// the mesh under a chain of translations, rotations and scalings.
+#[derive(Clone, Debug)]
+pub enum ReferenceGeometry {
+    DistanceField(Geometry),
+    Mesh(Arc<ReferenceMesh>, Geometry),
+}
+
+impl ReferenceGeometry {
+    /// Finds the first intersection of a ray with this geometry in the given
+    /// range, setting the start of the range to the intersection distance.
+    pub fn intersect(
+        &self,
+        parameters: &BTreeMap<String, f32>,
+        ray: &ReferenceRay,
+        range: &mut Vector2<f32>,
+        precision: f32,
+    ) -> Option<ReferenceHit> {
+        match self {
+            Self::DistanceField(geometry) => {
+                while range.x <= range.y {
+                    let dist = distance(geometry, parameters, ray.org + range.x * ray.dir).abs();
+
+                    if dist < precision {
+                        return Some(ReferenceHit::DistanceField);
+                    }
+
+                    range.x += dist;
+                }
+
+                None
+            }
+            Self::Mesh(mesh, geometry) => {
+                mesh.intersect(&mesh_ray(geometry, parameters, *ray), range, precision)
+            }
+        }
+    }
+
+    /// Returns the surface normal at an intersection point with this geometry.
+    pub fn normal(
+        &self,
+        parameters: &BTreeMap<String, f32>,
+        hit: ReferenceHit,
+        p: Vector3<f32>,
+        precision: f32,
+    ) -> Vector3<f32> {
+        match (self, hit) {
+            (
+                Self::Mesh(mesh, geometry),
+                ReferenceHit::Mesh {
+                    triangle,
+                    barycentrics,
+                },
+            ) => mesh_normal(geometry, parameters, mesh.normal(triangle, barycentrics)),
+            (Self::DistanceField(geometry), _) => match normal(geometry, parameters, p) {
+                Some(normal) => normal,
+                None => gradient_estimate(geometry, parameters, p, precision),
+            },
+            (Self::Mesh(..), ReferenceHit::DistanceField) => {
+                panic!("mesh normal requested for a distance field hit")
+            }
+        }
+    }
+}
+
+/// Transforms a ray into the local space of a mesh geometry, this mirrors the
+/// ray functions emitted by `add_mesh_function` in the geometry generator.
+fn mesh_ray(
+    geometry: &Geometry,
+    parameters: &BTreeMap<String, f32>,
+    mut ray: ReferenceRay,
+) -> ReferenceRay {
+    match geometry {
+        Geometry::Translate { translation, child } => {
+            ray.org -= vec3_parameter(translation, parameters);
+
+            mesh_ray(child, parameters, ray)
+        }
+        Geometry::Scale { factor, child } => {
+            let s = factor.value(parameters);
+
+            ray.org /= s;
+            ray.dir /= s;
+
+            mesh_ray(child, parameters, ray)
+        }
+        Geometry::Rotate { axis, angle, child } => {
+            let k = vec3_parameter(axis, parameters).normalize();
+            let theta = angle.value(parameters);
+
+            ray.org = rotate_about_axis(ray.org, k, -theta);
+            ray.dir = rotate_about_axis(ray.dir, k, -theta);
+
+            mesh_ray(child, parameters, ray)
+        }
+        _ => ray,
+    }
+}
+
+/// Transforms a normal out of the local space of a mesh geometry.
+fn mesh_normal(
+    geometry: &Geometry,
+    parameters: &BTreeMap<String, f32>,
+    n: Vector3<f32>,
+) -> Vector3<f32> {
+    match geometry {
+        Geometry::Translate { child, .. } | Geometry::Scale { child, .. } => {
+            mesh_normal(child, parameters, n)
+        }
+        Geometry::Rotate { axis, angle, child } => {
+            let k = vec3_parameter(axis, parameters).normalize();
+
+            rotate_about_axis(
+                mesh_normal(child, parameters, n),
+                k,
+                angle.value(parameters),
+            )
+        }
+        _ => n,
+    }
+}
+
+/// Returns whether a geometry contains a custom modifier, which is written in
+/// GLSL and therefore cannot be evaluated by the reference renderer.
+pub(crate) fn has_custom_modifier(geometry: &Geometry) -> bool {
+    match geometry {
+        Geometry::CustomModifier { .. } => true,
+        Geometry::Union { children }
+        | Geometry::Intersection { children }
+        | Geometry::SmoothUnion { children, .. }
+        | Geometry::SmoothIntersection { children, .. } => children.iter().any(has_custom_modifier),
+        Geometry::Subtraction { lhs, rhs } | Geometry::SmoothSubtraction { lhs, rhs, .. } => {
+            has_custom_modifier(lhs) || has_custom_modifier(rhs)
+        }
+        Geometry::Onion { child, .. }
+        | Geometry::Scale { child, .. }
+        | Geometry::Rotate { child, .. }
+        | Geometry::Translate { child, .. }
+        | Geometry::Round { child, .. }
+        | Geometry::ForceNumericalNormals { child }
+        | Geometry::Twist { child, .. }
+        | Geometry::Repeat { child, .. }
+        | Geometry::Mirror { child, .. }
+        | Geometry::PolarRepeat { child, .. } => has_custom_modifier(child),
+        _ => false,
+    }
+}
+
+fn vec3_parameter(
+    parameter: &[GeometryParameter; 3],
+    parameters: &BTreeMap<String, f32>,
+) -> Vector3<f32> {
+    Vector3::new(
+        parameter[0].value(parameters),
+        parameter[1].value(parameters),
+        parameter[2].value(parameters),
+    )
+}
+
+/// Polynomial smooth minimum, see `geo_smooth_min` in the geometry shader.
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    let h = (k - (a - b).abs()).max(0.0) / k;
+
+    a.min(b) - h * h * k * 0.25
+}
+
+fn smooth_max(a: f32, b: f32, k: f32) -> f32 {
+    -smooth_min(-a, -b, k)
+}
+
+/// Folds a point into the cell of a grid repetition containing the origin.
+fn repeat(
+    period: &[GeometryParameter; 3],
+    count: Option<[u32; 3]>,
+    parameters: &BTreeMap<String, f32>,
+    mut p: Vector3<f32>,
+) -> Vector3<f32> {
+    for i in 0..3 {
+        if period[i].as_constant() == Some(0.0) {
+            continue;
+        }
+
+        let c = period[i].value(parameters).max(MIN_REPEAT_PERIOD);
+
+        if let Some(count) = count {
+            let h = (count[i].max(1) - 1) as f32 * 0.5;
+
+            p[i] -= c * ((p[i] / c + h).round().max(0.0).min(2.0 * h) - h);
+        } else {
+            p[i] -= c * (p[i] / c).round();
+        }
+    }
+
+    p
+}
+
+/// Folds a point into the first sector of a polar repetition, returning the
+/// folded point along with the angle by which it was rotated.
+fn polar_fold(axis: Axis, count: u32, mut p: Vector3<f32>) -> (Vector3<f32>, f32) {
+    let (u, v) = axis.perpendicular();
+
+    let sector = 2.0 * std::f32::consts::PI / count.max(1) as f32;
+    let a = p[v].atan2(p[u]);
+    let b = (a + 0.5 * sector).rem_euclid(sector) - 0.5 * sector;
+
+    let r = Vector2::new(p[u], p[v]).magnitude();
+
+    p[u] = r * b.cos();
+    p[v] = r * b.sin();
+
+    (p, a - b)
+}
+
+/// Rotates a point about a unit axis using Rodrigues' rotation formula.
+fn rotate_about_axis(p: Vector3<f32>, k: Vector3<f32>, theta: f32) -> Vector3<f32> {
+    let (sin_theta, cos_theta) = theta.sin_cos();
+
+    p * cos_theta + k.cross(p) * sin_theta + k * k.dot(p) * (1.0 - cos_theta)
+}
+
+/// Evaluates the distance field of a geometry, this must exactly mirror the
+/// GLSL code emitted by the geometry generator for the device renderer.
+fn distance(geometry: &Geometry, parameters: &BTreeMap<String, f32>, p: Vector3<f32>) -> f32 {
+    match geometry {
+        Geometry::Sphere { radius } => p.magnitude() - radius.value(parameters),
+        Geometry::Ellipsoid { radius } => {
+            let r = vec3_parameter(radius, parameters);
+
+            (p.div_element_wise(r).magnitude() - 1.0) * r.x.min(r.y).min(r.z)
+        }
+        Geometry::Cuboid { dimensions } => {
+            let d = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs())
+                - vec3_parameter(dimensions, parameters);
+
+            let outside = Vector3::new(d.x.max(0.0), d.y.max(0.0), d.z.max(0.0));
+
+            outside.magnitude() + d.x.max(d.y.max(d.z)).min(0.0)
+        }
+        Geometry::Cylinder { height, radius } => {
+            let d = Vector2::new(
+                Vector2::new(p.x, p.z).magnitude() - radius.value(parameters),
+                p.y.abs() - height.value(parameters),
+            );
+
+            d.x.max(d.y).min(0.0) + Vector2::new(d.x.max(0.0), d.y.max(0.0)).magnitude()
+        }
+        Geometry::Torus {
+            major_radius,
+            minor_radius,
+        } => {
+            let q = Vector2::new(
+                Vector2::new(p.x, p.z).magnitude() - major_radius.value(parameters),
+                p.y,
+            );
+
+            q.magnitude() - minor_radius.value(parameters)
+        }
+        Geometry::Capsule { height, radius } => {
+            let h = height.value(parameters);
+
+            Vector3::new(p.x, p.y - p.y.max(-h).min(h), p.z).magnitude() - radius.value(parameters)
+        }
+        Geometry::Cone {
+            height,
+            bottom_radius,
+            top_radius,
+        } => {
+            let h = height.value(parameters);
+            let r1 = bottom_radius.value(parameters);
+            let r2 = top_radius.value(parameters);
+
+            let q = Vector2::new(Vector2::new(p.x, p.z).magnitude(), p.y);
+            let k1 = Vector2::new(r2, h);
+            let k2 = Vector2::new(r2 - r1, 2.0 * h);
+
+            let ca = Vector2::new(
+                q.x - q.x.min(if q.y < 0.0 { r1 } else { r2 }),
+                q.y.abs() - h,
+            );
+
+            let t = ((k1 - q).dot(k2) / k2.dot(k2)).max(0.0).min(1.0);
+            let cb = q - k1 + k2 * t;
+
+            let s = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+
+            s * ca.dot(ca).min(cb.dot(cb)).sqrt()
+        }
+        Geometry::Plane { normal, offset, .. } => {
+            p.dot(vec3_parameter(normal, parameters).normalize()) - offset.value(parameters)
+        }
+        Geometry::HexagonalPrism { height, radius } => {
+            let k = Vector3::new(-0.866_025_4, 0.5, 0.577_35);
+            let r = radius.value(parameters);
+
+            let mut p = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs());
+
+            let fold = 2.0 * (k.x * p.x + k.y * p.z).min(0.0);
+
+            p.x -= fold * k.x;
+            p.z -= fold * k.y;
+
+            let edge = Vector2::new(p.x - p.x.max(-k.z * r).min(k.z * r), p.z - r);
+
+            let d = Vector2::new(
+                edge.magnitude() * (p.z - r).signum(),
+                p.y - height.value(parameters),
+            );
+
+            d.x.max(d.y).min(0.0) + Vector2::new(d.x.max(0.0), d.y.max(0.0)).magnitude()
+        }
+        Geometry::TriangularPrism { height, radius } => {
+            let q = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs());
+
+            (q.y - height.value(parameters))
+                .max((q.x * 0.866_025_4 + p.z * 0.5).max(-p.z) - radius.value(parameters))
+        }
+        Geometry::Union { children } => children
+            .iter()
+            .map(|child| distance(child, parameters, p))
+            .fold(std::f32::INFINITY, f32::min),
+        Geometry::Intersection { children } => children
+            .iter()
+            .map(|child| distance(child, parameters, p))
+            .fold(std::f32::NEG_INFINITY, f32::max),
+        Geometry::Subtraction { lhs, rhs } => {
+            distance(lhs, parameters, p).max(-distance(rhs, parameters, p))
+        }
+        Geometry::SmoothUnion {
+            children,
+            blend_radius,
+        } => {
+            let k = blend_radius.value(parameters);
+
+            let mut distances = children.iter().map(|child| distance(child, parameters, p));
+            let first = distances.next().unwrap();
+
+            distances.fold(first, |d, x| smooth_min(d, x, k))
+        }
+        Geometry::SmoothIntersection {
+            children,
+            blend_radius,
+        } => {
+            let k = blend_radius.value(parameters);
+
+            let mut distances = children.iter().map(|child| distance(child, parameters, p));
+            let first = distances.next().unwrap();
+
+            distances.fold(first, |d, x| smooth_max(d, x, k))
+        }
+        Geometry::SmoothSubtraction {
+            lhs,
+            rhs,
+            blend_radius,
+        } => smooth_max(
+            distance(lhs, parameters, p),
+            -distance(rhs, parameters, p),
+            blend_radius.value(parameters),
+        ),
+        Geometry::Onion { thickness, child } => {
+            distance(child, parameters, p).abs() - thickness.value(parameters)
+        }
+        Geometry::Scale { factor, child } => {
+            let s = factor.value(parameters);
+
+            distance(child, parameters, p / s) * s
+        }
+        Geometry::Rotate { axis, angle, child } => {
+            let k = vec3_parameter(axis, parameters).normalize();
+
+            distance(
+                child,
+                parameters,
+                rotate_about_axis(p, k, -angle.value(parameters)),
+            )
+        }
+        Geometry::Translate { translation, child } => distance(
+            child,
+            parameters,
+            p - vec3_parameter(translation, parameters),
+        ),
+        Geometry::Round { radius, child } => {
+            distance(child, parameters, p) - radius.value(parameters)
+        }
+        Geometry::ForceNumericalNormals { child } => distance(child, parameters, p),
+        Geometry::Twist {
+            amount,
+            step,
+            child,
+        } => {
+            let k = amount.value(parameters) / (p.x * p.x + p.z * p.z).sqrt();
+            let (s, c) = (2.0 * std::f32::consts::PI * k * p.y).sin_cos();
+
+            let q = Vector3::new(p.x * c + p.z * s, p.y, -p.x * s + p.z * c);
+
+            distance(child, parameters, q) * step.value(parameters)
+        }
+        Geometry::Repeat {
+            period,
+            count,
+            child,
+            ..
+        } => distance(child, parameters, repeat(period, *count, parameters, p)),
+        Geometry::Mirror { axis, child } => {
+            let mut p = p;
+
+            p[axis.index()] = p[axis.index()].abs();
+
+            distance(child, parameters, p)
+        }
+        Geometry::PolarRepeat { axis, count, child } => {
+            distance(child, parameters, polar_fold(*axis, *count, p).0)
+        }
+        Geometry::CustomModifier { .. } => {
+            panic!("custom modifiers are not supported by the reference renderer")
+        }
+        Geometry::Mesh { .. } => {
+            panic!("meshes are not distance fields and must be root geometries")
+        }
+    }
+}
+
+/// Evaluates the analytical normal of a geometry if it has one, this mirrors
+/// the normal functions emitted by the geometry generator.
+fn normal(
+    geometry: &Geometry,
+    parameters: &BTreeMap<String, f32>,
+    p: Vector3<f32>,
+) -> Option<Vector3<f32>> {
+    match geometry {
+        Geometry::Sphere { .. } => Some(p.normalize()),
+        Geometry::Torus { major_radius, .. } => {
+            let ring = Vector3::new(p.x, 0.0, p.z).normalize() * major_radius.value(parameters);
+
+            Some((p - ring).normalize())
+        }
+        Geometry::Capsule { height, .. } => {
+            let h = height.value(parameters);
+
+            Some(Vector3::new(p.x, p.y - p.y.max(-h).min(h), p.z).normalize())
+        }
+        Geometry::Plane { normal, .. } => Some(vec3_parameter(normal, parameters).normalize()),
+        Geometry::Ellipsoid { radius } => {
+            let r = vec3_parameter(radius, parameters);
+
+            Some(p.div_element_wise(r.mul_element_wise(r)).normalize())
+        }
+        Geometry::Translate { translation, child } => normal(
+            child,
+            parameters,
+            p - vec3_parameter(translation, parameters),
+        ),
+        Geometry::Rotate { axis, angle, child } => {
+            let k = vec3_parameter(axis, parameters).normalize();
+            let theta = angle.value(parameters);
+
+            let n = normal(child, parameters, rotate_about_axis(p, k, -theta))?;
+
+            Some(rotate_about_axis(n, k, theta))
+        }
+        Geometry::Scale { factor, child } => {
+            normal(child, parameters, p / factor.value(parameters))
+        }
+        Geometry::Repeat {
+            period,
+            count,
+            child,
+            ..
+        } => normal(child, parameters, repeat(period, *count, parameters, p)),
+        Geometry::Mirror { axis, child } => {
+            let i = axis.index();
+            let s = if p[i] < 0.0 { -1.0 } else { 1.0 };
+
+            let mut p = p;
+            p[i] = p[i].abs();
+
+            let mut n = normal(child, parameters, p)?;
+            n[i] *= s;
+
+            Some(n)
+        }
+        Geometry::PolarRepeat { axis, count, child } => {
+            let (u, v) = axis.perpendicular();
+            let (p, angle) = polar_fold(*axis, *count, p);
+
+            let mut n = normal(child, parameters, p)?;
+            let (sd, cd) = angle.sin_cos();
+
+            let (nu, nv) = (n[u], n[v]);
+
+            n[u] = nu * cd - nv * sd;
+            n[v] = nu * sd + nv * cd;
+
+            Some(n)
+        }
+        _ => None,
+    }
+}
+
+fn gradient_estimate(
+    geometry: &Geometry,
+    parameters: &BTreeMap<String, f32>,
+    p: Vector3<f32>,
+    precision: f32,
+) -> Vector3<f32> {
+    let dx = Vector3::new(precision, 0.0, 0.0);
+    let dy = Vector3::new(0.0, precision, 0.0);
+    let dz = Vector3::new(0.0, 0.0, precision);
+
+    Vector3::new(
+        distance(geometry, parameters, p + dx) - distance(geometry, parameters, p - dx),
+        distance(geometry, parameters, p + dy) - distance(geometry, parameters, p - dy),
+        distance(geometry, parameters, p + dz) - distance(geometry, parameters, p - dz),
+    )
+    .normalize()
+}
@@ -0,0 +1,207 @@
+use crate::{luminance, rotate, BoundingBox, Light};
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use std::f32::consts::PI;
+
+/// Light sample towards a light from a point, see `light_sample` in the shader.
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    pub wi: Vector3<f32>,
+    pub distance: f32,
+    /// Incident radiance divided by the PDF of the sample.
+    pub weight: Vector3<f32>,
+}
+
+/// Analytic lights of a scene, sampled proportionally to their power.
+#[derive(Debug)]
+pub struct ReferenceLights {
+    lights: Vec<Light>,
+    power: Vec<f32>,
+    total: f32,
+}
+
+impl ReferenceLights {
+    /// Prepares the lights, with the bounding box of the scene for the purpose
+    /// of computing the power of directional lights.
+    pub fn new<'a>(lights: impl IntoIterator<Item = &'a Light>, bbox: &BoundingBox) -> Self {
+        let lights: Vec<Light> = lights.into_iter().copied().collect();
+
+        let extent = bbox.max - bbox.min;
+        let extent = extent.map(|x| if x.is_finite() { x.max(0.0) } else { 0.0 });
+
+        let power: Vec<f32> = lights
+            .iter()
+            .map(|light| light_power(light, extent))
+            .collect();
+
+        Self {
+            total: power.iter().sum(),
+            lights,
+            power,
+        }
+    }
+
+    /// Samples a direction towards one of the lights from a point, or returns
+    /// `None` if there are no lights or the sampled light is not visible.
+    pub fn sample(&self, point: Vector3<f32>, u1: f32, u2: f32, u3: f32) -> Option<LightSample> {
+        if self.total == 0.0 {
+            return None;
+        }
+
+        let mut u = u1 * self.total;
+        let mut index = 0;
+
+        while index < self.lights.len() - 1 && u >= self.power[index] {
+            u -= self.power[index];
+            index += 1;
+        }
+
+        if self.power[index] == 0.0 {
+            return None;
+        }
+
+        let light = &self.lights[index];
+
+        let emission = Vector3::from(light.emission()).map(|x| x.max(0.0));
+        let weight = emission * self.total / self.power[index];
+
+        match *light {
+            Light::Point { position, .. } => {
+                let d = Vector3::from(position) - point;
+                let distance = d.magnitude();
+
+                Some(LightSample {
+                    wi: d / distance,
+                    distance,
+                    weight: weight / d.magnitude2(),
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                angle,
+                blend,
+                ..
+            } => {
+                let d = Vector3::from(position) - point;
+                let distance = d.magnitude();
+                let wi = d / distance;
+
+                let falloff = spot_falloff(Vector3::from(direction).normalize(), angle, blend, -wi);
+
+                Some(LightSample {
+                    wi,
+                    distance,
+                    weight: weight * falloff / d.magnitude2(),
+                })
+            }
+            Light::Sphere {
+                position, radius, ..
+            } => {
+                let d = Vector3::from(position) - point;
+                let ratio = radius * radius / d.magnitude2();
+
+                if ratio >= 1.0 {
+                    return None; // inside the sphere
+                }
+
+                let cos_max = (1.0 - ratio).sqrt();
+                let one_minus_cos_max = ratio / (1.0 + cos_max);
+
+                let cos_theta = 1.0 - u2 * one_minus_cos_max;
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * u3;
+
+                let wi = rotate(
+                    Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin()),
+                    d.normalize(),
+                );
+
+                let b = d.dot(wi);
+
+                Some(LightSample {
+                    wi,
+                    distance: b - (b * b - d.magnitude2() * (1.0 - ratio)).max(0.0).sqrt(),
+                    weight: weight * 2.0 * PI * one_minus_cos_max,
+                })
+            }
+            Light::Rectangle {
+                position,
+                edge_u,
+                edge_v,
+                ..
+            } => {
+                let edge_u = Vector3::from(edge_u);
+                let edge_v = Vector3::from(edge_v);
+
+                let corner = Vector3::from(position) - (edge_u + edge_v) * 0.5;
+                let d = corner + edge_u * u2 + edge_v * u3 - point;
+
+                let distance = d.magnitude();
+                let wi = d / distance;
+
+                let normal = edge_u.cross(edge_v);
+                let area = normal.magnitude();
+                let cos_light = -wi.dot(normal) / area;
+
+                if cos_light <= 0.0 {
+                    return None;
+                }
+
+                Some(LightSample {
+                    wi,
+                    distance,
+                    weight: weight * area * cos_light / d.magnitude2(),
+                })
+            }
+            Light::Directional { direction, .. } => Some(LightSample {
+                wi: -Vector3::from(direction).normalize(),
+                distance: std::f32::INFINITY,
+                weight,
+            }),
+        }
+    }
+}
+
+/// Returns the luminance of the power emitted by a light into the scene.
+fn light_power(light: &Light, extent: Vector3<f32>) -> f32 {
+    let power = luminance(Vector3::from(light.emission()).map(|x| x.max(0.0)));
+
+    match *light {
+        Light::Point { .. } => power * 4.0 * PI,
+        Light::Spot { angle, .. } => power * 2.0 * PI * (1.0 - angle.max(0.0).min(PI).cos()),
+        Light::Sphere { radius, .. } => power * 4.0 * PI * PI * radius * radius,
+        Light::Rectangle { edge_u, edge_v, .. } => {
+            power * PI * Vector3::from(edge_u).cross(edge_v.into()).magnitude()
+        }
+        Light::Directional { direction, .. } => {
+            let direction = Vector3::from(direction).normalize();
+
+            power
+                * (direction.x.abs() * extent.y * extent.z
+                    + direction.y.abs() * extent.z * extent.x
+                    + direction.z.abs() * extent.x * extent.y)
+        }
+    }
+}
+
+/// Returns the fraction of a spot light's intensity emitted in a direction.
+fn spot_falloff(direction: Vector3<f32>, angle: f32, blend: f32, dir: Vector3<f32>) -> f32 {
+    let angle = angle.max(0.0).min(PI);
+    let blend = blend.max(0.0).min(1.0);
+
+    let cos_outer = angle.cos();
+    let cos_inner = (angle * (1.0 - blend)).cos();
+
+    let cos_theta = dir.dot(direction);
+
+    if cos_theta >= cos_inner {
+        1.0
+    } else if cos_theta <= cos_outer {
+        0.0
+    } else {
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+
+        t * t * (3.0 - 2.0 * t)
+    }
+}
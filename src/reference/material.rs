@@ -0,0 +1,1067 @@
+use crate::{CoatedBase, Material, MaterialParameter, ReferenceError};
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+use img2raw::{ColorSpace, DataFormat, Header};
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use zerocopy::LayoutVerified;
+
+/// BC1-compressed sRGB material texture.
+///
+/// Texels are decoded on the fly when sampled, so that textures use the same
+/// amount of memory as on the device. Sampling uses bilinear filtering with a
+/// repeating wrap mode, identical to the sampler state used by the device.
+#[derive(Debug)]
+pub struct ReferenceTexture {
+    blocks: Vec<u8>,
+}
+
+impl ReferenceTexture {
+    pub const COLS: usize = 2048;
+    pub const ROWS: usize = 2048;
+
+    pub fn new(asset_data: &[u8]) -> Result<Self, ReferenceError> {
+        let (header, data) = match LayoutVerified::<_, Header>::new_from_prefix(asset_data) {
+            Some(layout) => layout,
+            None => return Err(ReferenceError::new("invalid material texture")),
+        };
+
+        if header.data_format.try_parse() != Some(DataFormat::BC1) {
+            return Err(ReferenceError::new("expected BC1 material texture"));
+        }
+
+        if header.color_space.try_parse() != Some(ColorSpace::SRGB) {
+            return Err(ReferenceError::new("expected sRGB material texture"));
+        }
+
+        if header.dimensions[0] as usize != Self::COLS
+            || header.dimensions[1] as usize != Self::ROWS
+        {
+            return Err(ReferenceError::new("invalid material texture dimensions"));
+        }
+
+        if data.len() < Self::COLS * Self::ROWS / 2 {
+            return Err(ReferenceError::new("unexpected end of material texture"));
+        }
+
+        Ok(Self {
+            blocks: data[..Self::COLS * Self::ROWS / 2].to_vec(),
+        })
+    }
+
+    /// Samples this texture at some UV coordinates, returning a linear color.
+    pub fn sample(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let x = uv.x * Self::COLS as f32 - 0.5;
+        let y = uv.y * Self::ROWS as f32 - 0.5;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let c00 = self.texel(x0, y0);
+        let c10 = self.texel(x0 + 1, y0);
+        let c01 = self.texel(x0, y0 + 1);
+        let c11 = self.texel(x0 + 1, y0 + 1);
+
+        (c00 * (1.0 - fx) + c10 * fx) * (1.0 - fy) + (c01 * (1.0 - fx) + c11 * fx) * fy
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vector3<f32> {
+        let x = x.rem_euclid(Self::COLS as i64) as usize;
+        let y = y.rem_euclid(Self::ROWS as i64) as usize;
+
+        let offset = 8 * ((y / 4) * (Self::COLS / 4) + x / 4);
+        let block = &self.blocks[offset..offset + 8];
+
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+
+        let shift = 2 * (4 * (y % 4) + x % 4);
+        let bits = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+        let e0 = expand_rgb565(c0);
+        let e1 = expand_rgb565(c1);
+
+        let srgb = match ((bits >> shift) & 3, c0 > c1) {
+            (0, _) => e0,
+            (1, _) => e1,
+            (2, true) => (e0 * 2.0 + e1) / 3.0,
+            (3, true) => (e0 + e1 * 2.0) / 3.0,
+            (2, false) => (e0 + e1) / 2.0,
+            _ => Vector3::zero(),
+        };
+
+        srgb.map(srgb_to_linear)
+    }
+}
+
+fn expand_rgb565(color: u16) -> Vector3<f32> {
+    let r = (color >> 11) & 0x1f;
+    let g = (color >> 5) & 0x3f;
+    let b = color & 0x1f;
+
+    Vector3::new(
+        f32::from((r << 3) | (r >> 2)) / 255.0,
+        f32::from((g << 2) | (g >> 4)) / 255.0,
+        f32::from((b << 3) | (b >> 2)) / 255.0,
+    )
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Material parameter resolved against the list of loaded textures.
+#[derive(Clone, Debug)]
+struct ReferenceParameter {
+    base: Vector3<f32>,
+    factor: Vector3<f32>,
+    /// Vertical and horizontal texture indices, if the parameter is textured.
+    layers: Option<(usize, usize)>,
+    /// Contrast, which is negative if stochastic sampling is not enabled.
+    contrast: f32,
+    uv_rotation: f32,
+    uv_scale: f32,
+    uv_offset: Vector2<f32>,
+}
+
+impl ReferenceParameter {
+    pub fn new(parameter: &MaterialParameter, texture_layers: &BTreeMap<&str, usize>) -> Self {
+        match parameter {
+            MaterialParameter::Constant(base) => Self {
+                base: base.as_vec3().into(),
+                factor: Vector3::zero(),
+                layers: None,
+                contrast: 0.0,
+                uv_rotation: 0.0,
+                uv_scale: 0.0,
+                uv_offset: Vector2::zero(),
+            },
+            MaterialParameter::Textured(info) => Self {
+                base: info.base.as_vec3().into(),
+                factor: info.factor.as_vec3().into(),
+                layers: Some((
+                    texture_layers[info.texture.vert_texture()],
+                    texture_layers[info.texture.horz_texture()],
+                )),
+                contrast: if info.stochastic {
+                    info.contrast * 2.0
+                } else {
+                    info.contrast * -2.0
+                },
+                uv_rotation: info.uv_rotation.rem_euclid(2.0 * PI),
+                uv_scale: info.uv_scale,
+                uv_offset: info.uv_offset.into(),
+            },
+        }
+    }
+
+    /// Evaluates this parameter with triplanar mapping, see `mat_param_vec3`.
+    pub fn evaluate(
+        &self,
+        textures: &[ReferenceTexture],
+        normal: Vector3<f32>,
+        p: Vector3<f32>,
+    ) -> Vector3<f32> {
+        let (vert, horz) = match self.layers {
+            Some(layers) if self.factor != Vector3::zero() => layers,
+            _ => return self.base, // the texture is absent or irrelevant
+        };
+
+        let (s, c) = self.uv_rotation.sin_cos();
+        let (s, c) = (s * self.uv_scale, c * self.uv_scale);
+
+        let xfm = |x: f32, y: f32| Vector2::new(c * x + s * y, -s * x + c * y) + self.uv_offset;
+
+        // Offset all triplanar coordinates slightly based on the normal direction
+        // in order to randomize e.g. parallel sides of a box or a sheet of glass.
+
+        let zy_uv = if normal.x > 0.0 {
+            xfm(p.z, p.y)
+        } else {
+            xfm(p.z + 17.4326, p.y + 17.4326)
+        };
+
+        let xz_uv = if normal.y > 0.0 {
+            xfm(p.x, p.z)
+        } else {
+            xfm(p.x + 13.8193, p.z + 13.8193)
+        };
+
+        let xy_uv = if normal.z > 0.0 {
+            xfm(p.x, p.y)
+        } else {
+            xfm(p.x + 15.2175, p.y + 15.2175)
+        };
+
+        let tri = triplanar_weights(normal);
+
+        let sample = |weight: f32, texture: &ReferenceTexture, uv: Vector2<f32>| {
+            let value = if weight < 1e-4 {
+                Vector3::zero()
+            } else if self.contrast > 0.0 {
+                sample_texture_stochastic(texture, uv)
+            } else {
+                texture.sample(uv)
+            };
+
+            (value - Vector3::from_value(0.5)) * self.contrast.abs() + Vector3::from_value(0.5)
+        };
+
+        let zy_sample = sample(tri.x, &textures[vert], zy_uv);
+        let xz_sample = sample(tri.y, &textures[horz], xz_uv);
+        let xy_sample = sample(tri.z, &textures[vert], xy_uv);
+
+        self.base
+            + self
+                .factor
+                .mul_element_wise(zy_sample * tri.x + xz_sample * tri.y + xy_sample * tri.z)
+    }
+}
+
+fn triplanar_weights(normal: Vector3<f32>) -> Vector3<f32> {
+    let weights = normal.map(|x| x.abs().powi(12));
+
+    weights / (weights.x + weights.y + weights.z)
+}
+
+// Adapted from https://www.shadertoy.com/view/MdyfDV
+fn sample_texture_stochastic(texture: &ReferenceTexture, uv: Vector2<f32>) -> Vector3<f32> {
+    let v = Vector2::new(uv.x - 0.57735 * uv.y, 1.1547 * uv.y) * 4.0;
+    let i = Vector2::new(v.x.floor(), v.y.floor());
+
+    let fx = v.x - i.x;
+    let fy = v.y - i.y;
+    let fz = 1.0 - fx - fy;
+
+    // GLSL fract() is always positive unlike the Rust version
+    let fract = |x: f32| x - x.floor();
+
+    let rnd22 = |p: Vector2<f32>| {
+        Vector2::new(
+            fract((p.x * 127.1 + p.y * 311.7).sin() * 43_758.547),
+            fract((p.x * 269.5 + p.y * 183.3).sin() * 43_758.547),
+        )
+    };
+
+    let cdx = texture.sample(uv - rnd22(i + Vector2::new(1.0, 0.0)));
+    let cdy = texture.sample(uv - rnd22(i + Vector2::new(0.0, 1.0)));
+
+    let c = if fz > 0.0 {
+        texture.sample(uv - rnd22(i))
+    } else {
+        texture.sample(uv - rnd22(i + Vector2::new(1.0, 1.0)))
+    };
+
+    let color = if fz > 0.0 {
+        cdx * fx + cdy * fy + c * fz
+    } else {
+        cdy * (1.0 - fx) + cdx * (1.0 - fy) - c * fz
+    };
+
+    color.map(|x| x.max(0.0).min(1.0))
+}
+
+/// Material with its parameters resolved against the loaded textures.
+#[derive(Clone, Debug)]
+pub struct ReferenceMaterial {
+    material: Material,
+    parameters: Vec<ReferenceParameter>,
+}
+
+impl ReferenceMaterial {
+    pub fn new(material: &Material, texture_layers: &BTreeMap<&str, usize>) -> Self {
+        Self {
+            material: material.clone(),
+            parameters: material
+                .parameters()
+                .into_iter()
+                .map(|(_, parameter)| ReferenceParameter::new(parameter, texture_layers))
+                .collect(),
+        }
+    }
+
+    /// Loads this material's parameters at a shading point, see `MAT_DO_SWITCH`.
+    pub fn load(
+        &self,
+        textures: &[ReferenceTexture],
+        normal: Vector3<f32>,
+        p: Vector3<f32>,
+    ) -> LoadedMaterial {
+        let vec3 =
+            |index: usize| -> Vector3<f32> { self.parameters[index].evaluate(textures, normal, p) };
+
+        let float = |index: usize| luminance(vec3(index));
+
+        match self.material {
+            Material::Lambertian { .. } => LoadedMaterial::Lambertian {
+                albedo: saturate(vec3(0)),
+            },
+            Material::IdealReflection { .. } => LoadedMaterial::IdealReflection {
+                reflectance: saturate(vec3(0)),
+            },
+            Material::IdealRefraction { .. } => LoadedMaterial::IdealRefraction {
+                transmittance: saturate(vec3(0)),
+            },
+            Material::Phong { .. } => LoadedMaterial::Phong {
+                albedo: saturate(vec3(0)),
+                exponent: float(1).max(1.0),
+            },
+            Material::Dielectric { .. } => LoadedMaterial::Dielectric {
+                base_color: saturate(vec3(0)),
+                alpha: float(1).max(0.0).min(1.0).powi(2).max(1e-3),
+            },
+            Material::Emissive { .. } => LoadedMaterial::Emissive {
+                radiance: vec3(0).map(|x| x.max(0.0)),
+            },
+            Material::Conductor { .. } => {
+                let (eta, k, alpha_x, alpha_y) = self.load_conductor(&vec3, 0);
+
+                LoadedMaterial::Conductor {
+                    eta,
+                    k,
+                    alpha_x,
+                    alpha_y,
+                }
+            }
+            Material::Coated {
+                base: CoatedBase::Lambertian { .. },
+                ..
+            } => LoadedMaterial::CoatedLambertian {
+                albedo: saturate(vec3(0)),
+                coat: self.load_coat(&vec3, 1),
+            },
+            Material::Coated {
+                base: CoatedBase::Conductor { .. },
+                ..
+            } => {
+                let (eta, k, alpha_x, alpha_y) = self.load_conductor(&vec3, 0);
+
+                LoadedMaterial::CoatedConductor {
+                    eta,
+                    k,
+                    alpha_x,
+                    alpha_y,
+                    coat: self.load_coat(&vec3, 4),
+                }
+            }
+        }
+    }
+
+    fn load_conductor(
+        &self,
+        vec3: &dyn Fn(usize) -> Vector3<f32>,
+        start: usize,
+    ) -> (Vector3<f32>, Vector3<f32>, f32, f32) {
+        let roughness = luminance(vec3(start + 2)).max(0.0).min(1.0);
+        let anisotropy = luminance(vec3(start + 3)).max(0.0).min(1.0);
+
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+
+        (
+            vec3(start).map(|x| x.max(0.0)),
+            vec3(start + 1).map(|x| x.max(0.0)),
+            (roughness * roughness / aspect).max(1e-3),
+            (roughness * roughness * aspect).max(1e-3),
+        )
+    }
+
+    fn load_coat(&self, vec3: &dyn Fn(usize) -> Vector3<f32>, start: usize) -> LoadedCoat {
+        let roughness = luminance(vec3(start)).max(0.0).min(1.0);
+        let thickness = luminance(vec3(start + 1)).max(0.0);
+
+        LoadedCoat {
+            optical_depth: vec3(start + 2).map(|x| x.max(0.0)) * thickness,
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+}
+
+/// Refractive index of the clear coat of coated materials.
+const COAT_IOR: f32 = 1.5;
+
+/// Clear coat of a coated material, see the `mat_coat_*` shader functions.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedCoat {
+    optical_depth: Vector3<f32>,
+    alpha: f32,
+}
+
+impl LoadedCoat {
+    fn transmittance(&self, cos_w: f32, eta: f32) -> Vector3<f32> {
+        let cos_t = (1.0 - (1.0 - cos_w * cos_w) / (eta * eta)).max(0.0).sqrt();
+
+        (-self.optical_depth / cos_t.max(1e-4)).map(f32::exp)
+            * (1.0 - fresnel_dielectric(cos_w, eta))
+    }
+
+    fn probability(&self, cos_o: f32, eta: f32, base_reflectance: f32) -> f32 {
+        let coat = fresnel_dielectric(cos_o, eta);
+        let base = luminance(self.transmittance(cos_o, eta)) * base_reflectance;
+
+        if coat + base > 0.0 {
+            coat / (coat + base)
+        } else {
+            1.0
+        }
+    }
+
+    fn sample(&self, normal: Vector3<f32>, wo: Vector3<f32>, u1: f32, u2: f32) -> Vector3<f32> {
+        let local_wo = rotate(wo, normal);
+        let h = ggx_sample_visible_normal(local_wo, self.alpha, self.alpha, u1, u2);
+
+        rotate(reflect(-local_wo, h), normal)
+    }
+
+    /// Adds the coat lobe to a base BRDF evaluated in the same directions.
+    #[allow(clippy::too_many_arguments)]
+    fn eval(
+        &self,
+        normal: Vector3<f32>,
+        wi: Vector3<f32>,
+        wo: Vector3<f32>,
+        n1: f32,
+        base_f: Vector3<f32>,
+        base_pdf: f32,
+        base_reflectance: f32,
+    ) -> (Vector3<f32>, f32) {
+        let wi = rotate(wi, normal);
+        let wo = rotate(wo, normal);
+
+        if wi.y <= 0.0 || wo.y <= 0.0 {
+            return (Vector3::zero(), 0.0);
+        }
+
+        let eta = COAT_IOR / n1;
+
+        let h = (wi + wo).normalize();
+
+        let d = ggx_distribution(h, self.alpha, self.alpha);
+        let lambda_o = ggx_lambda(wo, self.alpha, self.alpha);
+        let lambda_i = ggx_lambda(wi, self.alpha, self.alpha);
+
+        let coat_f = fresnel_dielectric(wi.dot(h), eta) * d
+            / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i));
+        let coat_pdf = d / (4.0 * wo.y * (1.0 + lambda_o));
+
+        let p = self.probability(wo.y, eta, base_reflectance);
+
+        (
+            base_f
+                .mul_element_wise(self.transmittance(wi.y, eta))
+                .mul_element_wise(self.transmittance(wo.y, eta))
+                .map(|x| x + coat_f),
+            base_pdf + (coat_pdf - base_pdf) * p,
+        )
+    }
+}
+
+/// BSDF sample generated by a material.
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    /// Sampled incident direction.
+    pub wi: Vector3<f32>,
+    /// BSDF value times the cosine term divided by the sample PDF.
+    pub weight: Vector3<f32>,
+    /// Sample PDF, which is zero if the sample is invalid.
+    pub pdf: f32,
+}
+
+impl BsdfSample {
+    fn invalid(wi: Vector3<f32>) -> Self {
+        Self {
+            wi,
+            weight: Vector3::zero(),
+            pdf: 0.0,
+        }
+    }
+}
+
+/// Material with all parameters evaluated at a particular shading point.
+///
+/// The BSDFs below are direct translations of the material shader code and
+/// should be kept in sync with it, including their numerical edge cases.
+#[derive(Clone, Copy, Debug)]
+pub enum LoadedMaterial {
+    Lambertian {
+        albedo: Vector3<f32>,
+    },
+    IdealReflection {
+        reflectance: Vector3<f32>,
+    },
+    IdealRefraction {
+        transmittance: Vector3<f32>,
+    },
+    Phong {
+        albedo: Vector3<f32>,
+        exponent: f32,
+    },
+    Dielectric {
+        base_color: Vector3<f32>,
+        alpha: f32,
+    },
+    Emissive {
+        radiance: Vector3<f32>,
+    },
+    Conductor {
+        eta: Vector3<f32>,
+        k: Vector3<f32>,
+        alpha_x: f32,
+        alpha_y: f32,
+    },
+    CoatedLambertian {
+        albedo: Vector3<f32>,
+        coat: LoadedCoat,
+    },
+    CoatedConductor {
+        eta: Vector3<f32>,
+        k: Vector3<f32>,
+        alpha_x: f32,
+        alpha_y: f32,
+        coat: LoadedCoat,
+    },
+}
+
+impl LoadedMaterial {
+    /// Evaluates the BSDF for a pair of directions, returning its value along
+    /// with the PDF of sampling `wi` given `wo`; delta BSDFs always return 0.
+    pub fn eval(
+        &self,
+        normal: Vector3<f32>,
+        wi: Vector3<f32>,
+        wo: Vector3<f32>,
+        n1: f32,
+        n2: f32,
+    ) -> (Vector3<f32>, f32) {
+        match *self {
+            Self::Lambertian { albedo } => {
+                let wi_n = wi.dot(normal);
+
+                if wi_n <= 0.0 || wo.dot(normal) <= 0.0 {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                (albedo / PI, wi_n / PI)
+            }
+            Self::Phong { albedo, exponent } => {
+                let wi_n = wi.dot(normal);
+
+                if wi_n <= 0.0 || wo.dot(normal) <= 0.0 {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                let cos_alpha = reflect(-wo, normal).dot(wi).max(0.0).powf(exponent);
+
+                let pdf = cos_alpha * (exponent + 1.0) / (2.0 * PI);
+
+                (
+                    albedo * (exponent + 2.0) / (2.0 * PI) * cos_alpha / wi_n,
+                    pdf,
+                )
+            }
+            Self::Conductor {
+                eta,
+                k,
+                alpha_x,
+                alpha_y,
+            } => {
+                let wi = rotate(wi, normal);
+                let wo = rotate(wo, normal);
+
+                if wi.y <= 0.0 || wo.y <= 0.0 {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                let h = (wi + wo).normalize();
+
+                let d = ggx_distribution(h, alpha_x, alpha_y);
+                let lambda_o = ggx_lambda(wo, alpha_x, alpha_y);
+                let lambda_i = ggx_lambda(wi, alpha_x, alpha_y);
+
+                let f = fresnel_conductor(wi.dot(h), eta / n1, k / n1);
+
+                (
+                    f * d / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i)),
+                    d / (4.0 * wo.y * (1.0 + lambda_o)),
+                )
+            }
+            Self::Dielectric { base_color, alpha } => {
+                let normal = if wo.dot(normal) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+
+                let wi = rotate(wi, normal);
+                let wo = rotate(wo, normal);
+
+                if wo.y <= 0.0 || wi.y == 0.0 {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                let p = dielectric_reflection_probability(wo.y, n1, n2);
+
+                let lambda_o = ggx_lambda(wo, alpha, alpha);
+                let lambda_i = ggx_lambda(wi, alpha, alpha);
+
+                if wi.y > 0.0 {
+                    let h = (wi + wo).normalize();
+
+                    let d = ggx_distribution(h, alpha, alpha);
+                    let f = fresnel_dielectric(wo.dot(h), n2 / n1);
+
+                    return (
+                        base_color * f * d / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i)),
+                        p * d / (4.0 * wo.y * (1.0 + lambda_o)),
+                    );
+                }
+
+                let h = wo * n1 + wi * n2;
+
+                if h == Vector3::zero() {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                let h = h.normalize() * h.y.signum();
+
+                let wo_h = wo.dot(h);
+                let wi_h = wi.dot(h);
+
+                if wo_h <= 0.0 || wi_h >= 0.0 {
+                    return (Vector3::zero(), 0.0);
+                }
+
+                let d = ggx_distribution(h, alpha, alpha);
+                let f = fresnel_dielectric(wo_h, n2 / n1);
+
+                let denom = n1 * wo_h + n2 * wi_h;
+                let jacobian = n2 * n2 * -wi_h / (denom * denom).max(1e-7);
+
+                (
+                    base_color * (1.0 - f) * d * wo_h * jacobian
+                        / (wo.y * -wi.y * (1.0 + lambda_o + lambda_i)),
+                    (1.0 - p) * d * wo_h * jacobian / (wo.y * (1.0 + lambda_o)),
+                )
+            }
+            Self::CoatedLambertian { albedo, coat } => {
+                let eta = COAT_IOR / n1;
+                let base_pdf = wi.dot(normal).max(0.0) / PI;
+
+                // Light trapped underneath the coat is scattered again; see the shader.
+
+                let fdr = fresnel_diffuse_reflectance(1.0 / eta);
+                let base_f = albedo.map(|a| a / (PI * eta * eta * (1.0 - a * fdr)));
+
+                coat.eval(normal, wi, wo, n1, base_f, base_pdf, luminance(albedo))
+            }
+            Self::CoatedConductor {
+                eta,
+                k,
+                alpha_x,
+                alpha_y,
+                coat,
+            } => {
+                let base = Self::Conductor {
+                    eta,
+                    k,
+                    alpha_x,
+                    alpha_y,
+                };
+
+                let (base_f, base_pdf) = base.eval(normal, wi, wo, COAT_IOR, n2);
+                let reflectance = coated_conductor_reflectance(eta, k, wo.dot(normal));
+
+                coat.eval(normal, wi, wo, n1, base_f, base_pdf, reflectance)
+            }
+            Self::IdealReflection { .. } | Self::IdealRefraction { .. } | Self::Emissive { .. } => {
+                (Vector3::zero(), 0.0)
+            }
+        }
+    }
+
+    /// Samples an incident direction given an outgoing direction `wo`, and the
+    /// refractive indices on the side of the normal and on the opposite side.
+    pub fn sample(
+        &self,
+        normal: Vector3<f32>,
+        wo: Vector3<f32>,
+        n1: f32,
+        n2: f32,
+        u1: f32,
+        u2: f32,
+    ) -> BsdfSample {
+        match *self {
+            Self::Lambertian { albedo } => {
+                let r = u1.sqrt();
+                let phi = 2.0 * PI * u2;
+
+                let wi = rotate(
+                    Vector3::new(r * phi.cos(), (1.0 - u1).sqrt(), r * phi.sin()),
+                    normal,
+                );
+
+                let wi_n = wi.dot(normal);
+
+                if wi_n <= 0.0 || wo.dot(normal) <= 0.0 {
+                    return BsdfSample::invalid(wi);
+                }
+
+                BsdfSample {
+                    wi,
+                    weight: albedo,
+                    pdf: wi_n / PI,
+                }
+            }
+            Self::IdealReflection { reflectance } => BsdfSample {
+                wi: reflect(-wo, normal),
+                weight: reflectance,
+                pdf: 1.0,
+            },
+            Self::IdealRefraction { transmittance } => {
+                let wi = if wo.dot(normal) >= 0.0 {
+                    refract(-wo, normal, n1 / n2).unwrap_or_else(|| reflect(-wo, normal))
+                } else {
+                    refract(-wo, -normal, n2 / n1).unwrap_or_else(|| reflect(-wo, -normal))
+                };
+
+                BsdfSample {
+                    wi,
+                    weight: transmittance,
+                    pdf: 1.0,
+                }
+            }
+            Self::Phong { albedo, exponent } => {
+                let phi = 2.0 * PI * u1;
+                let theta = u2.powf(1.0 / (exponent + 1.0)).acos();
+
+                let ideal = reflect(-wo, normal);
+
+                let wi = rotate(to_spherical(phi, theta), ideal);
+
+                if wi.dot(normal) <= 0.0 || wo.dot(normal) <= 0.0 {
+                    return BsdfSample::invalid(wi);
+                }
+
+                let cos_alpha = ideal.dot(wi).max(0.0).powf(exponent);
+
+                BsdfSample {
+                    wi,
+                    weight: albedo * (exponent + 2.0) / (exponent + 1.0),
+                    pdf: cos_alpha * (exponent + 1.0) / (2.0 * PI),
+                }
+            }
+            Self::Dielectric { base_color, alpha } => {
+                let normal = if wo.dot(normal) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+
+                let local_wo = rotate(wo, normal);
+
+                if local_wo.y <= 0.0 {
+                    return BsdfSample::invalid(normal);
+                }
+
+                let p = dielectric_reflection_probability(local_wo.y, n1, n2);
+
+                let reflection = u1 < p;
+                let u1 = if reflection {
+                    u1 / p
+                } else {
+                    (u1 - p) / (1.0 - p)
+                };
+
+                let h = ggx_sample_visible_normal(local_wo, alpha, alpha, u1, u2);
+
+                let local_wi = if reflection {
+                    reflect(-local_wo, h)
+                } else {
+                    match refract(-local_wo, h, n1 / n2) {
+                        Some(local_wi) => local_wi,
+                        None => return BsdfSample::invalid(normal), // total internal reflection
+                    }
+                };
+
+                let wi = rotate(local_wi, normal);
+
+                if (local_wi.y > 0.0) != reflection || local_wi.y == 0.0 {
+                    return BsdfSample::invalid(wi);
+                }
+
+                let d = ggx_distribution(h, alpha, alpha);
+                let f = fresnel_dielectric(local_wo.dot(h), n2 / n1);
+
+                let lambda_o = ggx_lambda(local_wo, alpha, alpha);
+                let lambda_i = ggx_lambda(local_wi, alpha, alpha);
+
+                let g = (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i);
+
+                if reflection {
+                    return BsdfSample {
+                        wi,
+                        weight: base_color * f * g / p,
+                        pdf: p * d / (4.0 * local_wo.y * (1.0 + lambda_o)),
+                    };
+                }
+
+                let wo_h = local_wo.dot(h);
+                let wi_h = local_wi.dot(h);
+
+                let denom = n1 * wo_h + n2 * wi_h;
+                let jacobian = n2 * n2 * -wi_h / (denom * denom).max(1e-7);
+
+                BsdfSample {
+                    wi,
+                    weight: base_color * (1.0 - f) * g / (1.0 - p),
+                    pdf: (1.0 - p) * d * wo_h * jacobian / (local_wo.y * (1.0 + lambda_o)),
+                }
+            }
+            Self::Emissive { .. } => BsdfSample::invalid(normal),
+            Self::Conductor {
+                eta,
+                k,
+                alpha_x,
+                alpha_y,
+            } => {
+                let local_wo = rotate(wo, normal);
+
+                if local_wo.y <= 0.0 {
+                    return BsdfSample::invalid(normal);
+                }
+
+                let h = ggx_sample_visible_normal(local_wo, alpha_x, alpha_y, u1, u2);
+                let local_wi = reflect(-local_wo, h);
+
+                let wi = rotate(local_wi, normal);
+
+                if local_wi.y <= 0.0 {
+                    return BsdfSample::invalid(wi);
+                }
+
+                let d = ggx_distribution(h, alpha_x, alpha_y);
+                let lambda_o = ggx_lambda(local_wo, alpha_x, alpha_y);
+                let lambda_i = ggx_lambda(local_wi, alpha_x, alpha_y);
+
+                let f = fresnel_conductor(local_wi.dot(h), eta / n1, k / n1);
+
+                BsdfSample {
+                    wi,
+                    weight: f * (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i),
+                    pdf: d / (4.0 * local_wo.y * (1.0 + lambda_o)),
+                }
+            }
+            Self::CoatedLambertian { albedo, coat } => {
+                let base = Self::Lambertian { albedo };
+
+                self.sample_coated(&coat, base, luminance(albedo), normal, wo, n1, n2, u1, u2)
+            }
+            Self::CoatedConductor {
+                eta,
+                k,
+                alpha_x,
+                alpha_y,
+                coat,
+            } => {
+                let base = Self::Conductor {
+                    eta,
+                    k,
+                    alpha_x,
+                    alpha_y,
+                };
+
+                let reflectance = coated_conductor_reflectance(eta, k, wo.dot(normal));
+
+                self.sample_coated(&coat, base, reflectance, normal, wo, n1, n2, u1, u2)
+            }
+        }
+    }
+
+    /// Samples a direction from either the coat or the base of a coated material.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_coated(
+        &self,
+        coat: &LoadedCoat,
+        base: LoadedMaterial,
+        base_reflectance: f32,
+        normal: Vector3<f32>,
+        wo: Vector3<f32>,
+        n1: f32,
+        n2: f32,
+        u1: f32,
+        u2: f32,
+    ) -> BsdfSample {
+        let cos_o = wo.dot(normal);
+
+        if cos_o <= 0.0 {
+            return BsdfSample::invalid(normal);
+        }
+
+        let p = coat.probability(cos_o, COAT_IOR / n1, base_reflectance);
+
+        let wi = if u1 < p {
+            coat.sample(normal, wo, u1 / p, u2)
+        } else {
+            base.sample(normal, wo, COAT_IOR, n2, (u1 - p) / (1.0 - p), u2)
+                .wi
+        };
+
+        let (f, pdf) = self.eval(normal, wi, wo, n1, n2);
+
+        if pdf == 0.0 {
+            return BsdfSample::invalid(wi);
+        }
+
+        BsdfSample {
+            wi,
+            weight: f * wi.dot(normal) / pdf,
+            pdf,
+        }
+    }
+}
+
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+
+    0.5 * (rs * rs + rp * rp)
+}
+
+fn dielectric_reflection_probability(cos_o: f32, n1: f32, n2: f32) -> f32 {
+    fresnel_dielectric(cos_o, n2 / n1).max(0.05).min(0.95)
+}
+
+fn fresnel_diffuse_reflectance(eta: f32) -> f32 {
+    if eta < 1.0 {
+        return -1.4399 * eta * eta + 0.7099 * eta + 0.6681 + 0.0636 / eta;
+    }
+
+    let x = 1.0 / eta;
+
+    0.919_317 + x * (-3.4793 + x * (6.753_35 + x * (-7.809_89 + x * (4.985_54 - x * 1.368_81))))
+}
+
+fn coated_conductor_reflectance(eta: Vector3<f32>, k: Vector3<f32>, cos_o: f32) -> f32 {
+    luminance(fresnel_conductor(cos_o, eta / COAT_IOR, k / COAT_IOR))
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with refractive index `eta + ik`.
+fn fresnel_conductor(cos_i: f32, eta: Vector3<f32>, k: Vector3<f32>) -> Vector3<f32> {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+
+    let channel = |eta: f32, k: f32| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let t2 = 2.0 * cos_i * (0.5 * (a2b2 + t0)).sqrt();
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    Vector3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+fn ggx_distribution(h: Vector3<f32>, alpha_x: f32, alpha_y: f32) -> f32 {
+    let t = (h.x * h.x) / (alpha_x * alpha_x) + (h.z * h.z) / (alpha_y * alpha_y) + h.y * h.y;
+
+    1.0 / (PI * alpha_x * alpha_y * t * t)
+}
+
+fn ggx_lambda(w: Vector3<f32>, alpha_x: f32, alpha_y: f32) -> f32 {
+    let t = (alpha_x * alpha_x * w.x * w.x + alpha_y * alpha_y * w.z * w.z) / (w.y * w.y);
+
+    0.5 * ((1.0 + t).sqrt() - 1.0)
+}
+
+fn ggx_sample_visible_normal(
+    w: Vector3<f32>,
+    alpha_x: f32,
+    alpha_y: f32,
+    u1: f32,
+    u2: f32,
+) -> Vector3<f32> {
+    let v = Vector3::new(alpha_x * w.x, w.y, alpha_y * w.z).normalize();
+
+    let len2 = v.x * v.x + v.z * v.z;
+
+    let t1 = if len2 > 0.0 {
+        Vector3::new(v.z, 0.0, -v.x) / len2.sqrt()
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+
+    let t2 = v.cross(t1);
+
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let p2 = r * phi.sin();
+    let s = 0.5 * (1.0 + v.y);
+
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+    let h = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    Vector3::new(alpha_x * h.x, h.y.max(0.0), alpha_y * h.z).normalize()
+}
+
+pub(crate) fn luminance(color: Vector3<f32>) -> f32 {
+    color.dot(Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn saturate(color: Vector3<f32>) -> Vector3<f32> {
+    color.map(|x| x.max(0.0).min(1.0))
+}
+
+fn reflect(i: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
+    i - n * 2.0 * n.dot(i)
+}
+
+fn refract(i: Vector3<f32>, n: Vector3<f32>, eta: f32) -> Option<Vector3<f32>> {
+    let cos_i = n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+
+    if k < 0.0 {
+        None
+    } else {
+        Some(i * eta - n * (eta * cos_i + k.sqrt()))
+    }
+}
+
+/// Transforms (phi, theta) angles into a unit vector with (0, 1, 0) as north.
+pub(crate) fn to_spherical(phi: f32, theta: f32) -> Vector3<f32> {
+    let (sin_theta, cos_theta) = theta.sin_cos();
+
+    Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+}
+
+/// Rotates a vector by a rotation taking (0, 1, 0) to the unit vector `n`.
+pub(crate) fn rotate(a: Vector3<f32>, mut n: Vector3<f32>) -> Vector3<f32> {
+    let dir = if n.y > 0.0 { 1.0 } else { -1.0 };
+    n.y += dir;
+
+    n * (a.dot(n) / n.y) - a * dir
+}
@@ -0,0 +1,108 @@
+use crate::{rotate, Medium};
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use std::f32::consts::PI;
+
+/// Homogeneous medium, see `medium_t` in the shader.
+#[derive(Clone, Copy, Debug)]
+pub struct ReferenceMedium {
+    /// Extinction coefficient per unit distance.
+    pub extinction: Vector3<f32>,
+    /// Scattering coefficient per unit distance.
+    pub scattering: Vector3<f32>,
+    /// Asymmetry parameter of the Henyey-Greenstein phase function.
+    pub asymmetry: f32,
+    pub refractive_index: f32,
+}
+
+impl ReferenceMedium {
+    pub fn new(medium: &Medium) -> Self {
+        let wavenumbers = Vector3::new(685e-9, 530e-9, 470e-9).map(|x| 2.0 * PI / x);
+
+        let refractive_index = medium.nominal_refractive_index();
+
+        let extinction =
+            (Vector3::from(medium.extinction) * refractive_index).mul_element_wise(wavenumbers);
+
+        Self {
+            extinction,
+            scattering: extinction.mul_element_wise(Vector3::from(medium.scattering_albedo)),
+            asymmetry: medium.phase_asymmetry,
+            refractive_index,
+        }
+    }
+
+    /// Returns the medium surrounding instances without a parent.
+    pub fn vacuum() -> Self {
+        Self {
+            extinction: Vector3::zero(),
+            scattering: Vector3::zero(),
+            asymmetry: 0.0,
+            refractive_index: 1.0,
+        }
+    }
+
+    pub fn transmittance(&self, distance: f32) -> Vector3<f32> {
+        self.extinction
+            .map(|x| if x == 0.0 { 1.0 } else { (-x * distance).exp() })
+    }
+
+    /// Samples a scattering event along a ray segment of some length crossing
+    /// the medium, returning the distance to the event if one occurs, and the
+    /// transmittance divided by the probability of the outcome.
+    pub fn sample_distance(&self, distance: f32, u1: f32, u2: f32) -> (Option<f32>, Vector3<f32>) {
+        if self.scattering == Vector3::zero() {
+            return (None, self.transmittance(distance));
+        }
+
+        let extinction = self.extinction[((u1 * 3.0) as usize).min(2)];
+
+        let t = if extinction > 0.0 {
+            -(1.0 - u2).ln() / extinction
+        } else {
+            std::f32::INFINITY
+        };
+
+        if t >= distance {
+            let transmittance = self.transmittance(distance);
+            let probability = transmittance.sum() / 3.0;
+
+            return (None, transmittance / probability.max(1e-30));
+        }
+
+        let transmittance = self.transmittance(t);
+        let pdf = self.extinction.dot(transmittance) / 3.0;
+
+        (Some(t), transmittance / pdf.max(1e-30))
+    }
+
+    /// Evaluates the phase function for the cosine of the angle between the
+    /// propagation directions of light before and after scattering.
+    pub fn phase_eval(&self, cos_theta: f32) -> f32 {
+        let g = self.asymmetry;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// Samples a direction about a propagation direction by the phase function.
+    pub fn phase_sample(&self, dir: Vector3<f32>, u1: f32, u2: f32) -> Vector3<f32> {
+        let g = self.asymmetry;
+
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+
+            ((1.0 + g * g - s * s) / (2.0 * g)).max(-1.0).min(1.0)
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        rotate(
+            Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin()),
+            dir,
+        )
+    }
+}
@@ -0,0 +1,542 @@
+use crate::{
+    has_custom_modifier, load_environment_image, load_mesh, luminance, ray_bbox, BoundingBox,
+    ImageError, LoadedMaterial, MaterialParameter, MeshError, Raster, ReferenceCamera,
+    ReferenceEnvironment, ReferenceGeometry, ReferenceHit, ReferenceLights, ReferenceMaterial,
+    ReferenceMedium, ReferenceMesh, ReferenceRay, ReferenceTexture, Scene,
+};
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+/// Error encountered while preparing a scene for the reference renderer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceError(String);
+
+impl ReferenceError {
+    pub fn new(message: impl ToString) -> Self {
+        Self(message.to_string())
+    }
+}
+
+impl Display for ReferenceError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ReferenceError {}
+
+impl From<MeshError> for ReferenceError {
+    fn from(error: MeshError) -> Self {
+        Self::new(error)
+    }
+}
+
+impl From<ImageError> for ReferenceError {
+    fn from(error: ImageError) -> Self {
+        Self::new(error)
+    }
+}
+
+/// Linear HDR image produced by the reference renderer.
+///
+/// Pixels are stored in row-major order starting from the top-left corner of
+/// the image, and contain the average radiance over all samples taken.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+#[derive(Debug)]
+struct ReferenceInstance {
+    geometry: ReferenceGeometry,
+    parameters: BTreeMap<String, f32>,
+    bbox: BoundingBox,
+    material: usize,
+    sample_explicit: bool,
+    ext_medium: ReferenceMedium,
+    int_medium: ReferenceMedium,
+}
+
+impl ReferenceInstance {
+    /// Returns the medium on one side of the surface of this instance, along
+    /// with the refractive indices on that side and on the opposite side.
+    pub fn medium(&self, inside: bool) -> (&ReferenceMedium, f32, f32) {
+        let (medium, other) = if inside {
+            (&self.int_medium, &self.ext_medium)
+        } else {
+            (&self.ext_medium, &self.int_medium)
+        };
+
+        (medium, medium.refractive_index, other.refractive_index)
+    }
+}
+
+/// Closest intersection found during a scene traversal.
+#[derive(Clone, Copy, Debug)]
+struct Intersection {
+    instance: usize,
+    distance: f32,
+    hit: ReferenceHit,
+}
+
+/// Headless CPU path tracer for validating the device renderer.
+///
+/// This renderer evaluates the same geometry, materials, environment and camera
+/// model as the device shaders, but solves light transport with unidirectional
+/// path tracing instead of photon mapping: paths are continued at every surface
+/// rather than gathering photons at receivers, so the image converges to the
+/// same result as the device without any photon map bias. The path length is
+/// limited to the sum of the integrator's gather and scatter bounces.
+///
+/// Analytic lights cannot be hit by rays and are only sampled explicitly, so
+/// unlike the device this renderer does not produce caustics from them.
+///
+/// Light transport is always solved in RGB, so the spectral integrator option
+/// and the dispersion of media are ignored.
+///
+/// Custom geometry modifiers are written in GLSL and are therefore rejected.
+/// The scene is not validated, so callers should validate it beforehand.
+#[derive(Debug)]
+pub struct ReferenceRenderer {
+    camera: ReferenceCamera,
+    raster: Raster,
+    environment: ReferenceEnvironment,
+    lights: ReferenceLights,
+    instances: Vec<ReferenceInstance>,
+    materials: Vec<ReferenceMaterial>,
+    textures: Vec<ReferenceTexture>,
+    max_bounces: u32,
+    precision: f32,
+    pushback: f32,
+}
+
+impl ReferenceRenderer {
+    /// Prepares a scene for rendering, requesting assets as needed.
+    pub fn new(
+        scene: &Scene,
+        assets: impl Fn(&str) -> Result<Vec<u8>, ReferenceError>,
+    ) -> Result<Self, ReferenceError> {
+        let mut textures = vec![];
+
+        for material in scene.material_list.values() {
+            for (_, parameter) in material.parameters() {
+                if let MaterialParameter::Textured(info) = parameter {
+                    textures.push(info.texture.horz_texture());
+                    textures.push(info.texture.vert_texture());
+                }
+            }
+        }
+
+        textures.sort_unstable();
+        textures.dedup();
+
+        let mut texture_layers = BTreeMap::new();
+        let mut texture_data = Vec::with_capacity(textures.len());
+
+        for (index, &texture) in textures.iter().enumerate() {
+            texture_layers.insert(texture, index);
+            texture_data.push(ReferenceTexture::new(&assets(texture)?)?);
+        }
+
+        let mut material_index = BTreeMap::new();
+        let mut materials = Vec::with_capacity(scene.material_list.len());
+
+        for (index, (name, material)) in scene.material_list.iter().enumerate() {
+            material_index.insert(name.as_str(), index);
+            materials.push(ReferenceMaterial::new(material, &texture_layers));
+        }
+
+        let mut meshes: BTreeMap<&str, Arc<ReferenceMesh>> = BTreeMap::new();
+        let mut instances = vec![];
+
+        for (name, instance) in scene.instance_list.iter() {
+            if !instance.visible {
+                continue;
+            }
+
+            let error =
+                |message: &str| ReferenceError::new(format!("instance `{}': {}", name, message));
+
+            let geometry = match scene.geometry_list.get(&instance.geometry) {
+                Some(geometry) => geometry,
+                None => return Err(error("geometry not found")),
+            };
+
+            let material = match scene.material_list.get(&instance.material) {
+                Some(material) => material,
+                None => return Err(error("material not found")),
+            };
+
+            for parameter in geometry.symbolic_parameters() {
+                if !instance.parameters.contains_key(parameter) {
+                    return Err(error(&format!("parameter `{}' missing", parameter)));
+                }
+            }
+
+            if has_custom_modifier(geometry) {
+                return Err(error("custom modifiers are not supported"));
+            }
+
+            if geometry.has_nested_mesh() {
+                return Err(error("meshes must be root geometries or under transforms"));
+            }
+
+            let (geometry, bbox) = if let Some(mesh) = geometry.transformed_mesh() {
+                if !meshes.contains_key(mesh) {
+                    let data = load_mesh(mesh, &assets(mesh)?)?;
+                    meshes.insert(mesh, Arc::new(ReferenceMesh::new(data)));
+                }
+
+                let mesh = meshes[mesh].clone();
+                let bbox = geometry.mesh_bounding_box(mesh.bounding_box(), &instance.parameters);
+
+                (ReferenceGeometry::Mesh(mesh, geometry.clone()), bbox)
+            } else {
+                let bbox = geometry.bounding_box(&instance.parameters);
+
+                (ReferenceGeometry::DistanceField(geometry.clone()), bbox)
+            };
+
+            let ext_medium = match &instance.parent {
+                Some(parent) => match scene.instance_list.get(parent) {
+                    Some(parent) => ReferenceMedium::new(&parent.medium),
+                    None => return Err(error("parent not found")),
+                },
+                None => ReferenceMedium::vacuum(),
+            };
+
+            instances.push(ReferenceInstance {
+                geometry,
+                parameters: instance.parameters.clone(),
+                bbox,
+                material: material_index[instance.material.as_str()],
+                sample_explicit: instance.sample_explicit && !material.has_delta_bsdf(),
+                ext_medium,
+                int_medium: ReferenceMedium::new(&instance.medium),
+            });
+        }
+
+        let mut bbox = BoundingBox::neg_infinity_bounds();
+
+        for instance in &instances {
+            bbox.extend(&instance.bbox);
+        }
+
+        let map = match scene.environment_map.as_ref() {
+            Some(map) => Some(load_environment_image(map, &assets(map)?)?),
+            None => None,
+        };
+
+        Ok(Self {
+            camera: ReferenceCamera::new(&scene.camera),
+            raster: (*scene.raster).clone(),
+            environment: ReferenceEnvironment::new(&scene.environment, map.as_ref())?,
+            lights: ReferenceLights::new(scene.light_list.values(), &bbox),
+            instances,
+            materials,
+            textures: texture_data,
+            max_bounces: scene.integrator.max_gather_bounces + scene.integrator.max_scatter_bounces,
+            precision: scene.integrator.geometry_precision,
+            pushback: scene.integrator.geometry_pushback,
+        })
+    }
+
+    /// Renders the entire image with some number of samples per pixel.
+    ///
+    /// The output only depends on the scene, the number of samples and the seed.
+    pub fn render(&self, samples_per_pixel: u32, seed: u64) -> ReferenceImage {
+        let mut pixels = Vec::with_capacity((self.raster.width * self.raster.height) as usize);
+
+        for y in 0..self.raster.height {
+            for x in 0..self.raster.width {
+                pixels.push(self.render_pixel(x, y, samples_per_pixel, seed));
+            }
+        }
+
+        ReferenceImage {
+            width: self.raster.width,
+            height: self.raster.height,
+            pixels,
+        }
+    }
+
+    /// Renders a single pixel, where (0, 0) is the top-left corner.
+    ///
+    /// Every pixel has its own random number generator derived from the seed,
+    /// so that pixels may be rendered in any order or in parallel as needed.
+    pub fn render_pixel(&self, x: u32, y: u32, samples_per_pixel: u32, seed: u64) -> [f32; 3] {
+        let pixel = (u64::from(y) << 32) | u64::from(x);
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ pixel);
+
+        let mut radiance = Vector3::zero();
+
+        // The device renders with the fragment origin at the bottom-left corner.
+        let fragment = Vector2::new(x as f32, (self.raster.height - 1 - y) as f32);
+
+        let inv_dimensions = Vector2::new(
+            1.0 / self.raster.width as f32,
+            1.0 / self.raster.height as f32,
+        );
+
+        for _ in 0..samples_per_pixel {
+            let filter_offset = Vector2::new(
+                4.0 * self.raster.filter.importance_sample(rng.gen()) - 2.0,
+                4.0 * self.raster.filter.importance_sample(rng.gen()) - 2.0,
+            );
+
+            let mut uv = (fragment + filter_offset).mul_element_wise(inv_dimensions) * 2.0
+                - Vector2::new(1.0, 1.0);
+            uv.x *= self.raster.width as f32 * inv_dimensions.y; // maintain aspect ratio
+
+            let ray = self.camera.ray(uv, rng.gen(), rng.gen());
+
+            radiance += self.radiance(ray, &mut rng);
+        }
+
+        (radiance / samples_per_pixel.max(1) as f32).into()
+    }
+
+    /// Estimates the radiance along a ray, see `gather_photons` in the shader.
+    fn radiance(&self, mut ray: ReferenceRay, rng: &mut StdRng) -> Vector3<f32> {
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut radiance = Vector3::zero();
+
+        let mut material_pdf = 0.0;
+        let mut mis = false;
+
+        for bounce in 0..self.max_bounces {
+            let intersection = match self.traverse_scene(&ray) {
+                Some(intersection) => intersection,
+                None => {
+                    // If we began an MIS direct light sampling procedure in the previous bounce,
+                    // finish it now; the ray was clearly not occluded so weigh the light by MIS.
+
+                    let (light, light_pdf) = self.environment.eval(ray.dir);
+
+                    if mis && material_pdf != 0.0 && light_pdf != 0.0 {
+                        radiance += throughput.mul_element_wise(light)
+                            * power_heuristic(material_pdf, light_pdf);
+                    } else {
+                        radiance += throughput.mul_element_wise(light);
+                    }
+
+                    return radiance;
+                }
+            };
+
+            let instance = &self.instances[intersection.instance];
+
+            let normal = instance.geometry.normal(
+                &instance.parameters,
+                intersection.hit,
+                ray.org + ray.dir * intersection.distance,
+                self.precision,
+            );
+
+            let inside = ray.dir.dot(normal) > 0.0;
+
+            let (medium, n1, n2) = instance.medium(inside);
+
+            let previous_throughput = throughput;
+
+            let (event, weight) =
+                medium.sample_distance(intersection.distance, rng.gen(), rng.gen());
+            throughput.mul_assign_element_wise(weight);
+
+            if let Some(distance) = event {
+                ray.org += ray.dir * distance;
+                throughput.mul_assign_element_wise(medium.scattering);
+
+                // Unlike the device, continue the path after sampling lights explicitly, so
+                // emissive surfaces and the environment are found by sampling the phase function.
+
+                if let Some(light_sample) =
+                    self.lights.sample(ray.org, rng.gen(), rng.gen(), rng.gen())
+                {
+                    let shadow_ray = ReferenceRay {
+                        org: ray.org,
+                        dir: light_sample.wi,
+                    };
+
+                    let offset = self.pushback * self.precision;
+
+                    if !self.is_ray_occluded(&shadow_ray, light_sample.distance - offset) {
+                        let phase = medium.phase_eval(light_sample.wi.dot(ray.dir));
+
+                        radiance += throughput
+                            .mul_element_wise(light_sample.weight)
+                            .mul_element_wise(medium.transmittance(light_sample.distance))
+                            * phase;
+                    }
+                }
+
+                let q = (1.0 - luminance(throughput) / luminance(previous_throughput)).max(0.0);
+
+                if rng.gen::<f32>() < q {
+                    return radiance;
+                }
+
+                throughput /= 1.0 - q;
+
+                ray.dir = medium.phase_sample(ray.dir, rng.gen(), rng.gen());
+                mis = false;
+
+                continue;
+            }
+
+            ray.org += ray.dir * intersection.distance;
+
+            let material = self.materials[instance.material].load(&self.textures, normal, ray.org);
+
+            if let LoadedMaterial::Emissive { radiance: emitted } = material {
+                if !inside {
+                    radiance += throughput.mul_element_wise(emitted);
+                }
+
+                return radiance;
+            }
+
+            mis = instance.sample_explicit && bounce != self.max_bounces - 1 && !inside;
+
+            let wo = -ray.dir;
+
+            let (mis_wi, light, light_pdf) = if mis {
+                self.environment.sample(rng.gen(), rng.gen())
+            } else {
+                (Vector3::zero(), Vector3::zero(), 0.0)
+            };
+
+            let (mut mis_f, mis_material_pdf) = if light_pdf != 0.0 {
+                let (f, pdf) = material.eval(normal, mis_wi, wo, n1, n2);
+
+                (
+                    f.mul_element_wise(throughput) * mis_wi.dot(normal).abs(),
+                    pdf,
+                )
+            } else {
+                (Vector3::zero(), 0.0)
+            };
+
+            let light_sample = if mis {
+                self.lights.sample(ray.org, rng.gen(), rng.gen(), rng.gen())
+            } else {
+                None
+            };
+
+            let mut light_f = if let Some(light_sample) = light_sample {
+                let (f, _) = material.eval(normal, light_sample.wi, wo, n1, n2);
+
+                f.mul_element_wise(throughput) * light_sample.wi.dot(normal).abs()
+            } else {
+                Vector3::zero()
+            };
+
+            let sample = material.sample(normal, wo, n1, n2, rng.gen(), rng.gen());
+            material_pdf = sample.pdf;
+
+            let q = (1.0
+                - luminance(throughput.mul_element_wise(sample.weight)) / luminance(throughput))
+            .max(0.0);
+
+            if rng.gen::<f32>() < q {
+                return radiance;
+            }
+
+            let adjustment = 1.0 / (1.0 - q);
+
+            throughput = throughput.mul_element_wise(sample.weight) * adjustment;
+            mis_f *= adjustment;
+            light_f *= adjustment;
+
+            if light_pdf != 0.0 && mis_material_pdf != 0.0 {
+                let shadow_ray = ReferenceRay::leaving_surface(
+                    ray.org,
+                    mis_wi,
+                    normal,
+                    self.pushback * self.precision,
+                );
+
+                if !self.is_ray_occluded(&shadow_ray, std::f32::INFINITY) {
+                    radiance += mis_f.mul_element_wise(light)
+                        * power_heuristic(light_pdf, mis_material_pdf);
+                }
+            }
+
+            if let Some(light_sample) = light_sample {
+                let offset = self.pushback * self.precision;
+
+                let shadow_ray =
+                    ReferenceRay::leaving_surface(ray.org, light_sample.wi, normal, offset);
+
+                if !self.is_ray_occluded(&shadow_ray, light_sample.distance - offset) {
+                    let (medium, _, _) = instance.medium(light_sample.wi.dot(normal) < 0.0);
+
+                    radiance += light_f
+                        .mul_element_wise(light_sample.weight)
+                        .mul_element_wise(medium.transmittance(light_sample.distance));
+                }
+            }
+
+            ray = ReferenceRay::leaving_surface(
+                ray.org,
+                sample.wi,
+                normal,
+                self.pushback * self.precision,
+            );
+        }
+
+        radiance
+    }
+
+    fn traverse_scene(&self, ray: &ReferenceRay) -> Option<Intersection> {
+        let idir = Vector3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let mut closest: Option<Intersection> = None;
+
+        for (index, instance) in self.instances.iter().enumerate() {
+            let limit = closest.map_or(std::f32::INFINITY, |closest| closest.distance);
+            let mut range = Vector2::new(0.0, limit);
+
+            if !ray_bbox(ray, idir, &mut range, &instance.bbox, self.precision) {
+                continue;
+            }
+
+            let hit =
+                instance
+                    .geometry
+                    .intersect(&instance.parameters, ray, &mut range, self.precision);
+
+            if let Some(hit) = hit {
+                closest = Some(Intersection {
+                    instance: index,
+                    distance: range.x,
+                    hit,
+                });
+            }
+        }
+
+        closest
+    }
+
+    fn is_ray_occluded(&self, ray: &ReferenceRay, limit: f32) -> bool {
+        let idir = Vector3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+
+        self.instances.iter().any(|instance| {
+            let mut range = Vector2::new(0.0, limit);
+
+            ray_bbox(ray, idir, &mut range, &instance.bbox, self.precision)
+                && (instance.geometry)
+                    .intersect(&instance.parameters, ray, &mut range, self.precision)
+                    .is_some()
+        })
+    }
+}
+
+fn power_heuristic(f: f32, g: f32) -> f32 {
+    (f * f) / (f * f + g * g)
+}
@@ -4,7 +4,9 @@
 #include <instance.glsl>
 #include <material.glsl>
 #include <environment.glsl>
+#include <light.glsl>
 #include <integrator.glsl>
+#include <spectrum.glsl>
 #include <camera.glsl>
 #include <quasi.glsl>
 
@@ -13,44 +15,109 @@ uniform sampler2D photon_table_sum;
 
 layout(location = 0) out vec4 radiance_estimate;
 
-vec3 get_photon(cell_t cell, vec3 point, uint mat_type, material_t material, vec3 normal, vec3 wo, float n1, float n2) {
-    ivec2 coords = hash_entry_for_cell(cell);
+vec3 get_photon(cell_t cell, vec3 point, bool volume) {
+    ivec2 coords = hash_entry_for_cell(cell, volume);
 
     vec4 pos_data = texelFetch(photon_table_pos, coords, 0);
     vec3 position = pos_data.xyz / pos_data.w;
 
     if (dot(point - position, point - position) <= integrator.search_radius_squared) {
-        vec3 throughput = 65536.0 * texelFetch(photon_table_sum, coords, 0).rgb;
-
-        #define MAT_SWITCH_LOGIC(LOAD, EVAL, SAMPLE) {                                            \
-            float unused_pdf;                                                                     \
-            return throughput * EVAL(material, normal, wo, wo, n1, n2, unused_pdf);               \
-        }
-
-        MAT_DO_SWITCH(mat_type)
-        #undef MAT_SWITCH_LOGIC
+        return 65536.0 * texelFetch(photon_table_sum, coords, 0).rgb;
     }
 
     return vec3(0.0);
 }
 
-vec3 query_photon_map(vec3 point, vec3 wo, vec3 normal, uint mat_type, material_t material, float n1, float n2) {
+// Returns the density of photon flux around a point, per unit area for surface photons or per
+// unit volume for volume photons. The photon map does not record incident directions, so the
+// receivers convert this density to radiance as if they were diffuse or isotropic.
+vec3 query_photon_map(vec3 point, bool volume) {
     cell_t cell = cell_for_point(point);
 
     vec3 d = sign(fract(point / integrator.cell_size) - vec3(0.5));
 
     vec3 estimate = vec3(0.0);
 
-    estimate += get_photon(cell + vec3(0.0, 0.0, 0.0), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(0.0, 0.0, d.z), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(0.0, d.y, 0.0), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(0.0, d.y, d.z), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(d.x, 0.0, 0.0), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(d.x, 0.0, d.z), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(d.x, d.y, 0.0), point, mat_type, material, normal, wo, n1, n2);
-    estimate += get_photon(cell + vec3(d.x, d.y, d.z), point, mat_type, material, normal, wo, n1, n2);
+    estimate += get_photon(cell + vec3(0.0, 0.0, 0.0), point, volume);
+    estimate += get_photon(cell + vec3(0.0, 0.0, d.z), point, volume);
+    estimate += get_photon(cell + vec3(0.0, d.y, 0.0), point, volume);
+    estimate += get_photon(cell + vec3(0.0, d.y, d.z), point, volume);
+    estimate += get_photon(cell + vec3(d.x, 0.0, 0.0), point, volume);
+    estimate += get_photon(cell + vec3(d.x, 0.0, d.z), point, volume);
+    estimate += get_photon(cell + vec3(d.x, d.y, 0.0), point, volume);
+    estimate += get_photon(cell + vec3(d.x, d.y, d.z), point, volume);
+
+    if (volume) {
+        return estimate / (4.0 / 3.0 * M_PI * integrator.search_radius_squared
+                                             * integrator.search_radius);
+    } else {
+        return estimate / (M_PI * integrator.search_radius_squared);
+    }
+}
+
+// Traces a ray leaving a receiver and returns whether it escaped the scene. Emissive surfaces
+// are not sampled explicitly and never deposit first bounce photons, so if the ray hits the
+// front side of one, the radiance it emits towards the receiver is returned as well.
+bool trace_receiver_ray(ray_t ray, out vec3 emitted) {
+    emitted = vec3(0.0);
+
+#if EMITTERS_PRESENT
+    traversal_t traversal = traverse_scene(ray, 0U);
+
+    if (!traversal_has_hit(traversal)) {
+        return true;
+    }
+
+    if (MAT_IS_EMISSIVE(traversal.hit.y & 0xffffU)) {
+        ray.org += ray.dir * traversal.range.y;
+
+        vec3 normal = geo_normal(traversal.hit.x & 0xffffU, traversal.hit.x >> 16U, ray.org);
+
+        if (dot(ray.dir, normal) < 0.0) {
+            material_t material;
+            float n1, n2;
+
+            mat_emissive_load(traversal.hit.y >> 16U, normal, ray.org, material);
+
+            medium_t medium = medium_for_instance(traversal.hit.x >> 16U, false, n1, n2);
+
+            emitted = MAT_EMISSIVE_RADIANCE * medium_transmittance(medium, traversal.range.y);
+        }
+    }
+
+    return false;
+#else
+    return !is_ray_occluded(ray, 1.0 / 0.0);
+#endif
+}
+
+// Estimates the radiance scattered along a ray by a medium at its origin. Lights are sampled
+// explicitly, emissive surfaces are found by sampling the phase function, and the remaining
+// light comes from volume photons, for which the phase function is assumed to be isotropic.
+vec3 gather_medium(ray_t ray, medium_t medium, float u1, float u2, float u3, float u4, float u5) {
+    vec3 radiance = vec3(0.0);
+
+    vec3 light_wi;
+    float light_distance;
+
+    vec3 light_weight = light_sample(ray.org, light_wi, light_distance, u3, u4, u5);
+
+    if (light_weight != vec3(0.0)) {
+        if (!is_ray_occluded(ray_t(ray.org, light_wi), light_distance - PUSHBACK * PREC)) {
+            radiance += light_weight * medium_transmittance(medium, light_distance)
+                      * medium_phase_eval(medium, dot(light_wi, ray.dir));
+        }
+    }
+
+#if EMITTERS_PRESENT
+    vec3 emitted, wi = medium_phase_sample(medium, ray.dir, u1, u2);
+
+    if (!trace_receiver_ray(ray_t(ray.org, wi), emitted)) {
+        radiance += emitted;
+    }
+#endif
 
-    return estimate / (M_PI * integrator.search_radius_squared);
+    return radiance + query_photon_map(ray.org, true) / (M_4PI * integrator.photons_for_pass);
 }
 
 vec3 gather_photons(ray_t ray, quasi_t quasi) {
@@ -64,9 +131,10 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
         traversal_t traversal = traverse_scene(ray, 0U);
 
         if (traversal_has_hit(traversal)) {
-            ray.org += ray.dir * traversal.range.y;
+            float distance = traversal.range.y;
 
-            vec3 normal = geo_normal(traversal.hit.x & 0xffffU, traversal.hit.x >> 16U, ray.org);
+            vec3 normal = geo_normal(traversal.hit.x & 0xffffU, traversal.hit.x >> 16U,
+                                     ray.org + ray.dir * distance);
 
             uint mat_type = traversal.hit.y & 0xffffU;
             uint mat_inst = traversal.hit.y >> 16U;
@@ -81,11 +149,33 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
             float u3 = quasi_sample(quasi);
             float u4 = quasi_sample(quasi);
             float u5 = quasi_sample(quasi);
+            float u6 = quasi_sample(quasi);
+            float u7 = quasi_sample(quasi);
+            float u8 = quasi_sample(quasi);
+            float u9 = quasi_sample(quasi);
+            float u10 = quasi_sample(quasi);
 
             float n1, n2;
 
-            throughput *= medium_absorption(traversal.hit.x >> 16U, inside,
-                                            traversal.range.y, n1, n2);
+            medium_t medium = medium_for_instance(traversal.hit.x >> 16U, inside, n1, n2);
+
+            if (medium_sample_distance(medium, distance, throughput, u9, u10)) {
+                ray.org += ray.dir * distance;
+                throughput *= medium.scattering;
+
+                return radiance + throughput * gather_medium(ray, medium, u3, u4, u6, u7, u8);
+            }
+
+            ray.org += ray.dir * distance;
+
+            if (MAT_IS_EMISSIVE(mat_type)) {
+                if (!inside) {
+                    mat_emissive_load(mat_inst, normal, ray.org, material);
+                    radiance += throughput * MAT_EMISSIVE_RADIANCE;
+                }
+
+                return radiance;
+            }
 
             mis = MAT_SAMPLE_EXPLICIT(mat_type) && (bounce != integrator.max_gather_bounces - 1U)
                                                 && !inside;
@@ -96,6 +186,15 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
             light_pdf = 0.0;
             vec3 light = mis ? env_sample_light(mis_wi, light_pdf, u1, u2) : vec3(0.0);
 
+            vec3 light_f = vec3(0.0), light_wi;
+            float light_distance;
+
+            vec3 light_weight = vec3(0.0);
+
+            if (mis) {
+                light_weight = light_sample(ray.org, light_wi, light_distance, u6, u7, u8);
+            }
+
             #define MAT_SWITCH_LOGIC(LOAD, EVAL, SAMPLE) {                                        \
                 LOAD(mat_inst, normal, ray.org, material);                                        \
                                                                                                   \
@@ -104,6 +203,12 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
                           * abs(dot(mis_wi, normal)) * throughput;                                \
                 }                                                                                 \
                                                                                                   \
+                if (light_weight != vec3(0.0)) {                                                  \
+                    float unused_pdf;                                                             \
+                    light_f = EVAL(material, normal, light_wi, -ray.dir, n1, n2, unused_pdf)      \
+                            * abs(dot(light_wi, normal)) * throughput;                            \
+                }                                                                                 \
+                                                                                                  \
                 f = SAMPLE(material, normal, wi, -ray.dir, n1, n2, material_pdf, u3, u4);         \
             }
 
@@ -121,6 +226,7 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
 
                 throughput *= f * adjustment;
                 mis_f *= adjustment;
+                light_f *= adjustment;
             }
 
             if (light_pdf != 0.0 && mis_material_pdf != 0.0) {
@@ -129,21 +235,45 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
                 }
             }
 
+            if (light_weight != vec3(0.0)) {
+                // Lights may lie against surfaces, so stop the shadow ray just before them.
+
+                ray_t shadow_ray = make_ray(ray.org, light_wi, normal);
+
+                if (!is_ray_occluded(shadow_ray, light_distance - PUSHBACK * PREC)) {
+                    float unused_n1, unused_n2;
+
+                    medium_t light_medium = medium_for_instance(traversal.hit.x >> 16U,
+                                                                dot(light_wi, normal) < 0.0,
+                                                                unused_n1, unused_n2);
+
+                    radiance += light_f * light_weight
+                              * medium_transmittance(light_medium, light_distance);
+                }
+            }
+
             if (is_receiver) {
                 if (mis && material_pdf != 0.0) {
                     // Finish the MIS direct light sampling procedure we started earlier; this
                     // is done to ensure that the MIS weights result in an unbiased estimator.
 
-                    if (!is_ray_occluded(make_ray(ray.org, wi, normal), 1.0 / 0.0)) {
+                    vec3 emitted;
+
+                    if (trace_receiver_ray(make_ray(ray.org, wi, normal), emitted)) {
                         vec3 light = env_eval_light(wi, light_pdf);
 
                         if (light_pdf != 0.0) {
                             radiance += throughput * f * light * power_heuristic(material_pdf, light_pdf);
                         }
+                    } else {
+                        radiance += throughput * f * emitted;
                     }
                 }
 
-                vec3 li = query_photon_map(ray.org, -ray.dir, normal, mat_type, material, n1, n2);
+                // The sampled BSDF weight is an estimate of the directional albedo of the
+                // receiver, which is exactly the BSDF times pi for the Lambertian material.
+
+                vec3 li = query_photon_map(ray.org, false) * f / M_PI;
                 radiance += throughput * li / integrator.photons_for_pass; // SPPM photon estimate
 
                 return radiance;
@@ -172,9 +302,11 @@ vec3 gather_photons(ray_t ray, quasi_t quasi) {
 void main() {
     uint seed = (uint(gl_FragCoord.x) << 16U) + uint(gl_FragCoord.y);
 
-    quasi_t quasi = quasi_init(integrator.current_pass, decorrelate_sample(seed));
+    uint sequence = decorrelate_sample(seed, integrator.sample_key);
+
+    quasi_t quasi = quasi_init(integrator.current_pass, sequence);
 
     ray_t ray = evaluate_camera_ray(gl_FragCoord.xy - 0.5, quasi);
 
-    radiance_estimate = vec4(gather_photons(ray, quasi), 1.0);
+    radiance_estimate = vec4(gather_photons(ray, quasi) * integrator.wavelength_weight, 1.0);
 }
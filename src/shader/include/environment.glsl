@@ -1,4 +1,5 @@
 #include <common.glsl>
+#include <spectrum.glsl>
 
 uniform sampler2D envmap_color;
 uniform sampler2D envmap_marg_cdf;
@@ -10,73 +11,92 @@ layout (std140) uniform Environment {
     float rotation;
     int has_envmap;
     vec3 tint;
+    float luminance;
 } environment;
 
+// The CDFs have an implicit final value of 1, and the probability of each pixel
+// is recovered from CDF differences exactly as in the EnvironmentSampler type.
+
+float cdf_bucket_probability(sampler2D texture, int y, int index, int size) {
+    float next_cdf = (index + 1 < size) ? texelFetch(texture, ivec2(index + 1, y), 0).x : 1.0;
+
+    return next_cdf - texelFetch(texture, ivec2(index, y), 0).x;
+}
+
 float inverse_transform(sampler2D texture, int y, float u, int size, out int index) {
     int low = 0, high = size;
-    float this_cdf, next_cdf;
 
     while (low < high) {
         int mid = (low + high) / 2;
 
-        float Fx = texelFetch(texture, ivec2(mid, y), 0).x;
-
-        if (Fx > u) {
+        if (texelFetch(texture, ivec2(mid, y), 0).x > u) {
             high = mid;
         } else {
             low = mid + 1;
-            this_cdf = Fx;
         }
     }
 
-    index = clamp(low - 1, 0, size - 2);
-
-    float du = u - this_cdf;
+    index = max(low - 1, 0);
 
-    next_cdf = texelFetch(texture, ivec2(index + 1, y), 0).x;
+    float probability = cdf_bucket_probability(texture, y, index, size);
+    float du = u - texelFetch(texture, ivec2(index, y), 0).x;
 
-    if (next_cdf - this_cdf > 0.0) {
-        du /= (next_cdf - this_cdf);
+    if (probability > 0.0) {
+        du /= probability;
     }
 
-    return (float(index) + du) / float(size);
+    return (float(index) + min(du, 1.0)) / float(size);
+}
+
+// Returns the PDF with respect to UV area of sampling a pixel of the map.
+float env_pixel_pdf(ivec2 pixel) {
+    float marg_pdf = cdf_bucket_probability(envmap_marg_cdf,       0, pixel.y, environment.rows);
+    float cond_pdf = cdf_bucket_probability(envmap_cond_cdf, pixel.y, pixel.x, environment.cols);
+
+    return marg_pdf * cond_pdf * float(environment.rows) * float(environment.cols);
 }
 
 vec3 env_sample_light_image(out vec3 wi, out float pdf, float u1, float u2) {
-    int index;
+    int row, col;
 
-    float sampled_v = inverse_transform(envmap_marg_cdf,     0, u1, environment.rows, index);
-    float sampled_u = inverse_transform(envmap_cond_cdf, index, u2, environment.cols, index);
+    float sampled_v = inverse_transform(envmap_marg_cdf,   0, u1, environment.rows, row);
+    float sampled_u = inverse_transform(envmap_cond_cdf, row, u2, environment.cols, col);
 
     wi = equirectangular_to_direction(vec2(sampled_u, sampled_v), environment.rotation);
 
-    vec4 value = textureLod(envmap_color, vec2(sampled_u, sampled_v), 0.0);
-    value.w *= 1024.0;
+    vec3 value = textureLod(envmap_color, vec2(sampled_u, sampled_v), 0.0).rgb;
+    float pixel_pdf = env_pixel_pdf(ivec2(col, row));
 
     float sin_theta = sin(sampled_v * M_PI);
 
-    if (sin_theta == 0.0 || value.w == 0.0) {
+    if (sin_theta == 0.0 || pixel_pdf == 0.0) {
         return pdf = 0.0, vec3(0.0);
     }
 
-    pdf = value.w / (sin_theta * 2.0 * M_PI * M_PI);
+    pdf = pixel_pdf / (sin_theta * 2.0 * M_PI * M_PI);
 
-    return environment.tint * value.rgb * (sin_theta * 2.0 * M_PI * M_PI) / value.w;
+    return environment.tint * value * (sin_theta * 2.0 * M_PI * M_PI) / pixel_pdf;
 }
 
 vec3 env_eval_light_image(vec3 wi, out float pdf) {
-    vec4 value = textureLod(envmap_color, direction_to_equirectangular(wi, environment.rotation), 0.0);
-    value.w *= 1024.0;
+    vec2 uv = direction_to_equirectangular(wi, environment.rotation);
+
+    vec3 value = textureLod(envmap_color, uv, 0.0).rgb;
+
+    ivec2 size = ivec2(environment.cols, environment.rows);
+    ivec2 pixel = clamp(ivec2(vec2(fract(uv.x), uv.y) * vec2(size)), ivec2(0), size - 1);
+
+    float pixel_pdf = env_pixel_pdf(pixel);
 
     float sin_theta = sqrt(max(0.0, 1.0 - wi.y * wi.y));
 
-    if (sin_theta == 0.0 || value.w == 0.0) {
+    if (sin_theta == 0.0 || pixel_pdf == 0.0) {
         return pdf = 0.0, vec3(0.0);
     }
 
-    pdf = value.w / (sin_theta * 2.0 * M_PI * M_PI);
+    pdf = pixel_pdf / (sin_theta * 2.0 * M_PI * M_PI);
 
-    return environment.tint * value.rgb;
+    return environment.tint * value;
 }
 
 vec3 env_sample_light_solid(out vec3 wi, out float pdf, float u1, float u2) {
@@ -100,16 +120,16 @@ vec3 env_eval_light_solid(vec3 wi, out float pdf) {
 
 vec3 env_sample_light(out vec3 wi, out float pdf, float u1, float u2) {
     if (environment.has_envmap == 1) {
-        return env_sample_light_image(wi, pdf, u1, u2);
+        return spectral_upsample(env_sample_light_image(wi, pdf, u1, u2));
     } else {
-        return env_sample_light_solid(wi, pdf, u1, u2);
+        return spectral_upsample(env_sample_light_solid(wi, pdf, u1, u2));
     }
 }
 
 vec3 env_eval_light(vec3 wi, out float pdf) {
     if (environment.has_envmap == 1) {
-        return env_eval_light_image(wi, pdf);
+        return spectral_upsample(env_eval_light_image(wi, pdf));
     } else {
-        return env_eval_light_solid(wi, pdf);
+        return spectral_upsample(env_eval_light_solid(wi, pdf));
     }
 }
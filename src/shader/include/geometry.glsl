@@ -7,4 +7,20 @@ layout (std140) uniform Geometry {
 bool geo_intersect(uint geometry, uint inst, ray_t ray, inout vec2 range);
 vec3 geo_normal(uint geometry, uint inst, vec3 p);
 
+#include <mesh.glsl>
+
+// Polynomial smooth minimum and maximum; these differ from the regular minimum
+// and maximum by at most k / 4 and do not increase the Lipschitz constant, so
+// they are safe to use with the sphere tracer in generated geometry functions.
+
+float geo_smooth_min(float a, float b, float k) {
+    float h = max(k - abs(a - b), 0.0) / k;
+
+    return min(a, b) - h * h * k * 0.25;
+}
+
+float geo_smooth_max(float a, float b, float k) {
+    return -geo_smooth_min(-a, -b, k);
+}
+
 #include <geometry-user.glsl>
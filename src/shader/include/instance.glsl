@@ -4,6 +4,7 @@
 // requires-define PUSHBACK
 
 #include <geometry.glsl>
+#include <spectrum.glsl>
 
 // Maintains closest-hit information during a traversal.
 struct traversal_t {
@@ -42,25 +43,115 @@ layout (std140) uniform Instance {
     BvhNode data[INSTANCE_DATA_LEN];
 } instance_buffer;
 
-vec3 medium_absorption(uint inst, bool inside, float distance, out float n1, out float n2) {
-    const vec3 WAVENUMBERS = M_2PI / vec3(685e-9, 530e-9, 470e-9);
+// Homogeneous medium, with extinction and scattering coefficients per unit distance and the
+// asymmetry parameter of its Henyey-Greenstein phase function.
+struct medium_t {
+    vec3 extinction;
+    vec3 scattering;
+    float asymmetry;
+};
 
+// The media outside and inside of an instance are stored in its first six parameter blocks,
+// as the extinction and refractive index of both media followed by their scattering albedo
+// and phase function asymmetry, and by their dispersion as a polynomial in 1 / λ² (in μm).
+// Also returns the refractive indices on either side, which vary with wavelength only for
+// the spectral integrator; otherwise the extinction is evaluated at the RGB wavelengths.
+medium_t medium_for_instance(uint inst, bool inside, out float n1, out float n2) {
     vec4 ext_medium = geometry_buffer.data[inst + 0U];
     vec4 int_medium = geometry_buffer.data[inst + 1U];
+    vec4 ext_scattering = geometry_buffer.data[inst + 2U];
+    vec4 int_scattering = geometry_buffer.data[inst + 3U];
+
+#if SPECTRAL
+    float x = 1e6 / (integrator.wavelength * integrator.wavelength);
+
+    ext_medium.w = dot(geometry_buffer.data[inst + 4U].xyz, vec3(1.0, x, x * x));
+    int_medium.w = dot(geometry_buffer.data[inst + 5U].xyz, vec3(1.0, x, x * x));
+
+    vec3 wavenumbers = vec3(M_2PI / (integrator.wavelength * 1e-9));
+#else
+    const vec3 wavenumbers = M_2PI / vec3(685e-9, 530e-9, 470e-9);
+#endif
 
     if (inside) {
         n1 = int_medium.w;
         n2 = ext_medium.w;
 
-        return exp(-int_medium.xyz * int_medium.w * distance * WAVENUMBERS);
+        vec3 extinction = spectral_upsample(int_medium.xyz) * int_medium.w * wavenumbers;
+
+        return medium_t(extinction, extinction * spectral_upsample(int_scattering.xyz),
+                        int_scattering.w);
     } else {
         n1 = ext_medium.w;
         n2 = int_medium.w;
 
-        return exp(-ext_medium.xyz * ext_medium.w * distance * WAVENUMBERS);
+        vec3 extinction = spectral_upsample(ext_medium.xyz) * ext_medium.w * wavenumbers;
+
+        return medium_t(extinction, extinction * spectral_upsample(ext_scattering.xyz),
+                        ext_scattering.w);
     }
 }
 
+vec3 medium_transmittance(medium_t medium, float distance) {
+    // Avoid 0 * infinity for non-absorbing wavelengths along infinitely long rays.
+    return mix(exp(-medium.extinction * distance), vec3(1.0), equal(medium.extinction, vec3(0.0)));
+}
+
+// Samples a scattering event along a ray segment of some length crossing a medium, choosing
+// one of the wavelengths uniformly to sample a distance from. Returns whether an event occurs
+// before the end of the segment, in which case the distance is set to its location, and then
+// multiplies the throughput by the transmittance divided by the probability of the outcome.
+bool medium_sample_distance(medium_t medium, inout float distance, inout vec3 throughput,
+                            float u1, float u2) {
+    if (medium.scattering == vec3(0.0)) {
+        throughput *= medium_transmittance(medium, distance);
+
+        return false;
+    }
+
+    float extinction = medium.extinction[min(int(u1 * 3.0), 2)];
+    float t = (extinction > 0.0) ? -log(1.0 - u2) / extinction : 1.0 / 0.0;
+
+    if (t >= distance) {
+        vec3 transmittance = medium_transmittance(medium, distance);
+        throughput *= transmittance * 3.0 / max(1e-30, dot(transmittance, vec3(1.0)));
+
+        return false;
+    }
+
+    vec3 transmittance = medium_transmittance(medium, t);
+    throughput *= transmittance * 3.0 / max(1e-30, dot(medium.extinction, transmittance));
+    distance = t;
+
+    return true;
+}
+
+// Henyey-Greenstein phase function, where the angle is between the propagation directions of
+// light before and after scattering, so positive asymmetry parameters scatter light forwards.
+float medium_phase_eval(medium_t medium, float cos_theta) {
+    float g = medium.asymmetry;
+    float denom = 1.0 + g * g - 2.0 * g * cos_theta;
+
+    return (1.0 - g * g) / (M_4PI * denom * sqrt(denom));
+}
+
+vec3 medium_phase_sample(medium_t medium, vec3 dir, float u1, float u2) {
+    float g = medium.asymmetry;
+    float cos_theta;
+
+    if (abs(g) < 1e-3) {
+        cos_theta = 1.0 - 2.0 * u1;
+    } else {
+        float s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+        cos_theta = clamp((1.0 + g * g - s * s) / (2.0 * g), -1.0, 1.0);
+    }
+
+    float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    float phi = M_2PI * u2;
+
+    return rotate(vec3(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi)), dir);
+}
+
 void get_scene_bbox(out vec3 bbmin, out vec3 bbmax) {
     bbmin = vec3(instance_buffer.data[0].minx,
                  instance_buffer.data[0].miny,
@@ -9,7 +9,7 @@ layout (std140) uniform Integrator {
     uint current_pass;
     float photon_count;
     float sppm_alpha;
-    uint padding;
+    uint sample_key;
 
     float search_radius;
     float search_radius_squared;
@@ -23,18 +23,25 @@ layout (std140) uniform Integrator {
 
     uint max_scatter_bounces;
     uint max_gather_bounces;
+
+    vec3 wavelength_weight;
+    float wavelength;
 } integrator;
 
 cell_t cell_for_point(vec3 point) {
     return floor(point / integrator.cell_size);
 }
 
-ivec2 hash_entry_for_cell(cell_t cell) {
+// Volume photons are stored in the same table as surface photons, but their cells are hashed
+// with an additional key so that surface and volume photons in the same cell rarely collide;
+// like any other hash collision, this only biases the estimate slightly.
+ivec2 hash_entry_for_cell(cell_t cell, bool volume) {
     uvec3 inputs = floatBitsToUint(cell);
 
     uint index = decorrelate_sample(inputs.x, integrator.hash_key.x)
                ^ decorrelate_sample(inputs.y, integrator.hash_key.y)
-               ^ decorrelate_sample(inputs.z, integrator.hash_key.z);
+               ^ decorrelate_sample(inputs.z, integrator.hash_key.z)
+               ^ (volume ? integrator.hash_key.w : 0U);
 
     return ivec2(index & integrator.hash_cols_mask, (index >> 16U) & integrator.hash_rows_mask);
 }
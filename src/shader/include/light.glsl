@@ -0,0 +1,186 @@
+// requires-define LIGHT_DATA_LEN
+// requires-define LIGHT_COUNT
+
+#include <common.glsl>
+#include <instance.glsl>
+#include <spectrum.glsl>
+
+#define LIGHT_TYPE_POINT 0U
+#define LIGHT_TYPE_SPOT 1U
+#define LIGHT_TYPE_SPHERE 2U
+#define LIGHT_TYPE_RECTANGLE 3U
+#define LIGHT_TYPE_DIRECTIONAL 4U
+#define LIGHT_TYPE_EMISSIVE 5U
+
+// Rectangle lights are stored as a corner with two edges in "direction" and "tangent", and
+// with their area in "param0". Spot lights store the cosines of their outer and inner cone
+// angles in "param0" and "param1", and sphere lights store their radius in "param0".
+//
+// Instances with an emissive material are also stored as lights, which only emit photons,
+// with their bounding box surface area in "param0" and their instance BVH node index in
+// "param1"; their emission is an estimate of their average radiance.
+
+struct LightData {
+    vec3 position;
+    uint kind;
+    vec3 direction;
+    float param0;
+    vec3 tangent;
+    float param1;
+    vec3 emission;
+    float padding;
+};
+
+layout (std140) uniform Light {
+    LightData data[LIGHT_DATA_LEN];
+} light_buffer;
+
+// Returns the luminance of the total power emitted by a light into the scene. Directional
+// lights only need to illuminate the scene's bounding box so their power depends on it.
+// Emissive instances cannot be sampled explicitly, so only have power if photons is set.
+float light_power(LightData light, vec3 bbmin, vec3 bbmax, bool photons) {
+    float power = luminance(light.emission);
+    vec3 extent = bbmax - bbmin;
+
+    switch (light.kind) {
+        case LIGHT_TYPE_POINT:
+            return power * M_4PI;
+        case LIGHT_TYPE_SPOT:
+            return power * M_2PI * (1.0 - light.param0);
+        case LIGHT_TYPE_SPHERE:
+            return power * M_4PI * M_PI * light.param0 * light.param0;
+        case LIGHT_TYPE_RECTANGLE:
+            return power * M_PI * light.param0;
+        case LIGHT_TYPE_DIRECTIONAL:
+            return power * dot(abs(light.direction), extent.yzx * extent.zxy);
+        case LIGHT_TYPE_EMISSIVE:
+            return photons ? power * M_PI * light.param0 : 0.0;
+    }
+
+    return 0.0;
+}
+
+float light_total_power(vec3 bbmin, vec3 bbmax, bool photons) {
+    float total = 0.0;
+
+#if LIGHT_COUNT > 0
+    for (uint i = 0U; i < uint(LIGHT_COUNT); ++i) {
+        total += light_power(light_buffer.data[i], bbmin, bbmax, photons);
+    }
+#endif
+
+    return total;
+}
+
+// Selects a light with probability proportional to its power, where u is uniform in the
+// range [0, total power). The power of the selected light is returned alongside it.
+LightData light_select(float u, vec3 bbmin, vec3 bbmax, bool photons, out float power) {
+    uint index = 0U;
+
+#if LIGHT_COUNT > 1
+    for (; index < uint(LIGHT_COUNT) - 1U; ++index) {
+        power = light_power(light_buffer.data[index], bbmin, bbmax, photons);
+
+        if (u < power) {
+            return light_buffer.data[index];
+        }
+
+        u -= power;
+    }
+#endif
+
+    power = light_power(light_buffer.data[index], bbmin, bbmax, photons);
+
+    return light_buffer.data[index];
+}
+
+// Returns the fraction of a spot light's intensity emitted in a given direction.
+float light_spot_falloff(LightData light, vec3 dir) {
+    float cos_theta = dot(dir, light.direction);
+
+    if (cos_theta >= light.param1) {
+        return 1.0;
+    } else if (cos_theta <= light.param0) {
+        return 0.0;
+    }
+
+    return smoothstep(light.param0, light.param1, cos_theta);
+}
+
+// Samples a direction towards one of the lights from a point, and returns the radiance
+// arriving from that direction divided by the PDF of the sample, along with the distance
+// to the sampled point on the light. Lights are never intersected by rays, so unlike the
+// environment there is no need for multiple importance sampling here.
+vec3 light_sample(vec3 point, out vec3 wi, out float distance, float u1, float u2, float u3) {
+    vec3 bbmin, bbmax;
+
+    get_scene_bbox(bbmin, bbmax);
+
+    float total = light_total_power(bbmin, bbmax, false);
+
+    if (total == 0.0) {
+        return vec3(0.0);
+    }
+
+    float power;
+    LightData light = light_select(u1 * total, bbmin, bbmax, false, power);
+
+    if (power == 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 weight = spectral_upsample(light.emission) * total / power;
+    vec3 d = light.position - point;
+
+    if (light.kind == LIGHT_TYPE_POINT) {
+        distance = length(d);
+        wi = d / distance;
+
+        return weight / dot(d, d);
+    } else if (light.kind == LIGHT_TYPE_SPOT) {
+        distance = length(d);
+        wi = d / distance;
+
+        return weight * light_spot_falloff(light, -wi) / dot(d, d);
+    } else if (light.kind == LIGHT_TYPE_SPHERE) {
+        float ratio = light.param0 * light.param0 / dot(d, d);
+
+        if (ratio >= 1.0) {
+            return vec3(0.0); // inside the sphere
+        }
+
+        float cos_max = sqrt(1.0 - ratio);
+        float one_minus_cos_max = ratio / (1.0 + cos_max);
+
+        float cos_theta = 1.0 - u2 * one_minus_cos_max;
+        float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+        float phi = M_2PI * u3;
+
+        wi = rotate(vec3(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi)), normalize(d));
+
+        float b = dot(d, wi);
+        distance = b - sqrt(max(0.0, b * b - dot(d, d) * (1.0 - ratio)));
+
+        return weight * M_2PI * one_minus_cos_max;
+    } else if (light.kind == LIGHT_TYPE_RECTANGLE) {
+        d += light.direction * u2 + light.tangent * u3;
+
+        distance = length(d);
+        wi = d / distance;
+
+        float cos_light = -dot(wi, cross(light.direction, light.tangent)) / light.param0;
+
+        if (cos_light <= 0.0) {
+            return vec3(0.0);
+        }
+
+        return weight * light.param0 * cos_light / dot(d, d);
+    } else if (light.kind == LIGHT_TYPE_DIRECTIONAL) {
+        distance = 1.0 / 0.0;
+        wi = -light.direction;
+
+        return weight;
+    }
+
+    return vec3(0.0);
+}
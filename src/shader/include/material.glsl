@@ -1,6 +1,7 @@
 // requires-define MATERIAL_DATA_LEN
 
 #include <common.glsl>
+#include <spectrum.glsl>
 
 struct GeometryParameter {
     vec3 base;
@@ -50,7 +51,7 @@ vec3 sample_texture_wraparound(float layer, vec2 uv) {
     return textureLod(material_textures, vec3(uv, layer), 0.0).xyz;
 }
 
-vec3 mat_param_vec3(uint inst, vec3 normal, vec3 p) {
+vec3 mat_param_rgb(uint inst, vec3 normal, vec3 p) {
     GeometryParameter param = material_buffer.data[inst];
 
     if (param.layer == 0xffffffffU || param.factor.xyz == vec3(0.0)) {
@@ -93,8 +94,15 @@ vec3 mat_param_vec3(uint inst, vec3 normal, vec3 p) {
                                              +  xy_sample * tri.z);
 }
 
+// Color parameters are upsampled to the current wavelength for the spectral integrator, while
+// scalar parameters are always the luminance of their RGB value.
+
+vec3 mat_param_vec3(uint inst, vec3 normal, vec3 p) {
+    return spectral_upsample(mat_param_rgb(inst, normal, p));
+}
+
 float mat_param_float(uint inst, vec3 normal, vec3 p) {
-    return luminance(mat_param_vec3(inst, normal, p));
+    return luminance(mat_param_rgb(inst, normal, p));
 }
 
 // Prior to using a material, its parameters must be loaded as a function of normal and shading
@@ -102,7 +110,7 @@ float mat_param_float(uint inst, vec3 normal, vec3 p) {
 // below so that the actual BRDF evaluation logic never has to do any texture fetches directly.
 
 struct material_t {
-    vec4 data[1];
+    vec4 data[3];
 };
 
 // == LAMBERTIAN =================================================================================
@@ -116,7 +124,87 @@ struct material_t {
 #define MAT_PHONG_EXPONENT                                                   material.data[0].w
 // == DIELECTRIC =================================================================================
 #define MAT_DIELECTRIC_BASE_COLOR                                            material.data[0].xyz
-#define MAT_DIELECTRIC_CONE_ANGLE                                            material.data[0].w
+#define MAT_DIELECTRIC_ALPHA                                                 material.data[0].w
+// == EMISSIVE ===================================================================================
+#define MAT_EMISSIVE_RADIANCE                                                material.data[0].xyz
+// == CONDUCTOR ==================================================================================
+#define MAT_CONDUCTOR_ETA                                                    material.data[0].xyz
+#define MAT_CONDUCTOR_ALPHA_X                                                material.data[0].w
+#define MAT_CONDUCTOR_K                                                      material.data[1].xyz
+#define MAT_CONDUCTOR_ALPHA_Y                                                material.data[1].w
+// == COATED ====================================================================================
+#define MAT_COAT_OPTICAL_DEPTH                                               material.data[2].xyz
+#define MAT_COAT_ALPHA                                                       material.data[2].w
+
+// == MICROFACET FUNCTIONS =======================================================================
+
+// GGX microfacet distribution with visible normal sampling, see "Sampling the GGX Distribution of
+// Visible Normals" by Heitz (2018). All computations take place in the local frame of the `rotate`
+// function with the normal along the Y axis; as it is its own inverse, it transforms either way.
+
+float fresnel_dielectric(float cos_i, float eta) {
+    float sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+
+    if (sin2_t >= 1.0) {
+        return 1.0;
+    }
+
+    float cos_t = sqrt(1.0 - sin2_t);
+
+    float rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    float rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+vec3 fresnel_conductor(float cos_i, vec3 eta, vec3 k) {
+    float cos2 = cos_i * cos_i;
+    float sin2 = 1.0 - cos2;
+
+    vec3 t0 = eta * eta - k * k - sin2;
+    vec3 a2b2 = sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+    vec3 t1 = a2b2 + cos2;
+    vec3 t2 = 2.0 * cos_i * sqrt(0.5 * (a2b2 + t0));
+    vec3 rs = (t1 - t2) / (t1 + t2);
+
+    vec3 t3 = cos2 * a2b2 + sin2 * sin2;
+    vec3 t4 = t2 * sin2;
+    vec3 rp = rs * (t3 - t4) / (t3 + t4);
+
+    return 0.5 * (rp + rs);
+}
+
+float ggx_distribution(vec3 h, float alpha_x, float alpha_y) {
+    float t = (h.x * h.x) / (alpha_x * alpha_x) + (h.z * h.z) / (alpha_y * alpha_y) + h.y * h.y;
+
+    return 1.0 / (M_PI * alpha_x * alpha_y * t * t);
+}
+
+float ggx_lambda(vec3 w, float alpha_x, float alpha_y) {
+    float t = (alpha_x * alpha_x * w.x * w.x + alpha_y * alpha_y * w.z * w.z) / (w.y * w.y);
+
+    return 0.5 * (sqrt(1.0 + t) - 1.0);
+}
+
+vec3 ggx_sample_visible_normal(vec3 w, float alpha_x, float alpha_y, float u1, float u2) {
+    vec3 v = normalize(vec3(alpha_x * w.x, w.y, alpha_y * w.z));
+
+    float len2 = v.x * v.x + v.z * v.z;
+    vec3 t1 = len2 > 0.0 ? vec3(v.z, 0.0, -v.x) * inversesqrt(len2) : vec3(1.0, 0.0, 0.0);
+    vec3 t2 = cross(v, t1);
+
+    float r = sqrt(u1);
+    float phi = M_2PI * u2;
+    float p1 = r * cos(phi);
+    float p2 = r * sin(phi);
+    float s = 0.5 * (1.0 + v.y);
+
+    p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * p2;
+
+    vec3 h = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * v;
+
+    return normalize(vec3(alpha_x * h.x, max(0.0, h.y), alpha_y * h.z));
+}
 
 // == LAMBERTIAN BRDF ============================================================================
 
@@ -244,63 +332,379 @@ vec3 mat_phong_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, fl
 
 // == DIELECTRIC BSDF ============================================================================
 
+// GGX microfacet BSDF for rough interfaces, see "Microfacet Models for Refraction through Rough
+// Surfaces" by Walter et al. (2007). Like the ideal refraction BSDF, the transmission lobe is not
+// scaled by the squared ratio of refractive indices, as the factor is cancelled out as light exits
+// the medium; the only exception being light sources inside media, which we do not care about.
+
 void mat_dielectric_load(uint inst, vec3 normal, vec3 point, out material_t material) {
+    float roughness = clamp(mat_param_float(inst + 1U, normal, point), 0.0, 1.0);
+
     MAT_DIELECTRIC_BASE_COLOR = clamp(mat_param_vec3(inst + 0U, normal, point), 0.0, 1.0);
-    MAT_DIELECTRIC_CONE_ANGLE = cos(clamp(mat_param_float(inst + 1U, normal, point), 0.0, M_PI * 0.5));
+    MAT_DIELECTRIC_ALPHA = max(1e-3, roughness * roughness);
+}
+
+// Returns the probability of sampling the reflection lobe; it has to be chosen before sampling a
+// microfacet normal, so use the Fresnel reflectance of the surface and keep both lobes reachable.
+float mat_dielectric_reflection_probability(float cos_o, float n1, float n2) {
+    return clamp(fresnel_dielectric(cos_o, n2 / n1), 0.05, 0.95);
 }
 
 vec3 mat_dielectric_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, float n2, out float pdf) {
-    return pdf = 0.0, vec3(0.0);
+    if (dot(wo, normal) < 0.0) {
+        normal = -normal;
+    }
+
+    wi = rotate(wi, normal);
+    wo = rotate(wo, normal);
+
+    if (wo.y <= 0.0 || wi.y == 0.0) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float p = mat_dielectric_reflection_probability(wo.y, n1, n2);
+
+    float lambda_o = ggx_lambda(wo, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+    float lambda_i = ggx_lambda(wi, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+
+    if (wi.y > 0.0) {
+        vec3 h = normalize(wi + wo);
+
+        float d = ggx_distribution(h, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+        float f = fresnel_dielectric(dot(wo, h), n2 / n1);
+
+        pdf = p * d / (4.0 * wo.y * (1.0 + lambda_o));
+
+        return MAT_DIELECTRIC_BASE_COLOR * f * d / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i));
+    }
+
+    vec3 h = n1 * wo + n2 * wi;
+
+    if (h == vec3(0.0)) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    h = normalize(h) * sign(h.y);
+
+    float wo_h = dot(wo, h);
+    float wi_h = dot(wi, h);
+
+    if (wo_h <= 0.0 || wi_h >= 0.0) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float d = ggx_distribution(h, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+    float f = fresnel_dielectric(wo_h, n2 / n1);
+
+    float denom = n1 * wo_h + n2 * wi_h;
+    float jacobian = n2 * n2 * -wi_h / max(1e-7, denom * denom);
+
+    pdf = (1.0 - p) * d * wo_h * jacobian / (wo.y * (1.0 + lambda_o));
+
+    return MAT_DIELECTRIC_BASE_COLOR * (1.0 - f) * d * wo_h * jacobian
+         / (wo.y * -wi.y * (1.0 + lambda_o + lambda_i));
 }
 
 vec3 mat_dielectric_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, float n1, float n2, out float pdf, float u1, float u2) {
-    pdf = 1.0;
+    if (dot(wo, normal) < 0.0) {
+        normal = -normal;
+    }
 
-    float cosI = dot(-wo, normal);
+    vec3 local_wo = rotate(wo, normal);
 
-    if (cosI < 0.0) {
-        cosI = -cosI;
-    } else {
-        normal = -normal;
+    if (local_wo.y <= 0.0) {
+        wi = normal;
+
+        return pdf = 0.0, vec3(0.0);
     }
 
-    float eta = n1 / n2;
+    float p = mat_dielectric_reflection_probability(local_wo.y, n1, n2);
+
+    bool reflection = u1 < p;
+    u1 = reflection ? u1 / p : (u1 - p) / (1.0 - p);
 
-    float cosT = 1.0 - eta * eta * (1.0 - cosI * cosI);
+    vec3 h = ggx_sample_visible_normal(local_wo, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA, u1, u2);
+    vec3 local_wi = reflection ? reflect(-local_wo, h) : refract(-local_wo, h, n1 / n2);
 
-    if (cosT > 0.0) {
-        cosT = sqrt(cosT);
+    wi = rotate(local_wi, normal);
 
-        // Account for change in beam area and wave velocity; the change in wave
-        // velocity is only important if a light source exists inside the medium
-        // as otherwise the factor is cancelled out as the ray exits the medium.
+    if (local_wi == vec3(0.0) || (local_wi.y > 0.0) != reflection || local_wi.y == 0.0) {
+        return pdf = 0.0, vec3(0.0); // total internal reflection or wrong side of the surface
+    }
 
-        float ts = 1.0 / (n1 * cosI + n2 * cosT); // s-polarized fresnel
-        float tp = 1.0 / (n1 * cosT + n2 * cosI); // p-polarized fresnel
-        float t = 2.0 * (ts * ts + tp * tp) * (n1 * cosI) * (n2 * cosT);
+    float d = ggx_distribution(h, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+    float f = fresnel_dielectric(dot(local_wo, h), n2 / n1);
 
-        if (u1 < t) {
-            wi = (eta * cosI - cosT) * normal - eta * wo;
-        } else {
-            wi = reflect(-wo, normal);
-        }
+    float lambda_o = ggx_lambda(local_wo, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+    float lambda_i = ggx_lambda(local_wi, MAT_DIELECTRIC_ALPHA, MAT_DIELECTRIC_ALPHA);
+
+    float g = (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i);
+
+    if (reflection) {
+        pdf = p * d / (4.0 * local_wo.y * (1.0 + lambda_o));
+
+        return MAT_DIELECTRIC_BASE_COLOR * f * g / p;
+    }
+
+    float wo_h = dot(local_wo, h);
+    float wi_h = dot(local_wi, h);
+
+    float denom = n1 * wo_h + n2 * wi_h;
+    float jacobian = n2 * n2 * -wi_h / max(1e-7, denom * denom);
+
+    pdf = (1.0 - p) * d * wo_h * jacobian / (local_wo.y * (1.0 + lambda_o));
+
+    return MAT_DIELECTRIC_BASE_COLOR * (1.0 - f) * g / (1.0 - p);
+}
+
+// == EMISSIVE BSDF ==============================================================================
+
+// Emissive surfaces absorb all incident light, so their BSDF is zero; the light they emit is
+// accounted for separately by the integrator whenever a ray happens to hit their front side.
+
+void mat_emissive_load(uint inst, vec3 normal, vec3 point, out material_t material) {
+    MAT_EMISSIVE_RADIANCE = max(mat_param_vec3(inst + 0U, normal, point), 0.0);
+}
+
+vec3 mat_emissive_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, float n2, out float pdf) {
+    return pdf = 0.0, vec3(0.0);
+}
+
+vec3 mat_emissive_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, float n1, float n2, out float pdf, float u1, float u2) {
+    wi = normal;
+
+    return pdf = 0.0, vec3(0.0);
+}
+
+// == CONDUCTOR BRDF =============================================================================
+
+void mat_conductor_load(uint inst, vec3 normal, vec3 point, out material_t material) {
+    MAT_CONDUCTOR_ETA = max(mat_param_vec3(inst + 0U, normal, point), 0.0);
+    MAT_CONDUCTOR_K = max(mat_param_vec3(inst + 1U, normal, point), 0.0);
+
+    float roughness = clamp(mat_param_float(inst + 2U, normal, point), 0.0, 1.0);
+    float anisotropy = clamp(mat_param_float(inst + 3U, normal, point), 0.0, 1.0);
+
+    float aspect = sqrt(1.0 - 0.9 * anisotropy);
+
+    MAT_CONDUCTOR_ALPHA_X = max(1e-3, roughness * roughness / aspect);
+    MAT_CONDUCTOR_ALPHA_Y = max(1e-3, roughness * roughness * aspect);
+}
+
+vec3 mat_conductor_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, float n2, out float pdf) {
+    wi = rotate(wi, normal);
+    wo = rotate(wo, normal);
+
+    if (wi.y <= 0.0 || wo.y <= 0.0) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    vec3 h = normalize(wi + wo);
+
+    float d = ggx_distribution(h, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+    float lambda_o = ggx_lambda(wo, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+    float lambda_i = ggx_lambda(wi, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+
+    vec3 f = fresnel_conductor(dot(wi, h), MAT_CONDUCTOR_ETA / n1, MAT_CONDUCTOR_K / n1);
+
+    pdf = d / (4.0 * wo.y * (1.0 + lambda_o));
+
+    return f * d / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i));
+}
+
+vec3 mat_conductor_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, float n1, float n2, out float pdf, float u1, float u2) {
+    vec3 local_wo = rotate(wo, normal);
+
+    if (local_wo.y <= 0.0) {
+        wi = normal;
+
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    vec3 h = ggx_sample_visible_normal(local_wo, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y, u1, u2);
+    vec3 local_wi = reflect(-local_wo, h);
+
+    wi = rotate(local_wi, normal);
+
+    if (local_wi.y <= 0.0) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float d = ggx_distribution(h, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+    float lambda_o = ggx_lambda(local_wo, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+    float lambda_i = ggx_lambda(local_wi, MAT_CONDUCTOR_ALPHA_X, MAT_CONDUCTOR_ALPHA_Y);
+
+    vec3 f = fresnel_conductor(dot(local_wi, h), MAT_CONDUCTOR_ETA / n1, MAT_CONDUCTOR_K / n1);
+
+    pdf = d / (4.0 * local_wo.y * (1.0 + lambda_o));
+
+    return f * (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i);
+}
+
+// == COATED BSDF ================================================================================
+
+// Layered material made of a base BRDF, reusing the parameters of the Lambertian or conductor
+// materials, covered by a rough dielectric coat. The base is evaluated in the same directions as
+// the coat and is attenuated by the Fresnel transmittance and the absorption of the coat on the
+// way in and on the way out; the directions are chosen by sampling either the coat or the base.
+
+#define COAT_IOR 1.5
+
+// Fits of the hemispherical average of the dielectric Fresnel reflectance by Egan and Hilgeman
+// (1973) and d'Eon and Irving (2011), used to account for light trapped underneath the coat.
+
+float fresnel_diffuse_reflectance(float eta) {
+    if (eta < 1.0) {
+        return -1.4399 * eta * eta + 0.7099 * eta + 0.6681 + 0.0636 / eta;
+    }
+
+    float x = 1.0 / eta;
+
+    return 0.919317 + x * (-3.4793 + x * (6.75335 + x * (-7.80989 + x * (4.98554 - x * 1.36881))));
+}
+
+void mat_coat_load(uint inst, vec3 normal, vec3 point, inout material_t material) {
+    float roughness = clamp(mat_param_float(inst + 0U, normal, point), 0.0, 1.0);
+    float thickness = max(mat_param_float(inst + 1U, normal, point), 0.0);
+
+    MAT_COAT_ALPHA = max(1e-3, roughness * roughness);
+    MAT_COAT_OPTICAL_DEPTH = max(mat_param_vec3(inst + 2U, normal, point), 0.0) * thickness;
+}
+
+vec3 mat_coat_transmittance(material_t material, float cos_w, float eta) {
+    float cos_t = sqrt(max(0.0, 1.0 - (1.0 - cos_w * cos_w) / (eta * eta)));
+
+    return (1.0 - fresnel_dielectric(cos_w, eta)) * exp(-MAT_COAT_OPTICAL_DEPTH / max(cos_t, 1e-4));
+}
+
+// Returns the probability of sampling the coat rather than the base, given the luminance of the
+// reflectance of the base; this is an estimate of the fraction of light reflected by the coat.
+float mat_coat_probability(material_t material, float cos_o, float eta, float base_reflectance) {
+    float coat = fresnel_dielectric(cos_o, eta);
+    float base = luminance(mat_coat_transmittance(material, cos_o, eta)) * base_reflectance;
+
+    return (coat + base > 0.0) ? coat / (coat + base) : 1.0;
+}
+
+vec3 mat_coat_sample(material_t material, vec3 normal, vec3 wo, float u1, float u2) {
+    vec3 local_wo = rotate(wo, normal);
+    vec3 h = ggx_sample_visible_normal(local_wo, MAT_COAT_ALPHA, MAT_COAT_ALPHA, u1, u2);
+
+    return rotate(reflect(-local_wo, h), normal);
+}
+
+// Adds the coat lobe to a base BRDF evaluated in the same directions, along with their PDFs.
+vec3 mat_coat_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, vec3 base_f, float base_pdf, float base_reflectance, out float pdf) {
+    wi = rotate(wi, normal);
+    wo = rotate(wo, normal);
+
+    if (wi.y <= 0.0 || wo.y <= 0.0) {
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float eta = COAT_IOR / n1;
+
+    vec3 h = normalize(wi + wo);
+
+    float d = ggx_distribution(h, MAT_COAT_ALPHA, MAT_COAT_ALPHA);
+    float lambda_o = ggx_lambda(wo, MAT_COAT_ALPHA, MAT_COAT_ALPHA);
+    float lambda_i = ggx_lambda(wi, MAT_COAT_ALPHA, MAT_COAT_ALPHA);
+
+    float coat_f = fresnel_dielectric(dot(wi, h), eta) * d / (4.0 * wo.y * wi.y * (1.0 + lambda_o + lambda_i));
+    float coat_pdf = d / (4.0 * wo.y * (1.0 + lambda_o));
+
+    pdf = mix(base_pdf, coat_pdf, mat_coat_probability(material, wo.y, eta, base_reflectance));
+
+    return coat_f + base_f * mat_coat_transmittance(material, wi.y, eta)
+                           * mat_coat_transmittance(material, wo.y, eta);
+}
+
+void mat_coated_lambertian_load(uint inst, vec3 normal, vec3 point, out material_t material) {
+    mat_lambertian_load(inst, normal, point, material);
+    mat_coat_load(inst + 1U, normal, point, material);
+}
+
+vec3 mat_coated_lambertian_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, float n2, out float pdf) {
+    float eta = COAT_IOR / n1;
+    float base_pdf = max(0.0, dot(wi, normal)) / M_PI;
+
+    // Light scattered by the base is partly reflected back into it by the coat, and so on; the
+    // resulting geometric series also accounts for the radiance compression across the coat.
+
+    float fdr = fresnel_diffuse_reflectance(1.0 / eta);
+    vec3 base_f = MAT_LAMBERTIAN_ALBEDO / (M_PI * eta * eta * (1.0 - MAT_LAMBERTIAN_ALBEDO * fdr));
+
+    return mat_coat_eval(material, normal, wi, wo, n1, base_f, base_pdf,
+                         luminance(MAT_LAMBERTIAN_ALBEDO), pdf);
+}
+
+vec3 mat_coated_lambertian_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, float n1, float n2, out float pdf, float u1, float u2) {
+    float cos_o = dot(wo, normal);
+
+    if (cos_o <= 0.0) {
+        wi = normal;
+
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float p = mat_coat_probability(material, cos_o, COAT_IOR / n1, luminance(MAT_LAMBERTIAN_ALBEDO));
+
+    if (u1 < p) {
+        wi = mat_coat_sample(material, normal, wo, u1 / p, u2);
     } else {
-        wi = reflect(-wo, normal);
+        float unused_pdf;
+        mat_lambertian_sample(material, normal, wi, wo, n1, n2, unused_pdf, (u1 - p) / (1.0 - p), u2);
     }
 
-    // For basic, but not physically-based roughness support, adjust the sampled vector
-    // in a small cone of configurable angle around itself to simulate a rough surface.
+    vec3 f = mat_coated_lambertian_eval(material, normal, wi, wo, n1, n2, pdf);
+
+    return (pdf == 0.0) ? vec3(0.0) : f * dot(wi, normal) / pdf;
+}
+
+void mat_coated_conductor_load(uint inst, vec3 normal, vec3 point, out material_t material) {
+    mat_conductor_load(inst, normal, point, material);
+    mat_coat_load(inst + 4U, normal, point, material);
+}
+
+float mat_coated_conductor_reflectance(material_t material, float cos_o) {
+    return luminance(fresnel_conductor(cos_o, MAT_CONDUCTOR_ETA / COAT_IOR, MAT_CONDUCTOR_K / COAT_IOR));
+}
+
+vec3 mat_coated_conductor_eval(material_t material, vec3 normal, vec3 wi, vec3 wo, float n1, float n2, out float pdf) {
+    float base_pdf;
+    vec3 base_f = mat_conductor_eval(material, normal, wi, wo, COAT_IOR, n2, base_pdf);
+
+    return mat_coat_eval(material, normal, wi, wo, n1, base_f, base_pdf,
+                         mat_coated_conductor_reflectance(material, dot(wo, normal)), pdf);
+}
+
+vec3 mat_coated_conductor_sample(material_t material, vec3 normal, out vec3 wi, vec3 wo, float n1, float n2, out float pdf, float u1, float u2) {
+    float cos_o = dot(wo, normal);
 
-    if (MAT_DIELECTRIC_CONE_ANGLE < 1.0) {
-        float z = mix(MAT_DIELECTRIC_CONE_ANGLE, 1.0, u1);
-        float r = sqrt(1.0 - z * z);
+    if (cos_o <= 0.0) {
+        wi = normal;
 
-        wi = rotate(vec3(r * cos(M_2PI * u2), z, r * sin(M_2PI * u2)), wi);
+        return pdf = 0.0, vec3(0.0);
+    }
+
+    float p = mat_coat_probability(material, cos_o, COAT_IOR / n1,
+                                   mat_coated_conductor_reflectance(material, cos_o));
+
+    if (u1 < p) {
+        wi = mat_coat_sample(material, normal, wo, u1 / p, u2);
+    } else {
+        float unused_pdf;
+        mat_conductor_sample(material, normal, wi, wo, COAT_IOR, n2, unused_pdf, (u1 - p) / (1.0 - p), u2);
     }
 
-    return MAT_DIELECTRIC_BASE_COLOR;
+    vec3 f = mat_coated_conductor_eval(material, normal, wi, wo, n1, n2, pdf);
+
+    return (pdf == 0.0) ? vec3(0.0) : f * dot(wi, normal) / pdf;
 }
 
+#define MAT_IS_EMISSIVE(mat_type) \
+    ((mat_type & 0x3fffU) == 5U)
+
 #define MAT_IS_RECEIVER(mat_type) \
     ((mat_type & 0x8000U) != 0U)
 
@@ -337,4 +741,24 @@ vec3 mat_dielectric_sample(material_t material, vec3 normal, out vec3 wi, vec3 w
                              mat_dielectric_eval,                                                 \
                              mat_dielectric_sample)                                               \
             break;                                                                                \
+        case 5U:                                                                                  \
+            MAT_SWITCH_LOGIC(mat_emissive_load,                                                   \
+                             mat_emissive_eval,                                                   \
+                             mat_emissive_sample)                                                 \
+            break;                                                                                \
+        case 6U:                                                                                  \
+            MAT_SWITCH_LOGIC(mat_conductor_load,                                                  \
+                             mat_conductor_eval,                                                  \
+                             mat_conductor_sample)                                                \
+            break;                                                                                \
+        case 7U:                                                                                  \
+            MAT_SWITCH_LOGIC(mat_coated_lambertian_load,                                          \
+                             mat_coated_lambertian_eval,                                          \
+                             mat_coated_lambertian_sample)                                        \
+            break;                                                                                \
+        case 8U:                                                                                  \
+            MAT_SWITCH_LOGIC(mat_coated_conductor_load,                                           \
+                             mat_coated_conductor_eval,                                           \
+                             mat_coated_conductor_sample)                                         \
+            break;                                                                                \
     }
@@ -0,0 +1,131 @@
+// requires-define MESH_DATA_COLS
+// requires-define PREC
+
+#include <common.glsl>
+
+uniform sampler2D mesh_nodes;
+uniform sampler2D mesh_triangles;
+
+// Closest triangle found by the last successful call to `mesh_intersect`, this
+// is used by `mesh_normal` and must be consumed before the next mesh traversal.
+uint mesh_hit_triangle;
+vec2 mesh_hit_barycentrics;
+
+vec4 mesh_node_texel(uint index) {
+    return texelFetch(mesh_nodes, ivec2(index % MESH_DATA_COLS, index / MESH_DATA_COLS), 0);
+}
+
+vec4 mesh_triangle_texel(uint index) {
+    return texelFetch(mesh_triangles, ivec2(index % MESH_DATA_COLS, index / MESH_DATA_COLS), 0);
+}
+
+// Rotates a vector about a unit axis using Rodrigues' rotation formula, this is
+// used by mesh geometries to transform rays and normals into and out of meshes.
+vec3 mesh_rotate(vec3 v, vec3 k, float theta) {
+    float cosTheta = cos(theta);
+    float sinTheta = sin(theta);
+
+    return v * cosTheta + cross(k, v) * sinTheta + k * dot(k, v) * (1.0 - cosTheta);
+}
+
+// Moller-Trumbore ray-triangle intersection, which shortens the ray range if
+// the triangle is hit within that range (back-facing triangles are included).
+bool mesh_intersect_triangle(uint triangle, ray_t ray, inout vec2 range, out vec2 uv) {
+    vec3 v0 = mesh_triangle_texel(6U * triangle + 0U).xyz;
+    vec3 e1 = mesh_triangle_texel(6U * triangle + 1U).xyz - v0;
+    vec3 e2 = mesh_triangle_texel(6U * triangle + 2U).xyz - v0;
+
+    vec3 p = cross(ray.dir, e2);
+    float det = dot(e1, p);
+
+    if (det == 0.0) {
+        return false;
+    }
+
+    vec3 s = ray.org - v0;
+    vec3 q = cross(s, e1);
+
+    float u = dot(s, p) / det;
+    float v = dot(ray.dir, q) / det;
+    float t = dot(e2, q) / det;
+
+    if (u < 0.0 || v < 0.0 || u + v > 1.0 || t < range.x || t > range.y) {
+        return false;
+    }
+
+    range.y = t;
+    uv = vec2(u, v);
+
+    return true;
+}
+
+// Finds the closest triangle intersection within the BVH nodes [root, end),
+// setting the start of the ray range to the intersection distance on a hit.
+bool mesh_intersect(uint root, uint end, ray_t ray, inout vec2 range) {
+    vec3 idir = vec3(1.0) / ray.dir;
+    uint closest = 0xffffffffU;
+    vec2 closest_uv;
+    uint index = root;
+
+    while (index != end) {
+        vec4 lower = mesh_node_texel(2U * index + 0U);
+        vec4 upper = mesh_node_texel(2U * index + 1U);
+
+        vec2 node_range = range;
+
+        if (ray_bbox(ray.org, idir, node_range, lower.xyz - PREC, upper.xyz + PREC)) {
+            if (upper.w >= 0.0) {
+                uint leaf = uint(upper.w);
+
+                uint first = leaf >> 3U;
+                uint count = leaf & 7U;
+
+                for (uint triangle = first; triangle < first + count; ++triangle) {
+                    vec2 uv;
+
+                    if (mesh_intersect_triangle(triangle, ray, range, uv)) {
+                        closest = triangle;
+                        closest_uv = uv;
+                    }
+                }
+            }
+
+            index += 1U;
+        } else {
+            index = uint(lower.w);
+        }
+    }
+
+    if (closest == 0xffffffffU) {
+        return false;
+    }
+
+    mesh_hit_triangle = closest;
+    mesh_hit_barycentrics = closest_uv;
+    range.x = range.y;
+
+    return true;
+}
+
+// Interpolates the vertex normals of the last triangle hit, falling back to the
+// face normal if the interpolated normal vanishes (e.g. on degenerate normals).
+vec3 mesh_normal(vec3 p) {
+    vec3 n0 = mesh_triangle_texel(6U * mesh_hit_triangle + 3U).xyz;
+    vec3 n1 = mesh_triangle_texel(6U * mesh_hit_triangle + 4U).xyz;
+    vec3 n2 = mesh_triangle_texel(6U * mesh_hit_triangle + 5U).xyz;
+
+    float u = mesh_hit_barycentrics.x;
+    float v = mesh_hit_barycentrics.y;
+
+    vec3 normal = n0 * (1.0 - u - v) + n1 * u + n2 * v;
+
+    if (dot(normal, normal) == 0.0) {
+        vec3 v0 = mesh_triangle_texel(6U * mesh_hit_triangle + 0U).xyz;
+        vec3 v1 = mesh_triangle_texel(6U * mesh_hit_triangle + 1U).xyz;
+        vec3 v2 = mesh_triangle_texel(6U * mesh_hit_triangle + 2U).xyz;
+
+        return normalize(cross(v1 - v0, v2 - v0));
+    }
+
+    return normalize(normal);
+}
@@ -0,0 +1,19 @@
+// requires-define SPECTRAL
+
+#include <integrator.glsl>
+
+// The spectral integrator traces a single wavelength per pass, with every color converted to
+// its value at that wavelength in all three channels. Colors are upsampled using a smooth
+// basis splitting the visible range into blue, green and red regions, which keeps reflectances
+// within [0, 1] and approximately round-trips through the CIE color matching functions.
+
+vec3 spectral_upsample(vec3 color) {
+#if SPECTRAL
+    float b = 1.0 - smoothstep(445.0, 535.0, integrator.wavelength);
+    float r = smoothstep(570.0, 605.0, integrator.wavelength);
+
+    return vec3(dot(color, vec3(r, 1.0 - r - b, b)));
+#else
+    return color;
+#endif
+}
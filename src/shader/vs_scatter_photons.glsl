@@ -7,15 +7,17 @@ out vec3 photon_sum_data;
 #include <instance.glsl>
 #include <material.glsl>
 #include <environment.glsl>
+#include <light.glsl>
 #include <integrator.glsl>
+#include <spectrum.glsl>
 #include <quasi.glsl>
 
 layout (std140) uniform Raster {
     vec4 dimensions;
 } raster;
 
-void deposit_photon(ray_t ray, vec3 throughput) {
-    ivec2 coords = hash_entry_for_cell(cell_for_point(ray.org));
+void deposit_photon(ray_t ray, vec3 throughput, bool volume) {
+    ivec2 coords = hash_entry_for_cell(cell_for_point(ray.org), volume);
 
     photon_pos_data = ray.org;
     photon_sum_data = throughput / 65536.0;
@@ -29,9 +31,10 @@ void scatter_photon(ray_t ray, vec3 throughput, quasi_t quasi) {
         traversal_t traversal = traverse_scene(ray, 0U);
 
         if (traversal_has_hit(traversal)) {
-            ray.org += ray.dir * traversal.range.y;
+            float distance = traversal.range.y;
 
-            vec3 normal = geo_normal(traversal.hit.x & 0xffffU, traversal.hit.x >> 16U, ray.org);
+            vec3 normal = geo_normal(traversal.hit.x & 0xffffU, traversal.hit.x >> 16U,
+                                     ray.org + ray.dir * distance);
 
             uint mat_type = traversal.hit.y & 0xffffU;
             uint mat_inst = traversal.hit.y >> 16U;
@@ -41,6 +44,44 @@ void scatter_photon(ray_t ray, vec3 throughput, quasi_t quasi) {
             float u2 = quasi_sample(quasi);
             float u3 = quasi_sample(quasi);
             float u4 = quasi_sample(quasi);
+            float u5 = quasi_sample(quasi);
+            float u6 = quasi_sample(quasi);
+
+            bool inside = dot(ray.dir, normal) > 0.0;
+
+            float n1, n2;
+
+            medium_t medium = medium_for_instance(traversal.hit.x >> 16U, inside, n1, n2);
+
+            vec3 previous_throughput = throughput;
+
+            if (medium_sample_distance(medium, distance, throughput, u5, u6)) {
+                ray.org += ray.dir * distance;
+
+                // Like surfaces, media never receive first bounce photons since direct light is
+                // estimated by the gather pass. Volume photons are weighted to estimate fluence.
+
+                if (bounce != 0U) {
+                    deposit_photon(ray, throughput, true);
+                    return; // record this photon
+                }
+
+                throughput *= medium.scattering;
+
+                float q = max(0.0, 1.0 - luminance(throughput) / luminance(previous_throughput));
+
+                if (u4 < q) {
+                    return;
+                }
+
+                throughput /= 1.0 - q;
+
+                ray.dir = medium_phase_sample(medium, ray.dir, u2, u3);
+
+                continue;
+            }
+
+            ray.org += ray.dir * distance;
 
             // Note surfaces will NEVER receive first bounce photons. The "sample explicit" flag
             // is purely an optimization meant for when a surface cannot directly see any light.
@@ -49,17 +90,11 @@ void scatter_photon(ray_t ray, vec3 throughput, quasi_t quasi) {
 
             float deposit_weight = is_receiver ? u1 : 0.0;
 
-            bool inside = dot(ray.dir, normal) > 0.0;
             vec3 f;
 
-            float n1, n2;
-
-            throughput *= medium_absorption(traversal.hit.x >> 16U, inside,
-                                            traversal.range.y, n1, n2);
-
             #define MAT_SWITCH_LOGIC(LOAD, EVAL, SAMPLE) {                                        \
                 if (is_receiver) {                                                                \
-                    deposit_photon(ray, throughput);                                              \
+                    deposit_photon(ray, throughput, false);                                       \
                     return; /* record this photon */                                              \
                 }                                                                                 \
                                                                                                   \
@@ -87,32 +122,19 @@ void scatter_photon(ray_t ray, vec3 throughput, quasi_t quasi) {
     }
 }
 
-ray_t generate_photon_ray(out vec3 throughput, inout quasi_t quasi) {
-    vec3 bbmin, bbmax, wi;
-
-    get_scene_bbox(bbmin, bbmax);
-
-    float u1 = quasi_sample(quasi);
-    float u2 = quasi_sample(quasi);
-
-    float unused_pdf;
-    throughput = env_sample_light(wi, unused_pdf, u1, u2);
-    wi = -wi;
-
+// Generates a ray entering the scene's bounding box from some direction, and returns the
+// projected area of the bounding box along that direction, which is the ray's inverse PDF.
+ray_t generate_bbox_ray(vec3 wi, vec3 bbmin, vec3 bbmax, out float area, vec3 u) {
     vec3 coords = ceil(-wi);
 
     float x_area = (bbmax.y - bbmin.y) * (bbmax.z - bbmin.z) * abs(wi.x);
     float y_area = (bbmax.x - bbmin.x) * (bbmax.z - bbmin.z) * abs(wi.y);
     float z_area = (bbmax.x - bbmin.x) * (bbmax.y - bbmin.y) * abs(wi.z);
 
-    float area = x_area + y_area + z_area;
-    throughput *= area; // division by PDF
+    area = x_area + y_area + z_area;
 
-    float w = quasi_sample(quasi) * area;
-    vec2 surface_uv; // get surface point
-    
-    surface_uv.s = quasi_sample(quasi);
-    surface_uv.t = quasi_sample(quasi);
+    float w = u.x * area;
+    vec2 surface_uv = u.yz; // get surface point
 
     if (w < x_area) {
         coords.yz = surface_uv;
@@ -125,8 +147,187 @@ ray_t generate_photon_ray(out vec3 throughput, inout quasi_t quasi) {
     return ray_t(mix(bbmin, bbmax, coords) - wi, wi);
 }
 
+ray_t generate_env_photon_ray(vec3 bbmin, vec3 bbmax, out vec3 throughput, float u[6]) {
+    vec3 wi;
+
+    float unused_pdf;
+    throughput = env_sample_light(wi, unused_pdf, u[0], u[1]);
+    wi = -wi;
+
+    float area;
+    ray_t ray = generate_bbox_ray(wi, bbmin, bbmax, area, vec3(u[2], u[3], u[4]));
+    throughput *= area; // division by PDF
+
+    return ray;
+}
+
+// Generates a photon leaving the surface of an emissive instance. A line crossing the
+// instance's bounding box is chosen uniformly, and one of the points where it exits the
+// instance is selected uniformly by reservoir sampling; as the measure of lines crossing a
+// surface is its projected area, this samples photons in proportion to the emitted power.
+
+ray_t generate_emitter_photon_ray(LightData light, out vec3 throughput, float u[6]) {
+    BvhNode node = instance_buffer.data[uint(light.param1)];
+
+    vec3 bbmin = vec3(node.minx, node.miny, node.minz) - PREC;
+    vec3 bbmax = vec3(node.maxx, node.maxy, node.maxz) + PREC;
+
+    uint geometry = node.word1 & 0x7fffU, inst = node.word1 >> 16U;
+
+    float z = 2.0 * u[0] - 1.0;
+    vec3 dir = vec3(sqrt(1.0 - z * z) * cos(M_2PI * u[1]), z,
+                    sqrt(1.0 - z * z) * sin(M_2PI * u[1]));
+
+    float area;
+    ray_t ray = generate_bbox_ray(dir, bbmin, bbmax, area, vec3(u[2], u[3], u[4]));
+
+    float reservoir = u[5];
+    uint count = 0U;
+    vec3 point, normal;
+
+    for (uint i = 0U; i < 16U; ++i) {
+        vec2 range = vec2(0.0, 1.0 / 0.0);
+
+        if (!ray_bbox(ray.org, vec3(1.0) / ray.dir, range, bbmin, bbmax)) {
+            break;
+        }
+
+        if (!geo_intersect(geometry, inst, ray, range)) {
+            break;
+        }
+
+        ray.org += ray.dir * range.x;
+        vec3 n = geo_normal(geometry, inst, ray.org);
+
+        if (dot(ray.dir, n) > 0.0) {
+            float p = 1.0 / float(++count);
+
+            if (reservoir < p) {
+                point = ray.org;
+                normal = n;
+                reservoir /= p;
+            } else {
+                reservoir = (reservoir - p) / (1.0 - p);
+            }
+        }
+
+        ray = make_ray(ray.org, ray.dir, n);
+    }
+
+    if (count == 0U) {
+        throughput = vec3(0.0);
+
+        return ray;
+    }
+
+    material_t material;
+    mat_emissive_load(node.word2 >> 16U, normal, point, material);
+
+    throughput = MAT_EMISSIVE_RADIANCE * M_4PI * area * float(count);
+
+    return make_ray(point, dir, normal);
+}
+
+ray_t generate_light_photon_ray(LightData light, vec3 bbmin, vec3 bbmax, out vec3 throughput,
+                                float u[6]) {
+    float z = 2.0 * u[0] - 1.0;
+    vec3 sphere_dir = vec3(sqrt(1.0 - z * z) * cos(M_2PI * u[1]), z,
+                           sqrt(1.0 - z * z) * sin(M_2PI * u[1]));
+
+    // Area lights emit photons in a cosine-weighted distribution about their normal.
+
+    float r = sqrt(u[2]);
+    vec3 cosine_dir = vec3(r * cos(M_2PI * u[3]), sqrt(1.0 - u[2]), r * sin(M_2PI * u[3]));
+
+    vec3 emission = spectral_upsample(light.emission);
+
+    if (light.kind == LIGHT_TYPE_POINT) {
+        throughput = emission * M_4PI;
+
+        return ray_t(light.position, sphere_dir);
+    } else if (light.kind == LIGHT_TYPE_SPOT) {
+        float cos_theta = 1.0 - u[0] * (1.0 - light.param0);
+        float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+        float phi = M_2PI * u[1];
+
+        vec3 dir = rotate(vec3(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi)),
+                          light.direction);
+
+        throughput = emission * light_spot_falloff(light, dir)
+                   * M_2PI * (1.0 - light.param0);
+
+        return ray_t(light.position, dir);
+    } else if (light.kind == LIGHT_TYPE_SPHERE) {
+        throughput = emission * M_4PI * M_PI * light.param0 * light.param0;
+
+        return ray_t(light.position + sphere_dir * light.param0, rotate(cosine_dir, sphere_dir));
+    } else if (light.kind == LIGHT_TYPE_RECTANGLE) {
+        throughput = emission * M_PI * light.param0;
+
+        vec3 normal = cross(light.direction, light.tangent) / light.param0;
+        vec3 origin = light.position + light.direction * u[0] + light.tangent * u[1];
+
+        return ray_t(origin, rotate(cosine_dir, normal));
+    } else if (light.kind == LIGHT_TYPE_EMISSIVE) {
+        return generate_emitter_photon_ray(light, throughput, u);
+    } else {
+        float area;
+        ray_t ray = generate_bbox_ray(light.direction, bbmin, bbmax, area,
+                                      vec3(u[0], u[1], u[2]));
+        throughput = emission * area; // division by PDF
+
+        return ray;
+    }
+}
+
+// Chooses between the environment and the scene's lights with probability proportional
+// to their power; the environment's power is the power entering the scene bounding box.
+
+ray_t generate_photon_ray(out vec3 throughput, inout quasi_t quasi) {
+    vec3 bbmin, bbmax;
+
+    get_scene_bbox(bbmin, bbmax);
+
+    float u0 = quasi_sample(quasi);
+    float u[6];
+
+    u[0] = quasi_sample(quasi);
+    u[1] = quasi_sample(quasi);
+    u[2] = quasi_sample(quasi);
+    u[3] = quasi_sample(quasi);
+    u[4] = quasi_sample(quasi);
+    u[5] = quasi_sample(quasi);
+
+    vec3 extent = bbmax - bbmin;
+
+    float env_power = M_PI * environment.luminance * 2.0 * dot(extent, extent.yzx);
+    float lights_power = light_total_power(bbmin, bbmax, true);
+
+    float total = env_power + lights_power;
+    float w = u0 * total;
+
+    if (lights_power == 0.0) {
+        return generate_env_photon_ray(bbmin, bbmax, throughput, u);
+    } else if (w < env_power) {
+        ray_t ray = generate_env_photon_ray(bbmin, bbmax, throughput, u);
+        throughput *= total / env_power;
+
+        return ray;
+    }
+
+    float power;
+    LightData light = light_select(w - env_power, bbmin, bbmax, true, power);
+
+    ray_t ray = generate_light_photon_ray(light, bbmin, bbmax, throughput, u);
+    throughput *= (power != 0.0) ? total / power : 0.0;
+
+    return ray;
+}
+
 void main() {
-    quasi_t quasi = quasi_init(integrator.current_pass, decorrelate_sample(uint(gl_VertexID)));
+    uint sequence = decorrelate_sample(uint(gl_VertexID), integrator.sample_key);
+
+    quasi_t quasi = quasi_init(integrator.current_pass, sequence);
 
     vec3 throughput; // measure photon path contribution
     ray_t ray = generate_photon_ray(throughput, quasi);
@@ -134,5 +335,7 @@ void main() {
     gl_PointSize = 1.0;
     gl_Position = vec4(-1.0, -1.0, -1.0, 1.0);
 
-    scatter_photon(ray, throughput, quasi);
+    if (throughput != vec3(0.0)) {
+        scatter_photon(ray, throughput, quasi);
+    }
 }